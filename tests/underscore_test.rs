@@ -7,15 +7,31 @@ extern crate walkdir;
 use ansi_term::Colour::{Green, Red};
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 use std::process::Command;
 use walkdir::WalkDir;
 
+/// The programs each backend can't build yet, and why. A backend failing to build any
+/// other program the interpreter runs is a failure, and so is one of these building
+const UNSUPPORTED: &[(&str, &str, &str)] = &[
+    ("exe", "closure.us", "x86 has no closures"),
+    ("exe", "closure_call.us", "x86 has no closures"),
+    ("c", "closure.us", "C has no closures"),
+    ("c", "closure_call.us", "C has no closures"),
+    ("wasm", "add.us", "joining strings needs a heap"),
+    ("wasm", "operator/equals.us", "str_eq needs a heap"),
+    ("wasm", "operator/strings.us", "concat needs a heap"),
+    ("wasm", "struct.us", "structs need a heap"),
+    ("wasm", "generic.us", "arrays need a heap"),
+    ("wasm", "closure.us", "wasm has no closures"),
+    ("wasm", "closure_call.us", "wasm has no closures"),
+];
+
 fn main() {
     let mut pass = 0i32;
     let mut fail = 0i32;
 
     for entry in WalkDir::new("../tests/pass") {
-        let mut underscorec = Command::new("cargo");
         let entry = entry.unwrap();
         let mut expected = Vec::new();
 
@@ -40,12 +56,186 @@ fn main() {
             }
         }
 
-        underscorec.args(&["run", "--", entry.path().to_str().unwrap()]);
+        let exe = ::std::env::temp_dir().join(format!(
+            "underscore_test_{}",
+            entry.path().file_stem().unwrap().to_str().unwrap()
+        ));
+
+        // Each run writes the IR the program was lowered to next to the executable
+        // rather than over the one in the repository
+        let ir = exe.with_extension("ir");
+
+        let mut underscorec = lowered(entry.path(), &ir);
+
+        let _ = ::std::fs::remove_file(&ir);
 
         let output = underscorec.output().expect("failed to execute process");
 
+        let ran = output.status.success();
         let output = String::from_utf8_lossy(&output.stdout);
 
+        // The IR the program was lowered to has to run the same once it's read back in
+        if ir.exists() {
+            let reread = Command::new("cargo")
+                .args(&["run", "--", "--no-disassembly"])
+                .arg(&ir)
+                .output()
+                .expect("failed to execute process");
+            let reread = String::from_utf8_lossy(&reread.stdout);
+
+            if reread == output {
+                pass += 1;
+            } else {
+                fail += 1;
+            }
+        }
+
+        // The reference interpreter is the oracle the vm, the native code and the C
+        // translation have to agree with
+        let interpreted = lowered(entry.path(), &ir)
+            .arg("--interpret")
+            .output()
+            .expect("failed to execute process");
+
+        let oracle = String::from_utf8_lossy(&interpreted.stdout).into_owned();
+        let interpreted = interpreted.status.success();
+
+        // The vm has to run everything the interpreter can
+        if interpreted {
+            if ran && output == oracle {
+                pass += 1;
+            } else {
+                fail += 1;
+            }
+        }
+
+        // The path of the program under `tests/pass`, which `UNSUPPORTED` lists them by
+        let program = entry
+            .path()
+            .strip_prefix("../tests/pass")
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        // Native code has to behave the same as the interpreter, with and without
+        // optimisations
+        for level in &["-O0", "-O2"] {
+            let _ = ::std::fs::remove_file(&exe);
+
+            let compiled = lowered(entry.path(), &ir)
+                .args(&[level, "--emit", "exe", "-o"])
+                .arg(&exe)
+                .status()
+                .expect("failed to execute process")
+                .success();
+
+            if !built("exe", &program, compiled && exe.exists(), interpreted) {
+                fail += 1;
+                continue;
+            }
+
+            if compiled && exe.exists() {
+                let native = Command::new(&exe)
+                    .output()
+                    .expect("failed to execute process");
+                let native = String::from_utf8_lossy(&native.stdout);
+
+                let _ = ::std::fs::remove_file(&exe);
+
+                if native == oracle {
+                    pass += 1;
+                } else {
+                    fail += 1;
+                }
+
+                for expects in &expected {
+                    if native.lines().any(|line| line == expects.trim()) {
+                        pass += 1;
+                    } else {
+                        fail += 1;
+                    }
+                }
+            }
+        }
+
+        // So does the C translation, once a C compiler has built it
+        let c = exe.with_extension("c");
+        let _ = ::std::fs::remove_file(&c);
+
+        let translated = lowered(entry.path(), &ir)
+            .args(&["--emit", "c", "-o"])
+            .arg(&c)
+            .status()
+            .expect("failed to execute process")
+            .success()
+            && c.exists()
+            && Command::new("cc")
+                .args(&["-std=c99", "-o"])
+                .arg(&exe)
+                .arg(&c)
+                .status()
+                .expect("failed to execute process")
+                .success();
+
+        let _ = ::std::fs::remove_file(&c);
+
+        if !built("c", &program, translated, interpreted) {
+            fail += 1;
+        } else if translated {
+            let output = Command::new(&exe)
+                .output()
+                .expect("failed to execute process");
+            let _ = ::std::fs::remove_file(&exe);
+
+            let translated = String::from_utf8_lossy(&output.stdout);
+
+            if !interpreted || translated == oracle {
+                pass += 1;
+            } else {
+                fail += 1;
+            }
+
+            for expects in &expected {
+                if translated.lines().any(|line| line == expects.trim()) {
+                    pass += 1;
+                } else {
+                    fail += 1;
+                }
+            }
+        }
+
+        // And so does the wasm module, run under node
+        let wasm = exe.with_extension("wasm");
+        let _ = ::std::fs::remove_file(&wasm);
+
+        let emitted = lowered(entry.path(), &ir)
+            .args(&["--emit", "wasm", "-o"])
+            .arg(&wasm)
+            .status()
+            .expect("failed to execute process")
+            .success();
+
+        if !built("wasm", &program, emitted && wasm.exists(), interpreted) {
+            fail += 1;
+        } else if emitted && wasm.exists() {
+            let run = Command::new("node")
+                .arg("../underscore_wasm/host.js")
+                .arg(&wasm)
+                .output()
+                .expect("failed to run node, which the wasm backend is tested with");
+
+            let _ = ::std::fs::remove_file(&wasm);
+
+            let ran = run.status.success();
+            let run = String::from_utf8_lossy(&run.stdout);
+
+            if !interpreted || (ran && run == oracle) {
+                pass += 1;
+            } else {
+                fail += 1;
+            }
+        }
+
         for expects in expected {
             if output.contains(&expects) {
                 pass += 1;
@@ -59,6 +249,8 @@ fn main() {
                 fail += 1;
             }
         }
+
+        let _ = ::std::fs::remove_file(&ir);
     }
 
     println!(
@@ -117,3 +309,40 @@ fn main() {
         );
     }
 }
+
+/// Runs the compiler on a source file, writing the IR it is lowered to at `ir`. The vm
+/// only prints what the program does
+fn lowered(source: &Path, ir: &Path) -> Command {
+    let mut command = Command::new("cargo");
+
+    command
+        .args(&["run", "--", source.to_str().unwrap()])
+        .args(&["--no-disassembly", "--lowered"])
+        .arg(ir);
+
+    command
+}
+
+/// Whether a backend built the programs it should, every one the interpreter runs
+/// apart from those listed in `UNSUPPORTED`, and none of those
+fn built(backend: &str, program: &str, built: bool, interpreted: bool) -> bool {
+    let unsupported = UNSUPPORTED
+        .iter()
+        .find(|&&(unsupported, listed, _)| unsupported == backend && listed == program);
+
+    match unsupported {
+        Some(&(_, _, reason)) if built => {
+            eprintln!(
+                "`{}` builds with the {} backend but is listed as unsupported: {}",
+                program, backend, reason
+            );
+            false
+        }
+        Some(_) => true,
+        None if !built && interpreted => {
+            eprintln!("`{}` doesn't build with the {} backend", program, backend);
+            false
+        }
+        None => true,
+    }
+}
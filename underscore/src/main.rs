@@ -1,7 +1,9 @@
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
-extern crate underscore_x86;
+extern crate underscore_ir;
+extern crate underscore_wasm as wasm;
+extern crate underscore_x86 as x86;
 extern crate underscore_semant;
 extern crate underscore_syntax;
 extern crate underscore_util;
@@ -11,18 +13,25 @@ extern crate underscore_vm;
 use std::io::{self, Write};
 use std::rc::Rc;
 use structopt::StructOpt;
-use underscore_semant::{Codegen, Infer, TypeEnv};
+use underscore_ir::callgraph::CallGraph;
+use underscore_ir::dot;
+use underscore_ir::interpret::{self, InterpretError};
+use underscore_ir::ir::{self, Program};
+use underscore_ir::optimize::{Optimizer, Pass};
+use underscore_ir::parse::parse;
+use underscore_semant::{CCodegen, Codegen, Infer, TypeEnv};
+use underscore_syntax::ast::Linkage;
 use underscore_syntax::lexer::Lexer;
 use underscore_syntax::parser::Parser;
 use underscore_util::emitter::Reporter;
 use underscore_util::symbol::{SymbolMap, Symbols};
-use underscore_vm::{Chunk, VM};
+use underscore_vm::{Compiler, VMError, LIBC, VM};
 
 fn main() {
     let opts = Cli::from_args();
 
-    if let Some(file) = opts.source {
-        run(file, opts.file);
+    if let Some(ref file) = opts.source {
+        run(file.clone(), &opts);
     } else {
         repl()
     }
@@ -60,7 +69,7 @@ fn repl() {
     }
 }
 
-fn run(path: String, dump_file: Option<String>) {
+fn run(path: String, opts: &Cli) {
     use std::fs::File;
     use std::io::Read;
 
@@ -71,10 +80,23 @@ fn run(path: String, dump_file: Option<String>) {
     file.read_to_string(&mut contents)
         .expect("something went wrong reading the file");
 
+    let dump_file = opts.file.clone();
+
     let input = contents.trim();
 
-    if contents.is_empty() {
-        ::std::process::exit(0)
+    // IR in the text format skips the front end
+    if path.ends_with(".ir") {
+        let mut names = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let lowered = match parse(&contents, &mut names) {
+            Ok(lowered) => lowered,
+            Err(e) => {
+                println!("{:?}", e);
+                ::std::process::exit(65)
+            }
+        };
+
+        return compile(&path, opts, lowered, names);
     }
 
     let mut reporter = Reporter::new();
@@ -132,38 +154,298 @@ fn run(path: String, dump_file: Option<String>) {
         }
     };
 
-    let mut chunk = Chunk::new();
-    let mut constant = chunk.add_constant(&[12, 0, 0, 0], 1);
+    // C is generated from the typed program as it still knows about structs
+    if opts.emit.as_ref().map(|emit| emit == "c").unwrap_or(false) {
+        let output = opts.output.clone().unwrap_or_else(|| format!("{}.c", stem(&path)));
+
+        let names = Symbols::new(Rc::clone(&strings));
+
+        let source = match CCodegen::new(names).gen_program(&ast) {
+            Ok(source) => source,
+            Err(e) => {
+                println!("{:?}", e);
+                ::std::process::exit(65)
+            }
+        };
+
+        let mut file = File::create(output).expect("Couldn't create file");
+        file.write(source.as_bytes())
+            .expect("Couldn't write to the file");
+
+        return;
+    }
+
+    let mut codegen = Codegen::new(symbols);
 
-    chunk.write(2, 1); //Constant32
+    let lowered = match codegen.gen_program(ast) {
+        Ok(lowered) => lowered,
+        Err(e) => {
+            println!("{:?}", e);
+            ::std::process::exit(65)
+        }
+    };
+
+    let names: Symbols<()> = Symbols::new(Rc::clone(&strings));
 
-    chunk.write(constant as u8, 1); //index
+    {
+        let mut file = File::create(&opts.lowered).expect("Couldn't create file");
+        file.write(lowered.print(&names).as_bytes())
+            .expect("Couldn't write to the file");
+    }
 
-    constant = chunk.add_constant(&[25, 0, 0, 0], 1);
+    compile(&path, opts, lowered, names)
+}
 
-    chunk.write(2, 1);//Constant32
-    chunk.write(constant as u8, 1);//index
+/// Optimises the IR and then runs it or hands it to the backend that `--emit` asks for
+fn compile(path: &str, opts: &Cli, mut lowered: Program, mut names: Symbols<()>) {
+    // Broken IR is a bug in code generation, or in whatever wrote the text
+    if cfg!(debug_assertions) {
+        if let Err(errors) = ir::verify_named(&lowered, &names) {
+            for error in errors {
+                println!("{:?}", error);
+            }
 
-    chunk.write(7, 1); // Multiply
-    chunk.write(4, 1);
+            ::std::process::exit(70)
+        }
+    }
 
-    chunk.write(0, 2);
-    chunk.write(4, 2);
+    let mut optimizer = Optimizer::new(opts.opt_level);
 
-    println!("{:?}",chunk);
+    for name in opts.disable_passes.iter().flat_map(|passes| passes.split(',')) {
+        match Pass::from_name(name) {
+            Some(pass) => optimizer.disable(pass),
+            None => {
+                let passes: Vec<&str> = Pass::ALL.iter().map(Pass::name).collect();
+                println!("Unknown pass `{}`, expected one of {}", name, passes.join(", "));
+                ::std::process::exit(64)
+            }
+        }
+    }
+
+    optimizer.optimize(&mut lowered, &mut names);
+
+    if opts.interpret {
+        let stdout = io::stdout();
+
+        match interpret::run(&lowered, &names, &mut stdout.lock()) {
+            Ok(Some(code)) => ::std::process::exit(code),
+            Ok(None) => (),
+            Err(e) => {
+                println!("{:?}", e);
+
+                match e {
+                    InterpretError::Invalid(_) => ::std::process::exit(65),
+                    _ => ::std::process::exit(70),
+                }
+            }
+        }
+
+        return;
+    }
+
+    if let Some(ref emit) = opts.emit {
+        if emit == "wat" || emit == "wasm" {
+            return wasm(path, emit, opts.output.clone(), &lowered, &names);
+        }
+
+        if emit == "cfg-dot" {
+            return cfg_dot(path, opts.output.clone(), &lowered, &names);
+        }
+
+        return native(path, emit, opts.output.clone(), &lowered, &names);
+    }
+
+    let mut chunk = match Compiler::new().compile(&lowered, &names) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            println!("{:?}", e);
+            ::std::process::exit(65)
+        }
+    };
+
+    if chunk.entry.is_none() {
+        return;
+    }
 
     let mut vm = VM::new(&mut chunk);
+    vm.disassemble &= !opts.no_disassembly;
 
-    vm.run().expect("Err");
-    let mut codegen = Codegen::new(symbols);
+    for &(name, arity, function) in LIBC {
+        vm.register_native(name, arity, function);
+    }
+
+    if let Err(e) = vm.run() {
+        println!("{:?}", e);
 
-    let lowered = codegen.gen_program(ast);
+        // Externals without a native are reported when the chunk is linked
+        match e {
+            VMError::CompilerError(_) => ::std::process::exit(65),
+            _ => ::std::process::exit(70),
+        }
+    }
 
-    let mut file = File::create("lowered.ir").expect("Couldn't create file");
-    file.write(format!("{}", lowered).as_bytes())
+    if let Some(code) = vm.exit {
+        ::std::process::exit(code)
+    }
+}
+
+/// Compiles the program with the x86 backend instead of running it
+fn native(
+    path: &str,
+    emit: &str,
+    output: Option<String>,
+    lowered: &underscore_ir::ir::Program,
+    names: &Symbols<()>,
+) {
+    use std::fs::File;
+    use std::path::Path;
+
+    let asm = match x86::Codegen::new().compile(lowered, names) {
+        Ok(asm) => asm,
+        Err(e) => {
+            println!("{:?}", e);
+            ::std::process::exit(65)
+        }
+    };
+
+    let stem = stem(path);
+
+    match emit {
+        "asm" => {
+            let output = output.unwrap_or_else(|| format!("{}.s", stem));
+            let mut file = File::create(output).expect("Couldn't create file");
+
+            file.write(asm.to_string().as_bytes())
+                .expect("Couldn't write to the file");
+        }
+
+        "obj" => {
+            let output = output.unwrap_or_else(|| format!("{}.o", stem));
+
+            let object = match x86::object(&asm) {
+                Ok(object) => object,
+                Err(e) => {
+                    println!("{:?}", e);
+                    ::std::process::exit(65)
+                }
+            };
+
+            let mut file = File::create(output).expect("Couldn't create file");
+
+            file.write(&object).expect("Couldn't write to the file");
+        }
+
+        "exe" => {
+            let output = output.unwrap_or(stem);
+
+            if let Err(e) = x86::link(&asm, Path::new(&output)) {
+                println!("{:?}", e);
+                ::std::process::exit(65)
+            }
+        }
+
+        _ => {
+            println!(
+                "Unknown output `{}`, expected asm, obj, exe, c, wat, wasm or cfg-dot",
+                emit
+            );
+            ::std::process::exit(64)
+        }
+    }
+}
+
+/// Compiles the program to a WebAssembly module instead of running it, written in the
+/// text format for `wat` and the binary one for `wasm`
+fn wasm(
+    path: &str,
+    format: &str,
+    output: Option<String>,
+    lowered: &underscore_ir::ir::Program,
+    names: &Symbols<()>,
+) {
+    use std::fs::File;
+
+    let module = match wasm::Codegen::new().compile(lowered, names) {
+        Ok(module) => module,
+        Err(e) => {
+            println!("{:?}", e);
+            ::std::process::exit(65)
+        }
+    };
+
+    // A module that doesn't validate is a bug in the backend
+    if let Err(e) = wasm::validate(&module) {
+        println!("{:?}", e);
+        ::std::process::exit(70)
+    }
+
+    let output = output.unwrap_or_else(|| format!("{}.{}", stem(path), format));
+    let mut file = File::create(output).expect("Couldn't create file");
+
+    let bytes = if format == "wasm" {
+        wasm::encode(&module)
+    } else {
+        module.to_string().into_bytes()
+    };
+
+    file.write_all(&bytes).expect("Couldn't write to the file");
+}
+
+/// Writes a Graphviz graph of the control flow of each function and one of the calls
+/// between them, into the directory `output` names or the current one
+fn cfg_dot(path: &str, output: Option<String>, lowered: &Program, names: &Symbols<()>) {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    let dir = PathBuf::from(output.unwrap_or_else(|| ".".into()));
+    fs::create_dir_all(&dir).expect("Couldn't create the directory");
+
+    let stem = stem(path);
+
+    for function in &lowered.functions {
+        if function.linkage == Linkage::External {
+            continue;
+        }
+
+        let graph = match dot::cfg(function, names) {
+            Ok(graph) => graph,
+            Err(e) => {
+                println!("{:?}", e);
+                ::std::process::exit(65)
+            }
+        };
+
+        // Closures are named after where they are, which isn't always a good file name
+        let name: String = names
+            .name(function.name)
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+            .collect();
+
+        let mut file = File::create(dir.join(format!("{}.{}.cfg.dot", stem, name)))
+            .expect("Couldn't create file");
+        file.write(graph.as_bytes())
+            .expect("Couldn't write to the file");
+    }
+
+    let calls = dot::call_graph(&CallGraph::new(lowered), &lowered.functions, names);
+
+    let mut file =
+        File::create(dir.join(format!("{}.calls.dot", stem))).expect("Couldn't create file");
+    file.write(calls.as_bytes())
         .expect("Couldn't write to the file");
 }
 
+/// The name of the source file without its extension
+fn stem(path: &str) -> String {
+    use std::path::Path;
+
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "out".into())
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "underscore")]
 pub struct Cli {
@@ -174,4 +456,27 @@ pub struct Cli {
     pub file: Option<String>,
     #[structopt(short = "ir", long = "emit-ir")]
     pub emit_ir: bool,
+    /// Where to write the IR the program is lowered to
+    #[structopt(long = "lowered", default_value = "lowered.ir")]
+    pub lowered: String,
+    /// Compile to `asm`, an `obj` or an `exe` with the x86 backend, to `c` source or to
+    /// a `wat` or `wasm` module instead of running the program. `cfg-dot` writes Graphviz
+    /// graphs of each function's control flow and of the calls between functions
+    #[structopt(long = "emit")]
+    pub emit: Option<String>,
+    /// Where to write what `--emit` produces, a directory for `cfg-dot`
+    #[structopt(short = "o", long = "output")]
+    pub output: Option<String>,
+    /// How much to optimise the IR, from 0 to 2
+    #[structopt(short = "O", long = "opt-level", default_value = "0")]
+    pub opt_level: u8,
+    /// A comma separated list of passes to skip that the optimisation level would run
+    #[structopt(long = "disable-passes")]
+    pub disable_passes: Option<String>,
+    /// Don't print the disassembly of the chunk before running it with the bytecode vm
+    #[structopt(long = "no-disassembly")]
+    pub no_disassembly: bool,
+    /// Run the IR with the reference interpreter instead of the bytecode vm
+    #[structopt(long = "interpret")]
+    pub interpret: bool,
 }
@@ -0,0 +1,98 @@
+//! Which functions call which, from the targets of the program's calls. Closures are
+//! called through a temp so the calls they get aren't known.
+use ir::{Instruction, Label, Program};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallGraph {
+    /// Each function of the program, in order, with the functions it calls in the order
+    /// it first calls them
+    pub calls: Vec<(Label, Vec<Label>)>,
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> Self {
+        let calls = program
+            .functions
+            .iter()
+            .map(|function| {
+                let mut callees = Vec::new();
+
+                for instruction in &function.body {
+                    if let Instruction::Call(_, callee, _) = *instruction {
+                        if !callees.contains(&callee) {
+                            callees.push(callee);
+                        }
+                    }
+                }
+
+                (function.name, callees)
+            })
+            .collect();
+
+        CallGraph { calls }
+    }
+
+    /// The functions a function calls
+    pub fn callees(&self, function: Label) -> &[Label] {
+        self.calls
+            .iter()
+            .find(|&&(name, _)| name == function)
+            .map(|(_, callees)| &callees[..])
+            .unwrap_or(&[])
+    }
+
+    /// The functions that call a function
+    pub fn callers(&self, function: Label) -> Vec<Label> {
+        self.calls
+            .iter()
+            .filter(|&(_, callees)| callees.contains(&function))
+            .map(|&(name, _)| name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CallGraph;
+    use parse::parse;
+    use std::rc::Rc;
+    use util::symbol::{SymbolMap, Symbols};
+
+    #[test]
+    fn calls_are_edges() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program = "\
+function even(t1:i32) -> i32 {
+    t2 := call odd(t1)
+    t3 := call odd(t2)
+    ret t3
+}
+function odd(t1:i32) -> i32 {
+    t2 := call even(t1)
+    ret t2
+}
+function main() -> i32 {
+    t1 := 1:i32
+    t2 := call even(t1)
+    t3 := print_int(t2)
+    ret t2
+}
+";
+
+        let program = parse(program, &mut symbols).unwrap();
+        let graph = CallGraph::new(&program);
+
+        let (even, odd, main) = (
+            symbols.symbol("even"),
+            symbols.symbol("odd"),
+            symbols.symbol("main"),
+        );
+
+        assert_eq!(graph.callees(even), &[odd]);
+        assert_eq!(graph.callees(odd), &[even]);
+        assert_eq!(graph.callees(main), &[even]);
+        assert_eq!(graph.callers(even), vec![odd, main]);
+        assert!(graph.callers(main).is_empty());
+    }
+}
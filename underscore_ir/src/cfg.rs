@@ -0,0 +1,197 @@
+//! The control flow graph of a function body.
+//! Blocks cover a range of the body, start at a `Label` or after an instruction that
+//! jumps or returns and end at the next one. They are numbered in the order they
+//! appear in the body, so the entry is always block 0.
+use ir::{Instruction, Label};
+use std::collections::HashMap;
+
+pub type BlockId = usize;
+
+#[derive(Debug)]
+pub enum CfgError {
+    /// A jump to a label that isn't in the body
+    UndefinedLabel(Label),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    /// The label the block starts with
+    pub label: Option<Label>,
+    /// The range of the body the block covers, including the jump or return it ends with
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<BlockId>,
+    pub predecessors: Vec<BlockId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub entry: BlockId,
+}
+
+impl Cfg {
+    pub fn new(body: &[Instruction]) -> Result<Self, CfgError> {
+        let mut ranges = vec![];
+        let mut start = 0;
+
+        for (i, instruction) in body.iter().enumerate() {
+            match *instruction {
+                // A label starts a new block unless the current one is still empty
+                Instruction::Label(_) if i != start => {
+                    ranges.push((start, i));
+                    start = i;
+                }
+                Instruction::Jump(_)
+                | Instruction::CJump(_, _, _, _, _)
+                | Instruction::Return(_) => {
+                    ranges.push((start, i + 1));
+                    start = i + 1;
+                }
+                _ => (),
+            }
+        }
+
+        if start != body.len() || ranges.is_empty() {
+            ranges.push((start, body.len()));
+        }
+
+        let mut labels = HashMap::new();
+
+        for (id, &(start, end)) in ranges.iter().enumerate() {
+            if let Some(&Instruction::Label(label)) = body[start..end].first() {
+                labels.insert(label, id);
+            }
+        }
+
+        let target = |label: Label| match labels.get(&label) {
+            Some(&id) => Ok(id),
+            None => Err(CfgError::UndefinedLabel(label)),
+        };
+
+        let mut blocks = Vec::with_capacity(ranges.len());
+
+        for (id, &(start, end)) in ranges.iter().enumerate() {
+            let successors = match body[start..end].last() {
+                Some(&Instruction::Jump(label)) => vec![target(label)?],
+                Some(&Instruction::CJump(_, _, _, ltrue, lfalse)) if ltrue == lfalse => {
+                    vec![target(ltrue)?]
+                }
+                Some(&Instruction::CJump(_, _, _, ltrue, lfalse)) => {
+                    vec![target(ltrue)?, target(lfalse)?]
+                }
+                Some(&Instruction::Return(_)) => vec![],
+                // Anything else falls through to the next block
+                _ if id + 1 < ranges.len() => vec![id + 1],
+                _ => vec![],
+            };
+
+            blocks.push(Block {
+                label: match body[start..end].first() {
+                    Some(&Instruction::Label(label)) => Some(label),
+                    _ => None,
+                },
+                start,
+                end,
+                successors,
+                predecessors: vec![],
+            });
+        }
+
+        for id in 0..blocks.len() {
+            for successor in blocks[id].successors.clone() {
+                blocks[successor].predecessors.push(id);
+            }
+        }
+
+        Ok(Cfg { blocks, entry: 0 })
+    }
+
+    /// The blocks that can be reached from the entry, each one before its successors
+    /// apart from where an edge loops back
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(self.entry, 0)];
+
+        visited[self.entry] = true;
+
+        while let Some((block, next)) = stack.pop() {
+            match self.blocks[block].successors.get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cfg, CfgError};
+    use ir::{BinOp, CmpOp, Instruction, Temp, Value};
+    use std::rc::Rc;
+    use syntax::ast::{Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    #[test]
+    fn blocks_split_at_labels_and_jumps() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (start, body, end) = (
+            symbols.symbol("start"),
+            symbols.symbol("body"),
+            symbols.symbol("end"),
+        );
+        let (n, one) = (Temp::new(), Temp::new());
+
+        let cfg = Cfg::new(&[
+            Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+            Instruction::Label(start),
+            Instruction::CJump(n, CmpOp::GT, one, body, end),
+            Instruction::Label(body),
+            Instruction::BinOp(n, BinOp::Minus, one, n),
+            Instruction::Jump(start),
+            // Nothing jumps here
+            Instruction::Return(n),
+            Instruction::Label(end),
+            Instruction::Return(n),
+        ])
+        .unwrap();
+
+        let ranges: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(ranges, vec![(0, 1), (1, 3), (3, 6), (6, 7), (7, 9)]);
+
+        assert_eq!(cfg.blocks[0].successors, vec![1]);
+        assert_eq!(cfg.blocks[1].successors, vec![2, 4]);
+        assert_eq!(cfg.blocks[1].predecessors, vec![0, 2]);
+        assert_eq!(cfg.blocks[2].successors, vec![1]);
+        assert_eq!(cfg.blocks[3].predecessors, Vec::<usize>::new());
+        assert_eq!(cfg.blocks[4].label, Some(end));
+
+        // The unreachable return isn't visited
+        assert_eq!(cfg.reverse_postorder(), vec![0, 1, 4, 2]);
+    }
+
+    #[test]
+    fn jumps_need_a_label() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let missing = symbols.symbol("missing");
+
+        match Cfg::new(&[Instruction::Jump(missing)]) {
+            Err(CfgError::UndefinedLabel(label)) => assert_eq!(label, missing),
+            cfg => panic!("{:?}", cfg),
+        }
+
+        // An empty body still has an entry
+        assert_eq!(Cfg::new(&[]).unwrap().blocks.len(), 1);
+    }
+}
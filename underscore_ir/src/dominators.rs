@@ -0,0 +1,154 @@
+//! The dominator tree of a control flow graph, found with the algorithm of Cooper,
+//! Harvey and Kennedy, along with the dominance frontiers that phis are placed at.
+use cfg::{BlockId, Cfg};
+
+#[derive(Debug)]
+pub struct Dominators {
+    entry: BlockId,
+    /// The position of each block in reverse postorder, `None` if it can't be reached
+    order: Vec<Option<usize>>,
+    /// The immediate dominator of each reachable block, the entry dominates itself
+    idom: Vec<BlockId>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(cfg: &Cfg) -> Self {
+        let count = cfg.blocks.len();
+        let reverse_postorder = cfg.reverse_postorder();
+
+        let mut dominators = Dominators {
+            entry: cfg.entry,
+            order: vec![None; count],
+            idom: vec![cfg.entry; count],
+            children: vec![vec![]; count],
+        };
+
+        for (i, &block) in reverse_postorder.iter().enumerate() {
+            dominators.order[block] = Some(i);
+        }
+
+        let mut done = vec![false; count];
+        done[cfg.entry] = true;
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &block in reverse_postorder.iter().skip(1) {
+                let mut processed = cfg.blocks[block]
+                    .predecessors
+                    .iter()
+                    .filter(|&&pred| done[pred]);
+
+                let mut idom = *processed.next().unwrap();
+
+                for &pred in processed {
+                    idom = dominators.intersect(pred, idom);
+                }
+
+                if !done[block] || dominators.idom[block] != idom {
+                    dominators.idom[block] = idom;
+                    done[block] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        for &block in reverse_postorder.iter().skip(1) {
+            let idom = dominators.idom[block];
+            dominators.children[idom].push(block);
+        }
+
+        for children in &mut dominators.children {
+            children.sort();
+        }
+
+        dominators
+    }
+
+    fn intersect(&self, mut a: BlockId, mut b: BlockId) -> BlockId {
+        while a != b {
+            while self.order[a] > self.order[b] {
+                a = self.idom[a];
+            }
+
+            while self.order[b] > self.order[a] {
+                b = self.idom[b];
+            }
+        }
+
+        a
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.order[block].is_some()
+    }
+
+    /// The immediate dominator of a block, `None` for the entry and blocks that can't be reached
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        if block == self.entry || !self.is_reachable(block) {
+            None
+        } else {
+            Some(self.idom[block])
+        }
+    }
+
+    /// The blocks that a block immediately dominates
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+
+        loop {
+            if a == b {
+                return true;
+            }
+
+            if b == self.entry {
+                return false;
+            }
+
+            b = self.idom[b];
+        }
+    }
+
+    /// The blocks where the dominance of each block ends
+    pub fn frontiers(&self, cfg: &Cfg) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![vec![]; cfg.blocks.len()];
+
+        for (block, contents) in cfg.blocks.iter().enumerate() {
+            if contents.predecessors.len() < 2 || !self.is_reachable(block) {
+                continue;
+            }
+
+            for &pred in &contents.predecessors {
+                if !self.is_reachable(pred) {
+                    continue;
+                }
+
+                let mut runner = pred;
+
+                while runner != self.idom[block] {
+                    if !frontiers[runner].contains(&block) {
+                        frontiers[runner].push(block);
+                    }
+
+                    if runner == self.entry {
+                        break;
+                    }
+
+                    runner = self.idom[runner];
+                }
+            }
+        }
+
+        frontiers
+    }
+}
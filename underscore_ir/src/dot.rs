@@ -0,0 +1,192 @@
+//! Graphviz graphs of the control flow of a function and of the calls between functions,
+//! for looking at what code generation and the passes did.
+use callgraph::CallGraph;
+use cfg::{Cfg, CfgError};
+use ir::{Function, Instruction};
+use std::fmt::{self, Write};
+use syntax::ast::Linkage;
+use util::symbol::Symbols;
+
+/// Quotes a string for an id or a label
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The blocks of a function as nodes that list their instructions, with the edges of a
+/// conditional jump labelled `true` and `false`
+pub fn cfg<T: Clone>(function: &Function, symbols: &Symbols<T>) -> Result<String, CfgError> {
+    let cfg = Cfg::new(&function.body)?;
+    let mut dot = String::new();
+
+    write_cfg(&mut dot, function, &cfg, symbols).expect("Writing to a string can't fail");
+
+    Ok(dot)
+}
+
+fn write_cfg<T: Clone>(
+    dot: &mut String,
+    function: &Function,
+    cfg: &Cfg,
+    symbols: &Symbols<T>,
+) -> fmt::Result {
+    writeln!(dot, "digraph {} {{", quote(&symbols.name(function.name)))?;
+    writeln!(dot, "    node [shape=box, fontname=monospace];")?;
+
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        // `\l` ends a line that is aligned to the left
+        let mut label = format!("b{}\\l", id);
+
+        for instruction in &function.body[block.start..block.end] {
+            let text = instruction
+                .print(symbols)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            write!(label, "{}\\l", text)?;
+        }
+
+        writeln!(dot, "    b{} [label=\"{}\"];", id, label)?;
+    }
+
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        match function.body[block.start..block.end].last() {
+            Some(&Instruction::CJump(_, _, _, _, _)) if block.successors.len() == 1 => {
+                let to = block.successors[0];
+                writeln!(dot, "    b{} -> b{} [label=\"true\"];", id, to)?;
+                writeln!(dot, "    b{} -> b{} [label=\"false\"];", id, to)?;
+            }
+            Some(&Instruction::CJump(_, _, _, _, _)) => {
+                let (ltrue, lfalse) = (block.successors[0], block.successors[1]);
+                writeln!(dot, "    b{} -> b{} [label=\"true\"];", id, ltrue)?;
+                writeln!(dot, "    b{} -> b{} [label=\"false\"];", id, lfalse)?;
+            }
+            _ => {
+                for successor in &block.successors {
+                    writeln!(dot, "    b{} -> b{};", id, successor)?;
+                }
+            }
+        }
+    }
+
+    writeln!(dot, "}}")
+}
+
+/// The functions of a program as nodes with an edge from each one to the functions it
+/// calls. External functions are dashed
+pub fn call_graph<T: Clone>(
+    graph: &CallGraph,
+    functions: &[Function],
+    symbols: &Symbols<T>,
+) -> String {
+    let mut dot = String::new();
+
+    write_call_graph(&mut dot, graph, functions, symbols).expect("Writing to a string can't fail");
+
+    dot
+}
+
+fn write_call_graph<T: Clone>(
+    dot: &mut String,
+    graph: &CallGraph,
+    functions: &[Function],
+    symbols: &Symbols<T>,
+) -> fmt::Result {
+    writeln!(dot, "digraph calls {{")?;
+    writeln!(dot, "    node [shape=box, fontname=monospace];")?;
+
+    for function in functions {
+        let name = quote(&symbols.name(function.name));
+
+        if function.linkage == Linkage::External {
+            writeln!(dot, "    {} [style=dashed];", name)?;
+        } else {
+            writeln!(dot, "    {};", name)?;
+        }
+    }
+
+    for &(caller, ref callees) in &graph.calls {
+        for callee in callees {
+            writeln!(
+                dot,
+                "    {} -> {};",
+                quote(&symbols.name(caller)),
+                quote(&symbols.name(*callee))
+            )?;
+        }
+    }
+
+    writeln!(dot, "}}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{call_graph, cfg};
+    use callgraph::CallGraph;
+    use parse::parse;
+    use std::rc::Rc;
+    use util::symbol::{SymbolMap, Symbols};
+
+    const PROGRAM: &str = "\
+function max(t1:i32, t2:i32) -> i32 {
+    if t1 > t2 then first else second
+    label first
+    ret t1
+    label second
+    ret t2
+}
+function main() -> i32 {
+    t1 := \"a \\\"max\\\"\"
+    t2 := print_str(t1)
+    t3 := 1:i32
+    t4 := call max(t3, t3)
+    ret t4
+}
+";
+
+    #[test]
+    fn blocks_are_nodes_with_labelled_branches() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let program = parse(PROGRAM, &mut symbols).unwrap();
+
+        let max = cfg(&program.functions[0], &symbols).unwrap();
+
+        assert_eq!(
+            max,
+            "digraph \"max\" {
+    node [shape=box, fontname=monospace];
+    b0 [label=\"b0\\lif t1 > t2 then first else second\\l\"];
+    b1 [label=\"b1\\llabel first\\lret t1\\l\"];
+    b2 [label=\"b2\\llabel second\\lret t2\\l\"];
+    b0 -> b1 [label=\"true\"];
+    b0 -> b2 [label=\"false\"];
+}
+"
+        );
+
+        // Quotes in strings don't end the label
+        let main = cfg(&program.functions[1], &symbols).unwrap();
+        assert!(
+            main.contains("t1 := \\\"a \\\\\\\"max\\\\\\\"\\\"\\l"),
+            "{}",
+            main
+        );
+    }
+
+    #[test]
+    fn calls_are_edges_between_functions() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let program = parse(PROGRAM, &mut symbols).unwrap();
+
+        let graph = CallGraph::new(&program);
+
+        assert_eq!(
+            call_graph(&graph, &program.functions, &symbols),
+            "digraph calls {
+    node [shape=box, fontname=monospace];
+    \"max\";
+    \"main\";
+    \"main\" -> \"max\";
+}
+"
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Inlining of small functions into their callers and the elimination of the calls a
+//! function makes to itself just before returning.
+use ir::{new_named_label, Function, Instruction, Label, Program, Temp, Value};
+use std::collections::HashMap;
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::Symbols;
+
+/// The most instructions a function can have, not counting labels, and still be inlined
+pub const INLINE_SIZE: usize = 24;
+
+/// A copy of a function that is small enough to be inlined
+struct Callee {
+    params: Vec<Temp>,
+    returns: (Sign, Size),
+    body: Vec<Instruction>,
+}
+
+fn size(body: &[Instruction]) -> usize {
+    body.iter()
+        .filter(|instruction| !matches!(**instruction, Instruction::Label(_)))
+        .count()
+}
+
+/// Whether the function can be copied into the body of its callers. Closures and the
+/// functions that make them need a frame of their own to keep their upvalues in
+fn is_inlinable(function: &Function) -> bool {
+    function.linkage == Linkage::Normal
+        && function.upvalues.is_empty()
+        && size(&function.body) <= INLINE_SIZE
+        && function.body.iter().all(|instruction| match *instruction {
+            Instruction::Call(_, callee, _) => callee != function.name,
+            Instruction::Closure(_, _, _)
+            | Instruction::GetUpvalue(_, _)
+            | Instruction::SetUpvalue(_, _)
+            | Instruction::CloseUpvalue(_)
+            | Instruction::Phi(_, _) => false,
+            _ => true,
+        })
+}
+
+/// Replaces the calls to small functions that don't call themselves with their body.
+/// Callees are inlined as they were before any of them were changed, so a call that
+/// comes from an inlined body is left alone
+pub fn inline<T: Clone>(program: &mut Program, symbols: &mut Symbols<T>) -> bool {
+    let callees: HashMap<Label, Callee> = program
+        .functions
+        .iter()
+        .filter(|function| is_inlinable(function))
+        .map(|function| {
+            let callee = Callee {
+                params: function.params.clone(),
+                returns: function.returns,
+                body: function.body.clone(),
+            };
+
+            (function.name, callee)
+        })
+        .collect();
+
+    let mut changed = false;
+
+    for function in &mut program.functions {
+        let calls = function.body.iter().any(|instruction| match *instruction {
+            Instruction::Call(_, callee, _) => callees.contains_key(&callee),
+            _ => false,
+        });
+
+        if !calls {
+            continue;
+        }
+
+        let body = ::std::mem::take(&mut function.body);
+
+        for instruction in body {
+            match instruction {
+                Instruction::Call(to, callee, ref args) if callees.contains_key(&callee) => {
+                    let inlined = expand(&callees[&callee], to, args, symbols);
+                    function.body.extend(inlined);
+                }
+                instruction => function.body.push(instruction),
+            }
+        }
+
+        changed = true;
+    }
+
+    changed
+}
+
+/// The body of a callee with fresh temps and labels that binds the arguments to its
+/// parameters and leaves the returned value in `to`
+fn expand<T: Clone>(
+    callee: &Callee,
+    to: Temp,
+    args: &[Temp],
+    symbols: &mut Symbols<T>,
+) -> Vec<Instruction> {
+    let mut temps: HashMap<Temp, Temp> = HashMap::new();
+    let mut rename = |temp: Temp| *temps.entry(temp).or_insert_with(Temp::new);
+
+    let mut labels = HashMap::new();
+
+    for instruction in &callee.body {
+        if let Instruction::Label(label) = *instruction {
+            labels.insert(label, new_named_label("inline", symbols));
+        }
+    }
+
+    let relabel = |label: Label| labels.get(&label).cloned().unwrap_or(label);
+    let end = new_named_label("inline_end", symbols);
+    let (sign, size) = callee.returns;
+
+    let mut body = Vec::with_capacity(callee.body.len() + args.len() + 2);
+
+    for (param, arg) in callee.params.iter().zip(args) {
+        body.push(Instruction::Copy(rename(*param), *arg));
+    }
+
+    for instruction in &callee.body {
+        let mut instruction = instruction.clone();
+
+        instruction.rename_uses(&mut rename);
+        instruction.rename_def(&mut rename);
+
+        match instruction {
+            Instruction::Label(ref mut label) | Instruction::Jump(ref mut label) => {
+                *label = relabel(*label);
+            }
+            Instruction::CJump(_, _, _, ref mut ltrue, ref mut lfalse) => {
+                *ltrue = relabel(*ltrue);
+                *lfalse = relabel(*lfalse);
+            }
+            _ => (),
+        }
+
+        match instruction {
+            // Returning converts the value to the return type just like a cast does
+            Instruction::Return(temp) => {
+                body.push(Instruction::Cast(to, temp, sign, size));
+                body.push(Instruction::Jump(end));
+            }
+            instruction => body.push(instruction),
+        }
+    }
+
+    // A function that runs off the end of its body returns zero
+    match callee.body.last() {
+        Some(&Instruction::Jump(_))
+        | Some(&Instruction::CJump(_, _, _, _, _))
+        | Some(&Instruction::Return(_)) => (),
+        _ => body.push(Instruction::Store(to, Value::Const(0, sign, size))),
+    }
+
+    body.push(Instruction::Label(end));
+    body
+}
+
+/// Turns a call the function makes to itself whose result is returned straight away
+/// into a jump back to the start of the function, so that the recursion doesn't need
+/// a frame for each call. Functions that make closures are left alone as the closures
+/// would see their parameters change
+pub fn tail_calls<T: Clone>(function: &mut Function, symbols: &mut Symbols<T>) -> bool {
+    let makes_closures = function.body.iter().any(|instruction| {
+        matches!(
+            *instruction,
+            Instruction::Closure(_, _, _) | Instruction::CloseUpvalue(_)
+        )
+    });
+
+    let is_tail_call = |pair: &[Instruction]| match (&pair[0], &pair[1]) {
+        (&Instruction::Call(to, callee, _), &Instruction::Return(temp)) => {
+            callee == function.name && to == temp
+        }
+        _ => false,
+    };
+
+    if makes_closures || !function.body.windows(2).any(is_tail_call) {
+        return false;
+    }
+
+    let start = new_named_label("tail", symbols);
+    let body = ::std::mem::take(&mut function.body);
+
+    let mut rewritten = Vec::with_capacity(body.len() + 1);
+    rewritten.push(Instruction::Label(start));
+
+    let mut instructions = body.into_iter().peekable();
+
+    while let Some(instruction) = instructions.next() {
+        let args = match instruction {
+            Instruction::Call(to, callee, ref args) if callee == function.name => {
+                match instructions.peek() {
+                    Some(&Instruction::Return(temp)) if temp == to => Some(args.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let args = match args {
+            Some(args) => args,
+            None => {
+                rewritten.push(instruction);
+                continue;
+            }
+        };
+
+        instructions.next();
+
+        // The arguments can read the parameters, so they are all copied out before
+        // any parameter is written
+        let copies: Vec<Temp> = args.iter().map(|_| Temp::new()).collect();
+
+        for (copy, arg) in copies.iter().zip(&args) {
+            rewritten.push(Instruction::Copy(*copy, *arg));
+        }
+
+        for (param, copy) in function.params.iter().zip(&copies) {
+            rewritten.push(Instruction::Copy(*param, *copy));
+        }
+
+        rewritten.push(Instruction::Jump(start));
+    }
+
+    function.body = rewritten;
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::{inline, tail_calls};
+    use ir::{BinOp, CmpOp, Function, Instruction, Program, Temp, Value};
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    const I32: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+    #[test]
+    fn small_functions_are_inlined() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (id, main) = (symbols.symbol("id"), symbols.symbol("main"));
+        let (v, ten, x) = (Temp::new(), Temp::new(), Temp::new());
+
+        let mut program = Program {
+            functions: vec![
+                Function {
+                    name: id,
+                    params: vec![v],
+                    param_types: vec![I32],
+                    returns: I32,
+                    upvalues: vec![],
+                    body: vec![Instruction::Return(v)],
+                    linkage: Linkage::Normal,
+                },
+                Function {
+                    name: main,
+                    params: vec![],
+                    param_types: vec![],
+                    returns: I32,
+                    upvalues: vec![],
+                    body: vec![
+                        Instruction::Store(ten, Value::Const(10, Sign::Signed, Size::Bit32)),
+                        Instruction::Call(x, id, vec![ten]),
+                        Instruction::Return(x),
+                    ],
+                    linkage: Linkage::Normal,
+                },
+            ],
+        };
+
+        assert!(inline(&mut program, &mut symbols));
+
+        let body = &program.functions[1].body;
+
+        assert!(
+            body.iter().all(|instruction| !instruction.is_call()),
+            "{:#?}",
+            body
+        );
+
+        // The returned value ends up in the temp the call wrote to
+        match body[2] {
+            Instruction::Cast(to, _, Sign::Signed, Size::Bit32) => assert_eq!(to, x),
+            ref instruction => panic!("{:?}", instruction),
+        }
+
+        // The parameter of the inlined copy is a new temp
+        assert!(body.iter().all(|instruction| instruction.defs() != Some(v)));
+    }
+
+    #[test]
+    fn self_calls_in_tail_position_become_jumps() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (sum, done, more) = (
+            symbols.symbol("sum"),
+            symbols.symbol("done"),
+            symbols.symbol("more"),
+        );
+        let (n, acc, zero, one, next, total, result) = (
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+        );
+
+        let mut function = Function {
+            name: sum,
+            params: vec![n, acc],
+            param_types: vec![I32, I32],
+            returns: I32,
+            upvalues: vec![],
+            body: vec![
+                Instruction::Store(zero, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::CJump(n, CmpOp::EQ, zero, done, more),
+                Instruction::Label(done),
+                Instruction::Return(acc),
+                Instruction::Label(more),
+                Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+                Instruction::BinOp(n, BinOp::Minus, one, next),
+                Instruction::BinOp(acc, BinOp::Plus, n, total),
+                Instruction::Call(result, sum, vec![next, total]),
+                Instruction::Return(result),
+            ],
+            linkage: Linkage::Normal,
+        };
+
+        assert!(tail_calls(&mut function, &mut symbols));
+
+        let start = match function.body[0] {
+            Instruction::Label(start) => start,
+            ref instruction => panic!("{:?}", instruction),
+        };
+
+        assert!(function
+            .body
+            .iter()
+            .all(|instruction| !instruction.is_call()));
+
+        match function.body.last() {
+            Some(&Instruction::Jump(label)) => assert_eq!(label, start),
+            instruction => panic!("{:?}", instruction),
+        }
+
+        // Both parameters are written after both arguments have been read
+        let len = function.body.len();
+
+        match (&function.body[len - 3], &function.body[len - 2]) {
+            (&Instruction::Copy(first, _), &Instruction::Copy(second, _)) => {
+                assert_eq!((first, second), (n, acc))
+            }
+            instructions => panic!("{:?}", instructions),
+        }
+
+        // Nothing left to do the second time round
+        assert!(!tail_calls(&mut function, &mut symbols));
+    }
+}
@@ -0,0 +1,804 @@
+//! A reference interpreter that runs the IR directly. It does as little as it can, with
+//! the temps of each call in a map and labels resolved to the index of the instruction,
+//! so that what the bytecode vm and the backends do can be checked against it.
+use ir::{Capture, Instruction, Intrinsic, Label, Program, Temp, Value};
+use optimize::{binary, compare, extend, unary, Constant};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::Symbols;
+
+/// How many calls can be running at once before the program is stopped
+pub const MAX_DEPTH: usize = 10_000;
+
+#[derive(Debug)]
+pub enum InterpretError {
+    /// The IR can't be run, such as a jump to a label that doesn't exist
+    Invalid(String),
+    /// The program stopped with an error, such as dividing by zero
+    Runtime(String),
+    /// Calls nested deeper than `MAX_DEPTH`, in the named function
+    StackOverflow(String),
+}
+
+type Cell = Rc<RefCell<Constant>>;
+
+/// Where handles start, far from any integer a program uses, so arithmetic on a
+/// handle can be told apart from arithmetic on an integer
+const HEAP: u64 = 1 << 48;
+
+/// Strings, blocks, closures and allocated memory live on the heap and temps hold their
+/// index into it, offset by `HEAP`
+enum Object {
+    Str(Vec<u8>),
+    /// The bytes of an `Alloc`, read and written at an offset
+    Memory(Vec<u8>),
+    /// Nothing reads the elements of a block yet, so only its handle is kept
+    Block,
+    Closure(usize, Rc<Vec<Cell>>),
+}
+
+struct Frame {
+    /// The index of the running function
+    function: usize,
+    pc: usize,
+    temps: HashMap<Temp, Constant>,
+    /// The temps a closure captured that haven't left scope, which share their value
+    /// with the closure
+    open: HashMap<Temp, Cell>,
+    upvalues: Rc<Vec<Cell>>,
+    /// The label of the running block and of the block that came before it
+    block: Option<Label>,
+    previous: Option<Label>,
+    /// The temp of the caller that the returned value goes into
+    result: Option<Temp>,
+}
+
+struct Interpreter<'a, T: Clone + 'a, W: 'a> {
+    program: &'a Program,
+    symbols: &'a Symbols<T>,
+    out: &'a mut W,
+    functions: HashMap<Label, usize>,
+    /// The index of each label of each function
+    labels: Vec<HashMap<Label, usize>>,
+    heap: Vec<Object>,
+    frames: Vec<Frame>,
+}
+
+/// A value converted to a sign and width, like a cast does
+fn convert((value, _, _): Constant, (sign, size): (Sign, Size)) -> Constant {
+    (extend(value, sign, size), sign, size)
+}
+
+/// Runs the program's `main` function, writing what it prints to `out`. Returns the
+/// exit code if the program called `exit`
+pub fn run<T: Clone, W: Write>(
+    program: &Program,
+    symbols: &Symbols<T>,
+    out: &mut W,
+) -> Result<Option<i32>, InterpretError> {
+    let functions: HashMap<Label, usize> = program
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, function)| (function.name, i))
+        .collect();
+
+    let labels = program
+        .functions
+        .iter()
+        .map(|function| {
+            function
+                .body
+                .iter()
+                .enumerate()
+                .filter_map(|(i, instruction)| match *instruction {
+                    Instruction::Label(label) => Some((label, i)),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let main = match program
+        .functions
+        .iter()
+        .position(|function| symbols.name(function.name) == "main")
+    {
+        Some(main) => main,
+        None => return Ok(None),
+    };
+
+    let mut interpreter = Interpreter {
+        program,
+        symbols,
+        out,
+        functions,
+        labels,
+        heap: Vec::new(),
+        frames: Vec::new(),
+    };
+
+    interpreter.call(main, vec![], Rc::new(vec![]), None)?;
+    interpreter.run()
+}
+
+impl<'a, T: Clone, W: Write> Interpreter<'a, T, W> {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("No function is running")
+    }
+
+    fn function_name(&self) -> String {
+        let frame = self.frames.last().expect("No function is running");
+
+        self.symbols
+            .name(self.program.functions[frame.function].name)
+    }
+
+    fn get(&self, temp: Temp) -> Result<Constant, InterpretError> {
+        let frame = self.frames.last().expect("No function is running");
+
+        if let Some(cell) = frame.open.get(&temp) {
+            return Ok(*cell.borrow());
+        }
+
+        match frame.temps.get(&temp) {
+            Some(value) => Ok(*value),
+            None => Err(InterpretError::Invalid(format!(
+                "`{}` is read before it is written in `{}`",
+                temp,
+                self.function_name()
+            ))),
+        }
+    }
+
+    fn set(&mut self, temp: Temp, value: Constant) {
+        let frame = self.frame();
+
+        match frame.open.get(&temp) {
+            Some(cell) => *cell.borrow_mut() = value,
+            None => {
+                frame.temps.insert(temp, value);
+            }
+        }
+    }
+
+    fn args(&self, temps: &[Temp]) -> Result<Vec<Constant>, InterpretError> {
+        temps.iter().map(|temp| self.get(*temp)).collect()
+    }
+
+    fn alloc(&mut self, object: Object) -> Constant {
+        self.heap.push(object);
+
+        (HEAP + (self.heap.len() - 1) as u64, Sign::Unsigned, Size::Bit64)
+    }
+
+    fn index(&self, handle: u64) -> Option<usize> {
+        match handle.checked_sub(HEAP) {
+            Some(index) if index < self.heap.len() as u64 => Some(index as usize),
+            _ => None,
+        }
+    }
+
+    fn object(&self, (handle, _, _): Constant) -> Result<&Object, InterpretError> {
+        self.index(handle)
+            .map(|index| &self.heap[index])
+            .ok_or_else(|| InterpretError::Runtime(format!("Invalid reference `{}`", handle)))
+    }
+
+    /// Only integers can be added, negated and so on
+    fn integer(&self, value: Constant, what: &str) -> Result<Constant, InterpretError> {
+        match self.index(value.0) {
+            Some(_) => Err(InterpretError::Invalid(format!(
+                "Cannot use `{}` on a value that isn't an integer",
+                what
+            ))),
+            None => Ok(value),
+        }
+    }
+
+    fn string(&self, value: Constant) -> Result<&[u8], InterpretError> {
+        match *self.object(value)? {
+            Object::Str(ref bytes) => Ok(bytes),
+            _ => Err(InterpretError::Runtime("Expected a string".into())),
+        }
+    }
+
+    /// The bytes of allocated memory that a value of the given width at an offset
+    /// takes up
+    fn memory(
+        &mut self,
+        (handle, _, _): Constant,
+        offset: usize,
+        size: Size,
+    ) -> Result<&mut [u8], InterpretError> {
+        let end = offset + size.size() as usize;
+
+        let index = self.index(handle).unwrap_or(self.heap.len());
+
+        let bytes = match self.heap.get_mut(index) {
+            Some(&mut Object::Memory(ref mut bytes)) => bytes,
+            _ => return Err(InterpretError::Runtime("Expected allocated memory".into())),
+        };
+
+        if end > bytes.len() {
+            return Err(InterpretError::Runtime(format!(
+                "Offset {} is outside of {} allocated bytes",
+                offset,
+                bytes.len()
+            )));
+        }
+
+        Ok(&mut bytes[offset..end])
+    }
+
+    fn function(&self, label: Label) -> Result<usize, InterpretError> {
+        self.functions.get(&label).cloned().ok_or_else(|| {
+            InterpretError::Invalid(format!(
+                "Call to undefined function `{}`",
+                self.symbols.name(label)
+            ))
+        })
+    }
+
+    fn call(
+        &mut self,
+        index: usize,
+        args: Vec<Constant>,
+        upvalues: Rc<Vec<Cell>>,
+        result: Option<Temp>,
+    ) -> Result<(), InterpretError> {
+        let function = &self.program.functions[index];
+        let name = self.symbols.name(function.name);
+
+        if args.len() != function.params.len() {
+            return Err(InterpretError::Invalid(format!(
+                "`{}` takes {} arguments but was given {}",
+                name,
+                function.params.len(),
+                args.len()
+            )));
+        }
+
+        if function.linkage == Linkage::External {
+            let args: Vec<Constant> = args
+                .into_iter()
+                .zip(&function.param_types)
+                .map(|(arg, ty)| convert(arg, *ty))
+                .collect();
+            let value = convert(self.native(&name, &args)?, function.returns);
+
+            if let Some(result) = result {
+                self.set(result, value);
+            }
+
+            return Ok(());
+        }
+
+        if self.frames.len() >= MAX_DEPTH {
+            return Err(InterpretError::StackOverflow(name));
+        }
+
+        let temps = function
+            .params
+            .iter()
+            .zip(&function.param_types)
+            .zip(args)
+            .map(|((param, ty), arg)| (*param, convert(arg, *ty)))
+            .collect();
+
+        self.frames.push(Frame {
+            function: index,
+            pc: 0,
+            temps,
+            open: HashMap::new(),
+            upvalues,
+            block: None,
+            previous: None,
+            result,
+        });
+
+        Ok(())
+    }
+
+    /// Runs an external function the vm provides a native for, which behave like the C
+    /// library functions of the same name
+    fn native(&mut self, name: &str, args: &[Constant]) -> Result<Constant, InterpretError> {
+        match (name, args) {
+            ("abs", &[(value, _, _)]) | ("labs", &[(value, _, _)]) => {
+                Ok(((value as i64).wrapping_abs() as u64, Sign::Signed, Size::Bit64))
+            }
+            ("putchar", &[(value, _, _)]) => {
+                let byte = value as u8;
+
+                self.out.write_all(&[byte]).map_err(|e| {
+                    InterpretError::Runtime(format!("Couldn't write the output: {}", e))
+                })?;
+
+                Ok((u64::from(byte), Sign::Signed, Size::Bit32))
+            }
+            _ => Err(InterpretError::Invalid(format!(
+                "No native function for `{}`",
+                name
+            ))),
+        }
+    }
+
+    /// Leaves the running function, handing the value converted to its return type to
+    /// the caller
+    fn ret(&mut self, value: Constant) {
+        let frame = self.frames.pop().expect("No function is running");
+        let value = convert(value, self.program.functions[frame.function].returns);
+
+        if let Some(result) = frame.result {
+            self.set(result, value);
+        }
+    }
+
+    fn jump(&mut self, label: Label) -> Result<(), InterpretError> {
+        let function = self.frame().function;
+
+        match self.labels[function].get(&label) {
+            Some(&index) => {
+                self.frame().pc = index;
+                Ok(())
+            }
+            None => Err(InterpretError::Invalid(format!(
+                "Jump to undefined label `{}` in `{}`",
+                self.symbols.name(label),
+                self.function_name()
+            ))),
+        }
+    }
+
+    fn upvalue(&self, index: usize) -> Result<Cell, InterpretError> {
+        let frame = self.frames.last().expect("No function is running");
+
+        frame.upvalues.get(index).cloned().ok_or_else(|| {
+            InterpretError::Invalid(format!(
+                "`{}` has no upvalue {}",
+                self.function_name(),
+                index
+            ))
+        })
+    }
+
+    /// The phis at the start of a block all read their operands before any of them are
+    /// written
+    fn phis(&mut self, start: usize) -> Result<(), InterpretError> {
+        let program = self.program;
+        let (function, previous) = {
+            let frame = self.frame();
+            (frame.function, frame.previous)
+        };
+        let body = &program.functions[function].body;
+
+        let mut values = Vec::new();
+        let mut pc = start;
+
+        while let Some(&Instruction::Phi(temp, ref operands)) = body.get(pc) {
+            let operand = operands
+                .iter()
+                .find(|&&(label, _)| Some(label) == previous)
+                .map(|&(_, operand)| operand);
+
+            match operand {
+                Some(operand) => values.push((temp, self.get(operand)?)),
+                None => {
+                    return Err(InterpretError::Invalid(format!(
+                        "`{}` has no operand for the block it was reached from in `{}`",
+                        temp,
+                        self.function_name()
+                    )))
+                }
+            }
+
+            pc += 1;
+        }
+
+        for (temp, value) in values {
+            self.set(temp, value);
+        }
+
+        self.frame().pc = pc;
+
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<Option<i32>, InterpretError> {
+        let program = self.program;
+
+        while !self.frames.is_empty() {
+            let (function, pc) = {
+                let frame = self.frame();
+                frame.pc += 1;
+                (&program.functions[frame.function], frame.pc - 1)
+            };
+
+            let instruction = match function.body.get(pc) {
+                Some(instruction) => instruction,
+                // A function that runs off the end of its body returns zero
+                None => {
+                    let (sign, size) = function.returns;
+                    self.ret((0, sign, size));
+                    continue;
+                }
+            };
+
+            match *instruction {
+                Instruction::Store(temp, Value::Const(value, sign, size)) => {
+                    self.set(temp, (extend(value, sign, size), sign, size))
+                }
+                Instruction::Store(temp, Value::Temp(from)) | Instruction::Copy(temp, from) => {
+                    let value = self.get(from)?;
+                    self.set(temp, value)
+                }
+                Instruction::Store(temp, Value::Mem(ref bytes)) => {
+                    let value = self.alloc(Object::Str(bytes.clone()));
+                    self.set(temp, value)
+                }
+                Instruction::Store(_, Value::Name(name)) => {
+                    return Err(InterpretError::Invalid(format!(
+                        "Cannot store the address of `{}`",
+                        self.symbols.name(name)
+                    )))
+                }
+                Instruction::Cast(to, from, sign, size) => {
+                    let value = convert(self.get(from)?, (sign, size));
+                    self.set(to, value)
+                }
+                Instruction::BinOp(lhs, op, rhs, to) => {
+                    let what = op.to_string();
+                    let lhs = self.integer(self.get(lhs)?, &what)?;
+                    let rhs = self.integer(self.get(rhs)?, &what)?;
+
+                    match binary(op, lhs, rhs) {
+                        Some(value) => self.set(to, value),
+                        None if rhs.0 == 0 => {
+                            return Err(InterpretError::Runtime(
+                                "Attempted to divide by zero".into(),
+                            ))
+                        }
+                        None => return Err(InterpretError::Runtime("Division overflowed".into())),
+                    }
+                }
+                Instruction::UnOp(to, op, from) => {
+                    let value = self.integer(self.get(from)?, &op.to_string())?;
+                    let value = unary(op, value);
+                    self.set(to, value)
+                }
+                Instruction::Jump(label) => self.jump(label)?,
+                Instruction::CJump(lhs, op, rhs, ltrue, lfalse) => {
+                    if compare(op, self.get(lhs)?, self.get(rhs)?) {
+                        self.jump(ltrue)?
+                    } else {
+                        self.jump(lfalse)?
+                    }
+                }
+                Instruction::Label(label) => {
+                    let frame = self.frame();
+                    frame.previous = frame.block;
+                    frame.block = Some(label);
+                }
+                Instruction::Phi(_, _) => self.phis(pc)?,
+                Instruction::Value(_) => (),
+                Instruction::Return(temp) => {
+                    let value = self.get(temp)?;
+                    self.ret(value)
+                }
+                Instruction::Call(to, callee, ref args) => {
+                    let callee = self.function(callee)?;
+                    let args = self.args(args)?;
+                    self.call(callee, args, Rc::new(vec![]), Some(to))?
+                }
+                Instruction::Block(to, ref temps) => {
+                    self.args(temps)?;
+                    let value = self.alloc(Object::Block);
+                    self.set(to, value)
+                }
+                Instruction::Alloc(to, size) => {
+                    let value = self.alloc(Object::Memory(vec![0; size]));
+                    self.set(to, value)
+                }
+                Instruction::LoadAt(to, base, offset, sign, size) => {
+                    let base = self.get(base)?;
+                    let value = self
+                        .memory(base, offset, size)?
+                        .iter()
+                        .rev()
+                        .fold(0, |value, &byte| value << 8 | u64::from(byte));
+
+                    self.set(to, (extend(value, sign, size), sign, size))
+                }
+                Instruction::StoreAt(base, offset, from, _, size) => {
+                    let (base, (value, _, _)) = (self.get(base)?, self.get(from)?);
+
+                    // Values are stored with their least significant byte first
+                    for (i, byte) in self.memory(base, offset, size)?.iter_mut().enumerate() {
+                        *byte = (value >> (8 * i)) as u8;
+                    }
+                }
+                Instruction::Closure(to, callee, ref captures) => {
+                    let callee = self.function(callee)?;
+                    let mut cells = Vec::with_capacity(captures.len());
+
+                    for capture in captures {
+                        let cell = match *capture {
+                            Capture::Local(temp) => {
+                                match self.frames.last().and_then(|frame| frame.open.get(&temp)) {
+                                    Some(cell) => Rc::clone(cell),
+                                    None => {
+                                        let cell = Rc::new(RefCell::new(self.get(temp)?));
+                                        self.frame().open.insert(temp, Rc::clone(&cell));
+                                        cell
+                                    }
+                                }
+                            }
+                            Capture::Upvalue(index) => self.upvalue(index)?,
+                        };
+
+                        cells.push(cell);
+                    }
+
+                    let value = self.alloc(Object::Closure(callee, Rc::new(cells)));
+                    self.set(to, value)
+                }
+                Instruction::GetUpvalue(to, index) => {
+                    let value = *self.upvalue(index)?.borrow();
+                    let value = convert(value, function.upvalues[index]);
+                    self.set(to, value)
+                }
+                Instruction::SetUpvalue(index, temp) => {
+                    let value = convert(self.get(temp)?, function.upvalues[index]);
+                    *self.upvalue(index)?.borrow_mut() = value;
+                }
+                Instruction::CloseUpvalue(temp) => {
+                    let frame = self.frame();
+
+                    if let Some(cell) = frame.open.remove(&temp) {
+                        frame.temps.insert(temp, *cell.borrow());
+                    }
+                }
+                Instruction::CallClosure(to, callee, ref args, _) => {
+                    let (callee, upvalues) = match *self.object(self.get(callee)?)? {
+                        Object::Closure(callee, ref upvalues) => (callee, Rc::clone(upvalues)),
+                        _ => return Err(InterpretError::Runtime("Expected a closure".into())),
+                    };
+                    let args = self.args(args)?;
+                    self.call(callee, args, upvalues, Some(to))?
+                }
+                Instruction::Intrinsic(to, intrinsic, ref args) => {
+                    let args = self.args(args)?;
+                    let expected = match intrinsic {
+                        Intrinsic::StrEq | Intrinsic::StrConcat => 2,
+                        _ => 1,
+                    };
+
+                    if args.len() != expected {
+                        return Err(InterpretError::Invalid(format!(
+                            "`{:?}` takes {} arguments but was given {}",
+                            intrinsic,
+                            expected,
+                            args.len()
+                        )));
+                    }
+
+                    if let Some(code) = self.intrinsic(to, intrinsic, &args)? {
+                        return Ok(Some(code));
+                    }
+                }
+                Instruction::Load(_) => {
+                    return Err(InterpretError::Invalid(format!(
+                        "`{}` is not supported by the interpreter",
+                        instruction.print(self.symbols)
+                    )))
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs a function of the prelude, returning the exit code if it stops the program
+    fn intrinsic(
+        &mut self,
+        to: Temp,
+        intrinsic: Intrinsic,
+        args: &[Constant],
+    ) -> Result<Option<i32>, InterpretError> {
+        let arg = args[0];
+        let (value, sign, _) = arg;
+
+        let printed = match intrinsic {
+            Intrinsic::PrintInt if sign == Sign::Signed => writeln!(self.out, "{}", value as i64),
+            Intrinsic::PrintInt => writeln!(self.out, "{}", value),
+            Intrinsic::PrintBool => writeln!(self.out, "{}", value as u8 != 0),
+            Intrinsic::PrintChar => writeln!(self.out, "{}", value as u8 as char),
+            Intrinsic::PrintStr => {
+                let string = String::from_utf8_lossy(self.string(arg)?).into_owned();
+                writeln!(self.out, "{}", string)
+            }
+            Intrinsic::Assert if value as u8 == 0 => {
+                return Err(InterpretError::Runtime("Assertion failed".into()))
+            }
+            Intrinsic::Assert => Ok(()),
+            Intrinsic::Exit => return Ok(Some(convert(arg, (Sign::Signed, Size::Bit32)).0 as i32)),
+            Intrinsic::StrLen => {
+                let len = self.string(arg)?.len() as u64;
+                self.set(to, (len, Sign::Signed, Size::Bit32));
+                Ok(())
+            }
+            Intrinsic::StrEq => {
+                let equal = self.string(arg)? == self.string(args[1])?;
+                self.set(to, (equal as u64, Sign::Unsigned, Size::Bit8));
+                Ok(())
+            }
+            Intrinsic::StrConcat => {
+                let mut bytes = self.string(arg)?.to_vec();
+                bytes.extend_from_slice(self.string(args[1])?);
+
+                let value = self.alloc(Object::Str(bytes));
+                self.set(to, value);
+                Ok(())
+            }
+        };
+
+        printed
+            .map_err(|e| InterpretError::Runtime(format!("Couldn't write the output: {}", e)))?;
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, InterpretError};
+    use parse::parse;
+    use std::rc::Rc;
+    use util::symbol::{SymbolMap, Symbols};
+
+    fn interpret(text: &str) -> (Result<Option<i32>, InterpretError>, String) {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let program = parse(text, &mut symbols).unwrap();
+        let mut out = Vec::new();
+
+        let result = run(&program, &symbols, &mut out);
+
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn calls_loops_and_closures_run() {
+        let program = "\
+function fib(t1:i32) -> i32 {
+    t2 := 2:i32
+    if t1 < t2 then small else big
+    label small
+    ret t1
+    label big
+    t3 := 1:i32
+    t4 := t1 - t3
+    t5 := call fib(t4)
+    t6 := t1 - t2
+    t7 := call fib(t6)
+    t8 := t5 + t7
+    ret t8
+}
+function add(t1:i32) -> i32 upvalues(i32) {
+    t2 := upvalue 0
+    t3 := t2 + t1
+    upvalue 0 := t3
+    ret t3
+}
+function main() -> i32 {
+    t1 := 10:i32
+    t2 := call fib(t1)
+    t3 := print_int(t2)
+    t4 := 255:u8
+    t5 := t4 as i8
+    t6 := print_int(t5)
+    t7 := closure add(t1)
+    t8 := 5:i32
+    t9 := call t7(t8) -> i32
+    t10 := call t7(t8) -> i32
+    close t1
+    t11 := print_int(t1)
+    t12 := \"done\"
+    t13 := print_str(t12)
+    t14 := 3:i32
+    t15 := exit(t14)
+    t16 := print_int(t14)
+}
+";
+
+        let (result, out) = interpret(program);
+
+        assert_eq!(result.unwrap(), Some(3));
+        assert_eq!(out, "55\n-1\n20\ndone\n");
+    }
+
+    #[test]
+    fn memory_is_read_and_written_at_an_offset() {
+        let program = "\
+function main() -> i32 {
+    t1 := alloc 16
+    t2 := -2:i32
+    t1[8]:i32 := t2
+    t3 := 7:u8
+    t1[4]:u8 := t3
+    t4 := t1[8]:i32
+    t5 := print_int(t4)
+    t6 := t1[8]:u8
+    t7 := print_int(t6)
+    t8 := t1[0]:i64
+    t9 := print_int(t8)
+    t10 := t1[13]:i32
+}
+";
+
+        match interpret(program) {
+            (Err(InterpretError::Runtime(message)), out) => {
+                // The byte at 4 is read as the low byte of the upper half
+                assert_eq!(out, "-2\n254\n30064771072\n");
+                assert_eq!(message, "Offset 13 is outside of 16 allocated bytes");
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_program() {
+        let program = "\
+function main() -> i32 {
+    t1 := 1:i32
+    t2 := 0:i32
+    t3 := t1 / t2
+    t4 := print_int(t3)
+}
+";
+
+        match interpret(program) {
+            (Err(InterpretError::Runtime(message)), ref out) if out.is_empty() => {
+                assert_eq!(message, "Attempted to divide by zero")
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let program = "\
+function forever(t1:i32) -> i32 {
+    t2 := call forever(t1)
+    ret t2
+}
+function main() -> i32 {
+    t1 := 1:i32
+    t2 := call forever(t1)
+}
+";
+
+        match interpret(program).0 {
+            Err(InterpretError::StackOverflow(name)) => assert_eq!(name, "forever"),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_references_is_invalid() {
+        let program = "\
+function main() -> i32 {
+    t1 := \"ab\"
+    t2 := \"cd\"
+    t3 := t1 + t2
+    t4 := print_str(t3)
+}
+";
+
+        match interpret(program) {
+            (Err(InterpretError::Invalid(message)), ref out) if out.is_empty() => {
+                assert_eq!(message, "Cannot use `+` on a value that isn't an integer")
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+}
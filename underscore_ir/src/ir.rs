@@ -2,6 +2,7 @@ use std::fmt::{self, Display,Debug};
 use syntax::ast::{Sign, Size};
 use syntax::ast::Linkage;
 use util::symbol::{Symbol, Symbols};
+pub use verify::{verify, verify_function, verify_named, VerifyError};
 
 
 #[derive(Debug)]
@@ -12,12 +13,20 @@ pub struct Program {
 #[derive(Debug)]
 pub struct Function {
     pub name: Symbol,
+    /// The temps the arguments are bound to, in order
+    pub params: Vec<Temp>,
+    /// The sign and width of each parameter
+    pub param_types: Vec<(Sign, Size)>,
+    /// The sign and width of the returned value
+    pub returns: (Sign, Size),
+    /// The sign and width of each variable a closure captures
+    pub upvalues: Vec<(Sign, Size)>,
     pub body: Vec<Instruction>,
     pub linkage: Linkage,
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
     /// Store a value into a register
     Store(Temp, Value),
@@ -26,8 +35,8 @@ pub enum Instruction {
     Copy(Temp, Temp),
     /// Jump to a label
     Jump(Label),
-    /// CAST the expresion to a different type treating it a
-    Cast(Temp, Sign, Size), //TODO take into account sign
+    /// Cast the value of the second temp to a different sign and width, storing it in the first
+    Cast(Temp, Temp, Sign, Size),
 
     /// Binary operation and store in Temp
     BinOp(Temp,BinOp, Temp, Temp),
@@ -50,9 +59,63 @@ pub enum Instruction {
 
     /// Block
     Block(Temp, Vec<Temp>),
+
+    /// Allocate the given number of zeroed bytes, storing their address in the temp
+    Alloc(Temp, usize),
+    /// Read a value of the given sign and width at an offset from the address in the
+    /// second temp
+    LoadAt(Temp, Temp, usize, Sign, Size),
+    /// Write the second temp, as a value of the given sign and width, at an offset from
+    /// the address in the first
+    StoreAt(Temp, usize, Temp, Sign, Size),
+
+    /// Create a closure of a function capturing the given variables
+    Closure(Temp, Label, Vec<Capture>),
+    /// Read the upvalue at an index of the running closure
+    GetUpvalue(Temp, usize),
+    /// Write to the upvalue at an index of the running closure
+    SetUpvalue(usize, Temp),
+    /// The temp leaves scope so any closure that captured it keeps its own copy
+    CloseUpvalue(Temp),
+    /// Call the closure held in a temp, returning a value of the given sign and width
+    CallClosure(Temp, Temp, Vec<Temp>, (Sign, Size)),
+    /// Call a function of the prelude that the backend provides
+    Intrinsic(Temp, Intrinsic, Vec<Temp>),
+    /// Pick the temp coming from the block with the given label, only found at the
+    /// start of a block in SSA form
+    Phi(Temp, Vec<(Label, Temp)>),
 }
 
-#[derive(Debug)]
+/// The functions of the prelude
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Intrinsic {
+    /// Print an integer of the argument's sign and width
+    PrintInt,
+    PrintBool,
+    PrintChar,
+    PrintStr,
+    /// Stop with an error if the argument is false
+    Assert,
+    /// Stop with the argument as the exit code
+    Exit,
+    /// The number of bytes in a string
+    StrLen,
+    /// Whether two strings hold the same bytes
+    StrEq,
+    /// A new string holding the bytes of the first followed by those of the second
+    StrConcat,
+}
+
+/// A variable captured by a closure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// A temp of the enclosing function
+    Local(Temp),
+    /// An upvalue of the enclosing closure
+    Upvalue(usize),
+}
+
+#[derive(Debug, Clone)]
 pub enum Value {
     /// Integer Constant
     Const(u64, Sign, Size),
@@ -65,68 +128,172 @@ pub enum Value {
 }
 
 impl Instruction {
-    pub fn fmt<T: Clone>(&self, symbols: &mut Symbols<T>) -> String {
+    /// The instruction in the text format, with symbols written by name
+    pub fn print<T: Clone>(&self, symbols: &Symbols<T>) -> String {
+        let name = |symbol| symbols.name(symbol);
+        let mut text = String::new();
+
+        Text { name: &name }
+            .instruction(&mut text, self)
+            .expect("Writing to a string can't fail");
+
+        text
+    }
+
+    /// The temps the instruction reads
+    pub fn uses(&self) -> Vec<Temp> {
         match *self {
-            Instruction::Store(ref temp, ref value) => format!("   {} := {}", temp, value),
-            Instruction::Value(ref value) => format!("\n{}", value),
-            Instruction::BinOp(ref t1, ref op, ref v1, ref v2) => {
-                format!("{} := {} {} {}", t1, op,v1, v2)
+            Instruction::Store(_, Value::Temp(temp))
+            | Instruction::Copy(_, temp)
+            | Instruction::Cast(_, temp, _, _)
+            | Instruction::UnOp(_, _, temp)
+            | Instruction::Return(temp)
+            | Instruction::Load(temp)
+            | Instruction::SetUpvalue(_, temp)
+            | Instruction::CloseUpvalue(temp)
+            | Instruction::LoadAt(_, temp, _, _, _) => vec![temp],
+            Instruction::BinOp(lhs, _, rhs, _)
+            | Instruction::CJump(lhs, _, rhs, _, _)
+            | Instruction::StoreAt(lhs, _, rhs, _, _) => vec![lhs, rhs],
+            Instruction::Call(_, _, ref temps)
+            | Instruction::Block(_, ref temps)
+            | Instruction::Intrinsic(_, _, ref temps) => temps.clone(),
+            Instruction::Phi(_, ref operands) => operands.iter().map(|&(_, temp)| temp).collect(),
+            Instruction::CallClosure(_, callee, ref args, _) => {
+                let mut temps = args.clone();
+                temps.push(callee);
+                temps
             }
+            Instruction::Closure(_, _, ref captures) => captures
+                .iter()
+                .filter_map(|capture| match *capture {
+                    Capture::Local(temp) => Some(temp),
+                    Capture::Upvalue(_) => None,
+                })
+                .collect(),
+            Instruction::Store(_, _)
+            | Instruction::Jump(_)
+            | Instruction::Value(_)
+            | Instruction::Label(_)
+            | Instruction::GetUpvalue(_, _)
+            | Instruction::Alloc(_, _) => vec![],
+        }
+    }
 
-            Instruction::Block(ref temp, ref temps) => {
-                let mut fmt_str = format!("\n{} := [", temp);
+    /// The temp the instruction writes to
+    pub fn defs(&self) -> Option<Temp> {
+        match *self {
+            Instruction::Store(temp, _)
+            | Instruction::Copy(temp, _)
+            | Instruction::Cast(temp, _, _, _)
+            | Instruction::BinOp(_, _, _, temp)
+            | Instruction::UnOp(temp, _, _)
+            | Instruction::Call(temp, _, _)
+            | Instruction::Block(temp, _)
+            | Instruction::Closure(temp, _, _)
+            | Instruction::GetUpvalue(temp, _)
+            | Instruction::CallClosure(temp, _, _, _)
+            | Instruction::Intrinsic(temp, _, _)
+            | Instruction::Alloc(temp, _)
+            | Instruction::LoadAt(temp, _, _, _, _)
+            | Instruction::Phi(temp, _) => Some(temp),
+            Instruction::Jump(_)
+            | Instruction::CJump(_, _, _, _, _)
+            | Instruction::Value(_)
+            | Instruction::Label(_)
+            | Instruction::Return(_)
+            | Instruction::Load(_)
+            | Instruction::SetUpvalue(_, _)
+            | Instruction::CloseUpvalue(_)
+            | Instruction::StoreAt(_, _, _, _, _) => None,
+        }
+    }
 
-                for (i, temp) in temps.iter().enumerate() {
-                    if i + 1 == temps.len() {
-                        fmt_str.push_str(&format!("{}", temp))
-                    } else {
-                        fmt_str.push_str(&format!("{},", temp));
-                    }
+    /// Replaces each temp the instruction reads
+    pub fn rename_uses<F: FnMut(Temp) -> Temp>(&mut self, mut rename: F) {
+        match *self {
+            Instruction::Store(_, Value::Temp(ref mut temp))
+            | Instruction::Copy(_, ref mut temp)
+            | Instruction::Cast(_, ref mut temp, _, _)
+            | Instruction::UnOp(_, _, ref mut temp)
+            | Instruction::Return(ref mut temp)
+            | Instruction::Load(ref mut temp)
+            | Instruction::SetUpvalue(_, ref mut temp)
+            | Instruction::CloseUpvalue(ref mut temp)
+            | Instruction::LoadAt(_, ref mut temp, _, _, _) => *temp = rename(*temp),
+            Instruction::BinOp(ref mut lhs, _, ref mut rhs, _)
+            | Instruction::CJump(ref mut lhs, _, ref mut rhs, _, _)
+            | Instruction::StoreAt(ref mut lhs, _, ref mut rhs, _, _) => {
+                *lhs = rename(*lhs);
+                *rhs = rename(*rhs);
+            }
+            Instruction::Call(_, _, ref mut temps)
+            | Instruction::Block(_, ref mut temps)
+            | Instruction::Intrinsic(_, _, ref mut temps) => {
+                for temp in temps.iter_mut() {
+                    *temp = rename(*temp);
                 }
-
-                fmt_str.push_str("]");
-
-                fmt_str
             }
+            Instruction::CallClosure(_, ref mut callee, ref mut args, _) => {
+                for arg in args.iter_mut() {
+                    *arg = rename(*arg);
+                }
 
-            Instruction::Load(ref temp) => format!("\nload{}", temp),
-
-            Instruction::Copy(ref t1, ref t2) => format!("\n{} := {}", t1, t2),
-            Instruction::UnOp(ref t1, ref op, ref t2) => format!("\n{} := {} {}", t1, op, t2),
-            Instruction::Cast(ref t1, ref sign, ref size) => {
-                format!("\nt1 := {}:{}{}", t1, sign, size)
+                *callee = rename(*callee);
             }
-            Instruction::Call(ref t1, ref label, ref temps) => {
-                let mut fmt_str = format!("\n{} := {}.call(", t1, symbols.name(*label));
-
-                for (i, temp) in temps.iter().enumerate() {
-                    if i + 1 == temps.len() {
-                        fmt_str.push_str(&format!("{}", temp))
-                    } else {
-                        fmt_str.push_str(&format!("{},", temp));
+            Instruction::Closure(_, _, ref mut captures) => {
+                for capture in captures.iter_mut() {
+                    if let Capture::Local(ref mut temp) = *capture {
+                        *temp = rename(*temp);
                     }
                 }
+            }
+            Instruction::Phi(_, ref mut operands) => {
+                for operand in operands.iter_mut() {
+                    operand.1 = rename(operand.1);
+                }
+            }
+            Instruction::Store(_, _)
+            | Instruction::Jump(_)
+            | Instruction::Value(_)
+            | Instruction::Label(_)
+            | Instruction::GetUpvalue(_, _)
+            | Instruction::Alloc(_, _) => (),
+        }
+    }
 
-                fmt_str.push_str(")");
+    /// Replaces the temp the instruction writes to
+    pub fn rename_def<F: FnOnce(Temp) -> Temp>(&mut self, rename: F) {
+        match *self {
+            Instruction::Store(ref mut temp, _)
+            | Instruction::Copy(ref mut temp, _)
+            | Instruction::Cast(ref mut temp, _, _, _)
+            | Instruction::BinOp(_, _, _, ref mut temp)
+            | Instruction::UnOp(ref mut temp, _, _)
+            | Instruction::Call(ref mut temp, _, _)
+            | Instruction::Block(ref mut temp, _)
+            | Instruction::Closure(ref mut temp, _, _)
+            | Instruction::GetUpvalue(ref mut temp, _)
+            | Instruction::CallClosure(ref mut temp, _, _, _)
+            | Instruction::Intrinsic(ref mut temp, _, _)
+            | Instruction::Alloc(ref mut temp, _)
+            | Instruction::LoadAt(ref mut temp, _, _, _, _)
+            | Instruction::Phi(ref mut temp, _) => *temp = rename(*temp),
+            _ => (),
+        }
+    }
 
-                fmt_str
-            }
-            Instruction::Jump(ref label) => format!("\njump {}", symbols.name(*label)),
-            Instruction::CJump(ref t1, ref op ,ref t2, ref ltrue, ref lfalse) => format!(
-                "\nif {} {} {} then {} else {}",
-                t1,
-                op,
-                t2,
-                symbols.name(*ltrue),
-                symbols.name(*lfalse)
-            ),
-            Instruction::Label(ref label) => format!("\nlabel {}", symbols.name(*label)),
-            Instruction::Return(ref ret) => format!("\nret {}", ret),
+    /// Whether the instruction calls out to another function
+    pub fn is_call(&self) -> bool {
+        match *self {
+            Instruction::Call(_, _, _) | Instruction::CallClosure(_, _, _, _) => true,
+            Instruction::Intrinsic(_, intrinsic, _) => intrinsic != Intrinsic::StrLen,
+            _ => false,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BinOp {
     Plus,
     Minus,
@@ -136,13 +303,13 @@ pub enum BinOp {
     Or,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum UnOp {
     Bang,
     Minus,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CmpOp {
     LT,
     GT,
@@ -193,6 +360,23 @@ pub fn new_label_pair<T: Clone>(
     }
 }
 
+/// Makes sure a label read from text isn't made again by `new_named_label`, which numbers
+/// the labels it makes
+pub(crate) fn reserve_label(name: &str) {
+    let count = name
+        .rsplit('_')
+        .next()
+        .and_then(|count| count.parse::<u32>().ok());
+
+    if let (true, Some(count)) = (name.starts_with("l_"), count) {
+        unsafe {
+            if LABEL_COUNT <= count {
+                LABEL_COUNT = count + 1;
+            }
+        }
+    }
+}
+
 impl Temp {
     /// Makes a new temp with a given Ident.
     /// Warning: avoid repeated calls with the same name.
@@ -201,6 +385,15 @@ impl Temp {
         unsafe { TEMP_COUNT += 1 };
         Temp(value)
     }
+
+    /// Makes sure a temp read from text isn't handed out again by `new`
+    pub(crate) fn reserve(temp: Temp) {
+        unsafe {
+            if TEMP_COUNT <= temp.0 {
+                TEMP_COUNT = temp.0 + 1;
+            }
+        }
+    }
 }
 
 impl Debug for Temp {
@@ -215,62 +408,294 @@ impl Display for Temp {
     }
 }
 
-impl Display for Program {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for func in &self.functions {
-            write!(f, "{}", func)?;
+impl Program {
+    /// The program in the text format that `parse::parse` reads, with symbols written
+    /// by name
+    pub fn print<T: Clone>(&self, symbols: &Symbols<T>) -> String {
+        let name = |symbol| symbols.name(symbol);
+        let mut text = String::new();
+
+        Text { name: &name }
+            .program(&mut text, self)
+            .expect("Writing to a string can't fail");
+
+        text
+    }
+}
+
+/// The words of the text format, which have to be quoted to be used as a name
+pub(crate) const KEYWORDS: [&str; 21] = [
+    "alloc", "and", "as", "call", "close", "closure", "else", "external", "function", "if",
+    "jump", "label", "load", "or", "phi", "ret", "store", "then", "upvalue", "upvalues", "value",
+];
+
+/// Whether the name is a temp when it is written without quotes
+pub(crate) fn is_temp(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('t') && name[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Writes the IR in the text format, with `name` giving the name of each symbol. Names
+/// that aren't identifiers or could be read as a temp or keyword are put in backticks
+struct Text<'a> {
+    name: &'a dyn Fn(Symbol) -> String,
+}
+
+impl<'a> Text<'a> {
+    fn name<W: fmt::Write>(&self, f: &mut W, symbol: Symbol) -> fmt::Result {
+        let name = (self.name)(symbol);
+
+        let plain = name
+            .chars()
+            .next()
+            .map(|c| c.is_ascii_alphabetic() || c == '_')
+            .unwrap_or(false)
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !is_temp(&name)
+            && !KEYWORDS.contains(&name.as_str());
+
+        if plain {
+            return write!(f, "{}", name);
+        }
+
+        write!(f, "`")?;
+
+        for c in name.chars() {
+            if c == '`' || c == '\\' {
+                write!(f, "\\")?;
+            }
+
+            write!(f, "{}", c)?;
+        }
+
+        write!(f, "`")
+    }
+
+    fn temps<W: fmt::Write>(&self, f: &mut W, temps: &[Temp]) -> fmt::Result {
+        for (i, temp) in temps.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}", temp)?;
+        }
+
+        Ok(())
+    }
+
+    fn value<W: fmt::Write>(&self, f: &mut W, value: &Value) -> fmt::Result {
+        match *value {
+            // Signed constants are written as negative numbers when their top bit is set
+            Value::Const(value, Sign::Signed, size) => {
+                write!(f, "{}:{}{}", value as i64, Sign::Signed, size)
+            }
+            Value::Const(value, sign, size) => write!(f, "{}:{}{}", value, sign, size),
+            Value::Name(name) => self.name(f, name),
+            Value::Temp(temp) => write!(f, "{}", temp),
+            Value::Mem(ref bytes) => {
+                write!(f, "\"")?;
+
+                for &byte in bytes {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+                        b' '..=b'~' => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\{:02x}", byte)?,
+                    }
+                }
+
+                write!(f, "\"")
+            }
+        }
+    }
+
+    fn program<W: fmt::Write>(&self, f: &mut W, program: &Program) -> fmt::Result {
+        for (i, function) in program.functions.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+
+            self.function(f, function)?;
             writeln!(f)?;
         }
 
         Ok(())
     }
-}
 
-impl Display for Function {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "function {}", self.name)?;
+    fn function<W: fmt::Write>(&self, f: &mut W, function: &Function) -> fmt::Result {
+        if function.linkage == Linkage::External {
+            write!(f, "external ")?;
+        }
 
+        write!(f, "function ")?;
+        self.name(f, function.name)?;
         write!(f, "(")?;
 
-        
-        write!(f, ")")?;
+        for (i, (param, &(sign, size))) in function
+            .params
+            .iter()
+            .zip(&function.param_types)
+            .enumerate()
+        {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}:{}{}", param, sign, size)?;
+        }
 
-        
+        write!(f, ") -> {}{}", function.returns.0, function.returns.1)?;
 
-        write!(f, " {{")?;
+        if !function.upvalues.is_empty() {
+            write!(f, " upvalues(")?;
 
-        writeln!(f)?;
+            for (i, &(sign, size)) in function.upvalues.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
 
-        for instruction in &self.body {
-            writeln!(f, "    {}", instruction)?;
+                write!(f, "{}{}", sign, size)?;
+            }
+
+            write!(f, ")")?;
+        }
+
+        writeln!(f, " {{")?;
+
+        for instruction in &function.body {
+            write!(f, "    ")?;
+            self.instruction(f, instruction)?;
+            writeln!(f)?;
         }
 
         write!(f, "}}")
     }
-}
-impl Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Value::Const(ref v, ref sign, ref size) => write!(f, "{}:{}{}", v, sign, size),
-            Value::Name(ref name) => write!(f, "{:?}", name),
-            Value::Temp(ref temp) => write!(f, "{}", temp),
-            Value::Mem(ref bytes) => {
-                write!(f, "[")?;
 
-                for (i, byte) in bytes.iter().enumerate() {
-                    if i + 1 == bytes.len() {
-                        write!(f, "{}", byte)?;
-                    } else {
-                        write!(f, "{},", byte)?;
+    fn instruction<W: fmt::Write>(&self, f: &mut W, instruction: &Instruction) -> fmt::Result {
+        match *instruction {
+            Instruction::Store(temp, Value::Temp(from)) => write!(f, "{} := store {}", temp, from),
+            Instruction::Store(temp, ref value) => {
+                write!(f, "{} := ", temp)?;
+                self.value(f, value)
+            }
+            Instruction::Value(ref value) => {
+                write!(f, "value ")?;
+                self.value(f, value)
+            }
+            Instruction::Copy(to, from) => write!(f, "{} := {}", to, from),
+            Instruction::Cast(to, from, sign, size) => {
+                write!(f, "{} := {} as {}{}", to, from, sign, size)
+            }
+            Instruction::BinOp(lhs, op, rhs, dst) => write!(f, "{} := {} {} {}", dst, lhs, op, rhs),
+            Instruction::UnOp(dst, op, src) => write!(f, "{} := {} {}", dst, op, src),
+            Instruction::Block(temp, ref temps) => {
+                write!(f, "{} := [", temp)?;
+                self.temps(f, temps)?;
+                write!(f, "]")
+            }
+            Instruction::Load(temp) => write!(f, "load {}", temp),
+            Instruction::Alloc(temp, size) => write!(f, "{} := alloc {}", temp, size),
+            Instruction::LoadAt(to, base, offset, sign, size) => {
+                write!(f, "{} := {}[{}]:{}{}", to, base, offset, sign, size)
+            }
+            Instruction::StoreAt(base, offset, from, sign, size) => {
+                write!(f, "{}[{}]:{}{} := {}", base, offset, sign, size, from)
+            }
+            Instruction::Call(to, callee, ref args) => {
+                write!(f, "{} := call ", to)?;
+                self.name(f, callee)?;
+                write!(f, "(")?;
+                self.temps(f, args)?;
+                write!(f, ")")
+            }
+            Instruction::CallClosure(to, callee, ref args, (sign, size)) => {
+                write!(f, "{} := call {}(", to, callee)?;
+                self.temps(f, args)?;
+                write!(f, ") -> {}{}", sign, size)
+            }
+            Instruction::Intrinsic(to, intrinsic, ref args) => {
+                write!(f, "{} := {}(", to, intrinsic)?;
+                self.temps(f, args)?;
+                write!(f, ")")
+            }
+            Instruction::Closure(temp, function, ref captures) => {
+                write!(f, "{} := closure ", temp)?;
+                self.name(f, function)?;
+                write!(f, "(")?;
+
+                for (i, capture) in captures.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", capture)?;
+                }
+
+                write!(f, ")")
+            }
+            Instruction::GetUpvalue(temp, index) => write!(f, "{} := upvalue {}", temp, index),
+            Instruction::SetUpvalue(index, temp) => write!(f, "upvalue {} := {}", index, temp),
+            Instruction::CloseUpvalue(temp) => write!(f, "close {}", temp),
+            Instruction::Phi(temp, ref operands) => {
+                write!(f, "{} := phi(", temp)?;
+
+                for (i, &(label, operand)) in operands.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
                     }
+
+                    self.name(f, label)?;
+                    write!(f, ": {}", operand)?;
                 }
 
-                write!(f, "]")
+                write!(f, ")")
+            }
+            Instruction::Jump(label) => {
+                write!(f, "jump ")?;
+                self.name(f, label)
             }
+            Instruction::CJump(lhs, op, rhs, ltrue, lfalse) => {
+                write!(f, "if {} {} {} then ", lhs, op, rhs)?;
+                self.name(f, ltrue)?;
+                write!(f, " else ")?;
+                self.name(f, lfalse)
+            }
+            Instruction::Label(label) => {
+                write!(f, "label ")?;
+                self.name(f, label)
+            }
+            Instruction::Return(temp) => write!(f, "ret {}", temp),
         }
     }
 }
 
+/// Without the symbols to name them, the IR is written with each symbol as its number
+pub(crate) fn number(symbol: Symbol) -> String {
+    symbol.to_string()
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Text { name: &number }.program(f, self)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Text { name: &number }.function(f, self)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Text { name: &number }.value(f, self)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Text { name: &number }.instruction(f, self)
+    }
+}
+
 impl Display for BinOp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -306,55 +731,27 @@ impl Display for UnOp {
     }
 }
 
-impl Display for Instruction {
+impl Display for Intrinsic {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Instruction::Store(ref temp, ref value) => write!(f, "{} := {}", temp, value),
-            Instruction::Value(ref value) => write!(f, "{}", value),
-            Instruction::BinOp(ref op, ref v1, ref v2, ref t) => {
-                write!(f, "{} := {} {} {}", t, v1, op, v2)
-            }
-
-            Instruction::Block(ref temp, ref temps) => {
-                write!(f, "{} := [", temp)?;
-
-                for (i, temp) in temps.iter().enumerate() {
-                    if i + 1 == temps.len() {
-                        write!(f, "{}", temp)?;
-                    } else {
-                        write!(f, "{},", temp)?;
-                    }
-                }
-
-                write!(f, "]")
-            }
-
-            Instruction::Load(ref temp) => write!(f, "load{}", temp),
-
-            Instruction::Copy(ref t1, ref t2) => write!(f, "{} := {}", t1, t2),
-            Instruction::UnOp(ref op, ref t1, ref t2) => write!(f, "{} := {} {}", t1, op, t2),
-            Instruction::Cast(ref t1, ref sign, ref size) => {
-                write!(f, "t1 := {}:{}{}", t1, sign, size)
-            }
-            Instruction::Call(ref t1, ref label, ref temps) => {
-                write!(f, "{} := {}.call(", t1, label)?;
-
-                for (i, temp) in temps.iter().enumerate() {
-                    if i + 1 == temps.len() {
-                        write!(f, "{}", temp)?;
-                    } else {
-                        write!(f, "{},", temp)?;
-                    }
-                }
+            Intrinsic::PrintInt => write!(f, "print_int"),
+            Intrinsic::PrintBool => write!(f, "print_bool"),
+            Intrinsic::PrintChar => write!(f, "print_char"),
+            Intrinsic::PrintStr => write!(f, "print_str"),
+            Intrinsic::Assert => write!(f, "assert"),
+            Intrinsic::Exit => write!(f, "exit"),
+            Intrinsic::StrLen => write!(f, "len"),
+            Intrinsic::StrEq => write!(f, "str_eq"),
+            Intrinsic::StrConcat => write!(f, "concat"),
+        }
+    }
+}
 
-                write!(f, ")")
-            }
-            Instruction::Jump(ref label) => write!(f, "jump {}", label),
-            Instruction::CJump(ref op, ref t1, ref t2, ref ltrue, ref lfalse) => {
-                write!(f, "if {} {} {} then {} else {}", t1, op, t2, ltrue, lfalse)
-            }
-            Instruction::Label(ref label) => write!(f, "label {}", label),
-            Instruction::Return(ref ret) => write!(f, "ret {}", ret),
+impl Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Capture::Local(ref temp) => write!(f, "{}", temp),
+            Capture::Upvalue(ref index) => write!(f, "upvalue {}", index),
         }
     }
-}
\ No newline at end of file
+}
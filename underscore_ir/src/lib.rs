@@ -1,5 +1,15 @@
 extern crate underscore_syntax as syntax;
 extern crate underscore_util as util;
 
+pub mod callgraph;
+pub mod cfg;
+pub mod dominators;
+pub mod dot;
+pub mod inline;
+pub mod interpret;
 pub mod ir;
-pub mod optimize;
\ No newline at end of file
+pub mod liveness;
+pub mod optimize;
+pub mod parse;
+pub mod ssa;
+pub mod verify;
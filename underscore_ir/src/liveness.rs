@@ -0,0 +1,141 @@
+//! Works out which temps hold a value that may still be read at each instruction.
+//! Every instruction is treated as its own node so the result can be indexed by
+//! the position of an instruction in `Function::body`.
+use ir::{Function, Instruction, Label, Temp};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub struct Liveness {
+    /// The temps that are live before each instruction
+    pub live_in: Vec<HashSet<Temp>>,
+    /// The temps that are live after each instruction
+    pub live_out: Vec<HashSet<Temp>>,
+}
+
+/// The instructions control can flow to after each instruction
+pub fn successors(body: &[Instruction]) -> Vec<Vec<usize>> {
+    let labels: HashMap<Label, usize> = body
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instruction)| match *instruction {
+            Instruction::Label(label) => Some((label, i)),
+            _ => None,
+        })
+        .collect();
+
+    body.iter()
+        .enumerate()
+        .map(|(i, instruction)| match *instruction {
+            Instruction::Jump(label) => labels.get(&label).cloned().into_iter().collect(),
+            Instruction::CJump(_, _, _, ltrue, lfalse) => labels
+                .get(&ltrue)
+                .into_iter()
+                .chain(labels.get(&lfalse))
+                .cloned()
+                .collect(),
+            Instruction::Return(_) => vec![],
+            _ if i + 1 < body.len() => vec![i + 1],
+            _ => vec![],
+        })
+        .collect()
+}
+
+pub fn analyse(function: &Function) -> Liveness {
+    let body = &function.body;
+    let successors = successors(body);
+
+    let uses: Vec<Vec<Temp>> = body.iter().map(Instruction::uses).collect();
+    let defs: Vec<Option<Temp>> = body.iter().map(Instruction::defs).collect();
+
+    let mut live_in = vec![HashSet::new(); body.len()];
+    let mut live_out = vec![HashSet::new(); body.len()];
+
+    // Going backwards means most values settle in a single pass
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for i in (0..body.len()).rev() {
+            let mut out = HashSet::new();
+
+            for successor in &successors[i] {
+                out.extend(live_in[*successor].iter().cloned());
+            }
+
+            let mut in_: HashSet<Temp> = out
+                .iter()
+                .filter(|temp| Some(**temp) != defs[i])
+                .cloned()
+                .collect();
+
+            in_.extend(uses[i].iter().cloned());
+
+            if in_.len() != live_in[i].len() || out.len() != live_out[i].len() {
+                changed = true;
+            }
+
+            live_in[i] = in_;
+            live_out[i] = out;
+        }
+    }
+
+    Liveness { live_in, live_out }
+}
+
+#[cfg(test)]
+mod test {
+    use super::analyse;
+    use ir::{BinOp, CmpOp, Function, Instruction, Temp, Value};
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    #[test]
+    fn values_stay_live_around_loops() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (start, body, end) = (
+            symbols.symbol("start"),
+            symbols.symbol("body"),
+            symbols.symbol("end"),
+        );
+        let (n, total, one, dead) = (Temp::new(), Temp::new(), Temp::new(), Temp::new());
+
+        let function = Function {
+            name: symbols.symbol("sum"),
+            params: vec![n],
+            param_types: vec![(Sign::Signed, Size::Bit32)],
+            returns: (Sign::Signed, Size::Bit32),
+            upvalues: vec![],
+            body: vec![
+                Instruction::Store(total, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+                Instruction::Store(dead, Value::Const(2, Sign::Signed, Size::Bit32)),
+                Instruction::Label(start),
+                Instruction::CJump(n, CmpOp::GT, one, body, end),
+                Instruction::Label(body),
+                Instruction::BinOp(total, BinOp::Plus, n, total),
+                Instruction::BinOp(n, BinOp::Minus, one, n),
+                Instruction::Jump(start),
+                Instruction::Label(end),
+                Instruction::Return(total),
+            ],
+            linkage: Linkage::Normal,
+        };
+
+        let liveness = analyse(&function);
+
+        assert!(liveness.live_in[0].contains(&n));
+        assert!(!liveness.live_in[0].contains(&total));
+        assert!(!liveness.live_out[2].contains(&dead));
+
+        // Everything the loop reads is live at its back edge
+        for temp in &[n, total, one] {
+            assert!(liveness.live_out[8].contains(temp));
+        }
+
+        assert_eq!(liveness.live_out[10].len(), 0);
+        assert!(liveness.live_in[10].contains(&total));
+        assert!(!liveness.live_in[10].contains(&n));
+    }
+}
@@ -1,7 +1,182 @@
-use ir::{Instruction,Label};
-pub struct Optimizer {}
+//! The optimisation passes and the pass manager that runs them. Apart from jump
+//! threading, inlining and tail calls the passes work on SSA form, where a temp only
+//! ever holds one value, so anything known about a temp holds everywhere it is read.
+use cfg::{BlockId, Cfg};
+use dominators::Dominators;
+use inline;
+use ir::{
+    verify, verify_function, BinOp, CmpOp, Function, Instruction, Intrinsic, Label, Program,
+    Temp, UnOp, Value, VerifyError,
+};
+use ssa;
+use std::collections::{HashMap, HashSet};
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::Symbols;
+
+/// How many times the passes are run over a function before giving up on them
+/// finding anything else to do
+const MAX_ROUNDS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    /// Evaluates instructions and branches whose operands are constants
+    ConstantFolding,
+    /// Reads the original temp instead of a copy of it
+    CopyPropagation,
+    /// Reuses a value computed earlier on every path instead of computing it again
+    CommonSubexpressions,
+    /// Removes instructions whose result is never read and blocks that can't be reached
+    DeadCode,
+    /// Jumps straight to where a chain of jumps ends up
+    JumpThreading,
+    /// Replaces calls to small functions with their body
+    Inline,
+    /// Jumps back to the start of a function instead of returning a call to itself
+    TailCalls,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 7] = [
+        Pass::ConstantFolding,
+        Pass::CopyPropagation,
+        Pass::CommonSubexpressions,
+        Pass::DeadCode,
+        Pass::JumpThreading,
+        Pass::Inline,
+        Pass::TailCalls,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Pass::ConstantFolding => "constant-folding",
+            Pass::CopyPropagation => "copy-propagation",
+            Pass::CommonSubexpressions => "cse",
+            Pass::DeadCode => "dce",
+            Pass::JumpThreading => "jump-threading",
+            Pass::Inline => "inline",
+            Pass::TailCalls => "tail-calls",
+        }
+    }
+
+    /// Whether the pass works on a function in SSA form
+    fn is_ssa(self) -> bool {
+        !matches!(self, Pass::JumpThreading | Pass::Inline | Pass::TailCalls)
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.iter().find(|pass| pass.name() == name).cloned()
+    }
+}
+
+pub struct Optimizer {
+    passes: Vec<Pass>,
+}
 
 impl Optimizer {
+    /// Runs the passes of an optimisation level. Level 0 runs none, level 1 the cheap
+    /// ones and level 2 or above all of them
+    pub fn new(level: u8) -> Self {
+        let passes = match level {
+            0 => vec![],
+            1 => vec![
+                Pass::ConstantFolding,
+                Pass::CopyPropagation,
+                Pass::DeadCode,
+                Pass::TailCalls,
+            ],
+            _ => Pass::ALL.to_vec(),
+        };
+
+        Optimizer { passes }
+    }
+
+    pub fn enable(&mut self, pass: Pass) {
+        if !self.is_enabled(pass) {
+            self.passes.push(pass);
+        }
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.passes.retain(|enabled| *enabled != pass);
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+
+    /// Removes tail calls before inlining so that a function that only called itself in
+    /// tail position can be inlined, then optimises each function on its own
+    pub fn optimize<T: Clone>(&self, program: &mut Program, symbols: &mut Symbols<T>) {
+        if self.is_enabled(Pass::TailCalls) {
+            for function in &mut program.functions {
+                inline::tail_calls(function, symbols);
+            }
+
+            checked(Pass::TailCalls, || verify(program));
+        }
+
+        if self.is_enabled(Pass::Inline) {
+            inline::inline(program, symbols);
+            checked(Pass::Inline, || verify(program));
+        }
+
+        for function in &mut program.functions {
+            self.optimize_function(function, symbols);
+        }
+    }
+
+    /// Runs the enabled passes over a function until none of them changes it. A function
+    /// that jumps to a label it doesn't define is left alone
+    pub fn optimize_function<T: Clone>(&self, function: &mut Function, symbols: &mut Symbols<T>) {
+        // External functions are only a declaration
+        if self.passes.is_empty() || function.linkage == Linkage::External {
+            return;
+        }
+
+        let on_ssa: Vec<Pass> = self
+            .passes
+            .iter()
+            .cloned()
+            .filter(|pass| pass.is_ssa())
+            .collect();
+
+        if !on_ssa.is_empty() {
+            if ssa::construct(function, symbols).is_err() {
+                return;
+            }
+
+            for _ in 0..MAX_ROUNDS {
+                let mut changed = false;
+
+                for pass in &on_ssa {
+                    changed |= match *pass {
+                        Pass::ConstantFolding => constant_folding(&mut function.body),
+                        Pass::CopyPropagation => copy_propagation(&mut function.body),
+                        Pass::CommonSubexpressions => common_subexpressions(&mut function.body),
+                        Pass::DeadCode => dead_code(&mut function.body),
+                        Pass::JumpThreading | Pass::Inline | Pass::TailCalls => false,
+                    };
+
+                    checked(*pass, || verify_function(function));
+                }
+
+                if !changed {
+                    break;
+                }
+            }
+
+            ssa::destruct(function, symbols).expect("Labels are checked by SSA construction");
+        }
+
+        if self.is_enabled(Pass::JumpThreading) {
+            jump_threading(&mut function.body);
+            checked(Pass::JumpThreading, || verify_function(function));
+        }
+
+        Optimizer::strength_reduction(&mut function.body);
+        Optimizer::unused_labels(&mut vec![], &mut function.body);
+    }
+
     pub fn strength_reduction(ir: &mut Vec<Instruction>) {
         ir.retain(|&ref instruction| match instruction {
             &Instruction::Copy(ref lhs, ref rhs) => {
@@ -39,3 +214,633 @@ impl Optimizer {
         });
     }
 }
+
+/// Stops a debug build when a pass leaves the IR broken
+fn checked<F: FnOnce() -> Result<(), Vec<VerifyError>>>(pass: Pass, verify: F) {
+    if cfg!(debug_assertions) {
+        if let Err(errors) = verify() {
+            panic!("The {} pass broke the IR: {:#?}", pass.name(), errors)
+        }
+    }
+}
+
+/// A constant held in the low bits of a `u64`, sign or zero extended to 64 bits just
+/// like the backends keep it in a register
+pub(crate) type Constant = (u64, Sign, Size);
+
+pub(crate) fn extend(value: u64, sign: Sign, size: Size) -> u64 {
+    let shift = 64 - size.size() * 8;
+
+    match sign {
+        Sign::Signed => (((value << shift) as i64) >> shift) as u64,
+        Sign::Unsigned => (value << shift) >> shift,
+    }
+}
+
+/// The bits of a value that fit in its size
+pub(crate) fn truncate(value: u64, size: Size) -> u64 {
+    let shift = 64 - size.size() * 8;
+
+    (value << shift) >> shift
+}
+
+pub(crate) fn binary(
+    op: BinOp,
+    (lhs, sign, size): Constant,
+    (rhs, _, _): Constant,
+) -> Option<Constant> {
+    let signed = sign == Sign::Signed;
+
+    let (value, sign, size) = match op {
+        BinOp::Plus => (lhs.wrapping_add(rhs), sign, size),
+        BinOp::Minus => (lhs.wrapping_sub(rhs), sign, size),
+        BinOp::Mul => (lhs.wrapping_mul(rhs), sign, size),
+        // Dividing by zero stops the program so it is left to happen when it runs
+        BinOp::Div if rhs == 0 => return None,
+        BinOp::Div if signed => ((lhs as i64).checked_div(rhs as i64)? as u64, sign, size),
+        BinOp::Div => (lhs / rhs, sign, size),
+        BinOp::And => (lhs & rhs, Sign::Unsigned, Size::Bit8),
+        BinOp::Or => (lhs | rhs, Sign::Unsigned, Size::Bit8),
+    };
+
+    Some((extend(value, sign, size), sign, size))
+}
+
+pub(crate) fn unary(op: UnOp, (value, sign, size): Constant) -> Constant {
+    match op {
+        UnOp::Minus => (extend(value.wrapping_neg(), sign, size), sign, size),
+        UnOp::Bang => ((value == 0) as u64, Sign::Unsigned, Size::Bit8),
+    }
+}
+
+pub(crate) fn compare(op: CmpOp, (lhs, sign, _): Constant, (rhs, _, _): Constant) -> bool {
+    let ordering = if sign == Sign::Signed {
+        (lhs as i64).cmp(&(rhs as i64))
+    } else {
+        lhs.cmp(&rhs)
+    };
+
+    match op {
+        CmpOp::LT => ordering.is_lt(),
+        CmpOp::LTE => ordering.is_le(),
+        CmpOp::GT => ordering.is_gt(),
+        CmpOp::GTE => ordering.is_ge(),
+        CmpOp::EQ => ordering.is_eq(),
+        CmpOp::NE => ordering.is_ne(),
+    }
+}
+
+fn store(temp: Temp, (value, sign, size): Constant) -> Instruction {
+    Instruction::Store(temp, Value::Const(truncate(value, size), sign, size))
+}
+
+/// Removes the operands of phis that come from a block for which `removed` is true,
+/// given the label of the phi's block and the operand's label
+fn remove_operands<F: Fn(Label, Label) -> bool>(body: &mut [Instruction], removed: F) {
+    let mut block = None;
+
+    for instruction in body {
+        match *instruction {
+            Instruction::Label(label) => block = Some(label),
+            Instruction::Phi(_, ref mut operands) => {
+                if let Some(block) = block {
+                    operands.retain(|&(pred, _)| !removed(block, pred));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn constant_folding(body: &mut [Instruction]) -> bool {
+    let shared = ssa::shared(body);
+    let mut constants: HashMap<Temp, Constant> = HashMap::new();
+    // The edges from a block that branched on constants to the block it no longer goes to
+    let mut removed: Vec<(Label, Label)> = vec![];
+    let mut block = None;
+    let mut changed = false;
+
+    for instruction in body.iter_mut() {
+        let folded = match *instruction {
+            Instruction::Label(label) => {
+                block = Some(label);
+                None
+            }
+
+            Instruction::Store(temp, Value::Const(value, sign, size)) => {
+                if !shared.contains(&temp) {
+                    constants.insert(temp, (extend(value, sign, size), sign, size));
+                }
+
+                None
+            }
+
+            Instruction::Store(_, Value::Temp(from)) | Instruction::Copy(_, from) => {
+                constants.get(&from).cloned()
+            }
+
+            Instruction::Cast(_, from, sign, size) => constants
+                .get(&from)
+                .map(|&(value, _, _)| (extend(value, sign, size), sign, size)),
+
+            Instruction::BinOp(lhs, op, rhs, _) => match (constants.get(&lhs), constants.get(&rhs))
+            {
+                (Some(&lhs), Some(&rhs)) => binary(op, lhs, rhs),
+                _ => None,
+            },
+
+            Instruction::UnOp(_, op, from) => constants.get(&from).map(|&from| unary(op, from)),
+
+            // A phi that picks the same constant whichever way it is reached
+            Instruction::Phi(temp, ref operands) => {
+                let mut values = operands.iter().map(|&(_, operand)| constants.get(&operand));
+
+                if let Some(Some(&first)) = values.next() {
+                    if values.all(|value| value == Some(&first)) && !shared.contains(&temp) {
+                        constants.insert(temp, first);
+                    }
+                }
+
+                None
+            }
+
+            Instruction::CJump(lhs, op, rhs, ltrue, lfalse) => {
+                if let (Some(&lhs), Some(&rhs)) = (constants.get(&lhs), constants.get(&rhs)) {
+                    let (taken, untaken) = if compare(op, lhs, rhs) {
+                        (ltrue, lfalse)
+                    } else {
+                        (lfalse, ltrue)
+                    };
+
+                    if taken != untaken {
+                        if let Some(block) = block {
+                            removed.push((untaken, block));
+                        }
+                    }
+
+                    *instruction = Instruction::Jump(taken);
+                    changed = true;
+                }
+
+                None
+            }
+
+            _ => None,
+        };
+
+        if let (Some(constant), Some(temp)) = (folded, instruction.defs()) {
+            if !shared.contains(&temp) {
+                constants.insert(temp, constant);
+            }
+
+            *instruction = store(temp, constant);
+            changed = true;
+        }
+    }
+
+    remove_operands(body, |block, pred| removed.contains(&(block, pred)));
+
+    changed
+}
+
+fn copy_propagation(body: &mut [Instruction]) -> bool {
+    let shared = ssa::shared(body);
+    let mut copies: HashMap<Temp, Temp> = HashMap::new();
+
+    for instruction in body.iter() {
+        match *instruction {
+            Instruction::Store(to, Value::Temp(from)) | Instruction::Copy(to, from)
+                if to != from && !shared.contains(&to) && !shared.contains(&from) =>
+            {
+                copies.insert(to, from);
+            }
+
+            // A phi that picks the same temp whichever way it is reached, apart from
+            // the way round a loop that doesn't change it, is a copy of that temp
+            Instruction::Phi(to, ref operands) => {
+                let mut sources = operands
+                    .iter()
+                    .map(|&(_, operand)| operand)
+                    .filter(|operand| *operand != to);
+
+                if let Some(first) = sources.next() {
+                    if sources.all(|operand| operand == first)
+                        && !shared.contains(&to)
+                        && !shared.contains(&first)
+                    {
+                        copies.insert(to, first);
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    let original = |mut temp: Temp| {
+        // A chain of copies can't be longer than the number of copies
+        for _ in 0..copies.len() {
+            match copies.get(&temp) {
+                Some(&from) => temp = from,
+                None => break,
+            }
+        }
+
+        temp
+    };
+
+    let mut changed = false;
+
+    for instruction in body.iter_mut() {
+        instruction.rename_uses(|temp| {
+            let from = original(temp);
+            changed |= from != temp;
+            from
+        });
+    }
+
+    changed
+}
+
+/// An instruction that only depends on its operands. Loads aren't expressions, as
+/// a store or call between two of them can change what the second one reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Expression {
+    BinOp(Temp, BinOp, Temp),
+    UnOp(UnOp, Temp),
+    Cast(Temp, Sign, Size),
+}
+
+fn common_subexpressions(body: &mut [Instruction]) -> bool {
+    let cfg = match Cfg::new(body) {
+        Ok(cfg) => cfg,
+        Err(_) => return false,
+    };
+
+    let dominators = Dominators::new(&cfg);
+    let shared = ssa::shared(body);
+
+    let mut cse = Cse {
+        cfg: &cfg,
+        dominators: &dominators,
+        shared: &shared,
+        available: HashMap::new(),
+        changed: false,
+    };
+
+    cse.block(body, cfg.entry);
+    cse.changed
+}
+
+struct Cse<'a> {
+    cfg: &'a Cfg,
+    dominators: &'a Dominators,
+    shared: &'a HashSet<Temp>,
+    /// The temp holding each expression computed in the blocks that dominate this one
+    available: HashMap<Expression, Temp>,
+    changed: bool,
+}
+
+impl<'a> Cse<'a> {
+    fn block(&mut self, body: &mut [Instruction], block: BlockId) {
+        let mut added = vec![];
+        let (start, end) = (self.cfg.blocks[block].start, self.cfg.blocks[block].end);
+
+        for instruction in &mut body[start..end] {
+            let (to, expression) = match *instruction {
+                Instruction::BinOp(lhs, op, rhs, to) => (to, Expression::BinOp(lhs, op, rhs)),
+                Instruction::UnOp(to, op, from) => (to, Expression::UnOp(op, from)),
+                Instruction::Cast(to, from, sign, size) => (to, Expression::Cast(from, sign, size)),
+                _ => continue,
+            };
+
+            let operands = instruction.uses();
+
+            if self.shared.contains(&to) || operands.iter().any(|temp| self.shared.contains(temp)) {
+                continue;
+            }
+
+            match self.available.get(&expression) {
+                Some(&from) => {
+                    *instruction = Instruction::Copy(to, from);
+                    self.changed = true;
+                }
+                None => {
+                    self.available.insert(expression, to);
+                    added.push(expression);
+                }
+            }
+        }
+
+        let dominators = self.dominators;
+
+        for &child in dominators.children(block) {
+            self.block(body, child);
+        }
+
+        for expression in added {
+            self.available.remove(&expression);
+        }
+    }
+}
+
+/// Whether removing the instruction when nothing reads what it writes doesn't change
+/// what the program does
+fn is_pure(instruction: &Instruction) -> bool {
+    match *instruction {
+        Instruction::Store(_, _)
+        | Instruction::Copy(_, _)
+        | Instruction::Cast(_, _, _, _)
+        | Instruction::UnOp(_, _, _)
+        | Instruction::Block(_, _)
+        | Instruction::Alloc(_, _)
+        | Instruction::GetUpvalue(_, _)
+        | Instruction::Phi(_, _)
+        | Instruction::Intrinsic(_, Intrinsic::StrLen, _)
+        | Instruction::Intrinsic(_, Intrinsic::StrEq, _)
+        | Instruction::Intrinsic(_, Intrinsic::StrConcat, _) => true,
+        // Dividing by zero stops the program
+        Instruction::BinOp(_, op, _, _) => op != BinOp::Div,
+        _ => false,
+    }
+}
+
+fn dead_code(body: &mut Vec<Instruction>) -> bool {
+    let cfg = match Cfg::new(body) {
+        Ok(cfg) => cfg,
+        Err(_) => return false,
+    };
+
+    let mut reachable = vec![false; cfg.blocks.len()];
+
+    for block in cfg.reverse_postorder() {
+        reachable[block] = true;
+    }
+
+    let unreachable: HashSet<Label> = cfg
+        .blocks
+        .iter()
+        .zip(&reachable)
+        .filter(|&(_, reachable)| !reachable)
+        .filter_map(|(block, _)| block.label)
+        .collect();
+
+    let mut keep = vec![true; body.len()];
+
+    for (block, _) in cfg.blocks.iter().zip(&reachable).filter(|&(_, r)| !r) {
+        for keep in &mut keep[block.start..block.end] {
+            *keep = false;
+        }
+    }
+
+    remove_operands(body, |_, pred| unreachable.contains(&pred));
+
+    let shared = ssa::shared(body);
+    let mut live: HashSet<Temp> = HashSet::new();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (instruction, _) in body.iter().zip(&keep).filter(|&(_, keep)| *keep) {
+            let needed = !is_pure(instruction)
+                || instruction
+                    .defs()
+                    .map(|temp| live.contains(&temp) || shared.contains(&temp))
+                    .unwrap_or(true);
+
+            if needed {
+                for temp in instruction.uses() {
+                    changed |= live.insert(temp);
+                }
+            }
+        }
+    }
+
+    for (instruction, keep) in body.iter().zip(keep.iter_mut()) {
+        if let Some(temp) = instruction.defs() {
+            if is_pure(instruction) && !live.contains(&temp) && !shared.contains(&temp) {
+                *keep = false;
+            }
+        }
+    }
+
+    let before = body.len();
+    let mut keep = keep.into_iter();
+
+    body.retain(|_| keep.next().unwrap());
+
+    body.len() != before
+}
+
+/// Sends jumps to a label that is only followed by another jump straight to where
+/// that one goes, then removes jumps to the next instruction and code that can't be
+/// reached after a jump. This changes which blocks lead to which, so it runs after
+/// SSA form is gone
+fn jump_threading(body: &mut Vec<Instruction>) -> bool {
+    let mut forward: HashMap<Label, Label> = HashMap::new();
+
+    for i in 0..body.len() {
+        if let Instruction::Label(label) = body[i] {
+            let next = body[i..]
+                .iter()
+                .find(|instruction| !matches!(**instruction, Instruction::Label(_)));
+
+            if let Some(&Instruction::Jump(to)) = next {
+                forward.insert(label, to);
+            }
+        }
+    }
+
+    let destination = |label: Label| {
+        let mut to = label;
+
+        for _ in 0..forward.len() {
+            match forward.get(&to) {
+                Some(&next) if next != label => to = next,
+                // A loop that does nothing but jump goes nowhere else
+                Some(_) => return label,
+                None => break,
+            }
+        }
+
+        to
+    };
+
+    let mut changed = false;
+
+    for instruction in body.iter_mut() {
+        match *instruction {
+            Instruction::Jump(ref mut label) => {
+                let to = destination(*label);
+                changed |= to != *label;
+                *label = to;
+            }
+            Instruction::CJump(_, _, _, ref mut ltrue, ref mut lfalse) => {
+                let (to_true, to_false) = (destination(*ltrue), destination(*lfalse));
+                changed |= to_true != *ltrue || to_false != *lfalse;
+                *ltrue = to_true;
+                *lfalse = to_false;
+            }
+            _ => (),
+        }
+
+        if let Instruction::CJump(_, _, _, ltrue, lfalse) = *instruction {
+            if ltrue == lfalse {
+                *instruction = Instruction::Jump(ltrue);
+                changed = true;
+            }
+        }
+    }
+
+    // Jumps to the labels that come straight after them
+    let mut i = 0;
+
+    while i < body.len() {
+        let falls_through = match body[i] {
+            Instruction::Jump(to) => body[i + 1..]
+                .iter()
+                .take_while(|instruction| matches!(**instruction, Instruction::Label(_)))
+                .any(
+                    |instruction| matches!(*instruction, Instruction::Label(label) if label == to),
+                ),
+            _ => false,
+        };
+
+        if falls_through {
+            body.remove(i);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+
+    Optimizer::unused_labels(&mut vec![], body);
+
+    let before = body.len();
+    let mut reachable = true;
+
+    body.retain(|instruction| match *instruction {
+        Instruction::Label(_) => {
+            reachable = true;
+            true
+        }
+        Instruction::Jump(_) | Instruction::CJump(_, _, _, _, _) | Instruction::Return(_)
+            if reachable =>
+        {
+            reachable = false;
+            true
+        }
+        _ => reachable,
+    });
+
+    changed || body.len() != before
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Optimizer, Pass};
+    use ir::{BinOp, CmpOp, Function, Instruction, Intrinsic, Temp, Value};
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    fn function(body: Vec<Instruction>, symbols: &mut Symbols<()>) -> Function {
+        Function {
+            name: symbols.symbol("main"),
+            params: vec![],
+            param_types: vec![],
+            returns: (Sign::Signed, Size::Bit32),
+            upvalues: vec![],
+            body,
+            linkage: Linkage::Normal,
+        }
+    }
+
+    #[test]
+    fn constant_branches_fold_away() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (ltrue, lfalse, lend) = (
+            symbols.symbol("ltrue"),
+            symbols.symbol("lfalse"),
+            symbols.symbol("lend"),
+        );
+        let (a, b, sum, result, printed) = (
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+        );
+
+        let mut main = function(
+            vec![
+                Instruction::Store(a, Value::Const(250, Sign::Unsigned, Size::Bit8)),
+                Instruction::Store(b, Value::Const(10, Sign::Unsigned, Size::Bit8)),
+                // Wraps round to 4
+                Instruction::BinOp(a, BinOp::Plus, b, sum),
+                Instruction::CJump(sum, CmpOp::LT, b, ltrue, lfalse),
+                Instruction::Label(ltrue),
+                Instruction::Copy(result, a),
+                Instruction::Jump(lend),
+                Instruction::Label(lfalse),
+                Instruction::Copy(result, b),
+                Instruction::Label(lend),
+                Instruction::Intrinsic(printed, Intrinsic::PrintInt, vec![result]),
+                Instruction::Return(result),
+            ],
+            &mut symbols,
+        );
+
+        Optimizer::new(2).optimize_function(&mut main, &mut symbols);
+
+        let body: Vec<String> = main.body.iter().map(|i| i.to_string()).collect();
+
+        assert_eq!(body.len(), 3, "{:#?}", body);
+        assert!(body[0].ends_with(":= 250:u8"), "{:#?}", body);
+        assert!(body[1].contains("print_int"), "{:#?}", body);
+        assert!(body[2].starts_with("ret"), "{:#?}", body);
+    }
+
+    #[test]
+    fn passes_can_be_turned_off() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (a, b, c, d) = (Temp::new(), Temp::new(), Temp::new(), Temp::new());
+        let f = symbols.symbol("f");
+
+        let body = || {
+            vec![
+                Instruction::Call(a, f, vec![]),
+                Instruction::BinOp(a, BinOp::Mul, a, b),
+                Instruction::BinOp(a, BinOp::Mul, a, c),
+                Instruction::BinOp(b, BinOp::Plus, c, d),
+                Instruction::Return(d),
+            ]
+        };
+
+        let mut optimizer = Optimizer::new(2);
+        let mut main = function(body(), &mut symbols);
+
+        optimizer.optimize_function(&mut main, &mut symbols);
+
+        let multiplies = |main: &Function| {
+            main.body
+                .iter()
+                .filter(|instruction| {
+                    matches!(**instruction, Instruction::BinOp(_, BinOp::Mul, _, _))
+                })
+                .count()
+        };
+
+        assert_eq!(multiplies(&main), 1);
+
+        optimizer.disable(Pass::CommonSubexpressions);
+        assert!(!optimizer.is_enabled(Pass::CommonSubexpressions));
+
+        let mut main = function(body(), &mut symbols);
+        optimizer.optimize_function(&mut main, &mut symbols);
+
+        assert_eq!(multiplies(&main), 2);
+        assert_eq!(Pass::from_name("cse"), Some(Pass::CommonSubexpressions));
+    }
+}
@@ -0,0 +1,834 @@
+//! Reads back the text format that `Program::print` writes, so that IR can be edited by
+//! hand and fed to the optimiser and backends, and passes can be tested without the
+//! front end.
+//!
+//! A program is a list of functions and a function has one instruction on each line:
+//!
+//! ```text
+//! function fib(t1:i32) -> i32 {
+//!     t2 := 2:i32
+//!     if t1 < t2 then l_then_0 else l_else_0
+//!     label l_then_0
+//!     ret t1
+//!     label l_else_0
+//!     t3 := 1:i32
+//!     t4 := t1 - t3
+//!     t5 := call fib(t4)
+//!     t6 := t1 - t2
+//!     t7 := call fib(t6)
+//!     t8 := t5 + t7
+//!     ret t8
+//! }
+//!
+//! external function sin(t1:i32) -> i32 {
+//! }
+//! ```
+//!
+//! Temps are `t` followed by a number and types are `i` or `u` followed by 8, 32 or 64.
+//! Functions and labels are written by name, inside backticks when the name isn't an
+//! identifier or could be read as a temp or a keyword. `//` starts a comment that runs
+//! to the end of the line. A closure lists the types of its upvalues after the return
+//! type as `upvalues(i32, u8)`.
+//!
+//! | Instruction                          | Text                                     |
+//! |--------------------------------------|------------------------------------------|
+//! | `Store` of a constant                | `t1 := -1:i32`                           |
+//! | `Store` of a string                  | `t1 := "hi\0a"`, with `\"`, `\\` and hex escapes |
+//! | `Store` of a function's address      | `t1 := main`                             |
+//! | `Store` of a temp                    | `t1 := store t2`                         |
+//! | `Copy`                               | `t1 := t2`                               |
+//! | `Cast`                               | `t1 := t2 as u8`                         |
+//! | `BinOp`                              | `t1 := t2 + t3`, also `-`, `*`, `/`, `and` and `or` |
+//! | `UnOp`                               | `t1 := - t2` and `t1 := ! t2`            |
+//! | `Block`                              | `t1 := [t2, t3]`                         |
+//! | `Call`                               | `t1 := call f(t2, t3)`                   |
+//! | `CallClosure`                        | `t1 := call t2(t3) -> i32`               |
+//! | `Intrinsic`                          | `t1 := print_int(t2)`                    |
+//! | `Closure`                            | `t1 := closure f(t2, upvalue 0)`         |
+//! | `GetUpvalue` and `SetUpvalue`        | `t1 := upvalue 0` and `upvalue 0 := t1`  |
+//! | `CloseUpvalue`                       | `close t1`                               |
+//! | `Phi`                                | `t1 := phi(l_a: t2, l_b: t3)`            |
+//! | `Jump`, `Label` and `Return`         | `jump l`, `label l` and `ret t1`         |
+//! | `CJump`                              | `if t1 < t2 then l_a else l_b`, also `<=`, `>`, `>=`, `==` and `!=` |
+//! | `Load` and `Value`                   | `load t1` and `value 1:i32`              |
+//! | `Alloc`                              | `t1 := alloc 16`                         |
+//! | `LoadAt` and `StoreAt`               | `t1 := t2[8]:i32` and `t2[8]:i32 := t1`  |
+//!
+//! Printing a program that was read gives back the same text, apart from comments,
+//! spacing and needless backticks.
+use ir::{
+    is_temp, reserve_label, BinOp, Capture, CmpOp, Function, Instruction, Intrinsic, Label,
+    Program, Temp, UnOp, Value, KEYWORDS,
+};
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::Symbols;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Syntax(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Temp(u32),
+    /// An identifier written without backticks, which might be a keyword
+    Word(String),
+    /// A name written in backticks
+    Quoted(String),
+    Number(u64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+/// Longer punctuation comes first so that `:=` isn't read as `:`
+const PUNCTUATION: [&str; 21] = [
+    ":=", "->", "<=", ">=", "==", "!=", ":", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/",
+    "!", "<", ">",
+];
+
+pub fn parse<T: Clone>(input: &str, symbols: &mut Symbols<T>) -> Result<Program, ParseError> {
+    let mut lines = Vec::new();
+
+    for (i, text) in input.lines().enumerate() {
+        let tokens = lex(text).map_err(|e| ParseError::Syntax(format!("line {}: {}", i + 1, e)))?;
+
+        if !tokens.is_empty() {
+            lines.push(Line {
+                tokens,
+                current: 0,
+                number: i + 1,
+            });
+        }
+    }
+
+    let mut program = Program { functions: vec![] };
+    let mut lines = lines.into_iter();
+
+    while let Some(mut line) = lines.next() {
+        let mut function = line.header(symbols)?;
+
+        // An empty body can be closed on the same line
+        let mut closed = line.eat(&Token::Punct("}"));
+
+        line.end()?;
+
+        while !closed {
+            let mut line = match lines.next() {
+                Some(line) => line,
+                None => {
+                    return Err(ParseError::Syntax(format!(
+                        "Expected `}}` to close `{}`",
+                        symbols.name(function.name)
+                    )))
+                }
+            };
+
+            if line.eat(&Token::Punct("}")) {
+                closed = true;
+            } else {
+                function.body.push(line.instruction(symbols)?);
+            }
+
+            line.end()?;
+        }
+
+        program.functions.push(function);
+    }
+
+    Ok(program)
+}
+
+fn lex(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            byte if byte.is_ascii_whitespace() => i += 1,
+            b'/' if bytes.get(i + 1) == Some(&b'/') => break,
+            b'"' => {
+                let mut string = Vec::new();
+                i += 1;
+
+                loop {
+                    match bytes.get(i) {
+                        None => return Err("Unterminated string".into()),
+                        Some(&b'"') => break,
+                        Some(&b'\\') => {
+                            let escape = &bytes[i + 1..(i + 3).min(bytes.len())];
+
+                            if escape.len() == 2 && escape.iter().all(u8::is_ascii_hexdigit) {
+                                let hex = String::from_utf8_lossy(escape);
+                                string.push(u8::from_str_radix(&hex, 16).unwrap());
+                                i += 3;
+                            } else if escape.first() == Some(&b'"')
+                                || escape.first() == Some(&b'\\')
+                            {
+                                string.push(escape[0]);
+                                i += 2;
+                            } else {
+                                return Err("Invalid escape in string".into());
+                            }
+                        }
+                        Some(&byte) => {
+                            string.push(byte);
+                            i += 1;
+                        }
+                    }
+                }
+
+                tokens.push(Token::Str(string));
+                i += 1;
+            }
+            b'`' => {
+                let mut name = String::new();
+                let mut chars = text[i + 1..].char_indices();
+
+                loop {
+                    match chars.next() {
+                        None => return Err("Unterminated name".into()),
+                        Some((end, '`')) => {
+                            i += end + 2;
+                            break;
+                        }
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) if c == '`' || c == '\\' => name.push(c),
+                            _ => return Err("Invalid escape in name".into()),
+                        },
+                        Some((_, c)) => name.push(c),
+                    }
+                }
+
+                tokens.push(Token::Quoted(name));
+            }
+            b'0'..=b'9' | b'-' if bytes[i] != b'-' || is_digit(bytes.get(i + 1)) => {
+                let start = i;
+                i += 1;
+
+                while is_digit(bytes.get(i)) {
+                    i += 1;
+                }
+
+                let digits = &text[start..i];
+
+                // Negative numbers are stored as their two's complement
+                let number = if digits.starts_with('-') {
+                    digits.parse::<i64>().map(|number| number as u64).ok()
+                } else {
+                    digits.parse::<u64>().ok()
+                };
+
+                match number {
+                    Some(number) => tokens.push(Token::Number(number)),
+                    None => return Err(format!("`{}` is too big for a constant", digits)),
+                }
+            }
+            byte if byte.is_ascii_alphabetic() || byte == b'_' => {
+                let start = i;
+
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+
+                let word = &text[start..i];
+
+                match word[1..].parse::<u32>() {
+                    Ok(temp) if is_temp(word) => tokens.push(Token::Temp(temp)),
+                    _ => tokens.push(Token::Word(word.into())),
+                }
+            }
+            _ => match PUNCTUATION
+                .iter()
+                .find(|punct| text[i..].starts_with(*punct))
+            {
+                Some(punct) => {
+                    tokens.push(Token::Punct(punct));
+                    i += punct.len();
+                }
+                None => {
+                    let c = text[i..].chars().next().unwrap();
+                    return Err(format!("Unexpected `{}`", c));
+                }
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_digit(byte: Option<&u8>) -> bool {
+    byte.map(u8::is_ascii_digit).unwrap_or(false)
+}
+
+/// The tokens of one line of text
+struct Line {
+    tokens: Vec<Token>,
+    current: usize,
+    number: usize,
+}
+
+impl Line {
+    fn error(&self, expected: &str) -> ParseError {
+        let found = match self.tokens.get(self.current) {
+            Some(&Token::Temp(temp)) => format!("`t{}`", temp),
+            Some(Token::Word(word)) => format!("`{}`", word),
+            Some(Token::Quoted(name)) => format!("`{}`", name),
+            Some(&Token::Number(number)) => format!("`{}`", number),
+            Some(&Token::Str(_)) => "a string".into(),
+            Some(&Token::Punct(punct)) => format!("`{}`", punct),
+            None => "the end of the line".into(),
+        };
+
+        ParseError::Syntax(format!(
+            "line {}: expected {} but found {}",
+            self.number, expected, found
+        ))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.current).cloned();
+        self.current += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.eat(&Token::Word(keyword.into()))
+    }
+
+    fn expect(&mut self, punct: &'static str) -> Result<(), ParseError> {
+        if self.eat(&Token::Punct(punct)) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", punct)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}`", keyword)))
+        }
+    }
+
+    fn end(&self) -> Result<(), ParseError> {
+        if self.current < self.tokens.len() {
+            Err(self.error("the end of the line"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn temp(&mut self) -> Result<Temp, ParseError> {
+        match self.peek() {
+            Some(&Token::Temp(temp)) => {
+                self.current += 1;
+                Temp::reserve(Temp(temp));
+                Ok(Temp(temp))
+            }
+            _ => Err(self.error("a temp")),
+        }
+    }
+
+    fn name<T: Clone>(&mut self, symbols: &mut Symbols<T>) -> Result<Label, ParseError> {
+        let name = match self.peek() {
+            Some(Token::Word(word)) if !KEYWORDS.contains(&word.as_str()) => word.clone(),
+            Some(Token::Quoted(name)) => name.clone(),
+            _ => return Err(self.error("a name")),
+        };
+
+        self.current += 1;
+        reserve_label(&name);
+
+        Ok(symbols.symbol(&name))
+    }
+
+    fn number(&mut self) -> Result<u64, ParseError> {
+        match self.peek() {
+            Some(&Token::Number(number)) => {
+                self.current += 1;
+                Ok(number)
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+
+    fn ty(&mut self) -> Result<(Sign, Size), ParseError> {
+        let ty = match self.peek() {
+            Some(Token::Word(word)) => match word.as_str() {
+                "i8" => Some((Sign::Signed, Size::Bit8)),
+                "i32" => Some((Sign::Signed, Size::Bit32)),
+                "i64" => Some((Sign::Signed, Size::Bit64)),
+                "u8" => Some((Sign::Unsigned, Size::Bit8)),
+                "u32" => Some((Sign::Unsigned, Size::Bit32)),
+                "u64" => Some((Sign::Unsigned, Size::Bit64)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match ty {
+            Some(ty) => {
+                self.current += 1;
+                Ok(ty)
+            }
+            None => Err(self.error("a type")),
+        }
+    }
+
+    /// Items separated by commas up to a closing `)` or `]`, after the opening one
+    fn list<U, F>(&mut self, close: &'static str, mut item: F) -> Result<Vec<U>, ParseError>
+    where
+        F: FnMut(&mut Line) -> Result<U, ParseError>,
+    {
+        let mut items = vec![];
+
+        if self.eat(&Token::Punct(close)) {
+            return Ok(items);
+        }
+
+        loop {
+            items.push(item(self)?);
+
+            if self.eat(&Token::Punct(close)) {
+                return Ok(items);
+            }
+
+            self.expect(",")?;
+        }
+    }
+
+    fn temps(&mut self, close: &'static str) -> Result<Vec<Temp>, ParseError> {
+        self.list(close, Line::temp)
+    }
+
+    fn header<T: Clone>(&mut self, symbols: &mut Symbols<T>) -> Result<Function, ParseError> {
+        let linkage = if self.eat_keyword("external") {
+            Linkage::External
+        } else {
+            Linkage::Normal
+        };
+
+        self.keyword("function")?;
+
+        let name = self.name(symbols)?;
+
+        self.expect("(")?;
+
+        let params = self.list(")", |line| {
+            let param = line.temp()?;
+            line.expect(":")?;
+            Ok((param, line.ty()?))
+        })?;
+
+        self.expect("->")?;
+
+        let returns = self.ty()?;
+
+        let upvalues = if self.eat_keyword("upvalues") {
+            self.expect("(")?;
+            self.list(")", Line::ty)?
+        } else {
+            vec![]
+        };
+
+        self.expect("{")?;
+
+        Ok(Function {
+            name,
+            params: params.iter().map(|&(param, _)| param).collect(),
+            param_types: params.iter().map(|&(_, ty)| ty).collect(),
+            returns,
+            upvalues,
+            body: vec![],
+            linkage,
+        })
+    }
+
+    fn value<T: Clone>(&mut self, symbols: &mut Symbols<T>) -> Result<Value, ParseError> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.current += 1;
+                self.expect(":")?;
+
+                let (sign, size) = self.ty()?;
+
+                if !fits(number, sign, size) {
+                    let number = match sign {
+                        Sign::Signed => (number as i64).to_string(),
+                        Sign::Unsigned => number.to_string(),
+                    };
+
+                    return Err(ParseError::Syntax(format!(
+                        "line {}: `{}` is out of range for `{}{}`",
+                        self.number, number, sign, size
+                    )));
+                }
+
+                Ok(Value::Const(number, sign, size))
+            }
+            Some(Token::Str(bytes)) => {
+                self.current += 1;
+                Ok(Value::Mem(bytes))
+            }
+            Some(Token::Temp(_)) => Ok(Value::Temp(self.temp()?)),
+            _ => Ok(Value::Name(self.name(symbols)?)),
+        }
+    }
+
+    fn instruction<T: Clone>(
+        &mut self,
+        symbols: &mut Symbols<T>,
+    ) -> Result<Instruction, ParseError> {
+        if self.eat_keyword("label") {
+            return Ok(Instruction::Label(self.name(symbols)?));
+        }
+
+        if self.eat_keyword("jump") {
+            return Ok(Instruction::Jump(self.name(symbols)?));
+        }
+
+        if self.eat_keyword("if") {
+            let lhs = self.temp()?;
+
+            let op = match self.advance() {
+                Some(Token::Punct("<")) => CmpOp::LT,
+                Some(Token::Punct("<=")) => CmpOp::LTE,
+                Some(Token::Punct(">")) => CmpOp::GT,
+                Some(Token::Punct(">=")) => CmpOp::GTE,
+                Some(Token::Punct("==")) => CmpOp::EQ,
+                Some(Token::Punct("!=")) => CmpOp::NE,
+                _ => {
+                    self.current -= 1;
+                    return Err(self.error("a comparison"));
+                }
+            };
+
+            let rhs = self.temp()?;
+
+            self.keyword("then")?;
+            let ltrue = self.name(symbols)?;
+            self.keyword("else")?;
+            let lfalse = self.name(symbols)?;
+
+            return Ok(Instruction::CJump(lhs, op, rhs, ltrue, lfalse));
+        }
+
+        if self.eat_keyword("ret") {
+            return Ok(Instruction::Return(self.temp()?));
+        }
+
+        if self.eat_keyword("load") {
+            return Ok(Instruction::Load(self.temp()?));
+        }
+
+        if self.eat_keyword("close") {
+            return Ok(Instruction::CloseUpvalue(self.temp()?));
+        }
+
+        if self.eat_keyword("value") {
+            return Ok(Instruction::Value(self.value(symbols)?));
+        }
+
+        if self.eat_keyword("upvalue") {
+            let index = self.number()? as usize;
+            self.expect(":=")?;
+
+            return Ok(Instruction::SetUpvalue(index, self.temp()?));
+        }
+
+        let to = match self.peek() {
+            Some(&Token::Temp(_)) => self.temp()?,
+            _ => return Err(self.error("an instruction")),
+        };
+
+        if self.eat(&Token::Punct("[")) {
+            let (offset, sign, size) = self.offset()?;
+            self.expect(":=")?;
+
+            return Ok(Instruction::StoreAt(to, offset, self.temp()?, sign, size));
+        }
+
+        self.expect(":=")?;
+
+        self.assignment(to, symbols)
+    }
+
+    /// The offset and type of a field after the opening `[`, as in `[8]:i32`
+    fn offset(&mut self) -> Result<(usize, Sign, Size), ParseError> {
+        let offset = self.number()? as usize;
+        self.expect("]")?;
+        self.expect(":")?;
+
+        let (sign, size) = self.ty()?;
+
+        Ok((offset, sign, size))
+    }
+
+    /// The right hand side of an instruction that writes to `to`
+    fn assignment<T: Clone>(
+        &mut self,
+        to: Temp,
+        symbols: &mut Symbols<T>,
+    ) -> Result<Instruction, ParseError> {
+        if let Some(&Token::Temp(_)) = self.peek() {
+            let from = self.temp()?;
+
+            if self.eat_keyword("as") {
+                let (sign, size) = self.ty()?;
+                return Ok(Instruction::Cast(to, from, sign, size));
+            }
+
+            if self.eat(&Token::Punct("[")) {
+                let (offset, sign, size) = self.offset()?;
+                return Ok(Instruction::LoadAt(to, from, offset, sign, size));
+            }
+
+            let op = match self.peek() {
+                Some(&Token::Punct("+")) => BinOp::Plus,
+                Some(&Token::Punct("-")) => BinOp::Minus,
+                Some(&Token::Punct("*")) => BinOp::Mul,
+                Some(&Token::Punct("/")) => BinOp::Div,
+                Some(Token::Word(word)) if word == "and" => BinOp::And,
+                Some(Token::Word(word)) if word == "or" => BinOp::Or,
+                _ => return Ok(Instruction::Copy(to, from)),
+            };
+
+            self.current += 1;
+
+            return Ok(Instruction::BinOp(from, op, self.temp()?, to));
+        }
+
+        if self.eat(&Token::Punct("-")) {
+            return Ok(Instruction::UnOp(to, UnOp::Minus, self.temp()?));
+        }
+
+        if self.eat(&Token::Punct("!")) {
+            return Ok(Instruction::UnOp(to, UnOp::Bang, self.temp()?));
+        }
+
+        if self.eat(&Token::Punct("[")) {
+            return Ok(Instruction::Block(to, self.temps("]")?));
+        }
+
+        if self.eat_keyword("alloc") {
+            return Ok(Instruction::Alloc(to, self.number()? as usize));
+        }
+
+        if self.eat_keyword("store") {
+            return Ok(Instruction::Store(to, Value::Temp(self.temp()?)));
+        }
+
+        if self.eat_keyword("upvalue") {
+            return Ok(Instruction::GetUpvalue(to, self.number()? as usize));
+        }
+
+        if self.eat_keyword("call") {
+            if let Some(&Token::Temp(_)) = self.peek() {
+                let callee = self.temp()?;
+
+                self.expect("(")?;
+                let args = self.temps(")")?;
+                self.expect("->")?;
+
+                return Ok(Instruction::CallClosure(to, callee, args, self.ty()?));
+            }
+
+            let callee = self.name(symbols)?;
+
+            self.expect("(")?;
+
+            return Ok(Instruction::Call(to, callee, self.temps(")")?));
+        }
+
+        if self.eat_keyword("closure") {
+            let function = self.name(symbols)?;
+
+            self.expect("(")?;
+
+            let captures = self.list(")", |line| {
+                if line.eat_keyword("upvalue") {
+                    Ok(Capture::Upvalue(line.number()? as usize))
+                } else {
+                    Ok(Capture::Local(line.temp()?))
+                }
+            })?;
+
+            return Ok(Instruction::Closure(to, function, captures));
+        }
+
+        if self.eat_keyword("phi") {
+            self.expect("(")?;
+
+            let operands = self.list(")", |line| {
+                let label = line.name(symbols)?;
+                line.expect(":")?;
+                Ok((label, line.temp()?))
+            })?;
+
+            return Ok(Instruction::Phi(to, operands));
+        }
+
+        // An intrinsic is the only thing called without `call`
+        if self.tokens.get(self.current + 1) == Some(&Token::Punct("(")) {
+            let intrinsic = match self.peek() {
+                Some(Token::Word(word)) => match word.as_str() {
+                    "print_int" => Some(Intrinsic::PrintInt),
+                    "print_bool" => Some(Intrinsic::PrintBool),
+                    "print_char" => Some(Intrinsic::PrintChar),
+                    "print_str" => Some(Intrinsic::PrintStr),
+                    "assert" => Some(Intrinsic::Assert),
+                    "exit" => Some(Intrinsic::Exit),
+                    "len" => Some(Intrinsic::StrLen),
+                    "str_eq" => Some(Intrinsic::StrEq),
+                    "concat" => Some(Intrinsic::StrConcat),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            return match intrinsic {
+                Some(intrinsic) => {
+                    self.current += 2;
+                    Ok(Instruction::Intrinsic(to, intrinsic, self.temps(")")?))
+                }
+                None => Err(self.error("an intrinsic")),
+            };
+        }
+
+        Ok(Instruction::Store(to, self.value(symbols)?))
+    }
+}
+
+/// Whether a number, negative ones held as their two's complement, can be stored in
+/// the given type
+fn fits(number: u64, sign: Sign, size: Size) -> bool {
+    let bits = size.size() * 8;
+
+    match (sign, size) {
+        (_, Size::Bit64) => true,
+        (Sign::Unsigned, _) => number >> bits == 0,
+        (Sign::Signed, _) => {
+            let half = 1i64 << (bits - 1);
+            (-half..half).contains(&(number as i64))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, ParseError};
+    use std::rc::Rc;
+    use util::symbol::{SymbolMap, Symbols};
+
+    const PROGRAM: &str = r#"function `t3`(t1:i32, t2:u8) -> i32 {
+    t4 := -1:i32
+    t5 := 18446744073709551615:u64
+    t6 := "say \"hi\"\0a"
+    t7 := `t3`
+    t8 := store t1
+    t9 := t1
+    t10 := t2 as i32
+    t11 := t1 - t10
+    t12 := t11 and t4
+    t13 := - t12
+    t14 := ! t2
+    t15 := [t1, t4]
+    t16 := call `t3`(t13, t2)
+    t17 := call t7(t1) -> u64
+    t18 := print_str(t6)
+    t19 := closure lambda(t1, upvalue 0)
+    t20 := upvalue 1
+    upvalue 1 := t20
+    close t1
+    if t1 >= t4 then l_then_1 else `label`
+    label l_then_1
+    t21 := phi(l_then_1: t1, `label`: t4)
+    jump `label`
+    label `label`
+    load t21
+    value 3:u8
+    t22 := alloc 16
+    t22[8]:u8 := t2
+    t23 := t22[8]:u8
+    ret t21
+}
+
+function lambda(t24:i32) -> i32 upvalues(i32, u8) {
+    ret t24
+}
+
+external function sin(t25:i32) -> i32 {
+}
+"#;
+
+    #[test]
+    fn printed_programs_read_back_the_same() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program = parse(PROGRAM, &mut symbols).unwrap();
+
+        assert_eq!(program.functions.len(), 3);
+        assert_eq!(program.functions[0].body.len(), 30);
+        assert_eq!(program.print(&symbols), PROGRAM);
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program = "// A comment\nfunction main() -> i32 {\n    t1 := t2 %\n}\n";
+
+        match parse(program, &mut symbols) {
+            Err(ParseError::Syntax(message)) => assert!(message.starts_with("line 3:")),
+            program => panic!("{:?}", program),
+        }
+
+        match parse("function main() -> i32 {\n    ret t1\n", &mut symbols) {
+            Err(ParseError::Syntax(message)) => assert!(message.contains("`}`")),
+            program => panic!("{:?}", program),
+        }
+    }
+
+    #[test]
+    fn constants_have_to_fit_their_type() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program =
+            "function main() -> i32 {\n    t1 := 255:u8\n    t2 := -128:i8\n    ret t2\n}\n";
+        parse(program, &mut symbols).unwrap();
+
+        for (constant, message) in &[
+            ("300:u8", "line 2: `300` is out of range for `u8`"),
+            ("-129:i8", "line 2: `-129` is out of range for `i8`"),
+            (
+                "-1:u32",
+                "line 2: `18446744073709551615` is out of range for `u32`",
+            ),
+        ] {
+            let program = format!(
+                "function main() -> i32 {{\n    t1 := {}\n    ret t1\n}}\n",
+                constant
+            );
+
+            match parse(&program, &mut symbols) {
+                Err(ParseError::Syntax(ref error)) => assert_eq!(error, message),
+                program => panic!("{:?}", program),
+            }
+        }
+    }
+}
@@ -0,0 +1,541 @@
+//! Converts a function to SSA form, where every temp is assigned once, and back again.
+//! Phis are placed at the dominance frontiers of the blocks that assign a temp, as long
+//! as the temp is live there, then every temp is renamed walking down the dominator tree.
+//! Temps that a closure captures are shared with it, so they keep their name.
+use cfg::{BlockId, Cfg, CfgError};
+use dominators::Dominators;
+use ir::{new_named_label, Capture, Function, Instruction, Label, Temp};
+use liveness;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use util::symbol::Symbols;
+
+/// The temp a phi takes from each block that leads to it
+type Operands = Vec<(Label, Temp)>;
+
+#[derive(Debug)]
+pub enum SsaError {
+    Invalid(String),
+}
+
+pub fn construct<T: Clone>(
+    function: &mut Function,
+    symbols: &mut Symbols<T>,
+) -> Result<(), CfgError> {
+    prepare(function, symbols)?;
+
+    let cfg = Cfg::new(&function.body)?;
+    let dominators = Dominators::new(&cfg);
+    let frontiers = dominators.frontiers(&cfg);
+    let liveness = liveness::analyse(function);
+    let shared = shared(&function.body);
+
+    // The blocks that assign to each temp
+    let mut assigned: HashMap<Temp, Vec<BlockId>> = HashMap::new();
+
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        for instruction in &function.body[block.start..block.end] {
+            match instruction.defs() {
+                Some(temp) if !shared.contains(&temp) => {
+                    let blocks = assigned.entry(temp).or_default();
+
+                    if blocks.last() != Some(&id) {
+                        blocks.push(id);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut temps: Vec<Temp> = assigned.keys().cloned().collect();
+    temps.sort_by_key(|temp| temp.0);
+
+    // The temps that need a phi at the start of each block
+    let mut phis: Vec<Vec<Temp>> = vec![vec![]; cfg.blocks.len()];
+
+    for temp in temps {
+        let mut worklist = assigned[&temp].clone();
+
+        while let Some(block) = worklist.pop() {
+            for &frontier in &frontiers[block] {
+                let live = liveness.live_in[cfg.blocks[frontier].start].contains(&temp);
+
+                if live && !phis[frontier].contains(&temp) {
+                    phis[frontier].push(temp);
+                    // The phi is another assignment
+                    worklist.push(frontier);
+                }
+            }
+        }
+    }
+
+    let mut body = mem::take(&mut function.body).into_iter();
+
+    let mut renamer = Renamer {
+        cfg: &cfg,
+        dominators: &dominators,
+        shared: &shared,
+        blocks: cfg
+            .blocks
+            .iter()
+            .map(|block| body.by_ref().take(block.end - block.start).collect())
+            .collect(),
+        phis: phis
+            .into_iter()
+            .map(|temps| temps.into_iter().map(|temp| (temp, temp, vec![])).collect())
+            .collect(),
+        names: HashMap::new(),
+    };
+
+    renamer.rename(cfg.entry);
+
+    for (instructions, phis) in renamer.blocks.into_iter().zip(renamer.phis) {
+        let mut instructions = instructions.into_iter();
+
+        // Every block starts with its label
+        function.body.extend(instructions.next());
+        function.body.extend(
+            phis.into_iter()
+                .map(|(_, temp, operands)| Instruction::Phi(temp, operands)),
+        );
+        function.body.extend(instructions);
+    }
+
+    Ok(())
+}
+
+/// Drops the blocks that can't be reached and gives the entry a label of its own,
+/// so that every edge into a phi comes from a labelled block
+fn prepare<T: Clone>(function: &mut Function, symbols: &mut Symbols<T>) -> Result<(), CfgError> {
+    let cfg = Cfg::new(&function.body)?;
+    let mut reachable = vec![false; function.body.len()];
+
+    for block in cfg.reverse_postorder() {
+        for reachable in &mut reachable[cfg.blocks[block].start..cfg.blocks[block].end] {
+            *reachable = true;
+        }
+    }
+
+    let body = mem::take(&mut function.body);
+
+    function.body = body
+        .into_iter()
+        .zip(reachable)
+        .filter(|&(_, reachable)| reachable)
+        .map(|(instruction, _)| instruction)
+        .collect();
+
+    let entry = &cfg.blocks[cfg.entry];
+
+    if entry.label.is_none() || !entry.predecessors.is_empty() {
+        let label = new_named_label("entry", symbols);
+        function.body.insert(0, Instruction::Label(label));
+    }
+
+    Ok(())
+}
+
+/// The temps a closure captures, which it reads and writes as they change
+pub(crate) fn shared(body: &[Instruction]) -> HashSet<Temp> {
+    let mut shared = HashSet::new();
+
+    for instruction in body {
+        match *instruction {
+            Instruction::Closure(_, _, ref captures) => {
+                for capture in captures {
+                    if let Capture::Local(temp) = *capture {
+                        shared.insert(temp);
+                    }
+                }
+            }
+            Instruction::CloseUpvalue(temp) => {
+                shared.insert(temp);
+            }
+            _ => (),
+        }
+    }
+
+    shared
+}
+
+struct Renamer<'a> {
+    cfg: &'a Cfg,
+    dominators: &'a Dominators,
+    shared: &'a HashSet<Temp>,
+    blocks: Vec<Vec<Instruction>>,
+    /// The temp each phi is for, the name it assigns and its operands
+    phis: Vec<Vec<(Temp, Temp, Operands)>>,
+    /// The names of each temp that are in scope, latest last
+    names: HashMap<Temp, Vec<Temp>>,
+}
+
+impl<'a> Renamer<'a> {
+    /// The name a temp has at this point, or the temp itself if nothing has
+    /// assigned to it yet, like a parameter
+    fn current(&self, temp: Temp) -> Temp {
+        self.names
+            .get(&temp)
+            .and_then(|names| names.last())
+            .cloned()
+            .unwrap_or(temp)
+    }
+
+    fn assign(&mut self, temp: Temp, assigned: &mut Vec<Temp>) -> Temp {
+        if self.shared.contains(&temp) {
+            return temp;
+        }
+
+        let name = Temp::new();
+
+        self.names.entry(temp).or_default().push(name);
+        assigned.push(temp);
+
+        name
+    }
+
+    fn rename(&mut self, block: BlockId) {
+        let mut assigned = vec![];
+
+        for i in 0..self.phis[block].len() {
+            let temp = self.phis[block][i].0;
+            self.phis[block][i].1 = self.assign(temp, &mut assigned);
+        }
+
+        let mut instructions = mem::take(&mut self.blocks[block]);
+
+        for instruction in &mut instructions {
+            instruction.rename_uses(|temp| self.current(temp));
+
+            if let Some(temp) = instruction.defs() {
+                let name = self.assign(temp, &mut assigned);
+                instruction.rename_def(|_| name);
+            }
+        }
+
+        self.blocks[block] = instructions;
+
+        let cfg = self.cfg;
+        let label = cfg.blocks[block]
+            .label
+            .expect("Blocks are labelled before renaming");
+
+        for &successor in &cfg.blocks[block].successors {
+            for i in 0..self.phis[successor].len() {
+                let name = self.current(self.phis[successor][i].0);
+                self.phis[successor][i].2.push((label, name));
+            }
+        }
+
+        let dominators = self.dominators;
+
+        for &child in dominators.children(block) {
+            self.rename(child);
+        }
+
+        for temp in assigned {
+            self.names.get_mut(&temp).unwrap().pop();
+        }
+    }
+}
+
+/// Replaces the phis with copies at the end of the blocks that lead to them. The edges
+/// out of a block that branches are split so the copies only run on their edge
+pub fn destruct<T: Clone>(
+    function: &mut Function,
+    symbols: &mut Symbols<T>,
+) -> Result<(), CfgError> {
+    let cfg = Cfg::new(&function.body)?;
+
+    let labels: HashMap<Label, BlockId> = cfg
+        .blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(id, block)| block.label.map(|label| (label, id)))
+        .collect();
+
+    // The copies on each edge, by the block it leaves and the label it goes to
+    let mut copies: HashMap<(BlockId, Label), Vec<(Temp, Temp)>> = HashMap::new();
+
+    for block in &cfg.blocks {
+        for instruction in &function.body[block.start..block.end] {
+            if let Instruction::Phi(temp, ref operands) = *instruction {
+                let label = block.label.expect("Phis are in labelled blocks");
+
+                for &(pred, operand) in operands {
+                    match labels.get(&pred) {
+                        Some(&pred) => copies
+                            .entry((pred, label))
+                            .or_default()
+                            .push((temp, operand)),
+                        None => return Err(CfgError::UndefinedLabel(pred)),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut body = mem::take(&mut function.body).into_iter();
+
+    for (id, block) in cfg.blocks.iter().enumerate() {
+        let mut instructions: Vec<Instruction> = body
+            .by_ref()
+            .take(block.end - block.start)
+            .filter(|instruction| !matches!(*instruction, Instruction::Phi(_, _)))
+            .collect();
+
+        let mut exit = match instructions.last() {
+            Some(&Instruction::Jump(_))
+            | Some(&Instruction::CJump(_, _, _, _, _))
+            | Some(&Instruction::Return(_)) => instructions.pop(),
+            _ => None,
+        };
+
+        let mut split = vec![];
+
+        for &successor in &block.successors {
+            let label = match cfg.blocks[successor].label {
+                Some(label) => label,
+                None => continue,
+            };
+
+            let copies = match copies.remove(&(id, label)) {
+                Some(copies) => sequence(copies),
+                None => continue,
+            };
+
+            if block.successors.len() == 1 {
+                instructions.extend(copies);
+                continue;
+            }
+
+            let edge = new_named_label("edge", symbols);
+
+            if let Some(Instruction::CJump(_, _, _, ref mut ltrue, ref mut lfalse)) = exit {
+                if *ltrue == label {
+                    *ltrue = edge;
+                } else {
+                    *lfalse = edge;
+                }
+            }
+
+            split.push(Instruction::Label(edge));
+            split.extend(copies);
+            split.push(Instruction::Jump(label));
+        }
+
+        function.body.extend(instructions);
+        function.body.extend(exit);
+        function.body.extend(split);
+    }
+
+    Ok(())
+}
+
+/// Orders copies that all happen at once so that none of them overwrites a temp that
+/// another still has to read, going through a new temp to break cycles
+fn sequence(mut copies: Vec<(Temp, Temp)>) -> Vec<Instruction> {
+    let mut sequence = vec![];
+
+    copies.retain(|&(to, from)| to != from);
+
+    while !copies.is_empty() {
+        let ready = copies
+            .iter()
+            .position(|&(to, _)| copies.iter().all(|&(_, from)| from != to));
+
+        match ready {
+            Some(i) => {
+                let (to, from) = copies.remove(i);
+                sequence.push(Instruction::Copy(to, from));
+            }
+            None => {
+                let from = copies[0].1;
+                let temp = Temp::new();
+
+                sequence.push(Instruction::Copy(temp, from));
+
+                for copy in &mut copies {
+                    if copy.1 == from {
+                        copy.1 = temp;
+                    }
+                }
+            }
+        }
+    }
+
+    sequence
+}
+
+/// Checks that every temp is assigned once, apart from the ones closures capture, and
+/// that phis are at the start of a block with an operand for each predecessor
+pub fn verify(function: &Function) -> Result<(), SsaError> {
+    let cfg = Cfg::new(&function.body).map_err(|e| match e {
+        CfgError::UndefinedLabel(label) => invalid(format!("Jump to undefined label `{}`", label)),
+    })?;
+
+    let shared = shared(&function.body);
+    let mut assigned: HashSet<Temp> = HashSet::new();
+
+    for temp in function
+        .params
+        .iter()
+        .cloned()
+        .chain(function.body.iter().filter_map(Instruction::defs))
+    {
+        if !shared.contains(&temp) && !assigned.insert(temp) {
+            return Err(invalid(format!("`{}` is assigned more than once", temp)));
+        }
+    }
+
+    for block in &cfg.blocks {
+        let mut in_phis = true;
+
+        for (i, instruction) in function.body[block.start..block.end].iter().enumerate() {
+            match *instruction {
+                Instruction::Label(_) if i == 0 => (),
+                Instruction::Phi(temp, ref operands) if in_phis => {
+                    let mut labels: Vec<Label> = operands.iter().map(|&(label, _)| label).collect();
+                    labels.sort_by_key(|label| label.0);
+                    labels.dedup();
+
+                    let expected =
+                        block
+                            .predecessors
+                            .iter()
+                            .all(|&pred| match cfg.blocks[pred].label {
+                                Some(label) => labels.contains(&label),
+                                None => false,
+                            });
+
+                    if !expected
+                        || labels.len() != operands.len()
+                        || labels.len() != block.predecessors.len()
+                    {
+                        return Err(invalid(format!(
+                            "The phi for `{}` needs one operand for each predecessor",
+                            temp
+                        )));
+                    }
+                }
+                Instruction::Phi(temp, _) => {
+                    return Err(invalid(format!(
+                        "The phi for `{}` is not at the start of a block",
+                        temp
+                    )))
+                }
+                _ => in_phis = false,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn invalid(message: String) -> SsaError {
+    SsaError::Invalid(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{construct, destruct, verify};
+    use ir::{BinOp, CmpOp, Function, Instruction, Temp, Value};
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    #[test]
+    fn loops_get_phis_and_lose_them_again() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (body, end) = (symbols.symbol("body"), symbols.symbol("end"));
+        let (n, total, one) = (Temp::new(), Temp::new(), Temp::new());
+
+        let mut function = Function {
+            name: symbols.symbol("sum"),
+            params: vec![n],
+            param_types: vec![(Sign::Signed, Size::Bit32)],
+            returns: (Sign::Signed, Size::Bit32),
+            upvalues: vec![],
+            body: vec![
+                Instruction::Store(total, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+                Instruction::Label(body),
+                Instruction::BinOp(total, BinOp::Plus, n, total),
+                Instruction::BinOp(n, BinOp::Minus, one, n),
+                Instruction::CJump(n, CmpOp::GT, one, body, end),
+                Instruction::Label(end),
+                Instruction::Return(total),
+            ],
+            linkage: Linkage::Normal,
+        };
+
+        assert!(verify(&function).is_err());
+
+        construct(&mut function, &mut symbols).unwrap();
+        verify(&function).unwrap();
+
+        // `n` and `total` meet at the top of the loop but `one` is only assigned once
+        let phis: Vec<usize> = function
+            .body
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instruction)| match *instruction {
+                Instruction::Phi(temp, ref operands) => {
+                    assert_eq!(operands.len(), 2);
+                    assert!(temp != n && temp != total);
+                    Some(i)
+                }
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(phis, vec![4, 5]);
+
+        match function.body[4] {
+            Instruction::Phi(_, ref operands) => {
+                assert!(operands.iter().any(|&(_, operand)| operand == n))
+            }
+            _ => unreachable!(),
+        }
+
+        destruct(&mut function, &mut symbols).unwrap();
+
+        assert!(function
+            .body
+            .iter()
+            .all(|instruction| !matches!(*instruction, Instruction::Phi(_, _))));
+
+        // The edge back to the top of the loop is split to hold its copies
+        assert!(function.body.iter().any(|instruction| match *instruction {
+            Instruction::Jump(label) => label == body,
+            _ => false,
+        }));
+
+        assert!(function.body.iter().all(|instruction| match *instruction {
+            Instruction::CJump(_, _, _, ltrue, _) => ltrue != body,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn swapped_copies_use_a_temp() {
+        let (a, b) = (Temp::new(), Temp::new());
+
+        let copies = super::sequence(vec![(a, b), (b, a)]);
+
+        assert_eq!(copies.len(), 3);
+
+        match (&copies[0], &copies[1], &copies[2]) {
+            (
+                &Instruction::Copy(temp, from),
+                &Instruction::Copy(first, second),
+                &Instruction::Copy(last, saved),
+            ) => {
+                assert_eq!(from, b);
+                assert_eq!((first, second), (b, a));
+                assert_eq!((last, saved), (a, temp));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
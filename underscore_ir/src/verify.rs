@@ -0,0 +1,412 @@
+//! Checks that IR is well formed, so that a bug in code generation or in a pass shows up
+//! where it happens instead of as a program that misbehaves.
+use cfg::Cfg;
+use ir::{number, Function, Instruction, Label, Program, Temp};
+use std::collections::{HashMap, HashSet};
+use syntax::ast::Linkage;
+use util::symbol::{Symbol, Symbols};
+
+/// Each error names the function and the index of the instruction it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// A jump to a label the function doesn't define
+    UndefinedLabel(String),
+    /// A label the function defines more than once
+    DuplicateLabel(String),
+    /// A temp read on a path where it hasn't been written
+    UseBeforeDefinition(String),
+    /// A function that can run off the end of its body without returning
+    MissingReturn(String),
+    /// A call to a function that isn't in the program
+    UndefinedFunction(String),
+    /// A call with a different number of arguments than the function has parameters
+    WrongArgumentCount(String),
+}
+
+/// Checks every function of the program, with symbols written by number
+pub fn verify(program: &Program) -> Result<(), Vec<VerifyError>> {
+    Verifier::new(Some(program), &number).program(program)
+}
+
+/// Checks every function of the program, with symbols written by name
+pub fn verify_named<T: Clone>(
+    program: &Program,
+    symbols: &Symbols<T>,
+) -> Result<(), Vec<VerifyError>> {
+    let name = |symbol| symbols.name(symbol);
+
+    Verifier::new(Some(program), &name).program(program)
+}
+
+/// Checks a function on its own, which leaves out the calls it makes as it can't tell
+/// what they call
+pub fn verify_function(function: &Function) -> Result<(), Vec<VerifyError>> {
+    let mut verifier = Verifier::new(None, &number);
+
+    verifier.function(function);
+    verifier.finish()
+}
+
+struct Verifier<'a> {
+    /// The parameter count of each function, when the whole program is known
+    arity: Option<HashMap<Label, usize>>,
+    name: &'a dyn Fn(Symbol) -> String,
+    errors: Vec<VerifyError>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: Option<&Program>, name: &'a dyn Fn(Symbol) -> String) -> Self {
+        let arity = program.map(|program| {
+            program
+                .functions
+                .iter()
+                .map(|function| (function.name, function.params.len()))
+                .collect()
+        });
+
+        Verifier {
+            arity,
+            name,
+            errors: Vec::new(),
+        }
+    }
+
+    fn program(mut self, program: &Program) -> Result<(), Vec<VerifyError>> {
+        for function in &program.functions {
+            self.function(function);
+        }
+
+        self.finish()
+    }
+
+    fn finish(self) -> Result<(), Vec<VerifyError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn at(&self, function: &Function, index: usize, message: String) -> String {
+        format!(
+            "`{}` at instruction {}: {}",
+            (self.name)(function.name),
+            index,
+            message
+        )
+    }
+
+    fn function(&mut self, function: &Function) {
+        // External functions are only a declaration
+        if function.linkage == Linkage::External && function.body.is_empty() {
+            return;
+        }
+
+        let labels = self.labels(function);
+
+        if !self.jumps(function, &labels) {
+            return;
+        }
+
+        self.calls(function);
+
+        let cfg = Cfg::new(&function.body).expect("Jumps are checked above");
+        let reachable = cfg.reverse_postorder();
+
+        self.returns(function, &cfg, &reachable);
+        self.definitions(function, &cfg, &reachable);
+    }
+
+    /// The index of each label, reporting the ones that are defined twice
+    fn labels(&mut self, function: &Function) -> HashMap<Label, usize> {
+        let mut labels = HashMap::new();
+
+        for (i, instruction) in function.body.iter().enumerate() {
+            if let Instruction::Label(label) = *instruction {
+                if labels.insert(label, i).is_some() {
+                    let message = format!("label `{}` is defined again", (self.name)(label));
+                    let error = VerifyError::DuplicateLabel(self.at(function, i, message));
+                    self.errors.push(error);
+                }
+            }
+        }
+
+        labels
+    }
+
+    /// Reports the jumps to labels that aren't defined. Returns whether there were none,
+    /// as the control flow can't be followed otherwise
+    fn jumps(&mut self, function: &Function, labels: &HashMap<Label, usize>) -> bool {
+        let mut defined = true;
+
+        for (i, instruction) in function.body.iter().enumerate() {
+            let targets = match *instruction {
+                Instruction::Jump(label) => vec![label],
+                Instruction::CJump(_, _, _, ltrue, lfalse) => vec![ltrue, lfalse],
+                Instruction::Phi(_, ref operands) => {
+                    operands.iter().map(|&(label, _)| label).collect()
+                }
+                _ => continue,
+            };
+
+            for label in targets {
+                if !labels.contains_key(&label) {
+                    let message = format!("jump to undefined label `{}`", (self.name)(label));
+                    let error = VerifyError::UndefinedLabel(self.at(function, i, message));
+                    self.errors.push(error);
+                    defined = false;
+                }
+            }
+        }
+
+        defined
+    }
+
+    fn calls(&mut self, function: &Function) {
+        let arity = match self.arity {
+            Some(ref arity) => arity,
+            None => return,
+        };
+
+        let mut errors = Vec::new();
+
+        for (i, instruction) in function.body.iter().enumerate() {
+            let (callee, args) = match *instruction {
+                Instruction::Call(_, callee, ref args) => (callee, Some(args.len())),
+                Instruction::Closure(_, callee, _) => (callee, None),
+                _ => continue,
+            };
+
+            match (arity.get(&callee), args) {
+                (None, _) => {
+                    let message = format!("call to undefined function `{}`", (self.name)(callee));
+                    errors.push(VerifyError::UndefinedFunction(
+                        self.at(function, i, message),
+                    ));
+                }
+                (Some(&params), Some(args)) if params != args => {
+                    let message = format!(
+                        "`{}` takes {} arguments but is given {}",
+                        (self.name)(callee),
+                        params,
+                        args
+                    );
+                    errors.push(VerifyError::WrongArgumentCount(
+                        self.at(function, i, message),
+                    ));
+                }
+                _ => (),
+            }
+        }
+
+        self.errors.extend(errors);
+    }
+
+    /// Reports a function whose last block can be reached and doesn't end with a jump or
+    /// a return
+    fn returns(&mut self, function: &Function, cfg: &Cfg, reachable: &[usize]) {
+        let last = cfg.blocks.len() - 1;
+
+        let ends = matches!(
+            function.body.last(),
+            Some(&Instruction::Jump(_))
+                | Some(&Instruction::CJump(_, _, _, _, _))
+                | Some(&Instruction::Return(_))
+        );
+
+        if !ends && reachable.contains(&last) {
+            let message = "runs off the end of the function without returning".into();
+            let error = self.at(function, function.body.len(), message);
+            self.errors.push(VerifyError::MissingReturn(error));
+        }
+    }
+
+    /// Follows the temps that are written on every path to each block and reports the
+    /// ones read before that. A phi reads its operand at the end of the block it comes
+    /// from
+    fn definitions(&mut self, function: &Function, cfg: &Cfg, reachable: &[usize]) {
+        let params: HashSet<Temp> = function.params.iter().cloned().collect();
+        let blocks: HashMap<Label, usize> = cfg
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, block)| block.label.map(|label| (label, id)))
+            .collect();
+
+        // `None` stands for every temp, which is what a block no path has reached yet
+        // starts with
+        let mut outs: Vec<Option<HashSet<Temp>>> = vec![None; cfg.blocks.len()];
+
+        let entry = |id: usize, outs: &[Option<HashSet<Temp>>]| -> HashSet<Temp> {
+            let mut defined: Option<HashSet<Temp>> = None;
+
+            if id == cfg.entry {
+                defined = Some(params.clone());
+            }
+
+            for &pred in &cfg.blocks[id].predecessors {
+                if let Some(ref out) = outs[pred] {
+                    defined = Some(match defined {
+                        Some(defined) => defined.intersection(out).cloned().collect(),
+                        None => out.clone(),
+                    });
+                }
+            }
+
+            defined.unwrap_or_default()
+        };
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &id in reachable {
+                let block = &cfg.blocks[id];
+                let mut defined = entry(id, &outs);
+
+                defined.extend(
+                    function.body[block.start..block.end]
+                        .iter()
+                        .filter_map(Instruction::defs),
+                );
+
+                if outs[id].as_ref() != Some(&defined) {
+                    outs[id] = Some(defined);
+                    changed = true;
+                }
+            }
+        }
+
+        for &id in reachable {
+            let block = &cfg.blocks[id];
+            let mut defined = entry(id, &outs);
+
+            for i in block.start..block.end {
+                let instruction = &function.body[i];
+
+                let undefined: Vec<Temp> = match *instruction {
+                    Instruction::Phi(_, ref operands) => operands
+                        .iter()
+                        .filter(|&&(label, temp)| match outs[blocks[&label]] {
+                            Some(ref out) => !out.contains(&temp),
+                            None => false,
+                        })
+                        .map(|&(_, temp)| temp)
+                        .collect(),
+                    _ => instruction
+                        .uses()
+                        .into_iter()
+                        .filter(|temp| !defined.contains(temp))
+                        .collect(),
+                };
+
+                for temp in undefined {
+                    let message = format!("`{}` is read before it is written", temp);
+                    let error = self.at(function, i, message);
+                    self.errors.push(VerifyError::UseBeforeDefinition(error));
+                }
+
+                if let Some(temp) = instruction.defs() {
+                    defined.insert(temp);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify, verify_named, VerifyError};
+    use ir::{Instruction, Temp};
+    use parse::parse;
+    use std::rc::Rc;
+    use util::symbol::{SymbolMap, Symbols};
+
+    #[test]
+    fn well_formed_programs_pass() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program = "\
+function max(t1:i32, t2:i32) -> i32 {
+    if t1 > t2 then first else second
+    label first
+    t3 := t1
+    jump end
+    label second
+    t3 := t2
+    label end
+    ret t3
+}
+function main() -> i32 {
+    t1 := 1:i32
+    t2 := call max(t1, t1)
+    ret t2
+}
+";
+
+        let program = parse(program, &mut symbols).unwrap();
+
+        assert_eq!(verify(&program), Ok(()));
+    }
+
+    #[test]
+    fn errors_name_the_function_and_instruction() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        let program = "\
+function id(t1:i32) -> i32 {
+    ret t1
+}
+function main() -> i32 {
+    t1 := 1:i32
+    if t1 > t1 then set else skip
+    label set
+    t2 := 2:i32
+    label skip
+    t3 := t2 + t1
+    t4 := call id(t1, t3)
+    t5 := call missing(t4)
+    jump nowhere
+}
+function fall() -> i32 {
+    t1 := 1:i32
+}
+";
+
+        let mut program = parse(program, &mut symbols).unwrap();
+        let missing_return = VerifyError::MissingReturn(
+            "`fall` at instruction 1: runs off the end of the function without returning".into(),
+        );
+
+        assert_eq!(
+            verify_named(&program, &symbols),
+            Err(vec![
+                VerifyError::UndefinedLabel(
+                    "`main` at instruction 8: jump to undefined label `nowhere`".into()
+                ),
+                missing_return.clone(),
+            ])
+        );
+
+        // The rest is found once the control flow can be followed
+        program.functions[1].body.pop();
+        program.functions[1].body.push(Instruction::Return(Temp(5)));
+
+        assert_eq!(
+            verify_named(&program, &symbols),
+            Err(vec![
+                VerifyError::WrongArgumentCount(
+                    "`main` at instruction 6: `id` takes 1 arguments but is given 2".into()
+                ),
+                VerifyError::UndefinedFunction(
+                    "`main` at instruction 7: call to undefined function `missing`".into()
+                ),
+                VerifyError::UseBeforeDefinition(
+                    "`main` at instruction 5: `t2` is read before it is written".into()
+                ),
+                missing_return,
+            ])
+        );
+    }
+}
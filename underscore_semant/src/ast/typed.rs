@@ -53,6 +53,8 @@ pub enum Statement {
         ident: Symbol,
         ty: Type,
         expr: Option<TypedExpression>,
+        /// Captured by a closure so it has to be closed over when it leaves scope
+        escapes: bool,
     },
     Return(TypedExpression),
     While(TypedExpression, Box<Statement>),
@@ -91,7 +93,7 @@ pub enum Expression {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Var {
-    /// Field access i.e foo.bar;
+    /// Field access i.e foo.bar; holds the variable, the field and its type
     Field(Symbol, Symbol, Type),
     /// Simple var i.e x;
     Simple(Symbol, Type),
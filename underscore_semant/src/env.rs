@@ -1,6 +1,7 @@
 
 use std::collections::HashMap;
 use std::rc::Rc;
+use prelude::add_prelude;
 use syntax::ast::{Sign, Size};
 use types::{TyCon, Type, TypeVar};
 use util::symbol::{Symbol, SymbolMap, Symbols};
@@ -22,6 +23,8 @@ pub enum VarType {
 pub enum VarEntry {
     Var(Type),
     Fun { ty: Type },
+    /// A prelude function with a monomorphic type for each type it can be called with
+    Overloaded(Vec<Type>),
 }
 
 impl VarEntry {
@@ -29,6 +32,7 @@ impl VarEntry {
         match self {
             VarEntry::Var(ty) => ty,
             VarEntry::Fun { ty, .. } => ty,
+            VarEntry::Overloaded(mut tys) => tys.remove(0),
         }
     }
 }
@@ -87,12 +91,16 @@ impl Env {
         types.enter(nil_ident, Type::Nil);
         types.enter(string_ident, Type::App(TyCon::String, vec![]));
 
-        Env {
+        let mut env = Env {
             types: Symbols::new(Rc::clone(strings)),
             tvars: HashMap::new(),
             vars: Symbols::new(Rc::clone(strings)),
             escapes: Symbols::new(Rc::clone(strings)),
-        }
+        };
+
+        add_prelude(&mut env);
+
+        env
     }
 
     pub fn symbol(&mut self, name: &str) -> Symbol {
@@ -22,28 +22,50 @@ impl FindEscape {
         Ok(())
     }
 
+    /// `depth` counts how many functions deep we are so a variable escapes
+    /// when it is used from a function nested inside the one declaring it
     fn escape_function(
         &mut self,
         function: &mut Spanned<Function>,
         env: &mut Symbols<(u32, bool)>,
     ) -> InferResult<()> {
-        self.escape_statement(&mut function.value.body, env)
+        self.depth += 1;
+        env.begin_scope();
+
+        for param in &function.value.params.value {
+            env.enter(param.value.name.value, (self.depth, false));
+        }
+
+        let result = self.escape_statement(&mut function.value.body, env);
+
+        env.end_scope();
+        self.depth -= 1;
+
+        result
     }
 
     fn check_ident(&mut self, ident: Symbol, env: &mut Symbols<(u32, bool)>) -> InferResult<()> {
-        if env.look(ident).is_none() {
-            env.enter(ident, (self.depth, false));
-            return Ok(());
-        }
+        let d = match env.look(ident) {
+            Some(&(d, _)) => d,
+            None => return Ok(()),
+        };
 
-        let d = env.look(ident).unwrap().0;
+        if d != self.depth {
+            env.replace(ident, (d, true));
+        }
 
-        if d == self.depth {
-            Ok(())
-        } else {
-            env.enter(ident, (self.depth, true));
+        Ok(())
+    }
 
-            Ok(())
+    /// Sets `escapes` on a `Let` once every use of its binding has been seen
+    fn settle(&self, statement: &mut Spanned<Statement>, env: &Symbols<(u32, bool)>) {
+        if let Statement::Let {
+            ref ident,
+            ref mut escapes,
+            ..
+        } = statement.value
+        {
+            *escapes = env.look(ident.value).map(|&(_, e)| e).unwrap_or(false);
         }
     }
 
@@ -55,13 +77,34 @@ impl FindEscape {
         match statement.value {
             Statement::Block(ref mut statements) => {
                 env.begin_scope();
-                self.depth += 1;
-                for statement in statements {
-                    self.escape_statement(statement, env)?;
+
+                // The lets in this block whose binding is still visible
+                let mut pending: Vec<(usize, Symbol)> = Vec::new();
+
+                for i in 0..statements.len() {
+                    let ident = match statements[i].value {
+                        Statement::Let { ref ident, .. } => Some(ident.value),
+                        _ => None,
+                    };
+
+                    if let Some(ident) = ident {
+                        // A redeclaration hides the earlier binding so settle it first
+                        if let Some(pos) = pending.iter().position(|&(_, s)| s == ident) {
+                            let (j, _) = pending.remove(pos);
+                            self.settle(&mut statements[j], env);
+                        }
+
+                        pending.push((i, ident));
+                    }
+
+                    self.escape_statement(&mut statements[i], env)?;
+                }
+
+                for (i, _) in pending {
+                    self.settle(&mut statements[i], env);
                 }
 
                 env.end_scope();
-                self.depth -= 1;
                 Ok(())
             }
 
@@ -71,10 +114,11 @@ impl FindEscape {
             }
             Statement::For { ref mut body, .. } => self.escape_statement(body, env),
             Statement::If {
+                ref mut cond,
                 ref mut then,
                 ref mut otherwise,
-                ..
             } => {
+                self.escape_expression(cond, env)?;
                 self.escape_statement(then, env)?;
 
                 if let Some(ref mut otherwise) = *otherwise {
@@ -85,27 +129,25 @@ impl FindEscape {
             }
 
             Statement::Let {
-                ref mut escapes,
                 ref ident,
+                ref mut expr,
                 ..
             } => {
-                if env.look(ident.value).is_none() {
-                    env.enter(ident.value, (self.depth, false));
-                    return Ok(());
+                if let Some(ref mut expr) = *expr {
+                    self.escape_expression(expr, env)?;
                 }
 
-                let d = env.look(ident.value).unwrap().0;
-
-                if d == self.depth {
-                    Ok(())
-                } else {
-                    env.enter(ident.value, (self.depth, true));
+                env.enter(ident.value, (self.depth, false));
 
-                    *escapes = true;
-                    Ok(())
-                }
+                Ok(())
+            }
+            Statement::While {
+                ref mut cond,
+                ref mut body,
+            } => {
+                self.escape_expression(cond, env)?;
+                self.escape_statement(body, env)
             }
-            Statement::While { ref mut body, .. } => self.escape_statement(body, env),
         }
     }
 
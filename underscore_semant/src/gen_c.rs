@@ -0,0 +1,1211 @@
+//! Translates a monomorphized program into a single C99 source file.
+//! Structs live on the heap and are passed around by pointer, so they can be nil and
+//! contain themselves. A field whose type is a type parameter is stored in a union
+//! that can hold any value. Fixed arrays are C arrays wrapped in a struct so they can
+//! be copied, passed and returned like every other value.
+use ast::typed as t;
+use ir::ir::Intrinsic;
+use prelude;
+use std::collections::{HashMap, HashSet};
+use syntax::ast::{Linkage, Literal, Op, Sign, Size, UnaryOp};
+use types::{TyCon, Type};
+use util::symbol::{Symbol, Symbols};
+
+#[derive(Debug)]
+pub enum CError {
+    /// The program uses something that can't be expressed in C
+    Unsupported(String),
+}
+
+pub struct CCodegen {
+    symbols: Symbols<()>,
+    /// The fields of each struct, `None` when the field's type is a type parameter
+    structs: HashMap<Symbol, Vec<(Symbol, Option<Type>)>>,
+    /// The params, return type and linkage of each function
+    functions: HashMap<Symbol, (Vec<Type>, Type, Linkage)>,
+    /// The element type and length of every array type, `us_array_n` wraps the nth one
+    arrays: Vec<(String, usize)>,
+    /// The runtime helpers the program calls
+    helpers: HashSet<&'static str>,
+    /// The C name and type of the variables in scope, innermost scope last
+    scopes: Vec<HashMap<Symbol, (String, Type)>>,
+    /// Used to give every local of a function its own name
+    locals: usize,
+    /// The type the function being translated returns
+    returns: Type,
+}
+
+const PRELUDE: &str = "\
+/* Generated by the underscore compiler */
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Holds the value of a field whose type is a type parameter */
+typedef union {
+    int64_t i;
+    uint64_t u;
+    bool b;
+    const char *s;
+    void *p;
+} us_any;
+";
+
+/// The helpers a program may call, the helpers they call and their source.
+/// Helpers come after the ones they use
+const HELPERS: &[(&str, &[&str], &str)] = &[
+    (
+        "us_rt_error",
+        &[],
+        "static void us_rt_error(const char *message) {
+    printf(\"RuntimeError(\\\"%s\\\")\\n\", message);
+    exit(70);
+}",
+    ),
+    (
+        "us_rt_print_int",
+        &[],
+        "static void us_rt_print_int(int64_t value) {
+    printf(\"%lld\\n\", (long long)value);
+}",
+    ),
+    (
+        "us_rt_print_uint",
+        &[],
+        "static void us_rt_print_uint(uint64_t value) {
+    printf(\"%llu\\n\", (unsigned long long)value);
+}",
+    ),
+    (
+        "us_rt_print_bool",
+        &[],
+        "static void us_rt_print_bool(bool value) {
+    puts(value ? \"true\" : \"false\");
+}",
+    ),
+    (
+        "us_rt_print_char",
+        &[],
+        "static void us_rt_print_char(char value) {
+    printf(\"%c\\n\", value);
+}",
+    ),
+    (
+        "us_rt_print_str",
+        &[],
+        "static void us_rt_print_str(const char *value) {
+    printf(\"%s\\n\", value);
+}",
+    ),
+    (
+        "us_rt_assert",
+        &["us_rt_error"],
+        "static void us_rt_assert(bool value) {
+    if (!value) {
+        us_rt_error(\"Assertion failed\");
+    }
+}",
+    ),
+    (
+        "us_rt_exit",
+        &[],
+        "static void us_rt_exit(int32_t code) {
+    exit(code);
+}",
+    ),
+    (
+        "us_rt_len",
+        &[],
+        "static int32_t us_rt_len(const char *string) {
+    return (int32_t)strlen(string);
+}",
+    ),
+    (
+        "us_rt_divisor",
+        &["us_rt_error"],
+        "static uint64_t us_rt_divisor(uint64_t divisor) {
+    if (divisor == 0) {
+        us_rt_error(\"Attempted to divide by zero\");
+    }
+
+    return divisor;
+}",
+    ),
+    (
+        "us_rt_alloc",
+        &["us_rt_error"],
+        "static void *us_rt_alloc(size_t size) {
+    void *memory = malloc(size);
+
+    if (memory == NULL) {
+        us_rt_error(\"Out of memory\");
+    }
+
+    return memory;
+}",
+    ),
+    (
+        "us_rt_concat",
+        &["us_rt_alloc"],
+        "static const char *us_rt_concat(const char *lhs, const char *rhs) {
+    size_t length = strlen(lhs);
+    char *string = us_rt_alloc(length + strlen(rhs) + 1);
+
+    strcpy(string, lhs);
+    strcpy(string + length, rhs);
+
+    return string;
+}",
+    ),
+    (
+        "us_rt_box",
+        &["us_rt_alloc"],
+        "static void *us_rt_box(const void *value, size_t size) {
+    return memcpy(us_rt_alloc(size), value, size);
+}",
+    ),
+];
+
+const KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int", "long",
+    "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+    "true", "typedef", "union", "unsigned", "void", "volatile", "while",
+];
+
+impl CCodegen {
+    pub fn new(symbols: Symbols<()>) -> Self {
+        Self {
+            symbols,
+            structs: HashMap::new(),
+            functions: HashMap::new(),
+            arrays: Vec::new(),
+            helpers: HashSet::new(),
+            scopes: Vec::new(),
+            locals: 0,
+            returns: Type::Nil,
+        }
+    }
+
+    pub fn gen_program(mut self, program: &t::Program) -> Result<String, CError> {
+        for def in &program.structs {
+            let fields = def
+                .fields
+                .iter()
+                .map(|field| match field.ty {
+                    Type::Var(_) => (field.name, None),
+                    ref ty => (field.name, Some(ty.clone())),
+                })
+                .collect();
+
+            self.structs.insert(def.name, fields);
+        }
+
+        for function in &program.functions {
+            self.functions.insert(
+                function.name,
+                (
+                    function
+                        .params
+                        .iter()
+                        .map(|param| param.ty.clone())
+                        .collect(),
+                    function.returns.clone(),
+                    function.linkage,
+                ),
+            );
+        }
+
+        let mut prototypes = String::new();
+        let mut definitions = String::new();
+        let mut main = None;
+        // Every instantiation of a generic function at the same types has the same name
+        let mut seen = HashSet::new();
+
+        for function in &program.functions {
+            if !seen.insert(function.name) {
+                continue;
+            }
+
+            let prototype = self.prototype(function)?;
+
+            if function.linkage == Linkage::External {
+                prototypes.push_str(&format!("extern {};\n", prototype));
+                continue;
+            }
+
+            if self.symbols.name(function.name) == "main" {
+                main = Some(function);
+            }
+
+            prototypes.push_str(&format!("{};\n", prototype));
+            definitions.push_str(&format!("\n{} ", prototype));
+            definitions.push_str(&self.gen_function(function)?);
+        }
+
+        let mut out = String::from(PRELUDE);
+
+        if !self.structs.is_empty() {
+            out.push('\n');
+
+            for name in self.struct_names(program) {
+                out.push_str(&format!("struct {};\n", self.struct_name(name)));
+            }
+        }
+
+        for (i, &(ref elem, len)) in self.arrays.iter().enumerate() {
+            out.push_str(&format!(
+                "\ntypedef struct {{\n    {};\n}} us_array_{};\n",
+                declare(elem, &format!("items[{}]", len)),
+                i
+            ));
+        }
+
+        for name in self.struct_names(program) {
+            out.push_str(&self.gen_struct(name)?);
+        }
+
+        let mut included = HashSet::new();
+
+        for &(name, uses, _) in HELPERS {
+            if self.helpers.contains(name) {
+                included.insert(name);
+                included.extend(uses.iter().cloned());
+            }
+        }
+
+        // A helper may use one that the program doesn't call directly
+        for &(name, uses, _) in HELPERS.iter().rev() {
+            if included.contains(name) {
+                included.extend(uses.iter().cloned());
+            }
+        }
+
+        for &(name, _, source) in HELPERS {
+            if included.contains(name) {
+                out.push_str(&format!("\n{}\n", source));
+            }
+        }
+
+        if !prototypes.is_empty() {
+            out.push('\n');
+            out.push_str(&prototypes);
+        }
+
+        out.push_str(&definitions);
+
+        // Without a main function the program does nothing, like it does in the vm
+        match main {
+            Some(main) => out.push_str(&self.gen_main(main)?),
+            None => out.push_str("\nint main(void) {\n    return 0;\n}\n"),
+        }
+
+        Ok(out)
+    }
+
+    /// The structs of the program, skipping any that are defined twice
+    fn struct_names(&self, program: &t::Program) -> Vec<Symbol> {
+        let mut seen = HashSet::new();
+
+        program
+            .structs
+            .iter()
+            .map(|def| def.name)
+            .filter(|name| seen.insert(*name))
+            .collect()
+    }
+
+    fn gen_struct(&mut self, name: Symbol) -> Result<String, CError> {
+        let mut out = format!("\nstruct {} {{\n", self.struct_name(name));
+        let fields = self.structs[&name].clone();
+
+        if fields.is_empty() {
+            out.push_str("    char empty;\n");
+        }
+
+        for (field, ty) in fields {
+            let ty = match ty {
+                Some(ty) => self.value_type(&ty)?,
+                None => "us_any".into(),
+            };
+
+            out.push_str(&format!("    {};\n", declare(&ty, &self.field_name(field))));
+        }
+
+        out.push_str("};\n");
+
+        Ok(out)
+    }
+
+    fn prototype(&mut self, function: &t::Function) -> Result<String, CError> {
+        let name = self.function_name(function.name);
+        let returns = self.c_type(&function.returns)?;
+
+        let mut params = Vec::with_capacity(function.params.len());
+
+        for param in &function.params {
+            params.push(self.value_type(&param.ty)?);
+        }
+
+        let params = if params.is_empty() {
+            "void".into()
+        } else if function.linkage == Linkage::External {
+            params.join(", ")
+        } else {
+            let mut named = Vec::with_capacity(params.len());
+
+            for (param, ty) in function.params.iter().zip(&params) {
+                // Params are the first locals so they always get the same name
+                named.push(declare(
+                    ty,
+                    &format!("{}_{}", escape(&self.symbols.name(param.name)), named.len()),
+                ));
+            }
+
+            named.join(", ")
+        };
+
+        Ok(format!("{}({})", declare(&returns, &name), params))
+    }
+
+    fn gen_function(&mut self, function: &t::Function) -> Result<String, CError> {
+        self.locals = 0;
+        self.returns = function.returns.clone();
+        self.scopes.push(HashMap::new());
+
+        for param in &function.params {
+            let name = self.local(param.name);
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(param.name, (name, param.ty.clone()));
+        }
+
+        let mut body = String::new();
+        self.gen_statement(&function.body, 1, &mut body)?;
+
+        self.scopes.pop();
+
+        // Falling off the end of a function returns zero
+        if !is_void(&function.returns) {
+            let ty = self.c_type(&function.returns)?;
+            body.push_str(&format!("\n    return ({}){{0}};\n", ty));
+        }
+
+        Ok(format!("{{\n{}}}\n", body))
+    }
+
+    /// Calls the program's main function, which gives the exit code if it returns an int
+    fn gen_main(&mut self, main: &t::Function) -> Result<String, CError> {
+        let mut args = Vec::with_capacity(main.params.len());
+
+        for param in &main.params {
+            args.push(format!("({}){{0}}", self.value_type(&param.ty)?));
+        }
+
+        let call = format!("{}({})", self.function_name(main.name), args.join(", "));
+
+        Ok(match main.returns {
+            Type::App(TyCon::Int(_, _), _) | Type::App(TyCon::Bool, _) => {
+                format!("\nint main(void) {{\n    return (int){};\n}}\n", call)
+            }
+            _ => format!("\nint main(void) {{\n    {};\n    return 0;\n}}\n", call),
+        })
+    }
+
+    fn gen_statement(
+        &mut self,
+        statement: &t::Statement,
+        depth: usize,
+        out: &mut String,
+    ) -> Result<(), CError> {
+        let indent = "    ".repeat(depth);
+
+        match *statement {
+            t::Statement::Block(ref statements) => {
+                self.scopes.push(HashMap::new());
+
+                for statement in statements {
+                    self.gen_statement(statement, depth, out)?;
+                }
+
+                self.scopes.pop();
+            }
+
+            t::Statement::Break => out.push_str(&format!("{}break;\n", indent)),
+
+            t::Statement::Continue => out.push_str(&format!("{}continue;\n", indent)),
+
+            t::Statement::Expr(ref expr) => match *expr.expr {
+                // Empty blocks are a nil expression
+                t::Expression::Literal(Literal::Nil) => (),
+                t::Expression::Call(_, _) | t::Expression::Assign(_, _) => {
+                    let expr = self.gen_expression(expr)?;
+                    out.push_str(&format!("{}{};\n", indent, strip(&expr)));
+                }
+                _ => {
+                    let expr = self.gen_expression(expr)?;
+                    out.push_str(&format!("{}(void){};\n", indent, expr));
+                }
+            },
+
+            t::Statement::If {
+                ref cond,
+                ref then,
+                ref otherwise,
+            } => {
+                let cond = self.gen_expression(cond)?;
+
+                out.push_str(&format!("{}if ({}) {{\n", indent, strip(&cond)));
+                self.gen_block(then, depth + 1, out)?;
+
+                if let Some(ref otherwise) = *otherwise {
+                    out.push_str(&format!("{}}} else {{\n", indent));
+                    self.gen_block(otherwise, depth + 1, out)?;
+                }
+
+                out.push_str(&format!("{}}}\n", indent));
+            }
+
+            t::Statement::Let {
+                ident,
+                ref ty,
+                ref expr,
+                ..
+            } => {
+                let ty = match *expr {
+                    Some(ref expr) if is_var(ty) || is_void(ty) => self.type_of(expr),
+                    _ => ty.clone(),
+                };
+
+                let value = match *expr {
+                    Some(ref expr) if is_void(&ty) => {
+                        // Only the side effects of a nil value matter
+                        if let t::Expression::Call(_, _) = *expr.expr {
+                            let call = self.gen_expression(expr)?;
+                            out.push_str(&format!("{}{};\n", indent, call));
+                        }
+
+                        "0".into()
+                    }
+                    Some(ref expr) => strip(&self.gen_expression(expr)?).to_string(),
+                    None => match ty {
+                        Type::Array(_, _) => "{0}".into(),
+                        _ => "0".into(),
+                    },
+                };
+
+                let c_type = self.value_type(&ty)?;
+                let name = self.local(ident);
+
+                out.push_str(&format!(
+                    "{}{} = {};\n",
+                    indent,
+                    declare(&c_type, &name),
+                    value
+                ));
+
+                self.scopes.last_mut().unwrap().insert(ident, (name, ty));
+            }
+
+            t::Statement::Return(ref expr) => {
+                if is_void(&self.returns) {
+                    if let t::Expression::Call(_, _) = *expr.expr {
+                        let call = self.gen_expression(expr)?;
+                        out.push_str(&format!("{}{};\n", indent, call));
+                    }
+
+                    out.push_str(&format!("{}return;\n", indent));
+                } else {
+                    let value = self.gen_expression(expr)?;
+                    out.push_str(&format!("{}return {};\n", indent, strip(&value)));
+                }
+            }
+
+            t::Statement::While(ref cond, ref body) => {
+                let cond = self.gen_expression(cond)?;
+
+                out.push_str(&format!("{}while ({}) {{\n", indent, strip(&cond)));
+                self.gen_block(body, depth + 1, out)?;
+                out.push_str(&format!("{}}}\n", indent));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The statements of a block whose braces were already written
+    fn gen_block(
+        &mut self,
+        statement: &t::Statement,
+        depth: usize,
+        out: &mut String,
+    ) -> Result<(), CError> {
+        self.scopes.push(HashMap::new());
+        let result = self.gen_statement(statement, depth, out);
+        self.scopes.pop();
+
+        result
+    }
+
+    /// Every expression is wrapped in parentheses so they can be nested without
+    /// worrying about precedence
+    fn gen_expression(&mut self, expr: &t::TypedExpression) -> Result<String, CError> {
+        match *expr.expr {
+            t::Expression::Array(ref items) => {
+                if items.is_empty() {
+                    return Err(CError::Unsupported("Empty arrays".into()));
+                }
+
+                let ty = self.value_type(&self.type_of(expr))?;
+                let mut values = Vec::with_capacity(items.len());
+
+                for item in items {
+                    values.push(strip(&self.gen_expression(item)?).to_string());
+                }
+
+                Ok(format!("(({}){{{{{}}}}})", ty, values.join(", ")))
+            }
+
+            t::Expression::Assign(ref var, ref value) => {
+                let target = self.gen_lvalue(var)?;
+                let value = match *var {
+                    // Generic fields are written through the member of the union for the value
+                    t::Var::Field(ident, field, _) if self.is_generic(ident, field) => {
+                        let ty = self.type_of(value);
+                        let value = self.gen_expression(value)?;
+
+                        return Ok(format!("({}.{} = {})", target, member(&ty)?, strip(&value)));
+                    }
+                    _ => self.gen_expression(value)?,
+                };
+
+                Ok(format!("({} = {})", target, strip(&value)))
+            }
+
+            t::Expression::Binary(ref lhs, op, ref rhs) => self.gen_binary(expr, lhs, op, rhs),
+
+            t::Expression::Cast(ref from, ref ty) => {
+                let ty = self.value_type(ty)?;
+                let from = self.gen_expression(from)?;
+
+                Ok(format!("(({}){})", ty, from))
+            }
+
+            t::Expression::Call(name, ref args) => self.gen_call(name, args),
+
+            t::Expression::Closure(_) => Err(CError::Unsupported("Closures".into())),
+
+            t::Expression::Grouping { ref expr } => self.gen_expression(expr),
+
+            t::Expression::Literal(ref literal) => Ok(match *literal {
+                Literal::Number(ref number) if number.value <= i32::MAX as u64 => {
+                    format!("{}", number.value)
+                }
+                Literal::Number(ref number) => format!(
+                    "(({}){}ULL)",
+                    self.value_type(&self.type_of(expr))?,
+                    number.value
+                ),
+                Literal::Char(ch) => format!("((uint8_t){})", ch as u64),
+                Literal::True(_) => "true".into(),
+                Literal::False(_) => "false".into(),
+                Literal::Nil => "0".into(),
+                Literal::Str(ref string) => quote(string),
+            }),
+
+            t::Expression::StructLit(name, ref values) => {
+                let c_name = format!("struct {}", self.struct_name(name));
+                let fields = match self.structs.get(&name) {
+                    Some(fields) => fields.clone(),
+                    None => {
+                        return Err(CError::Unsupported(format!(
+                            "The undefined struct `{}`",
+                            self.symbols.name(name)
+                        )))
+                    }
+                };
+
+                let mut inits = Vec::with_capacity(values.len());
+
+                for (&(field, ref ty), value) in fields.iter().zip(values) {
+                    let field = self.field_name(field);
+                    let ty_of_value = self.type_of(value);
+                    let value = strip(&self.gen_expression(value)?).to_string();
+
+                    if ty.is_none() {
+                        inits.push(format!(
+                            ".{} = {{.{} = {}}}",
+                            field,
+                            member(&ty_of_value)?,
+                            value
+                        ));
+                    } else {
+                        inits.push(format!(".{} = {}", field, value));
+                    }
+                }
+
+                self.helpers.insert("us_rt_box");
+
+                Ok(format!(
+                    "(({} *)us_rt_box(&({}){{{}}}, sizeof({})))",
+                    c_name,
+                    c_name,
+                    inits.join(", "),
+                    c_name
+                ))
+            }
+
+            t::Expression::Unary(UnaryOp::Bang, ref value) => {
+                Ok(format!("(!{})", self.gen_expression(value)?))
+            }
+
+            t::Expression::Unary(UnaryOp::Minus, ref value) => {
+                let ty = self.type_of(expr);
+                let value = self.gen_expression(value)?;
+                let (signed, unsigned) = int_types(&ty);
+
+                Ok(format!("(({})-({}){})", signed, unsigned, value))
+            }
+
+            t::Expression::Var(ref var) => self.gen_var(var),
+
+            t::Expression::Field(_, _) | t::Expression::Index(_, _) => Err(CError::Unsupported(
+                "Field and index expressions outside of a variable".into(),
+            )),
+        }
+    }
+
+    fn gen_binary(
+        &mut self,
+        expr: &t::TypedExpression,
+        lhs: &t::TypedExpression,
+        op: Op,
+        rhs: &t::TypedExpression,
+    ) -> Result<String, CError> {
+        let is_string = matches!(
+            (self.type_of(lhs), self.type_of(rhs)),
+            (Type::App(TyCon::String, _), Type::App(TyCon::String, _))
+        );
+
+        let (l, r) = (self.gen_expression(lhs)?, self.gen_expression(rhs)?);
+
+        Ok(match op {
+            Op::And => format!("({} && {})", l, r),
+            Op::Or => format!("({} || {})", l, r),
+
+            Op::Equal | Op::NEq if is_string => {
+                let cmp = if op == Op::Equal { "==" } else { "!=" };
+                format!("(strcmp({}, {}) {} 0)", strip(&l), strip(&r), cmp)
+            }
+
+            Op::Equal => format!("({} == {})", l, r),
+            Op::NEq => format!("({} != {})", l, r),
+            Op::LT => format!("({} < {})", l, r),
+            Op::LTE => format!("({} <= {})", l, r),
+            Op::GT => format!("({} > {})", l, r),
+            Op::GTE => format!("({} >= {})", l, r),
+
+            Op::Plus if is_string => {
+                self.helpers.insert("us_rt_concat");
+                format!("us_rt_concat({}, {})", strip(&l), strip(&r))
+            }
+
+            _ if is_string => return Err(CError::Unsupported(format!("`{:?}` on strings", op))),
+
+            Op::Slash => {
+                let ty = self.value_type(&self.type_of(expr))?;
+
+                self.helpers.insert("us_rt_divisor");
+
+                format!("(({})({} / ({})us_rt_divisor((uint64_t){})))", ty, l, ty, r)
+            }
+
+            Op::Plus | Op::Minus | Op::Star => {
+                // Unsigned arithmetic wraps instead of overflowing
+                let (signed, unsigned) = int_types(&self.type_of(expr));
+                let op = match op {
+                    Op::Plus => "+",
+                    Op::Minus => "-",
+                    _ => "*",
+                };
+
+                format!(
+                    "(({})(({}){} {} ({}){}))",
+                    signed, unsigned, l, op, unsigned, r
+                )
+            }
+        })
+    }
+
+    fn gen_call(&mut self, name: Symbol, args: &[t::TypedExpression]) -> Result<String, CError> {
+        let mut values = Vec::with_capacity(args.len());
+
+        for arg in args {
+            values.push(strip(&self.gen_expression(arg)?).to_string());
+        }
+
+        if self.lookup(name).is_some() {
+            return Err(CError::Unsupported("Calling a closure".into()));
+        }
+
+        if self.functions.contains_key(&name) {
+            return Ok(format!(
+                "{}({})",
+                self.function_name(name),
+                values.join(", ")
+            ));
+        }
+
+        let tys: Vec<Type> = args.iter().map(|arg| self.type_of(arg)).collect();
+
+        let helper = match prelude::intrinsic(&self.symbols.name(name), &tys) {
+            Some(Intrinsic::PrintInt) => match tys[0] {
+                Type::App(TyCon::Int(Sign::Unsigned, _), _) => "us_rt_print_uint",
+                Type::App(TyCon::Int(_, _), _) | Type::Var(_) => "us_rt_print_int",
+                ref ty => {
+                    return Err(CError::Unsupported(format!(
+                        "Printing a value of type `{}`",
+                        ty
+                    )))
+                }
+            },
+            Some(Intrinsic::PrintBool) => "us_rt_print_bool",
+            Some(Intrinsic::PrintChar) => "us_rt_print_char",
+            Some(Intrinsic::PrintStr) => "us_rt_print_str",
+            Some(Intrinsic::Assert) => "us_rt_assert",
+            Some(Intrinsic::Exit) => "us_rt_exit",
+            Some(Intrinsic::StrLen) => "us_rt_len",
+            Some(Intrinsic::StrEq) | Some(Intrinsic::StrConcat) | None => {
+                return Err(CError::Unsupported(format!(
+                    "Call to undefined function `{}`",
+                    self.symbols.name(name)
+                )))
+            }
+        };
+
+        self.helpers.insert(helper);
+
+        Ok(format!("{}({})", helper, values.join(", ")))
+    }
+
+    fn gen_var(&mut self, var: &t::Var) -> Result<String, CError> {
+        match *var {
+            t::Var::Field(ident, field, ref ty) if self.is_generic(ident, field) => {
+                let target = self.gen_lvalue(var)?;
+                let c_type = self.value_type(ty)?;
+
+                Ok(format!("(({}){}.{})", c_type, target, member(ty)?))
+            }
+
+            t::Var::SubScript(ident, _, _) => {
+                let target = self.gen_lvalue(var)?;
+
+                match self.lookup(ident) {
+                    Some((_, Type::App(TyCon::String, _))) => Ok(format!("((uint8_t){})", target)),
+                    _ => Ok(target),
+                }
+            }
+
+            _ => self.gen_lvalue(var),
+        }
+    }
+
+    /// A variable as something that can be assigned to. Generic fields are the union
+    /// rather than one of its members
+    fn gen_lvalue(&mut self, var: &t::Var) -> Result<String, CError> {
+        match *var {
+            t::Var::Simple(ident, _) => self.variable(ident).map(|(name, _)| name),
+
+            t::Var::Field(ident, field, _) => {
+                let (name, _) = self.variable(ident)?;
+                Ok(format!("{}->{}", name, self.field_name(field)))
+            }
+
+            t::Var::SubScript(ident, ref index, _) => {
+                let (name, ty) = self.variable(ident)?;
+                let index = strip(&self.gen_expression(index)?).to_string();
+
+                match ty {
+                    Type::Array(_, _) => Ok(format!("{}.items[{}]", name, index)),
+                    _ => Ok(format!("{}[{}]", name, index)),
+                }
+            }
+        }
+    }
+
+    /// Whether a field of the struct held in a variable has a type parameter as its type
+    fn is_generic(&self, ident: Symbol, field: Symbol) -> bool {
+        let name = match self.lookup(ident) {
+            Some((_, Type::Struct(name, _, _))) => name,
+            _ => return false,
+        };
+
+        self.structs
+            .get(&name)
+            .and_then(|fields| fields.iter().find(|&&(f, _)| f == field))
+            .map(|(_, ty)| ty.is_none())
+            .unwrap_or(false)
+    }
+
+    fn lookup(&self, ident: Symbol) -> Option<(String, Type)> {
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|scope| scope.get(&ident))
+            .next()
+            .cloned()
+    }
+
+    fn variable(&self, ident: Symbol) -> Result<(String, Type), CError> {
+        self.lookup(ident).ok_or_else(|| {
+            CError::Unsupported(format!("`{}` used as a value", self.symbols.name(ident)))
+        })
+    }
+
+    /// The type of an expression, looking through the type variables of generic
+    /// functions whose bodies were copied without being substituted
+    fn type_of(&self, expr: &t::TypedExpression) -> Type {
+        if !is_var(&expr.ty) {
+            return expr.ty.clone();
+        }
+
+        match *expr.expr {
+            t::Expression::Var(t::Var::Simple(ident, _)) => self
+                .lookup(ident)
+                .map(|(_, ty)| ty)
+                .unwrap_or_else(|| expr.ty.clone()),
+            t::Expression::Call(name, _) => self
+                .functions
+                .get(&name)
+                .map(|function| function.1.clone())
+                .unwrap_or_else(|| expr.ty.clone()),
+            t::Expression::Binary(ref lhs, _, _) => self.type_of(lhs),
+            t::Expression::Grouping { ref expr }
+            | t::Expression::Unary(UnaryOp::Minus, ref expr)
+            | t::Expression::Assign(_, ref expr) => self.type_of(expr),
+            _ => expr.ty.clone(),
+        }
+    }
+
+    /// The C type of a value that is returned, which is `void` for nil
+    fn c_type(&mut self, ty: &Type) -> Result<String, CError> {
+        if is_void(ty) {
+            return Ok("void".into());
+        }
+
+        self.value_type(ty)
+    }
+
+    /// The C type of a value that is stored somewhere
+    fn value_type(&mut self, ty: &Type) -> Result<String, CError> {
+        Ok(match *ty {
+            Type::App(TyCon::Int(sign, size), _) => int_type(sign, size).into(),
+            Type::App(TyCon::Bool, _) => "bool".into(),
+            Type::App(TyCon::Char, _) => "char".into(),
+            Type::App(TyCon::String, _) => "const char *".into(),
+            Type::App(TyCon::Void, _) | Type::Nil => "void *".into(),
+            // Number literals whose type was never pinned down are i32
+            Type::Var(_) => "int32_t".into(),
+            Type::Struct(name, _, _) => format!("struct {} *", self.struct_name(name)),
+            Type::Array(ref elem, len) => {
+                if len == 0 {
+                    return Err(CError::Unsupported("Empty arrays".into()));
+                }
+
+                let elem = self.value_type(elem)?;
+
+                let index = match self
+                    .arrays
+                    .iter()
+                    .position(|array| array.0 == elem && array.1 == len)
+                {
+                    Some(index) => index,
+                    None => {
+                        self.arrays.push((elem, len));
+                        self.arrays.len() - 1
+                    }
+                };
+
+                format!("us_array_{}", index)
+            }
+            ref ty => return Err(CError::Unsupported(format!("Values of type `{}`", ty))),
+        })
+    }
+
+    fn function_name(&self, name: Symbol) -> String {
+        match self.functions.get(&name) {
+            Some(&(_, _, Linkage::External)) => self.symbols.name(name),
+            _ => format!("us_{}", escape(&self.symbols.name(name))),
+        }
+    }
+
+    fn struct_name(&self, name: Symbol) -> String {
+        format!("us_{}", escape(&self.symbols.name(name)))
+    }
+
+    /// Fields keep their name unless it is a keyword
+    fn field_name(&self, field: Symbol) -> String {
+        let name = self.symbols.name(field);
+
+        if KEYWORDS.contains(&name.as_str()) {
+            format!("{}_", name)
+        } else {
+            name
+        }
+    }
+
+    /// A new name for a local, which can't clash with any other local of the function
+    fn local(&mut self, ident: Symbol) -> String {
+        let name = format!("{}_{}", escape(&self.symbols.name(ident)), self.locals);
+        self.locals += 1;
+        name
+    }
+}
+
+fn is_var(ty: &Type) -> bool {
+    matches!(*ty, Type::Var(_))
+}
+
+fn is_void(ty: &Type) -> bool {
+    matches!(*ty, Type::Nil | Type::App(TyCon::Void, _))
+}
+
+fn int_type(sign: Sign, size: Size) -> &'static str {
+    match (sign, size) {
+        (Sign::Signed, Size::Bit8) => "int8_t",
+        (Sign::Signed, Size::Bit32) => "int32_t",
+        (Sign::Signed, Size::Bit64) => "int64_t",
+        (Sign::Unsigned, Size::Bit8) => "uint8_t",
+        (Sign::Unsigned, Size::Bit32) => "uint32_t",
+        (Sign::Unsigned, Size::Bit64) => "uint64_t",
+    }
+}
+
+/// An int type and the unsigned type of the same width
+fn int_types(ty: &Type) -> (&'static str, &'static str) {
+    let (sign, size) = match *ty {
+        Type::App(TyCon::Int(sign, size), _) => (sign, size),
+        _ => (Sign::Signed, Size::Bit32),
+    };
+
+    (int_type(sign, size), int_type(Sign::Unsigned, size))
+}
+
+/// The member of `us_any` that holds a value of `ty`
+fn member(ty: &Type) -> Result<&'static str, CError> {
+    match *ty {
+        Type::App(TyCon::Int(Sign::Unsigned, _), _) => Ok("u"),
+        Type::App(TyCon::Int(Sign::Signed, _), _) | Type::App(TyCon::Char, _) | Type::Var(_) => {
+            Ok("i")
+        }
+        Type::App(TyCon::Bool, _) => Ok("b"),
+        Type::App(TyCon::String, _) => Ok("s"),
+        Type::Struct(_, _, _) | Type::Nil | Type::App(TyCon::Void, _) => Ok("p"),
+        ref ty => Err(CError::Unsupported(format!(
+            "Storing a value of type `{}` in a generic field",
+            ty
+        ))),
+    }
+}
+
+/// Declares `name` with the type `ty`, keeping pointers next to the name
+fn declare(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+/// Hex escapes anything that isn't allowed in an identifier. Underscores are doubled
+/// so an escape can't be mistaken for part of the name
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            escaped.push(c);
+        } else if c == '_' {
+            escaped.push_str("__");
+        } else {
+            escaped.push_str(&format!("_x{:x}_", c as u32));
+        }
+    }
+
+    escaped
+}
+
+/// A C string literal. Octal escapes are used as they can't run into the next character
+fn quote(string: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for byte in string.bytes() {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'?' => quoted.push_str("\\?"),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Removes the parentheses around an expression when they wrap all of it
+fn strip(expr: &str) -> &str {
+    if !expr.starts_with('(') || !expr.ends_with(')') {
+        return expr;
+    }
+
+    let bytes = expr.as_bytes();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'(' if !in_string => depth += 1,
+            b')' if !in_string => {
+                depth -= 1;
+
+                if depth == 0 && i + 1 != bytes.len() {
+                    return expr;
+                }
+            }
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    &expr[1..expr.len() - 1]
+}
+
+#[cfg(test)]
+mod test {
+    use super::CCodegen;
+    use env::Env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::process::Command;
+    use std::rc::Rc;
+    use syntax::lexer::Lexer;
+    use syntax::parser::Parser;
+    use util::emitter::Reporter;
+    use util::symbol::{SymbolMap, Symbols};
+    use Infer;
+
+    fn translate(source: &str) -> String {
+        let reporter = Reporter::new();
+        let strings = Rc::new(SymbolMap::new());
+        let mut table = Symbols::new(Rc::clone(&strings));
+
+        let tokens = Lexer::new(source, reporter.clone()).lex().unwrap();
+        let mut ast = Parser::new(tokens, reporter.clone(), &mut table)
+            .parse()
+            .unwrap();
+
+        let mut env = Env::new(&strings);
+        let program = Infer::new()
+            .infer(&mut ast, &mut env, &mut reporter.clone())
+            .unwrap();
+
+        CCodegen::new(Symbols::new(strings))
+            .gen_program(&program)
+            .unwrap()
+    }
+
+    /// Compiles the translated source with a strict C compiler and returns what it prints
+    fn run(source: &str) -> String {
+        let source = translate(source);
+
+        let path = ::std::env::temp_dir().join(format!(
+            "underscore_c_{}_{:?}",
+            ::std::process::id(),
+            ::std::thread::current().id()
+        ));
+        let c = path.with_extension("c");
+
+        File::create(&c)
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+
+        let status = Command::new("cc")
+            .args(["-std=c99", "-pedantic-errors", "-Wall", "-Werror", "-o"])
+            .arg(&path)
+            .arg(&c)
+            .status()
+            .unwrap();
+
+        let _ = ::std::fs::remove_file(&c);
+
+        assert!(status.success(), "{}", source);
+
+        let output = Command::new(&path).output().unwrap();
+        let _ = ::std::fs::remove_file(&path);
+
+        assert!(output.status.success(), "{}", source);
+
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    #[test]
+    fn structs() {
+        let output = run("struct Point { x: i32, y: i32 }
+             struct Box<T> { value: T }
+
+             fn area(p: Point) -> i32 {
+                 return p.x * p.y;
+             }
+
+             fn main() -> i32 {
+                 let p = Point { x: 6, y: 7 };
+                 let b = Box { value: \"boxed\" };
+                 p.y = p.y + 1;
+                 print(area(p));
+                 print(b.value);
+                 return 0;
+             }");
+
+        assert_eq!(output, "48\nboxed\n");
+    }
+
+    #[test]
+    fn arrays() {
+        let output = run("fn main() -> i32 {
+                 let a = [1u8, 2u8, 255u8];
+                 a[0] = a[2] + a[1];
+                 print(a[0]);
+                 print(a[2]);
+                 print(10 / 3);
+                 return 0;
+             }");
+
+        assert_eq!(output, "1\n255\n3\n");
+    }
+
+    #[test]
+    fn extern_prototypes() {
+        let source = "external fn labs(x: i64) -> i64 {}
+
+             fn main() -> i32 {
+                 print(labs(-3));
+                 return 0;
+             }";
+
+        let translated = translate(source);
+
+        // Declared for the linker without a definition
+        assert_eq!(translated.matches("labs(int64_t").count(), 1);
+        assert!(translated.contains("extern int64_t labs(int64_t);"));
+        assert_eq!(run(source), "3\n");
+    }
+}
@@ -2,10 +2,18 @@ use ast::typed as t;
 use ir::{ir,
               optimize::Optimizer,
               ir::{new_label_pair, new_named_label, Label, Temp}};
+use layout::{layout, Layout};
+use prelude;
+use std::collections::{HashMap, HashSet};
 use std::u64;
-use syntax::ast::{Literal, Op, Sign, Size, UnaryOp};
-use types::{TyCon, Type};
-use util::symbol::Symbols;
+use syntax::ast::{Linkage, Literal, Op, Sign, Size, UnaryOp};
+use types::{Field, TyCon, Type};
+use util::symbol::{Symbol, Symbols};
+
+#[derive(Debug)]
+pub enum CodegenError {
+    Unsupported(String),
+}
 
 #[derive(Debug)]
 pub struct Codegen {
@@ -13,9 +21,36 @@ pub struct Codegen {
     loop_label: Option<Label>,
     loop_break_label: Option<Label>,
     symbols: Symbols<Temp>,
+    /// The functions closures lower to, added to the program after the rest
+    closures: Vec<ir::Function>,
+    /// One context for each function being lowered, innermost last
+    contexts: Vec<Context>,
+    /// The functions the program defines, which hide any prelude function of the same name
+    defined: HashSet<Symbol>,
+    /// The fields each struct is declared with
+    structs: HashMap<Symbol, Vec<Field>>,
+    /// The type of the variable each temp is bound to
+    types: HashMap<Temp, Type>,
+    /// The first construct that couldn't be lowered
+    error: Option<CodegenError>,
 }
 
-const HP: Temp = Temp(0);
+/// What is known about the function currently being lowered
+#[derive(Debug, Default)]
+struct Context {
+    /// The temps the function's params and lets are bound to
+    temps: HashSet<Temp>,
+    /// The temps of enclosing functions the function captures
+    upvalues: Vec<Temp>,
+    /// Where the enclosing function finds each upvalue
+    captures: Vec<ir::Capture>,
+    /// The sign and width of each upvalue
+    types: Vec<(Sign, Size)>,
+    /// The escaping temps declared in each enclosing block
+    scopes: Vec<Vec<Temp>>,
+    /// How many blocks deep the innermost loop is
+    loop_scope: usize,
+}
 
 impl Codegen {
     pub fn new(symbols: Symbols<Temp>) -> Self {
@@ -24,6 +59,12 @@ impl Codegen {
             loop_label: None,
             loop_break_label: None,
             instructions: vec![],
+            closures: vec![],
+            contexts: vec![],
+            defined: HashSet::new(),
+            structs: HashMap::new(),
+            types: HashMap::new(),
+            error: None,
         }
     }
 
@@ -34,7 +75,7 @@ impl Codegen {
         let mut file = File::create(path).expect("Couldn't create file");
 
         for instruction in &self.instructions {
-            file.write(instruction.fmt(&mut self.symbols).as_bytes())
+            file.write(format!("{}\n", instruction.print(&self.symbols)).as_bytes())
                 .expect("Couldn't write to the file");
         }
 
@@ -42,34 +83,157 @@ impl Codegen {
             .expect("Couldn't write to the file");
     }
 
-    pub fn gen_program(&mut self, program: t::Program) -> ir::Program {
+    pub fn gen_program(&mut self, program: t::Program) -> Result<ir::Program, CodegenError> {
         let mut lowered = ir::Program {
             functions: Vec::new(),
         };
 
+        self.defined = program.functions.iter().map(|function| function.name).collect();
+        self.structs = program
+            .structs
+            .iter()
+            .map(|def| (def.name, def.fields.clone()))
+            .collect();
+
         for function in program.functions {
-            let mut instructions = vec![];
-            self.gen_function(&function, &mut instructions);
+            let (function, _) = self.lower_function(&function);
+            lowered.functions.push(function);
+        }
 
-            Optimizer::strength_reduction(&mut instructions);
-            Optimizer::unused_labels(&mut vec![], &mut instructions);
+        lowered.functions.append(&mut self.closures);
 
-            lowered.functions.push(ir::Function {
-                name: function.name,
-                body: instructions,
-                linkage: function.linkage,
-            });
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(lowered),
         }
+    }
+
+    /// Records that something can't be lowered. Lowering carries on so the rest of the
+    /// program is still walked but its output is thrown away
+    fn unsupported(&mut self, what: &str) {
+        if self.error.is_none() {
+            self.error = Some(CodegenError::Unsupported(what.into()));
+        }
+    }
+
+    /// Lowers a function in a context of its own. The context is returned so a
+    /// closure knows what it captured
+    fn lower_function(&mut self, function: &t::Function) -> (ir::Function, Context) {
+        let loop_labels = (self.loop_label.take(), self.loop_break_label.take());
+
+        self.contexts.push(Context::default());
+
+        let mut instructions = vec![];
+        let params = self.gen_function(function, &mut instructions);
+
+        let context = self.contexts.pop().unwrap();
+
+        self.loop_label = loop_labels.0;
+        self.loop_break_label = loop_labels.1;
+
+        Optimizer::strength_reduction(&mut instructions);
+        Optimizer::unused_labels(&mut vec![], &mut instructions);
+
+        let returns = scalar(&function.returns);
+
+        // A body that runs off the end returns zero, which is spelt out so that every
+        // function ends with a jump or a return
+        if function.linkage == Linkage::Normal {
+            match instructions.last() {
+                Some(&ir::Instruction::Jump(_))
+                | Some(&ir::Instruction::CJump(_, _, _, _, _))
+                | Some(&ir::Instruction::Return(_)) => (),
+                _ => {
+                    let zero = Temp::new();
+                    instructions.push(ir::Instruction::Store(
+                        zero,
+                        ir::Value::Const(0, returns.0, returns.1),
+                    ));
+                    instructions.push(ir::Instruction::Return(zero));
+                }
+            }
+        }
+
+        let lowered = ir::Function {
+            name: function.name,
+            params,
+            param_types: function.params.iter().map(|param| scalar(&param.ty)).collect(),
+            returns,
+            upvalues: context.types.clone(),
+            body: instructions,
+            linkage: function.linkage,
+        };
+
+        (lowered, context)
+    }
+
+    fn context(&mut self) -> &mut Context {
+        self.contexts.last_mut().expect("Lowering outside of a function")
+    }
+
+    /// Finds how the function at `depth` reaches a temp, capturing it as an
+    /// upvalue when it belongs to an enclosing function
+    fn resolve(&mut self, depth: usize, temp: Temp, ty: (Sign, Size)) -> ir::Capture {
+        if depth == 0 || self.contexts[depth].temps.contains(&temp) {
+            return ir::Capture::Local(temp);
+        }
+
+        if let Some(index) = self.contexts[depth].upvalues.iter().position(|t| *t == temp) {
+            return ir::Capture::Upvalue(index);
+        }
+
+        let capture = self.resolve(depth - 1, temp, ty);
+
+        let context = &mut self.contexts[depth];
+
+        context.upvalues.push(temp);
+        context.captures.push(capture);
+        context.types.push(ty);
+
+        ir::Capture::Upvalue(context.upvalues.len() - 1)
+    }
+
+    fn resolve_var(&mut self, symbol: Symbol, ty: (Sign, Size)) -> ir::Capture {
+        let temp = *self.symbols.look(symbol).unwrap();
+        let depth = self.contexts.len() - 1;
+
+        self.resolve(depth, temp, ty)
+    }
 
-        lowered
+    /// Closes the escaping temps of every block from `scope` inwards
+    fn close_scopes(&mut self, scope: usize, instructions: &mut Vec<ir::Instruction>) {
+        for temps in &self.context().scopes[scope..] {
+            for temp in temps.iter().rev() {
+                instructions.push(ir::Instruction::CloseUpvalue(*temp))
+            }
+        }
     }
 
-    fn gen_function(&mut self, func: &t::Function, instructions: &mut Vec<ir::Instruction>) {
+    fn gen_function(
+        &mut self,
+        func: &t::Function,
+        instructions: &mut Vec<ir::Instruction>,
+    ) -> Vec<Temp> {
+        let mut params = Vec::with_capacity(func.params.len());
+
+        self.symbols.begin_scope();
+
         for param in &func.params {
-            self.symbols.enter(param.name, Temp::new());
+            let temp = Temp::new();
+            self.symbols.enter(param.name, temp);
+            self.context().temps.insert(temp);
+            self.types.insert(temp, param.ty.clone());
+            params.push(temp);
+        }
+
+        // The body of an external function comes from the library it is linked against
+        if func.linkage == Linkage::Normal {
+            self.gen_statement(&func.body, instructions);
         }
 
-        self.gen_statement(&func.body, instructions);
+        self.symbols.end_scope();
+
+        params
     }
 
     fn gen_statement(&mut self, statement: &t::Statement, instructions: &mut Vec<ir::Instruction>) {
@@ -79,47 +243,58 @@ impl Codegen {
                 // instructions.push(ir::Instruction::Label(start));
 
                 self.symbols.begin_scope();
+                self.context().scopes.push(vec![]);
+
                 for statement in statements {
                     self.gen_statement(statement, instructions)
                 }
+
+                let scope = self.context().scopes.len() - 1;
+                self.close_scopes(scope, instructions);
+
+                self.context().scopes.pop();
                 self.symbols.end_scope();
                 // instructions.push(ir::Instruction::Label(end));
             }
 
-            t::Statement::Break => instructions.push(ir::Instruction::Jump(
-                self.loop_break_label.expect("Using continue out side loop"),
-            )),
+            t::Statement::Break => {
+                let scope = self.context().loop_scope;
+                self.close_scopes(scope, instructions);
+
+                instructions.push(ir::Instruction::Jump(
+                    self.loop_break_label.expect("Using continue out side loop"),
+                ))
+            }
+
+            t::Statement::Continue => {
+                let scope = self.context().loop_scope;
+                self.close_scopes(scope, instructions);
 
-            t::Statement::Continue => instructions.push(ir::Instruction::Jump(
-                self.loop_label.expect("Using continue out side loop"),
-            )),
+                instructions.push(ir::Instruction::Jump(
+                    self.loop_label.expect("Using continue out side loop"),
+                ))
+            }
             t::Statement::Let {
                 ref ident,
                 ref ty,
                 ref expr,
-                ..
+                escapes,
             } => {
                 let id_temp = Temp::new();
-                self.symbols.enter(*ident, id_temp);
 
                 if let Some(ref expr) = *expr {
-                    let id_temp = Temp::new();
-                    self.symbols.enter(*ident, id_temp);
-
-                    match *ty {
-                        Type::Array(_, ref len) => {
-                            instructions.push(ir::Instruction::Copy(HP, id_temp));
-
-                            let temp = Temp::new();
-
-                            instructions.push(ir::Instruction::Store(
-                                temp,
-                                ir::Value::Const(4 * (*len as u64), Sign::Unsigned, Size::Bit64),
-                            ));
+                    self.gen_expression(expr, id_temp, instructions);
+                }
 
-                            instructions.push(ir::Instruction::BinOp(HP,ir::BinOp::Plus,  temp, HP));
-                        }
-                        _ => self.gen_expression(expr, id_temp, instructions),
+                self.symbols.enter(*ident, id_temp);
+                self.context().temps.insert(id_temp);
+                self.types.insert(id_temp, ty.clone());
+
+                // Escaping lets stay in their slot until the block ends and are then
+                // moved into the upvalue of any closure that captured them
+                if escapes {
+                    if let Some(scope) = self.context().scopes.last_mut() {
+                        scope.push(id_temp);
                     }
                 }
             }
@@ -165,8 +340,11 @@ impl Codegen {
                 let ltrue = new_named_label("while_true", &mut self.symbols);
                 let lfalse = new_named_label("while_false", &mut self.symbols);
 
+                let enclosing = (self.loop_label, self.loop_break_label, self.context().loop_scope);
+
                 self.loop_break_label = Some(end);
                 self.loop_label = Some(lbody);
+                self.context().loop_scope = self.context().scopes.len();
 
                 instructions.push(ir::Instruction::Label(start));
 
@@ -183,6 +361,10 @@ impl Codegen {
                 instructions.push(ir::Instruction::Label(lfalse));
 
                 instructions.push(ir::Instruction::Label(end));
+
+                self.loop_label = enclosing.0;
+                self.loop_break_label = enclosing.1;
+                self.context().loop_scope = enclosing.2;
             }
 
             t::Statement::Return(ref expr) => {
@@ -214,6 +396,26 @@ impl Codegen {
 
                 instructions.push(ir::Instruction::Block(temp, block))
             }
+            t::Expression::Assign(t::Var::Simple(ref symbol, ref ty), ref value) => {
+                match self.resolve_var(*symbol, scalar(ty)) {
+                    ir::Capture::Local(temp) => self.gen_expression(value, temp, instructions),
+                    ir::Capture::Upvalue(index) => {
+                        let temp = Temp::new();
+
+                        self.gen_expression(value, temp, instructions);
+
+                        instructions.push(ir::Instruction::SetUpvalue(index, temp))
+                    }
+                }
+            }
+            t::Expression::Assign(t::Var::Field(var, field, _), ref value) => {
+                let (base, offset, (sign, size)) = self.field(var, field, instructions);
+                let from = Temp::new();
+
+                self.gen_expression(value, from, instructions);
+
+                instructions.push(ir::Instruction::StoreAt(base, offset, from, sign, size))
+            }
             t::Expression::Assign(ref name, ref value) => {
                 let temp = self.gen_var(name, instructions);
 
@@ -228,6 +430,17 @@ impl Codegen {
                 let rhs_temp = Temp::new();
 
                 match *op {
+                    // Adding strings joins them together
+                    Op::Plus if is_string(&lhs.ty) => {
+                        self.gen_expression(lhs, lhs_temp, instructions);
+                        self.gen_expression(rhs, rhs_temp, instructions);
+                        instructions.push(ir::Instruction::Intrinsic(
+                            temp,
+                            ir::Intrinsic::StrConcat,
+                            vec![lhs_temp, rhs_temp],
+                        ));
+                    }
+
                     Op::Plus | Op::Minus | Op::Slash | Op::Star => {
                         self.gen_expression(lhs, lhs_temp, instructions);
                         self.gen_expression(rhs, rhs_temp, instructions);
@@ -238,8 +451,24 @@ impl Codegen {
                     _ => {
                         let ltrue = new_named_label("ltrue", &mut self.symbols);
                         let lfalse = new_named_label("lfalse", &mut self.symbols);
+                        let lend = new_named_label("lend", &mut self.symbols);
+
+                        self.gen_cond(expr, ltrue, lfalse, instructions);
 
-                        self.gen_cond(expr, ltrue, lfalse, instructions)
+                        instructions.push(ir::Instruction::Label(ltrue));
+                        instructions.push(ir::Instruction::Store(
+                            temp,
+                            ir::Value::Const(true as u64, Sign::Unsigned, Size::Bit8),
+                        ));
+                        instructions.push(ir::Instruction::Jump(lend));
+
+                        instructions.push(ir::Instruction::Label(lfalse));
+                        instructions.push(ir::Instruction::Store(
+                            temp,
+                            ir::Value::Const(false as u64, Sign::Unsigned, Size::Bit8),
+                        ));
+
+                        instructions.push(ir::Instruction::Label(lend));
                     }
                 }
             }
@@ -253,16 +482,54 @@ impl Codegen {
                     params.push(temp)
                 }
 
-                instructions.push(ir::Instruction::Call(temp, *name, params))
+                if self.symbols.look(*name).is_none() && !self.defined.contains(name) {
+                    let tys: Vec<Type> = exprs.iter().map(|expr| expr.ty.clone()).collect();
+
+                    if let Some(intrinsic) = prelude::intrinsic(&self.symbols.name(*name), &tys) {
+                        instructions.push(ir::Instruction::Intrinsic(temp, intrinsic, params));
+                        return;
+                    }
+                }
+
+                // Calling a variable rather than a function calls the closure it holds
+                if self.symbols.look(*name).is_none() {
+                    instructions.push(ir::Instruction::Call(temp, *name, params));
+                    return;
+                }
+
+                let callee = match self.resolve_var(*name, (Sign::Unsigned, Size::Bit64)) {
+                    ir::Capture::Local(callee) => callee,
+                    ir::Capture::Upvalue(index) => {
+                        let callee = Temp::new();
+                        instructions.push(ir::Instruction::GetUpvalue(callee, index));
+                        callee
+                    }
+                };
+
+                instructions.push(ir::Instruction::CallClosure(
+                    temp,
+                    callee,
+                    params,
+                    scalar(&expr.ty),
+                ))
+            }
+
+            t::Expression::Closure(ref closure) => {
+                let (function, context) = self.lower_function(closure);
+
+                self.closures.push(function);
+
+                instructions.push(ir::Instruction::Closure(temp, closure.name, context.captures))
             }
 
             t::Expression::Cast(ref from, _) => {
-                let temp = Temp::new();
-                self.gen_expression(from, temp, instructions);
+                let from_temp = Temp::new();
+
+                self.gen_expression(from, from_temp, instructions);
 
                 match expr.ty {
                     Type::App(TyCon::Int(sign, size), _) => {
-                        instructions.push(ir::Instruction::Cast(temp, sign, size))
+                        instructions.push(ir::Instruction::Cast(temp, from_temp, sign, size))
                     }
 
                     _ => panic!("Can only cast to ints"),
@@ -277,7 +544,7 @@ impl Codegen {
                         ir::Value::Const(*b as u64, Sign::Unsigned, Size::Bit8)
                     }
 
-                   Literal::Nil => ir::Value::Mem(vec![0x00000000]),
+                   Literal::Nil => ir::Value::Const(0, Sign::Unsigned, Size::Bit8),
 
                     Literal::Number(ref number) => match number.ty {
                         Some((sign, size)) => ir::Value::Const(number.value, sign, size),
@@ -289,13 +556,7 @@ impl Codegen {
                             _ => unreachable!(),
                         },
                     },
-                   Literal::Str(ref string) => {
-                        let mut bytes = vec![];
-                        bytes.push(string.len() as u8);
-                        bytes.extend(string.as_bytes());
-
-                        ir::Value::Mem(bytes)
-                    }
+                   Literal::Str(ref string) => ir::Value::Mem(string.as_bytes().to_vec()),
                 };
 
                 instructions.push(ir::Instruction::Store(temp, value))
@@ -309,42 +570,129 @@ impl Codegen {
                 instructions.push(ir::Instruction::UnOp(temp, op ,new_temp))
             }
 
+            t::Expression::Var(t::Var::Simple(ref symbol, ref ty)) => {
+                match self.resolve_var(*symbol, scalar(ty)) {
+                    ir::Capture::Local(t) => instructions.push(ir::Instruction::Copy(temp, t)),
+                    ir::Capture::Upvalue(index) => {
+                        instructions.push(ir::Instruction::GetUpvalue(temp, index))
+                    }
+                }
+            }
+
             t::Expression::Var(ref var) => {
                 let t = self.gen_var(var, instructions);
                 instructions.push(ir::Instruction::Copy(temp, t))
             }
 
-            _ => unimplemented!(),
+            t::Expression::Field(var, field) => {
+                let (base, offset, (sign, size)) = self.field(var, field, instructions);
+                instructions.push(ir::Instruction::LoadAt(temp, base, offset, sign, size))
+            }
+
+            t::Expression::StructLit(_, ref values) => {
+                let (fields, layout) = self.layout(&expr.ty);
+                let mut temps = Vec::with_capacity(values.len());
+
+                // The values are worked out first as they may read the variable the
+                // struct is stored in
+                for value in values {
+                    let temp = Temp::new();
+                    self.gen_expression(value, temp, instructions);
+                    temps.push(temp);
+                }
+
+                instructions.push(ir::Instruction::Alloc(temp, layout.size));
+
+                for ((from, field), offset) in temps.into_iter().zip(&fields).zip(&layout.offsets) {
+                    let (sign, size) = scalar(&field.ty);
+                    instructions.push(ir::Instruction::StoreAt(temp, *offset, from, sign, size));
+                }
+            }
+
+            t::Expression::Index(_, _) => self.unsupported("Indexing an array"),
         }
     }
 
     fn gen_var(&mut self, var: &t::Var, instructions: &mut Vec<ir::Instruction>) -> Temp {
         match *var {
-            t::Var::Simple(ref sym, _) => *self.symbols.look(*sym).unwrap(),
+            t::Var::Simple(ref sym, ref ty) => match self.resolve_var(*sym, scalar(ty)) {
+                ir::Capture::Local(temp) => temp,
+                ir::Capture::Upvalue(index) => {
+                    let temp = Temp::new();
+                    instructions.push(ir::Instruction::GetUpvalue(temp, index));
+                    temp
+                }
+            },
 
-            t::Var::SubScript(ref sym, ref expr, _) => {
-                let base = *self.symbols.look(*sym).unwrap();
+            t::Var::SubScript(_, _, _) => {
+                self.unsupported("Indexing an array");
+                Temp::new()
+            }
 
-                let addr = Temp::new();
+            t::Var::Field(var, field, _) => {
+                let (base, offset, (sign, size)) = self.field(var, field, instructions);
+                let temp = Temp::new();
 
-                self.gen_expression(expr, addr, instructions);
+                instructions.push(ir::Instruction::LoadAt(temp, base, offset, sign, size));
 
-                let temp = Temp::new();
+                temp
+            }
+        }
+    }
 
-                instructions.push(ir::Instruction::Store(
-                    temp,
-                    ir::Value::Const(4, Sign::Unsigned, Size::Bit64),
-                ));
+    /// The fields of a struct type and where they go. A field whose declared type is a
+    /// type parameter takes the type it has in the instance, the rest keep the declared
+    /// one so a field that was given `nil` is still as big as a pointer
+    fn layout(&self, ty: &Type) -> (Vec<Field>, Layout) {
+        let (name, instance) = match *ty {
+            Type::Struct(name, ref fields, _) => (name, fields),
+            ref ty => panic!("Expected a struct but found `{:?}`", ty),
+        };
 
-                instructions.push(ir::Instruction::BinOp(addr,ir::BinOp::Mul, temp, addr));
+        let fields: Vec<Field> = match self.structs.get(&name) {
+            Some(declared) => declared
+                .iter()
+                .map(|field| match field.ty {
+                    Type::Var(_) => instance
+                        .iter()
+                        .find(|instance| instance.name == field.name)
+                        .cloned()
+                        .unwrap_or_else(|| field.clone()),
+                    _ => field.clone(),
+                })
+                .collect(),
+            None => instance.clone(),
+        };
 
-                instructions.push(ir::Instruction::BinOp(addr,ir::BinOp::Plus, base, addr));
+        let layout = layout(&fields);
 
-                addr
-            }
+        (fields, layout)
+    }
 
-            _ => unimplemented!(),
-        }
+    /// The temp holding the address of a struct variable along with the offset and the
+    /// sign and width of one of its fields
+    fn field(
+        &mut self,
+        var: Symbol,
+        field: Symbol,
+        instructions: &mut Vec<ir::Instruction>,
+    ) -> (Temp, usize, (Sign, Size)) {
+        let ty = self
+            .symbols
+            .look(var)
+            .and_then(|temp| self.types.get(temp))
+            .cloned()
+            .expect("Field of an undefined variable");
+
+        let (fields, layout) = self.layout(&ty);
+        let index = fields
+            .iter()
+            .position(|f| f.name == field)
+            .expect("Fields are checked by the type checker");
+
+        let base = self.gen_var(&t::Var::Simple(var, ty), instructions);
+
+        (base, layout.offsets[index], scalar(&fields[index].ty))
     }
 
     fn gen_cond(
@@ -356,32 +704,52 @@ impl Codegen {
     ) {
         match *cond.expr {
             t::Expression::Binary(ref lhs, ref op, ref rhs) => match *op {
-                Op::NEq => self.gen_cond(cond, lfalse, ltrue, instructions),
-
                 Op::And => {
                     let lnext = new_named_label("next", &mut self.symbols);
 
                     self.gen_cond(lhs, lnext, lfalse, instructions);
 
-                    instructions.push(ir::Instruction::Jump(lnext));
+                    instructions.push(ir::Instruction::Label(lnext));
 
                     self.gen_cond(rhs, ltrue, lfalse, instructions);
-
-                    instructions.push(ir::Instruction::Label(lnext));
                 }
                 Op::Or => {
                     let lnext = new_named_label("next", &mut self.symbols);
 
                     self.gen_cond(lhs, ltrue, lnext, instructions);
 
-                    instructions.push(ir::Instruction::Jump(lnext));
+                    instructions.push(ir::Instruction::Label(lnext));
 
                     self.gen_cond(rhs, ltrue, lfalse, instructions);
+                }
 
-                    instructions.push(ir::Instruction::Label(lnext));
+                // Strings are equal when they hold the same bytes
+                Op::Equal | Op::NEq if is_string(&lhs.ty) && is_string(&rhs.ty) => {
+                    let (lhs_temp, rhs_temp) = (Temp::new(), Temp::new());
+                    let (equal, true_temp) = (Temp::new(), Temp::new());
+
+                    self.gen_expression(lhs, lhs_temp, instructions);
+                    self.gen_expression(rhs, rhs_temp, instructions);
+
+                    instructions.push(ir::Instruction::Intrinsic(
+                        equal,
+                        ir::Intrinsic::StrEq,
+                        vec![lhs_temp, rhs_temp],
+                    ));
+                    instructions.push(ir::Instruction::Store(
+                        true_temp,
+                        ir::Value::Const(true as u64, Sign::Unsigned, Size::Bit8),
+                    ));
+                    instructions.push(ir::Instruction::CJump(
+                        equal,
+                        gen_cmp_op(op),
+                        true_temp,
+                        ltrue,
+                        lfalse,
+                    ));
                 }
 
-                Op::LT | Op::GT | Op::GTE | Op::LTE | Op::Equal => {
+                Op::LT | Op::GT | Op::GTE | Op::LTE | Op::Equal | Op::NEq => {
                     let lhs_temp = Temp::new();
                     let rhs_temp = Temp::new();
                     self.gen_expression(lhs, lhs_temp, instructions);
@@ -405,10 +773,10 @@ impl Codegen {
 
                 self.gen_expression(cond, expr_temp, instructions);
 
-                ir::Instruction::Store(
+                instructions.push(ir::Instruction::Store(
                     true_temp,
                     ir::Value::Const(true as u64, Sign::Unsigned, Size::Bit8),
-                );
+                ));
 
                 instructions.push(ir::Instruction::CJump(
                     expr_temp,
@@ -446,9 +814,29 @@ fn gen_cmp_op(op: &Op) -> ir::CmpOp {
         _ => unreachable!(),
     }
 }
+
+fn is_string(ty: &Type) -> bool {
+    matches!(*ty, Type::App(TyCon::String, _))
+}
+
 fn gen_un_op(op: &UnaryOp) -> ir::UnOp {
     match *op {
         UnaryOp::Minus => ir::UnOp::Minus,
         UnaryOp::Bang => ir::UnOp::Bang,
     }
 }
+
+/// The sign and width a value of `ty` occupies once lowered.
+/// Anything that isn't an int or a single byte is passed around by pointer.
+pub(crate) fn scalar(ty: &Type) -> (Sign, Size) {
+    match *ty {
+        Type::App(TyCon::Int(sign, size), _) => (sign, size),
+        Type::App(TyCon::Bool, _) | Type::App(TyCon::Char, _) | Type::App(TyCon::Void, _) => {
+            (Sign::Unsigned, Size::Bit8)
+        }
+        Type::Nil => (Sign::Unsigned, Size::Bit8),
+        // Number literals whose type was never pinned down are i32
+        Type::Var(_) => (Sign::Signed, Size::Bit32),
+        _ => (Sign::Unsigned, Size::Bit64),
+    }
+}
@@ -4,10 +4,10 @@ use cast_check::*;
 //               translate::{Level, Translator}};
 use env::{Entry, Env, VarEntry, VarType};
 use std::collections::HashMap;
-use syntax::ast::{Call, Expression, Function, Literal, Op, Sign, Size, Statement, StructLit,
-                  UnaryOp, Var};
+use syntax::ast::{Call, Expression, Function, Linkage, Literal, Op, Sign, Size, Statement,
+                  StructLit, UnaryOp, Var};
 use types::{Field, TyCon, Type, TypeVar};
-use util::{emitter::Reporter, pos::Spanned};
+use util::{emitter::Reporter, pos::Spanned, symbol::Symbol};
 
 use ast::typed as t;
 
@@ -75,13 +75,16 @@ impl Infer {
 
         let body = self.infer_statement(&function.value.body, env, reporter)?;
 
-        self.unify(
-            &returns,
-            &self.body,
-            reporter,
-            function.value.body.span,
-            env,
-        )?;
+        // External functions are implemented by the host so their body returns nothing
+        if function.value.linkage != Linkage::External {
+            self.unify(
+                &returns,
+                &self.body,
+                reporter,
+                function.value.body.span,
+                env,
+            )?;
+        }
 
         env.end_scope();
         self.body = Type::Nil;
@@ -273,6 +276,7 @@ impl Infer {
                             ident: ident.value,
                             ty: t,
                             expr: Some(expr_tyexpr),
+                            escapes: *escapes,
                         });
                     }
 
@@ -282,6 +286,7 @@ impl Infer {
                         ident: ident.value,
                         ty: expr_tyexpr.ty.clone(),
                         expr: Some(expr_tyexpr),
+                        escapes: *escapes,
                     })
                 } else {
                     if let Some(ref ty) = *ty {
@@ -293,6 +298,7 @@ impl Infer {
                             ident: ident.value,
                             ty,
                             expr: None,
+                            escapes: *escapes,
                         });
                     }
 
@@ -302,6 +308,7 @@ impl Infer {
                         ident: ident.value,
                         ty: Type::Nil,
                         expr: None,
+                        escapes: *escapes,
                     })
                 }
             }
@@ -311,7 +318,7 @@ impl Infer {
 
 impl Infer {
     fn infer_expr(
-        &self,
+        &mut self,
         expr: &Spanned<Expression>,
         env: &mut Env,
         reporter: &mut Reporter,
@@ -399,6 +406,19 @@ impl Infer {
 
                         let ty = lhs.ty.clone();
 
+                        // Strings can only be joined
+                        if op.value != Op::Plus && ty == Type::App(TyCon::String, vec![]) {
+                            let symbol = match op.value {
+                                Op::Minus => "-",
+                                Op::Star => "*",
+                                Op::Slash => "/",
+                                _ => unreachable!(),
+                            };
+                            let msg = format!("Cannot use `{}` on type `{}`", symbol, ty.print(env));
+                            reporter.error(msg, op.span);
+                            return Err(());
+                        }
+
                         (t::Expression::Binary(lhs, op.value, rhs), ty)
                     }
                 }
@@ -422,49 +442,18 @@ impl Infer {
                 }
             }
             Expression::Call(ref call) => self.infer_call(call, env, reporter)?,
-            //            Expression::Closure(ref closure) => {
-            //                let mut param_tys = Vec::with_capacity(closure.value.params.value.len());
-            //
-            //                for param in &closure.value.params.value {
-            //                    param_tys.push(self.trans_ty(&param.value.ty, env, reporter)?)
-            //                }
-            //
-            //                let label = temp::new_label(&mut env.escapes.clone());
-            //
-            //                env.add_var(
-            //                    closure.value.name.value.name.value,
-            //                    VarEntry::Fun {
-            //                        level: level.clone(),
-            //                        ty: Type::App(TyCon::Arrow, param_tys.clone()),
-            //                        label,
-            //                    },
-            //                );
-            //
-            //                env.begin_scope();
-            //
-            //                for (param, ident) in param_tys
-            //                    .clone()
-            //                    .into_iter()
-            //                    .zip(&closure.value.params.value)
-            //                {
-            //                    env.add_var(ident.value.name.value, VarEntry::Var(None, param))
-            //                }
-            //
-            //                param_tys.push(self.trans_statement(
-            //                    &closure.value.body,
-            //                    level,
-            //                    ctx,
-            //                    env,
-            //                    reporter,
-            //                )?); // Add the return type of the body
-            //
-            //                env.end_scope();
-            //
-            //                Ok(Type::Poly(
-            //                    Vec::with_capacity(0),
-            //                    Box::new(Type::App(TyCon::Arrow, param_tys)),
-            //                ))
-            //            }
+            Expression::Closure(ref closure) => {
+                let closure = self.infer_closure(closure, env, reporter)?;
+                let mut fn_types: Vec<Type> =
+                    closure.params.iter().map(|param| param.ty.clone()).collect();
+
+                fn_types.push(closure.returns.clone());
+
+                (
+                    t::Expression::Closure(Box::new(closure)),
+                    Type::App(TyCon::Arrow, fn_types),
+                )
+            }
             Expression::Grouping { ref expr } => return self.infer_expr(expr, env, reporter),
             Expression::Literal(ref literal) => {
                 let ty = self.infer_literal(literal, env);
@@ -531,8 +520,57 @@ impl Infer {
         })
     }
 
+    /// Closures see the variables of the scope they are created in. Their return
+    /// type is that of the values they return
+    fn infer_closure(
+        &mut self,
+        closure: &Spanned<Function>,
+        env: &mut Env,
+        reporter: &mut Reporter,
+    ) -> InferResult<t::Function> {
+        let mut params = Vec::with_capacity(closure.value.params.value.len());
+
+        for param in &closure.value.params.value {
+            params.push(t::FunctionParam {
+                name: param.value.name.value,
+                ty: self.trans_ty(&param.value.ty, env, reporter)?,
+            })
+        }
+
+        let enclosing = ::std::mem::replace(&mut self.body, Type::Nil);
+
+        env.begin_scope();
+
+        for param in &params {
+            env.add_var(param.name, VarEntry::Var(param.ty.clone()))
+        }
+
+        let body = self.infer_statement(&closure.value.body, env, reporter);
+
+        env.end_scope();
+
+        let returns = ::std::mem::replace(&mut self.body, enclosing);
+        let body = body?;
+
+        if let Some(ref ty) = closure.value.returns {
+            let ty = self.trans_ty(ty, env, reporter)?;
+
+            self.unify(&ty, &returns, reporter, closure.value.body.span, env)?;
+        }
+
+        Ok(t::Function {
+            span: closure.span,
+            generic: false,
+            name: closure.value.name.value.name.value,
+            params,
+            returns,
+            body,
+            linkage: closure.value.linkage,
+        })
+    }
+
     fn infer_struct_lit(
-        &self,
+        &mut self,
         lit: &Spanned<StructLit>,
 
         env: &mut Env,
@@ -741,7 +779,7 @@ impl Infer {
 
     fn infer_literal(&self, literal: &Literal, env: &mut Env) -> Type {
         match *literal {
-            Literal::Char(_) => Type::App(TyCon::Int(Sign::Unsigned, Size::Bit8), vec![]),
+            Literal::Char(_) => Type::App(TyCon::Char, vec![]),
 
             Literal::False(_) | Literal::True(_) => Type::App(TyCon::Bool, vec![]),
 
@@ -763,7 +801,7 @@ impl Infer {
     }
 
     fn infer_var(
-        &self,
+        &mut self,
         var: &Spanned<Var>,
 
         env: &mut Env,
@@ -774,6 +812,15 @@ impl Infer {
                 if let Some(var) = env.look_var(ident.value).cloned() {
                     // Ok((ident.value, var.get_ty()))
 
+                    if let VarEntry::Overloaded(_) = var {
+                        let msg = format!(
+                            "`{}` is overloaded and can only be called",
+                            env.name(ident.value)
+                        );
+                        reporter.error(msg, ident.span);
+                        return Err(());
+                    }
+
                     let ty = var.get_ty();
 
                     Ok((t::Var::Simple(ident.value, ty.clone()), ty))
@@ -799,11 +846,11 @@ impl Infer {
                 let record = record.get_ty();
 
                 match record {
-                    Type::Struct(ref ident, ref fields, _) => {
+                    Type::Struct(ref name, ref fields, _) => {
                         for field in fields {
                             if field.name == value.value {
                                 return Ok((
-                                    t::Var::Field(*ident, field.name, field.ty.clone()),
+                                    t::Var::Field(ident.value, field.name, field.ty.clone()),
                                     field.ty.clone(),
                                 ));
                             }
@@ -811,7 +858,7 @@ impl Infer {
 
                         let msg = format!(
                             "struct `{}` doesn't have a field named `{}`",
-                            env.name(*ident),
+                            env.name(*name),
                             env.name(value.value)
                         );
 
@@ -902,7 +949,7 @@ impl Infer {
     }
 
     fn infer_call(
-        &self,
+        &mut self,
         call: &Spanned<Call>,
 
         env: &mut Env,
@@ -923,65 +970,73 @@ impl Infer {
                     return Err(());
                 };
 
-                match func.get_ty() {
-                    Type::Poly(ref tvars, ref ret) => match **ret {
-                        Type::App(TyCon::Arrow, ref fn_types) => {
-                            if fn_types.len() - 1 != args.len() {
-                                let msg = format!(
-                                    "Expected `{}` args found `{}` ",
-                                    fn_types.len() - 1,
-                                    args.len()
-                                );
-                                reporter.error(msg, call.span);
-                                return Err(());
-                            }
+                if let VarEntry::Overloaded(ref overloads) = func {
+                    return self.infer_overloaded(callee, args, overloads, env, reporter);
+                }
 
-                            let mut mappings = HashMap::new();
+                // A closure held in a variable has a plain arrow type
+                let (tvars, ret) = match func.get_ty() {
+                    Type::Poly(tvars, ret) => (tvars, ret),
+                    ty @ Type::App(TyCon::Arrow, _) => (vec![], Box::new(ty)),
+                    _ => {
+                        let msg = format!("`{}` is not callable", env.name(callee.value));
 
-                            let mut arg_tys = Vec::new();
-                            let mut callee_exprs = vec![];
+                        reporter.error(msg, callee.span);
 
-                            if tvars.is_empty() {
-                                for arg in args {
-                                    let ty_expr = self.infer_expr(arg, env, reporter)?;
-                                    arg_tys.push((ty_expr.ty.clone(), arg.span));
-                                    callee_exprs.push(ty_expr)
-                                }
-                            } else {
-                                for (tvar, arg) in tvars.iter().zip(args) {
-                                    let ty = self.infer_expr(arg, env, reporter)?;
-                                    mappings.insert(*tvar, ty.ty.clone());
+                        return Err(());
+                    }
+                };
 
-                                    arg_tys.push((ty.ty.clone(), arg.span));
-                                    callee_exprs.push(ty);
-                                }
-                            }
+                match *ret {
+                    Type::App(TyCon::Arrow, ref fn_types) => {
+                        if fn_types.len() - 1 != args.len() {
+                            let msg = format!(
+                                "Expected `{}` args found `{}` ",
+                                fn_types.len() - 1,
+                                args.len()
+                            );
+                            reporter.error(msg, call.span);
+                            return Err(());
+                        }
+
+                        let mut mappings = HashMap::new();
 
-                            for (ty, arg) in fn_types.iter().zip(arg_tys) {
-                                self.unify(
-                                    &self.subst(ty, &mut mappings),
-                                    &self.subst(&arg.0, &mut mappings),
-                                    reporter,
-                                    arg.1,
-                                    env,
-                                )?;
+                        let mut arg_tys = Vec::new();
+                        let mut callee_exprs = vec![];
+
+                        if tvars.is_empty() {
+                            for arg in args {
+                                let ty_expr = self.infer_expr(arg, env, reporter)?;
+                                arg_tys.push((ty_expr.ty.clone(), arg.span));
+                                callee_exprs.push(ty_expr)
                             }
+                        } else {
+                            for (tvar, arg) in tvars.iter().zip(args) {
+                                let ty = self.infer_expr(arg, env, reporter)?;
+                                mappings.insert(*tvar, ty.ty.clone());
 
-                            Ok((
-                                t::Expression::Call(callee.value, callee_exprs),
-                                self.subst(fn_types.last().unwrap(), &mut mappings),
-                            ))
+                                arg_tys.push((ty.ty.clone(), arg.span));
+                                callee_exprs.push(ty);
+                            }
                         }
 
-                        _ => unreachable!(), // Structs are not stored in the var environment so this path cannot be reached
-                    },
-                    _ => {
-                        let msg = format!("`{}` is not callable", env.name(callee.value));
-
-                        reporter.error(msg, callee.span);
+                        for (ty, arg) in fn_types.iter().zip(arg_tys) {
+                            self.unify(
+                                &self.subst(ty, &mut mappings),
+                                &self.subst(&arg.0, &mut mappings),
+                                reporter,
+                                arg.1,
+                                env,
+                            )?;
+                        }
 
-                        Err(())
+                        Ok((
+                            t::Expression::Call(callee.value, callee_exprs),
+                            self.subst(fn_types.last().unwrap(), &mut mappings),
+                        ))
                     }
+
+                    _ => unreachable!(), // Structs are not stored in the var environment so this path cannot be reached
                 }
             }
 
@@ -1069,4 +1124,57 @@ impl Infer {
             }
         }
     }
+
+    /// Calls the first overload whose params the arguments unify with
+    fn infer_overloaded(
+        &mut self,
+        callee: &Spanned<Symbol>,
+        args: &[Spanned<Expression>],
+        overloads: &[Type],
+        env: &mut Env,
+        reporter: &mut Reporter,
+    ) -> InferResult<(t::Expression, Type)> {
+        let mut callee_exprs = Vec::with_capacity(args.len());
+
+        for arg in args {
+            callee_exprs.push(self.infer_expr(arg, env, reporter)?);
+        }
+
+        for overload in overloads {
+            let fn_types = match *overload {
+                Type::App(TyCon::Arrow, ref fn_types) if fn_types.len() - 1 == args.len() => {
+                    fn_types
+                }
+                _ => continue,
+            };
+
+            let mut matches = true;
+
+            for (ty, (arg, expr)) in fn_types.iter().zip(args.iter().zip(&callee_exprs)) {
+                if self.unify(ty, &expr.ty, reporter, arg.span, env).is_err() {
+                    reporter.pop_error();
+                    matches = false;
+                    break;
+                }
+            }
+
+            if matches {
+                return Ok((
+                    t::Expression::Call(callee.value, callee_exprs),
+                    fn_types.last().unwrap().clone(),
+                ));
+            }
+        }
+
+        let tys: Vec<String> = callee_exprs.iter().map(|expr| expr.ty.print(env)).collect();
+
+        let msg = format!(
+            "`{}` cannot be called with `{}`",
+            env.name(callee.value),
+            tys.join(", ")
+        );
+
+        reporter.error(msg, callee.span);
+        Err(())
+    }
 }
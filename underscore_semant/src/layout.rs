@@ -0,0 +1,95 @@
+//! Where the fields of a struct are kept in memory once it is lowered. Each field takes
+//! the size of the value it lowers to and is aligned to that size, like C lays out a
+//! struct.
+use gen_ir::scalar;
+use types::Field;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    /// The offset of each field, in the order they are declared
+    pub offsets: Vec<usize>,
+    /// The size of the struct, padded to a multiple of its alignment
+    pub size: usize,
+    /// The alignment of the field that needs the most
+    pub align: usize,
+}
+
+pub fn layout(fields: &[Field]) -> Layout {
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut size = 0;
+    let mut align = 1;
+
+    for field in fields {
+        let width = scalar(&field.ty).1.size() as usize;
+
+        size = align_to(size, width);
+        offsets.push(size);
+
+        size += width;
+        align = align.max(width);
+    }
+
+    Layout {
+        offsets,
+        size: align_to(size, align),
+        align,
+    }
+}
+
+/// Rounds an offset up to the next multiple of `align`
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+#[cfg(test)]
+mod test {
+    use super::{layout, Layout};
+    use syntax::ast::{Sign, Size};
+    use types::{Field, TyCon, Type};
+    use util::symbol::Symbol;
+
+    fn field(name: u32, ty: Type) -> Field {
+        Field {
+            name: Symbol(name),
+            ty,
+        }
+    }
+
+    #[test]
+    fn fields_are_aligned_to_their_size() {
+        let fields = vec![
+            field(0, Type::App(TyCon::Bool, vec![])),
+            field(1, Type::App(TyCon::Int(Sign::Signed, Size::Bit32), vec![])),
+            field(2, Type::App(TyCon::Char, vec![])),
+            field(3, Type::App(TyCon::String, vec![])),
+            field(4, Type::App(TyCon::Int(Sign::Unsigned, Size::Bit8), vec![])),
+        ];
+
+        assert_eq!(
+            layout(&fields),
+            Layout {
+                offsets: vec![0, 4, 8, 16, 24],
+                size: 32,
+                align: 8,
+            }
+        );
+
+        assert_eq!(
+            layout(&fields[..2]),
+            Layout {
+                offsets: vec![0, 4],
+                size: 8,
+                align: 4,
+            }
+        );
+
+        assert_eq!(
+            layout(&[]),
+            Layout {
+                offsets: vec![],
+                size: 0,
+                align: 1,
+            }
+        );
+    }
+}
@@ -4,13 +4,14 @@ extern crate underscore_util as util;
 
 mod ast;
 mod cast_check;
-mod cfg;
 mod env;
 mod escape;
-mod gen_cfg;
+mod gen_c;
 mod gen_ir;
 mod infer;
+mod layout;
 mod monomorphize;
+mod prelude;
 mod resolver;
 mod subst;
 mod types;
@@ -19,8 +20,10 @@ mod unify;
 use ast::typed as t;
 pub use env::Env as TypeEnv;
 use env::Env;
+use escape::FindEscape;
 
-pub use gen_ir::Codegen;
+pub use gen_c::{CCodegen, CError};
+pub use gen_ir::{Codegen, CodegenError};
 use monomorphize::Mono;
 use resolver::Resolver;
 use syntax::ast::Program;
@@ -49,6 +52,8 @@ impl Infer {
             structs: vec![],
         };
 
+        FindEscape::new().find_escape(program, &mut env.escapes)?;
+
         for alias in &program.type_alias {
             self.infer_alias(alias, env, reporter)?
         }
@@ -349,18 +349,25 @@ impl Mono {
                     }
                 }
             }
-            t::Statement::Let { ident, ty, expr } => {
+            t::Statement::Let {
+                ident,
+                ty,
+                expr,
+                escapes,
+            } => {
                 if let Some(texpr) = expr {
                     t::Statement::Let {
                         ident,
                         ty,
                         expr: Some(self.gen_new_expr(texpr, env)),
+                        escapes,
                     }
                 } else {
                     t::Statement::Let {
                         ident,
                         ty,
                         expr: None,
+                        escapes,
                     }
                 }
             }
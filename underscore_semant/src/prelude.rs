@@ -0,0 +1,71 @@
+//! The functions every program can call without declaring them.
+//! Each one is lowered to an intrinsic that the backend provides.
+use env::{Env, VarEntry};
+use ir::ir::Intrinsic;
+use syntax::ast::{Sign, Size};
+use types::{TyCon, Type};
+
+/// Adds the prelude to the environment
+pub fn add_prelude(env: &mut Env) {
+    // print takes any primitive, so it has one overload for each of them
+    let mut primitives = vec![TyCon::Bool, TyCon::Char, TyCon::String];
+
+    for &sign in &[Sign::Signed, Sign::Unsigned] {
+        for &size in &[Size::Bit8, Size::Bit32, Size::Bit64] {
+            primitives.push(TyCon::Int(sign, size));
+        }
+    }
+
+    let overloads = primitives
+        .into_iter()
+        .map(|primitive| Type::App(TyCon::Arrow, vec![Type::App(primitive, vec![]), Type::Nil]))
+        .collect();
+
+    let print = env.symbol("print");
+    env.add_var(print, VarEntry::Overloaded(overloads));
+
+    add(env, "assert", vec![Type::App(TyCon::Bool, vec![])], Type::Nil);
+
+    add(
+        env,
+        "exit",
+        vec![Type::App(TyCon::Int(Sign::Signed, Size::Bit32), vec![])],
+        Type::Nil,
+    );
+
+    add(
+        env,
+        "len",
+        vec![Type::App(TyCon::String, vec![])],
+        Type::App(TyCon::Int(Sign::Signed, Size::Bit32), vec![]),
+    );
+}
+
+fn add(env: &mut Env, name: &str, mut params: Vec<Type>, returns: Type) {
+    let symbol = env.symbol(name);
+
+    params.push(returns);
+
+    env.add_var(
+        symbol,
+        VarEntry::Fun {
+            ty: Type::Poly(vec![], Box::new(Type::App(TyCon::Arrow, params))),
+        },
+    );
+}
+
+/// The intrinsic a call to a prelude function lowers to, given the types of its arguments
+pub fn intrinsic(name: &str, args: &[Type]) -> Option<Intrinsic> {
+    match name {
+        "print" => Some(match args.first() {
+            Some(&Type::App(TyCon::Bool, _)) => Intrinsic::PrintBool,
+            Some(&Type::App(TyCon::Char, _)) => Intrinsic::PrintChar,
+            Some(&Type::App(TyCon::String, _)) => Intrinsic::PrintStr,
+            _ => Intrinsic::PrintInt,
+        }),
+        "assert" => Some(Intrinsic::Assert),
+        "exit" => Some(Intrinsic::Exit),
+        "len" => Some(Intrinsic::StrLen),
+        _ => None,
+    }
+}
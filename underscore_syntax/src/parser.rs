@@ -489,6 +489,7 @@ impl<'a, 'b> Parser<'a, 'b> {
     /// },
     fn parse_function(&mut self) -> ParserResult<Spanned<Function>> {
         let linkage = if self.recognise(TokenType::EXTERNAL) {
+            self.advance();
             Linkage::External
         } else {
             Linkage::Normal
@@ -2,13 +2,44 @@
 /// the line from which an instruction orginated from
 use op::{OpCode, TryFrom};
 use std::ops::{Index, Range};
+use syntax::ast::{Sign, Size};
 
 type Line = usize;
+
+/// Long form constants address the pool with three bytes
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
 #[derive(Debug, Clone, Default)]
 pub struct Chunk {
     code: Vec<u8>,
     pub constants: Vec<u8>,
     lines: Vec<Line>,
+    /// Every function compiled into this chunk
+    pub functions: Vec<Function>,
+    /// The index of the function execution starts in
+    pub entry: Option<usize>,
+    /// The external functions called from this chunk
+    pub natives: Vec<Native>,
+}
+
+/// A function compiled into a chunk
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    /// The offset of the first instruction
+    pub offset: usize,
+    /// The number of local slots a call frame needs
+    pub locals: usize,
+}
+
+/// An external function the vm has to provide when the chunk is run
+#[derive(Debug, Clone)]
+pub struct Native {
+    pub name: String,
+    /// The sign and width of each parameter
+    pub params: Vec<(Sign, Size)>,
+    /// The sign and width of the returned value
+    pub returns: (Sign, Size),
 }
 
 #[cfg(feature = "debug")]
@@ -17,6 +48,12 @@ pub fn simple_instruction(name: &str, offset: usize) -> usize {
     offset + 2
 }
 
+#[cfg(feature = "debug")]
+pub fn single_byte_instruction(name: &str, offset: usize) -> usize {
+    println!("{}", name);
+    offset + 1
+}
+
 macro_rules! to_num {
     ([$stack:expr, $top:expr] => $type:ty) => {{
         use std::default;
@@ -41,9 +78,28 @@ impl Chunk {
         self.code.push(byte.into())
     }
 
-    /// Write multiple bytes to this chunk
-    pub fn write_values(&mut self, bytes: &[u8], line: Line) {
-        self.lines.push(line);
+    /// Write a two byte operand to this chunk
+    pub fn write_u16(&mut self, value: u16, line: Line) {
+        self.write(value as u8, line);
+        self.write((value >> 8) as u8, line);
+    }
+
+    /// Overwrite a byte that was already written. Used to patch jump offsets
+    pub fn patch(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte
+    }
+
+    /// The number of bytes of code in this chunk
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Write multiple bytes to the constant pool
+    pub fn write_values(&mut self, bytes: &[u8], _line: Line) {
         self.constants.extend(bytes)
     }
 
@@ -56,12 +112,47 @@ impl Chunk {
         len
     }
 
+    /// Adds a constant of 1, 4 or 8 bytes to the constant pool and writes the
+    /// instruction that loads it. Offsets that don't fit in a byte use the long form
+    /// which takes a three byte offset.
+    /// Returns the offset at which the constant is stored
+    pub fn write_constant(&mut self, bytes: &[u8], line: Line) -> usize {
+        let offset = self.add_constant(bytes, line);
+
+        assert!(offset < MAX_CONSTANTS, "Too many constants in one chunk");
+
+        let (short, long) = match bytes.len() {
+            1 => (OpCode::Constant8, OpCode::Constant8Long),
+            4 => (OpCode::Constant32, OpCode::Constant32Long),
+            8 => (OpCode::Constant64, OpCode::Constant64Long),
+            len => panic!("Constants can't be {} bytes long", len),
+        };
+
+        if offset <= u8::MAX as usize {
+            self.write(short, line);
+            self.write(offset as u8, line);
+        } else {
+            self.write(long, line);
+            self.write(offset as u8, line);
+            self.write((offset >> 8) as u8, line);
+            self.write((offset >> 16) as u8, line);
+        }
+
+        offset
+    }
+
     #[cfg(feature = "debug")]
     pub fn dissassemble(&mut self, name: &str) {
         println!("== {} ==", name);
 
         let mut i = 0;
         while i < self.code.len() {
+            for function in &self.functions {
+                if function.offset == i {
+                    println!("{}:", function.name);
+                }
+            }
+
             i = self.dissassemble_instruction(i);
         }
     }
@@ -80,11 +171,26 @@ impl Chunk {
 
         match OpCode::try_from(instruction) {
             Ok(OpCode::Return) => simple_instruction("OP_RETURN", offset),
-            Ok(OpCode::Neg) => simple_instruction("OP_NEG", offset),
-            Ok(OpCode::Add) => simple_instruction("OP_ADD", offset),
-            Ok(OpCode::Subtract) => simple_instruction("OP_SUBTRACT", offset),
-            Ok(OpCode::Multiply) => simple_instruction("OP_MULTIPLY", offset),
-            Ok(OpCode::Divide) => simple_instruction("OP_DIVIDE", offset),
+            Ok(OpCode::Neg) => self.arithmetic_instruction("OP_NEG", offset),
+            Ok(OpCode::Add) => self.arithmetic_instruction("OP_ADD", offset),
+            Ok(OpCode::Subtract) => self.arithmetic_instruction("OP_SUBTRACT", offset),
+            Ok(OpCode::Multiply) => self.arithmetic_instruction("OP_MULTIPLY", offset),
+            Ok(OpCode::Divide) => self.arithmetic_instruction("OP_DIVIDE", offset),
+            Ok(OpCode::Not) => single_byte_instruction("OP_NOT", offset),
+            Ok(OpCode::Equal) => self.arithmetic_instruction("OP_EQUAL", offset),
+            Ok(OpCode::NotEqual) => self.arithmetic_instruction("OP_NOT_EQUAL", offset),
+            Ok(OpCode::Less) => self.arithmetic_instruction("OP_LESS", offset),
+            Ok(OpCode::LessEqual) => self.arithmetic_instruction("OP_LESS_EQUAL", offset),
+            Ok(OpCode::Greater) => self.arithmetic_instruction("OP_GREATER", offset),
+            Ok(OpCode::GreaterEqual) => self.arithmetic_instruction("OP_GREATER_EQUAL", offset),
+            Ok(OpCode::And) => single_byte_instruction("OP_AND", offset),
+            Ok(OpCode::Or) => single_byte_instruction("OP_OR", offset),
+            Ok(OpCode::Jump) => self.jump_instruction("OP_JUMP", offset),
+            Ok(OpCode::JumpIfFalse) => self.jump_instruction("OP_JUMP_IF_FALSE", offset),
+            Ok(OpCode::Call) => self.call_instruction("OP_CALL", offset),
+            Ok(OpCode::GetLocal) => self.local_instruction("OP_GET_LOCAL", offset),
+            Ok(OpCode::SetLocal) => self.local_instruction("OP_SET_LOCAL", offset),
+            Ok(OpCode::Cast) => self.cast_instruction("OP_CAST", offset),
 
             Ok(OpCode::Constant8) => self.constant_instruction("OP_CONSTANT8", 1, offset as usize),
             Ok(OpCode::Constant32) => {
@@ -93,6 +199,74 @@ impl Chunk {
             Ok(OpCode::Constant64) => {
                 self.constant_instruction("OP_CONSTANT64", 8, offset as usize)
             }
+            Ok(OpCode::Constant8Long) => {
+                self.constant_instruction("OP_CONSTANT8_LONG", 1, offset as usize)
+            }
+            Ok(OpCode::Constant32Long) => {
+                self.constant_instruction("OP_CONSTANT32_LONG", 4, offset as usize)
+            }
+            Ok(OpCode::Constant64Long) => {
+                self.constant_instruction("OP_CONSTANT64_LONG", 8, offset as usize)
+            }
+
+            Ok(OpCode::String) => self.string_instruction("OP_STRING", offset),
+            Ok(OpCode::Array) => {
+                let count = self.read_u24(offset + 1);
+                let size = self.code[offset + 4];
+
+                println!("{:16} {} x {}", "OP_ARRAY", count, size);
+
+                offset + 5
+            }
+            Ok(OpCode::Struct) => {
+                println!("{:16} {}", "OP_STRUCT", self.read_u16(offset + 1));
+
+                offset + 3
+            }
+            Ok(OpCode::GetField) => self.local_instruction("OP_GET_FIELD", offset),
+            Ok(OpCode::SetField) => self.local_instruction("OP_SET_FIELD", offset),
+            Ok(OpCode::Closure) => self.closure_instruction("OP_CLOSURE", offset),
+            Ok(OpCode::GetUpvalue) => self.local_instruction("OP_GET_UPVALUE", offset),
+            Ok(OpCode::SetUpvalue) => self.local_instruction("OP_SET_UPVALUE", offset),
+            Ok(OpCode::CloseUpvalue) => {
+                println!("{:16} {:04}", "OP_CLOSE_UPVALUE", self.read_u16(offset + 1));
+
+                offset + 3
+            }
+            Ok(OpCode::CallClosure) => single_byte_instruction("OP_CALL_CLOSURE", offset),
+            Ok(OpCode::CallNative) => {
+                let native = self.read_u16(offset + 1) as usize;
+
+                match self.natives.get(native) {
+                    Some(native) => println!("{:16} {}", "OP_CALL_NATIVE", native.name),
+                    None => println!("{:16} {:04}", "OP_CALL_NATIVE", native),
+                }
+
+                offset + 3
+            }
+            Ok(OpCode::Print) => {
+                let kind = match self.code[offset + 1] {
+                    ::op::PRINT_BOOL => "bool",
+                    ::op::PRINT_CHAR => "char",
+                    ::op::PRINT_STR => "str",
+                    _ => "int",
+                };
+
+                println!(
+                    "{:16} {} {} {}",
+                    "OP_PRINT",
+                    kind,
+                    self.code[offset + 2],
+                    self.code[offset + 3]
+                );
+
+                offset + 4
+            }
+            Ok(OpCode::Assert) => single_byte_instruction("OP_ASSERT", offset),
+            Ok(OpCode::Exit) => single_byte_instruction("OP_EXIT", offset),
+            Ok(OpCode::Length) => single_byte_instruction("OP_LENGTH", offset),
+            Ok(OpCode::StringEqual) => single_byte_instruction("OP_STRINGEQUAL", offset),
+            Ok(OpCode::Concat) => single_byte_instruction("OP_CONCAT", offset),
 
             _ => {
                 println!("Unknown opcode {}", instruction);
@@ -106,40 +280,144 @@ impl Chunk {
     /// Matches on the instruction and uses that pointer offset for
     /// were the value is stored
     pub fn constant_instruction(&self, name: &str, size: usize, offset: usize) -> usize {
-        // opcode start
-
-        let index = self.code[offset + 1] as usize;
+        let long = match OpCode::try_from(self.code[offset]) {
+            Ok(OpCode::Constant8Long)
+            | Ok(OpCode::Constant32Long)
+            | Ok(OpCode::Constant64Long) => true,
+            _ => false,
+        };
+
+        let index = if long {
+            self.read_u24(offset + 1)
+        } else {
+            self.code[offset + 1] as usize
+        };
 
-        println!("{:?}",index);
+        let bytes = &self.constants[index..index + size];
 
         match size {
-            1 => {
-                println!("{:16} {:04}", name, to_num!([&self.constants[index..index+1],offset] => i8));
-            }
-            4 => {
-                let mut slice = [0; 4];
+            1 => println!("{:16} {:04} {}", name, index, to_num!([bytes, index] => i8)),
+            4 => println!("{:16} {:04} {}", name, index, to_num!([bytes, index] => i32)),
+            8 => println!("{:16} {:04} {}", name, index, to_num!([bytes, index] => i64)),
+            ref e => unreachable!("{:?}", e),
+        }
 
-                slice.copy_from_slice(&self.constants[index..index + size]);
+        if long {
+            offset + 4
+        } else {
+            offset + 2
+        }
+    }
 
-                println!(
-                    "{:16} {:04}",
-                    name,
-                    to_num!([&self.constants[index..index+size],index] => i32)
-                );
-            }
+    #[cfg(feature = "debug")]
+    /// Prints the string the instruction allocates
+    pub fn string_instruction(&self, name: &str, offset: usize) -> usize {
+        let index = self.read_u24(offset + 1);
 
-            8 => {
-                println!(
-                    "{:16} {:04}",
-                    name,
-                    to_num!([&self.constants,offset] => i64)
-                );
-            }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&self.constants[index..index + 4]);
+        let len = u32::from_le_bytes(len) as usize;
 
-            ref e => unreachable!("{:?}", e),
+        let string = String::from_utf8_lossy(&self.constants[index + 4..index + 4 + len]);
+
+        println!("{:16} {:04} {:?}", name, index, string);
+
+        offset + 4
+    }
+
+    #[cfg(feature = "debug")]
+    fn read_u24(&self, offset: usize) -> usize {
+        self.code[offset] as usize
+            | (self.code[offset + 1] as usize) << 8
+            | (self.code[offset + 2] as usize) << 16
+    }
+
+    #[cfg(feature = "debug")]
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from(self.code[offset]) | u16::from(self.code[offset + 1]) << 8
+    }
+
+    #[cfg(feature = "debug")]
+    /// Prints the sign and width the operation works on
+    pub fn arithmetic_instruction(&self, name: &str, offset: usize) -> usize {
+        let size = self.code[offset + 1];
+        let sign = if self.code[offset + 2] != 0 { "i" } else { "u" };
+
+        println!("{:16} {}{}", name, sign, size * 8);
+
+        offset + 3
+    }
+
+    #[cfg(feature = "debug")]
+    /// Prints the jump and the offset it lands on
+    pub fn jump_instruction(&self, name: &str, offset: usize) -> usize {
+        let jump = self.read_u16(offset + 1) as i16;
+        let target = (offset as isize + 3 + jump as isize) as usize;
+
+        println!("{:16} {:04} -> {:04}", name, jump, target);
+
+        offset + 3
+    }
+
+    #[cfg(feature = "debug")]
+    /// Prints the call and the name of the function called
+    pub fn call_instruction(&self, name: &str, offset: usize) -> usize {
+        let function = self.read_u16(offset + 1) as usize;
+
+        match self.functions.get(function) {
+            Some(function) => println!("{:16} {}", name, function.name),
+            None => println!("{:16} {:04}", name, function),
+        }
+
+        offset + 3
+    }
+
+    #[cfg(feature = "debug")]
+    /// Prints the slot and the size of the local
+    pub fn local_instruction(&self, name: &str, offset: usize) -> usize {
+        let slot = self.read_u16(offset + 1);
+        let size = self.code[offset + 3];
+
+        println!("{:16} {:04} {}", name, slot, size);
+
+        offset + 4
+    }
+
+    #[cfg(feature = "debug")]
+    /// Prints the function the closure is made from and what it captures
+    pub fn closure_instruction(&self, name: &str, offset: usize) -> usize {
+        let function = self.read_u16(offset + 1) as usize;
+        let count = self.code[offset + 3] as usize;
+
+        match self.functions.get(function) {
+            Some(function) => print!("{:16} {}", name, function.name),
+            None => print!("{:16} {:04}", name, function),
+        }
+
+        let mut offset = offset + 4;
+
+        for _ in 0..count {
+            let kind = if self.code[offset] != 0 { "local" } else { "upvalue" };
+
+            print!(" {} {}", kind, self.read_u16(offset + 1));
+
+            offset += 3;
         }
 
-        offset as usize + 2
+        println!();
+
+        offset
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn cast_instruction(&self, name: &str, offset: usize) -> usize {
+        let from = self.code[offset + 1];
+        let to = self.code[offset + 2];
+        let sign = if self.code[offset + 3] != 0 { "i" } else { "u" };
+
+        println!("{:16} {}{} -> {}", name, sign, from * 8, to * 8);
+
+        offset + 4
     }
 }
 
@@ -181,6 +459,7 @@ mod test {
 
     chunk.write(7, 1); // Multiply
     chunk.write(4, 1);
+    chunk.write(1, 1); // signed
 
     chunk.write(0, 2);
     chunk.write(4, 2);
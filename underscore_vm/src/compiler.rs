@@ -0,0 +1,795 @@
+//! Lowers an `ir::Program` into a `Chunk` of bytecode.
+//! Every temp gets its own local slot in the frame of the function it is used in
+//! and every instruction loads its operands onto the stack, operates on them
+//! and stores the result back into a slot.
+use chunk::{Chunk, Function, Native, MAX_CONSTANTS};
+use ir::ir::{self, BinOp, Capture, CmpOp, Instruction, Intrinsic, Label, Temp, UnOp, Value};
+use op::{OpCode, PRINT_BOOL, PRINT_CHAR, PRINT_INT, PRINT_STR};
+use std::collections::HashMap;
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::{Symbol, Symbols};
+use vm::VMError;
+
+type CompileResult<T> = Result<T, VMError>;
+
+/// The ir carries no positions so every instruction is attributed to the same line
+const LINE: usize = 0;
+
+/// The type given to a temp that is read before anything was stored in it
+const DEFAULT_TYPE: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+#[derive(Debug)]
+pub struct Compiler {
+    chunk: Chunk,
+    /// Maps a function to its index in the chunk and the type it returns
+    functions: HashMap<Symbol, (u16, (Sign, Size))>,
+    /// Maps an external function to its index in the chunk's natives and the type it returns
+    natives: HashMap<Symbol, (u16, (Sign, Size))>,
+    /// The type of each param of every function and external function
+    params: HashMap<Symbol, Vec<(Sign, Size)>>,
+    /// The type the function being compiled returns
+    returns: (Sign, Size),
+    /// The type of each upvalue of the function being compiled
+    upvalues: Vec<(Sign, Size)>,
+    /// Maps a temp to its local slot in the function being compiled
+    slots: HashMap<Temp, u16>,
+    /// The sign and width of each temp in the function being compiled
+    types: HashMap<Temp, (Sign, Size)>,
+    /// The offset each label in the function was placed at
+    labels: HashMap<Label, usize>,
+    /// Jump operands waiting for their label to be placed
+    jumps: Vec<(usize, Label)>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            chunk: Chunk::new(),
+            functions: HashMap::new(),
+            natives: HashMap::new(),
+            params: HashMap::new(),
+            returns: DEFAULT_TYPE,
+            upvalues: Vec::new(),
+            slots: HashMap::new(),
+            types: HashMap::new(),
+            labels: HashMap::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    pub fn compile<T: Clone>(
+        mut self,
+        program: &ir::Program,
+        symbols: &Symbols<T>,
+    ) -> CompileResult<Chunk> {
+        if program.functions.len() > u16::MAX as usize {
+            return Err(VMError::CompilerError(format!(
+                "Too many functions, a chunk can hold at most {}",
+                u16::MAX
+            )));
+        }
+
+        for function in &program.functions {
+            let name = symbols.name(function.name);
+
+            self.params
+                .insert(function.name, function.param_types.clone());
+
+            // External functions are provided by the vm when the chunk is linked
+            if function.linkage == Linkage::External {
+                let index = self.chunk.natives.len() as u16;
+
+                self.natives.insert(function.name, (index, function.returns));
+                self.chunk.natives.push(Native {
+                    name,
+                    params: function.param_types.clone(),
+                    returns: function.returns,
+                });
+
+                continue;
+            }
+
+            let index = self.chunk.functions.len();
+
+            if name == "main" {
+                self.chunk.entry = Some(index);
+            }
+
+            self.functions
+                .insert(function.name, (index as u16, function.returns));
+            self.chunk.functions.push(Function {
+                name,
+                offset: 0,
+                locals: 0,
+            });
+        }
+
+        for function in &program.functions {
+            if function.linkage == Linkage::External {
+                continue;
+            }
+
+            let index = self.functions[&function.name].0 as usize;
+            self.compile_function(index, function)?;
+        }
+
+        Ok(self.chunk)
+    }
+
+    fn compile_function(&mut self, index: usize, function: &ir::Function) -> CompileResult<()> {
+        self.slots.clear();
+        self.types.clear();
+        self.labels.clear();
+        self.jumps.clear();
+
+        self.returns = function.returns;
+        self.upvalues = function.upvalues.clone();
+        self.chunk.functions[index].offset = self.chunk.len();
+
+        for (param, ty) in function.params.iter().zip(&function.param_types) {
+            self.types.insert(*param, *ty);
+        }
+
+        self.infer_types(function);
+
+        // The arguments are pushed in order so the last one is on top of the stack
+        for param in function.params.iter().rev() {
+            let ty = self.type_of(*param);
+            self.emit_set(*param, ty)?;
+        }
+
+        for instruction in &function.body {
+            self.compile_instruction(instruction)?;
+        }
+
+        // Falling of the end of a function returns zero
+        let (_, size) = function.returns;
+        self.emit_constant(&0u64.to_le_bytes()[..size.size() as usize])?;
+        self.emit_sized(OpCode::Return, size);
+
+        self.patch_jumps()?;
+
+        self.chunk.functions[index].locals = self.slots.len();
+
+        Ok(())
+    }
+
+    fn compile_instruction(&mut self, instruction: &Instruction) -> CompileResult<()> {
+        match *instruction {
+            Instruction::Store(temp, ref value) => {
+                let ty = match *value {
+                    Value::Const(value, sign, size) => {
+                        self.emit_constant(&value.to_le_bytes()[..size.size() as usize])?;
+                        (sign, size)
+                    }
+
+                    Value::Temp(from) => self.emit_get(from)?,
+
+                    Value::Mem(ref bytes) => {
+                        self.emit_string(bytes)?;
+                        (Sign::Unsigned, Size::Bit64)
+                    }
+
+                    Value::Name(ref label) => {
+                        return Err(VMError::CompilerError(format!(
+                            "Cannot store the address of `{}`",
+                            label
+                        )))
+                    }
+                };
+
+                self.emit_set(temp, ty)
+            }
+
+            Instruction::Copy(to, from) => {
+                let ty = self.emit_get(from)?;
+                self.emit_set(to, ty)
+            }
+
+            Instruction::BinOp(lhs, ref op, rhs, to) => {
+                let (sign, size) = self.emit_get(lhs)?;
+                let (rhs_sign, rhs_size) = self.emit_get(rhs)?;
+
+                if rhs_size != size {
+                    self.emit_cast(rhs_sign, rhs_size, size);
+                }
+
+                let ty = match *op {
+                    BinOp::Plus => {
+                        self.emit_arithmetic(OpCode::Add, sign, size);
+                        (sign, size)
+                    }
+                    BinOp::Minus => {
+                        self.emit_arithmetic(OpCode::Subtract, sign, size);
+                        (sign, size)
+                    }
+                    BinOp::Mul => {
+                        self.emit_arithmetic(OpCode::Multiply, sign, size);
+                        (sign, size)
+                    }
+                    BinOp::Div => {
+                        self.emit_arithmetic(OpCode::Divide, sign, size);
+                        (sign, size)
+                    }
+                    BinOp::And => {
+                        self.chunk.write(OpCode::And, LINE);
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                    BinOp::Or => {
+                        self.chunk.write(OpCode::Or, LINE);
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                };
+
+                self.emit_set(to, ty)
+            }
+
+            Instruction::UnOp(to, ref op, from) => {
+                let (sign, size) = self.emit_get(from)?;
+
+                let ty = match *op {
+                    UnOp::Minus => {
+                        self.emit_arithmetic(OpCode::Neg, sign, size);
+                        (sign, size)
+                    }
+                    UnOp::Bang => {
+                        self.chunk.write(OpCode::Not, LINE);
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                };
+
+                self.emit_set(to, ty)
+            }
+
+            Instruction::Cast(to, from, sign, size) => {
+                let (from_sign, from_size) = self.emit_get(from)?;
+
+                self.emit_cast(from_sign, from_size, size);
+
+                self.emit_set(to, (sign, size))
+            }
+
+            Instruction::CJump(lhs, ref op, rhs, ltrue, lfalse) => {
+                // Both sides are compared at the width of the wider one
+                let (sign, lhs_size) = self.type_of(lhs);
+                let (rhs_sign, rhs_size) = self.type_of(rhs);
+                let size = if lhs_size.size() >= rhs_size.size() {
+                    lhs_size
+                } else {
+                    rhs_size
+                };
+
+                self.emit_get(lhs)?;
+
+                if lhs_size != size {
+                    self.emit_cast(sign, lhs_size, size);
+                }
+
+                self.emit_get(rhs)?;
+
+                if rhs_size != size {
+                    self.emit_cast(rhs_sign, rhs_size, size);
+                }
+
+                let op = match *op {
+                    CmpOp::EQ => OpCode::Equal,
+                    CmpOp::NE => OpCode::NotEqual,
+                    CmpOp::LT => OpCode::Less,
+                    CmpOp::LTE => OpCode::LessEqual,
+                    CmpOp::GT => OpCode::Greater,
+                    CmpOp::GTE => OpCode::GreaterEqual,
+                };
+
+                self.emit_arithmetic(op, sign, size);
+                self.emit_jump(OpCode::JumpIfFalse, lfalse);
+                self.emit_jump(OpCode::Jump, ltrue);
+
+                Ok(())
+            }
+
+            Instruction::Jump(label) => {
+                self.emit_jump(OpCode::Jump, label);
+                Ok(())
+            }
+
+            Instruction::Label(label) => {
+                self.labels.insert(label, self.chunk.len());
+                Ok(())
+            }
+
+            Instruction::Call(to, callee, ref args) => {
+                let (op, (index, returns)) = match self.functions.get(&callee) {
+                    Some(function) => (OpCode::Call, *function),
+                    None if self.natives.contains_key(&callee) => {
+                        (OpCode::CallNative, self.natives[&callee])
+                    }
+                    None => {
+                        return Err(VMError::CompilerError(format!(
+                            "Call to undefined function `{}`",
+                            callee
+                        )))
+                    }
+                };
+
+                let params = self.params[&callee].clone();
+
+                for (arg, &(_, size)) in args.iter().zip(&params) {
+                    let (sign, from) = self.emit_get(*arg)?;
+
+                    if from != size {
+                        self.emit_cast(sign, from, size);
+                    }
+                }
+
+                self.chunk.write(op, LINE);
+                self.chunk.write_u16(index, LINE);
+
+                self.emit_set(to, returns)
+            }
+
+            Instruction::Return(temp) => {
+                let (sign, size) = self.emit_get(temp)?;
+                let (_, returns) = self.returns;
+
+                if size != returns {
+                    self.emit_cast(sign, size, returns);
+                }
+
+                self.emit_sized(OpCode::Return, returns);
+
+                Ok(())
+            }
+
+            Instruction::Value(_) => Ok(()),
+
+            Instruction::Block(temp, ref elements) => {
+                if elements.len() >= MAX_CONSTANTS {
+                    return Err(VMError::CompilerError(format!(
+                        "Too many elements, an array can hold at most {}",
+                        MAX_CONSTANTS - 1
+                    )));
+                }
+
+                let mut size = Size::Bit8;
+
+                for element in elements {
+                    size = self.emit_get(*element)?.1;
+                }
+
+                let count = elements.len();
+
+                self.chunk.write(OpCode::Array, LINE);
+                self.chunk.write(count as u8, LINE);
+                self.chunk.write((count >> 8) as u8, LINE);
+                self.chunk.write((count >> 16) as u8, LINE);
+                self.chunk.write(size.size() as u8, LINE);
+
+                self.emit_set(temp, (Sign::Unsigned, Size::Bit64))
+            }
+
+            Instruction::Alloc(temp, size) => {
+                if size > u16::MAX as usize {
+                    return Err(VMError::CompilerError(format!(
+                        "Too big to allocate, a struct can be at most {} bytes",
+                        u16::MAX
+                    )));
+                }
+
+                // The fields start out as zero and are popped off the stack
+                let mut left = size;
+
+                for &chunk in &[8, 4, 1] {
+                    while left >= chunk {
+                        self.emit_constant(&[0; 8][..chunk])?;
+                        left -= chunk;
+                    }
+                }
+
+                self.chunk.write(OpCode::Struct, LINE);
+                self.chunk.write_u16(size as u16, LINE);
+
+                self.emit_set(temp, (Sign::Unsigned, Size::Bit64))
+            }
+
+            Instruction::LoadAt(to, base, offset, sign, size) => {
+                self.emit_get(base)?;
+
+                self.chunk.write(OpCode::GetField, LINE);
+                self.chunk.write_u16(self.field_offset(offset)?, LINE);
+                self.chunk.write(size.size() as u8, LINE);
+
+                self.emit_set(to, (sign, size))
+            }
+
+            Instruction::StoreAt(base, offset, from, _, size) => {
+                self.emit_get(base)?;
+
+                let (from_sign, from_size) = self.emit_get(from)?;
+
+                if from_size != size {
+                    self.emit_cast(from_sign, from_size, size);
+                }
+
+                self.chunk.write(OpCode::SetField, LINE);
+                self.chunk.write_u16(self.field_offset(offset)?, LINE);
+                self.chunk.write(size.size() as u8, LINE);
+
+                Ok(())
+            }
+
+            Instruction::Closure(temp, function, ref captures) => {
+                let index = match self.functions.get(&function) {
+                    Some(&(index, _)) => index,
+                    None => {
+                        return Err(VMError::CompilerError(format!(
+                            "Closure of undefined function `{}`",
+                            function
+                        )))
+                    }
+                };
+
+                if captures.len() > u8::MAX as usize {
+                    return Err(VMError::CompilerError(format!(
+                        "Too many upvalues, a closure can capture at most {}",
+                        u8::MAX
+                    )));
+                }
+
+                let mut operands = Vec::with_capacity(captures.len());
+
+                for capture in captures {
+                    operands.push(match *capture {
+                        Capture::Local(temp) => (true, self.slot(temp)?),
+                        Capture::Upvalue(index) => (false, index as u16),
+                    });
+                }
+
+                self.chunk.write(OpCode::Closure, LINE);
+                self.chunk.write_u16(index, LINE);
+                self.chunk.write(captures.len() as u8, LINE);
+
+                for (local, index) in operands {
+                    self.chunk.write(local as u8, LINE);
+                    self.chunk.write_u16(index, LINE);
+                }
+
+                self.emit_set(temp, (Sign::Unsigned, Size::Bit64))
+            }
+
+            Instruction::GetUpvalue(temp, index) => {
+                let ty = self.upvalue_type(index)?;
+
+                self.chunk.write(OpCode::GetUpvalue, LINE);
+                self.chunk.write_u16(index as u16, LINE);
+                self.chunk.write(ty.1.size() as u8, LINE);
+
+                self.emit_set(temp, ty)
+            }
+
+            Instruction::SetUpvalue(index, temp) => {
+                let (_, size) = self.upvalue_type(index)?;
+                let (from_sign, from_size) = self.emit_get(temp)?;
+
+                if from_size != size {
+                    self.emit_cast(from_sign, from_size, size);
+                }
+
+                self.chunk.write(OpCode::SetUpvalue, LINE);
+                self.chunk.write_u16(index as u16, LINE);
+                self.chunk.write(size.size() as u8, LINE);
+
+                Ok(())
+            }
+
+            Instruction::CloseUpvalue(temp) => {
+                let slot = self.slot(temp)?;
+
+                self.chunk.write(OpCode::CloseUpvalue, LINE);
+                self.chunk.write_u16(slot, LINE);
+
+                Ok(())
+            }
+
+            Instruction::CallClosure(to, callee, ref args, returns) => {
+                for arg in args {
+                    self.emit_get(*arg)?;
+                }
+
+                self.emit_get(callee)?;
+                self.chunk.write(OpCode::CallClosure, LINE);
+
+                self.emit_set(to, returns)
+            }
+
+            Instruction::Intrinsic(to, intrinsic, ref args) => {
+                let mut tys = Vec::with_capacity(args.len());
+
+                for arg in args {
+                    tys.push(self.emit_get(*arg)?);
+                }
+
+                let (sign, size) = tys.first().cloned().unwrap_or(DEFAULT_TYPE);
+
+                let kind = match intrinsic {
+                    Intrinsic::PrintInt => PRINT_INT,
+                    Intrinsic::PrintBool => PRINT_BOOL,
+                    Intrinsic::PrintChar => PRINT_CHAR,
+                    Intrinsic::PrintStr => PRINT_STR,
+                    Intrinsic::Assert => {
+                        self.chunk.write(OpCode::Assert, LINE);
+                        return Ok(());
+                    }
+                    Intrinsic::Exit => {
+                        if size != Size::Bit32 {
+                            self.emit_cast(sign, size, Size::Bit32);
+                        }
+
+                        self.chunk.write(OpCode::Exit, LINE);
+                        return Ok(());
+                    }
+                    Intrinsic::StrLen => {
+                        self.chunk.write(OpCode::Length, LINE);
+                        return self.emit_set(to, (Sign::Signed, Size::Bit32));
+                    }
+                    Intrinsic::StrEq => {
+                        self.chunk.write(OpCode::StringEqual, LINE);
+                        return self.emit_set(to, (Sign::Unsigned, Size::Bit8));
+                    }
+                    Intrinsic::StrConcat => {
+                        self.chunk.write(OpCode::Concat, LINE);
+                        return self.emit_set(to, (Sign::Unsigned, Size::Bit64));
+                    }
+                };
+
+                self.chunk.write(OpCode::Print, LINE);
+                self.chunk.write(kind, LINE);
+                self.emit_arithmetic_operands(sign, size);
+
+                Ok(())
+            }
+
+            Instruction::Load(_) | Instruction::Phi(_, _) => Err(VMError::CompilerError(
+                format!("`{}` is not supported by the bytecode compiler", instruction),
+            )),
+        }
+    }
+
+    fn type_of(&self, temp: Temp) -> (Sign, Size) {
+        *self.types.get(&temp).unwrap_or(&DEFAULT_TYPE)
+    }
+
+    /// Gives every temp the type of the widest value the function stores in it, so a
+    /// temp has the same type wherever it is used whatever order the blocks are in
+    fn infer_types(&mut self, function: &ir::Function) {
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for instruction in &function.body {
+                let (temp, ty) = match self.defined_type(instruction) {
+                    Some(defined) => defined,
+                    None => continue,
+                };
+
+                let wider = match self.types.get(&temp) {
+                    Some(&(_, size)) => ty.1.size() > size.size(),
+                    None => true,
+                };
+
+                if wider {
+                    self.types.insert(temp, ty);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// The temp an instruction stores a value in and the type of that value, if it is
+    /// known yet
+    fn defined_type(&self, instruction: &Instruction) -> Option<(Temp, (Sign, Size))> {
+        const BOOL: (Sign, Size) = (Sign::Unsigned, Size::Bit8);
+        const REFERENCE: (Sign, Size) = (Sign::Unsigned, Size::Bit64);
+
+        let defined = match *instruction {
+            Instruction::Store(temp, Value::Const(_, sign, size)) => (temp, (sign, size)),
+            Instruction::Store(temp, Value::Mem(_)) => (temp, REFERENCE),
+            Instruction::Store(temp, Value::Temp(from)) | Instruction::Copy(temp, from) => {
+                (temp, *self.types.get(&from)?)
+            }
+            Instruction::BinOp(_, BinOp::And, _, to)
+            | Instruction::BinOp(_, BinOp::Or, _, to)
+            | Instruction::UnOp(to, UnOp::Bang, _) => (to, BOOL),
+            Instruction::BinOp(lhs, _, _, to) => (to, *self.types.get(&lhs)?),
+            Instruction::UnOp(to, UnOp::Minus, from) => (to, *self.types.get(&from)?),
+            Instruction::Cast(to, _, sign, size) | Instruction::LoadAt(to, _, _, sign, size) => {
+                (to, (sign, size))
+            }
+            Instruction::Call(to, callee, _) => {
+                let &(_, returns) = self
+                    .functions
+                    .get(&callee)
+                    .or_else(|| self.natives.get(&callee))?;
+                (to, returns)
+            }
+            Instruction::CallClosure(to, _, _, returns) => (to, returns),
+            Instruction::GetUpvalue(temp, index) => (temp, *self.upvalues.get(index)?),
+            Instruction::Intrinsic(to, Intrinsic::StrLen, _) => (to, (Sign::Signed, Size::Bit32)),
+            Instruction::Intrinsic(to, Intrinsic::StrEq, _) => (to, BOOL),
+            Instruction::Intrinsic(to, Intrinsic::StrConcat, _) => (to, REFERENCE),
+            Instruction::Block(temp, _)
+            | Instruction::Alloc(temp, _)
+            | Instruction::Closure(temp, _, _) => (temp, REFERENCE),
+            _ => return None,
+        };
+
+        Some(defined)
+    }
+
+    fn upvalue_type(&self, index: usize) -> CompileResult<(Sign, Size)> {
+        match self.upvalues.get(index) {
+            Some(ty) => Ok(*ty),
+            None => Err(VMError::CompilerError(format!(
+                "Upvalue {} is not captured by the function",
+                index
+            ))),
+        }
+    }
+
+    fn field_offset(&self, offset: usize) -> CompileResult<u16> {
+        if offset > u16::MAX as usize {
+            return Err(VMError::CompilerError(format!(
+                "Offset {} is too far, a field can be at most {} bytes in",
+                offset,
+                u16::MAX
+            )));
+        }
+
+        Ok(offset as u16)
+    }
+
+    fn slot(&mut self, temp: Temp) -> CompileResult<u16> {
+        if let Some(slot) = self.slots.get(&temp) {
+            return Ok(*slot);
+        }
+
+        if self.slots.len() == u16::MAX as usize {
+            return Err(VMError::CompilerError(format!(
+                "Too many locals, a function can have at most {}",
+                u16::MAX
+            )));
+        }
+
+        let slot = self.slots.len() as u16;
+        self.slots.insert(temp, slot);
+        Ok(slot)
+    }
+
+    /// Push the value in a temp onto the stack and return its type
+    fn emit_get(&mut self, temp: Temp) -> CompileResult<(Sign, Size)> {
+        let slot = self.slot(temp)?;
+        let ty = self.type_of(temp);
+
+        self.chunk.write(OpCode::GetLocal, LINE);
+        self.chunk.write_u16(slot, LINE);
+        self.chunk.write(ty.1.size() as u8, LINE);
+
+        Ok(ty)
+    }
+
+    /// Pop the value on top of the stack into a temp, converting it to the temp's type
+    fn emit_set(&mut self, temp: Temp, (sign, size): (Sign, Size)) -> CompileResult<()> {
+        let slot = self.slot(temp)?;
+        let (_, to) = *self.types.entry(temp).or_insert((sign, size));
+
+        if to != size {
+            self.emit_cast(sign, size, to);
+        }
+
+        self.chunk.write(OpCode::SetLocal, LINE);
+        self.chunk.write_u16(slot, LINE);
+        self.chunk.write(to.size() as u8, LINE);
+
+        Ok(())
+    }
+
+    fn emit_sized(&mut self, op: OpCode, size: Size) {
+        self.chunk.write(op, LINE);
+        self.chunk.write(size.size() as u8, LINE);
+    }
+
+    /// Arithmetic and comparisons need to know the sign of their operands as well as their size
+    fn emit_arithmetic(&mut self, op: OpCode, sign: Sign, size: Size) {
+        self.chunk.write(op, LINE);
+        self.emit_arithmetic_operands(sign, size);
+    }
+
+    fn emit_arithmetic_operands(&mut self, sign: Sign, size: Size) {
+        self.chunk.write(size.size() as u8, LINE);
+        self.chunk.write((sign == Sign::Signed) as u8, LINE);
+    }
+
+    fn emit_cast(&mut self, sign: Sign, from: Size, to: Size) {
+        self.chunk.write(OpCode::Cast, LINE);
+        self.chunk.write(from.size() as u8, LINE);
+        self.chunk.write(to.size() as u8, LINE);
+        self.chunk.write((sign == Sign::Signed) as u8, LINE);
+    }
+
+    fn emit_constant(&mut self, bytes: &[u8]) -> CompileResult<()> {
+        if self.chunk.constants.len() + bytes.len() > MAX_CONSTANTS {
+            return Err(VMError::CompilerError(format!(
+                "Too many constants, a chunk can hold at most {} bytes of constants",
+                MAX_CONSTANTS
+            )));
+        }
+
+        self.chunk.write_constant(bytes, LINE);
+
+        Ok(())
+    }
+
+    /// Strings live in the constant pool prefixed by their length and are copied
+    /// onto the heap each time the instruction runs
+    fn emit_string(&mut self, bytes: &[u8]) -> CompileResult<()> {
+        if self.chunk.constants.len() + bytes.len() + 4 > MAX_CONSTANTS {
+            return Err(VMError::CompilerError(format!(
+                "Too many constants, a chunk can hold at most {} bytes of constants",
+                MAX_CONSTANTS
+            )));
+        }
+
+        let offset = self
+            .chunk
+            .add_constant(&(bytes.len() as u32).to_le_bytes(), LINE);
+        self.chunk.add_constant(bytes, LINE);
+
+        self.chunk.write(OpCode::String, LINE);
+        self.chunk.write(offset as u8, LINE);
+        self.chunk.write((offset >> 8) as u8, LINE);
+        self.chunk.write((offset >> 16) as u8, LINE);
+
+        Ok(())
+    }
+
+    fn emit_jump(&mut self, op: OpCode, label: Label) {
+        self.chunk.write(op, LINE);
+        self.jumps.push((self.chunk.len(), label));
+        self.chunk.write_u16(0, LINE);
+    }
+
+    /// Now that every label has been placed fill in the jump offsets.
+    /// Offsets are relative to the instruction after the jump
+    fn patch_jumps(&mut self) -> CompileResult<()> {
+        for &(operand, label) in &self.jumps {
+            let target = match self.labels.get(&label) {
+                Some(target) => *target as isize,
+                None => {
+                    return Err(VMError::CompilerError(format!(
+                        "Jump to undefined label `{}`",
+                        label
+                    )))
+                }
+            };
+
+            let offset = target - (operand as isize + 2);
+
+            if offset < i16::MIN as isize || offset > i16::MAX as isize {
+                return Err(VMError::CompilerError("Too much code to jump over".into()));
+            }
+
+            let offset = offset as i16 as u16;
+
+            self.chunk.patch(operand, offset as u8);
+            self.chunk.patch(operand + 1, (offset >> 8) as u8);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
@@ -0,0 +1,203 @@
+//! The objects the vm allocates and the garbage collector that frees them.
+//!
+//! Objects are referred to by an 8 byte reference, the index of the object
+//! tagged with `REF_TAG` in its upper bytes. Values on the stack carry no type,
+//! so the collector is conservative: any 8 bytes that look like a reference
+//! to a live object keep that object alive.
+use std::mem;
+
+/// Marks a value as a reference to a heap object
+const REF_TAG: u64 = 0x5F5F << 48;
+
+/// The mask for the index of the object a reference points to
+const INDEX_MASK: u64 = (1 << 48) - 1;
+
+/// Collect once this many bytes have been allocated since the last collection
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+
+/// What kind of value an object holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    /// The bytes of a string
+    String,
+    /// The elements of a fixed size array, laid out one after the other
+    Array,
+    /// The fields of a struct instance, laid out one after the other
+    Struct,
+    /// The eight byte index of a function followed by a reference to each upvalue
+    Closure,
+    /// An eight byte variable captured by a closure once it has left scope
+    Upvalue,
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+    marked: bool,
+}
+
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    /// Slots in `objects` that have been freed and can be reused
+    free: Vec<usize>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    /// Collect on every allocation
+    pub stress: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            free: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_THRESHOLD,
+            stress: cfg!(feature = "stress_gc"),
+        }
+    }
+
+    /// The number of live objects
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the next allocation should be preceded by a collection
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated > self.next_gc
+    }
+
+    /// Allocates an object and returns a reference to it
+    pub fn alloc(&mut self, kind: ObjectKind, data: Vec<u8>) -> u64 {
+        self.bytes_allocated += data.len() + mem::size_of::<Object>();
+
+        let object = Some(Object {
+            kind,
+            data,
+            marked: false,
+        });
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = object;
+                index
+            }
+            None => {
+                self.objects.push(object);
+                self.objects.len() - 1
+            }
+        };
+
+        REF_TAG | index as u64
+    }
+
+    pub fn get(&self, reference: u64) -> Option<&Object> {
+        self.index(reference)
+            .and_then(|index| self.objects[index].as_ref())
+    }
+
+    pub fn get_mut(&mut self, reference: u64) -> Option<&mut Object> {
+        match self.index(reference) {
+            Some(index) => self.objects[index].as_mut(),
+            None => None,
+        }
+    }
+
+    /// Frees every object that can't be reached from the roots
+    pub fn collect(&mut self, roots: &[&[u8]]) {
+        let mut grey = Vec::new();
+
+        for root in roots {
+            self.mark_bytes(root, &mut grey);
+        }
+
+        // An object's data is moved out while the objects it refers to are marked, which
+        // is safe as it is already marked itself
+        while let Some(index) = grey.pop() {
+            let data = match self.objects[index] {
+                Some(ref mut object) => mem::take(&mut object.data),
+                None => continue,
+            };
+
+            self.mark_bytes(&data, &mut grey);
+
+            if let Some(ref mut object) = self.objects[index] {
+                object.data = data;
+            }
+        }
+
+        self.sweep();
+
+        self.next_gc = ::std::cmp::max(self.bytes_allocated * 2, INITIAL_THRESHOLD);
+    }
+
+    /// Marks every object referenced from `bytes` and queues it to be traced
+    fn mark_bytes(&mut self, bytes: &[u8], grey: &mut Vec<usize>) {
+        if bytes.len() < 8 {
+            return;
+        }
+
+        for start in 0..bytes.len() - 7 {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[start..start + 8]);
+
+            let index = match self.index(u64::from_le_bytes(word)) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            if let Some(ref mut object) = self.objects[index] {
+                if !object.marked {
+                    object.marked = true;
+                    grey.push(index);
+                }
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let free = match *slot {
+                Some(ref mut object) if object.marked => {
+                    object.marked = false;
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            };
+
+            if free {
+                let object = slot.take().unwrap();
+                self.bytes_allocated -= object.data.len() + mem::size_of::<Object>();
+                self.free.push(index);
+            }
+        }
+    }
+
+    /// The index of the object a reference points to, if it is one
+    fn index(&self, reference: u64) -> Option<usize> {
+        if reference & !INDEX_MASK != REF_TAG {
+            return None;
+        }
+
+        let index = (reference & INDEX_MASK) as usize;
+
+        if index < self.objects.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
@@ -1,9 +1,19 @@
+extern crate underscore_ir as ir;
+extern crate underscore_syntax as syntax;
+extern crate underscore_util as util;
+
 #[macro_use]
 mod chunk;
 
+mod compiler;
+mod heap;
+mod native;
 mod op;
 #[macro_use]
 mod vm;
 
 pub use chunk::Chunk;
-pub use vm::VM;
+pub use compiler::Compiler;
+pub use heap::{Heap, Object, ObjectKind};
+pub use native::{NativeFn, Value, LIBC};
+pub use vm::{VMError, VM};
@@ -0,0 +1,72 @@
+//! Host functions that `external fn` declarations are bound to.
+use std::io::{self, Write};
+use syntax::ast::{Sign, Size};
+use vm::VMError;
+
+/// A value passed to or returned from a native function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    UInt(u64),
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, VMError>;
+
+impl Value {
+    /// Widens the little endian bytes of a value of the given sign
+    pub fn from_bytes(bytes: &[u8], sign: Sign) -> Value {
+        let mut buf = [0u8; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+
+        let value = u64::from_le_bytes(buf);
+
+        match sign {
+            Sign::Unsigned => Value::UInt(value),
+            Sign::Signed => {
+                let shift = 64 - bytes.len() * 8;
+                Value::Int(((value << shift) as i64) >> shift)
+            }
+        }
+    }
+
+    /// The little endian bytes of the value truncated to `size`
+    pub fn to_bytes(self, size: Size) -> Vec<u8> {
+        let value = match self {
+            Value::Int(value) => value as u64,
+            Value::UInt(value) => value,
+        };
+
+        value.to_le_bytes()[..size.size() as usize].to_vec()
+    }
+}
+
+/// Natives that behave like the C library functions of the same name, so a program
+/// runs the same in the vm as it does once compiled and linked against libc
+pub const LIBC: &[(&str, usize, NativeFn)] = &[
+    ("abs", 1, abs),
+    ("labs", 1, abs),
+    ("putchar", 1, putchar),
+];
+
+impl Value {
+    fn int(self) -> i64 {
+        match self {
+            Value::Int(value) => value,
+            Value::UInt(value) => value as i64,
+        }
+    }
+}
+
+fn abs(args: &[Value]) -> Result<Value, VMError> {
+    Ok(Value::Int(args[0].int().wrapping_abs()))
+}
+
+fn putchar(args: &[Value]) -> Result<Value, VMError> {
+    let byte = args[0].int() as u8;
+
+    io::stdout()
+        .write_all(&[byte])
+        .map_err(|e| VMError::RuntimeError(e.to_string()))?;
+
+    Ok(Value::Int(i64::from(byte)))
+}
@@ -17,15 +17,92 @@ macro_rules! op_inner {
 
 op! {
     Return,
+    /// Followed by the offset of the constant in the pool
     Constant8,
     Constant32,
     Constant64,
+    /// Arithmetic is followed by the size of the operands and whether they are signed.
+    /// It wraps on overflow
     Neg,
     Add,
     Subtract ,
     Multiply ,
     Divide,
+    Not,
+    /// Comparisons are followed by the size of the operands and whether they are signed.
+    /// They push a single byte bool
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    /// Followed by a two byte signed offset relative to the next instruction
+    Jump,
+    JumpIfFalse,
+    /// Followed by a two byte function index
+    Call,
+    /// Followed by a two byte slot and the size of the value
+    GetLocal,
+    SetLocal,
+    /// Followed by the size to cast from, the size to cast to and
+    /// whether the value being cast is signed
+    Cast,
+    /// Followed by a three byte offset of the constant in the pool
+    Constant8Long,
+    Constant32Long,
+    Constant64Long,
+    /// Followed by the three byte offset of a string in the pool.
+    /// The string is stored as its four byte length followed by its bytes
+    String,
+    /// Followed by a three byte element count and the size of each element.
+    /// Pops the elements and pushes a reference to the new array
+    Array,
+    /// Followed by the two byte size of the struct.
+    /// Pops the fields and pushes a reference to the new struct
+    Struct,
+    /// Followed by the two byte offset and the size of the field.
+    /// Pops the struct reference
+    GetField,
+    /// Followed by the two byte offset and the size of the field.
+    /// Pops the value and the struct reference
+    SetField,
+    /// Followed by a two byte function index and the number of upvalues. Each
+    /// upvalue is a byte saying whether it captures a local of the running function
+    /// and a two byte slot, or an upvalue of the running closure and its index
+    Closure,
+    /// Followed by the two byte index of the upvalue and the size of the value
+    GetUpvalue,
+    SetUpvalue,
+    /// Followed by a two byte slot whose upvalue should be closed
+    CloseUpvalue,
+    /// Pops a closure reference and calls it with the arguments below it
+    CallClosure,
+    /// Followed by a two byte index of the native function to call
+    CallNative,
+    /// Followed by what is being printed, the size of the value and whether it is signed.
+    /// Pops the value and writes it to stdout followed by a newline
+    Print,
+    /// Pops a bool and stops with a runtime error if it is false
+    Assert,
+    /// Pops a four byte exit code and stops the program
+    Exit,
+    /// Pops a string reference and pushes its four byte length
+    Length,
+    /// Pops two string references and pushes a bool saying whether they hold the same bytes
+    StringEqual,
+    /// Pops two string references and pushes a reference to a new string holding both
+    Concat,
 }
+
+/// What a `Print` prints its value as
+pub const PRINT_INT: u8 = 0;
+pub const PRINT_BOOL: u8 = 1;
+pub const PRINT_CHAR: u8 = 2;
+pub const PRINT_STR: u8 = 3;
+
 pub trait TryFrom<T>: Sized {
     /// The type returned in the event of a conversion error.
     type Error;
@@ -55,6 +132,41 @@ impl TryFrom<u8> for OpCode {
             6 => Ok(Subtract),
             7 => Ok(Multiply),
             8 => Ok(Divide),
+            9 => Ok(Not),
+            10 => Ok(Equal),
+            11 => Ok(NotEqual),
+            12 => Ok(Less),
+            13 => Ok(LessEqual),
+            14 => Ok(Greater),
+            15 => Ok(GreaterEqual),
+            16 => Ok(And),
+            17 => Ok(Or),
+            18 => Ok(Jump),
+            19 => Ok(JumpIfFalse),
+            20 => Ok(Call),
+            21 => Ok(GetLocal),
+            22 => Ok(SetLocal),
+            23 => Ok(Cast),
+            24 => Ok(Constant8Long),
+            25 => Ok(Constant32Long),
+            26 => Ok(Constant64Long),
+            27 => Ok(String),
+            28 => Ok(Array),
+            29 => Ok(Struct),
+            30 => Ok(GetField),
+            31 => Ok(SetField),
+            32 => Ok(Closure),
+            33 => Ok(GetUpvalue),
+            34 => Ok(SetUpvalue),
+            35 => Ok(CloseUpvalue),
+            36 => Ok(CallClosure),
+            37 => Ok(CallNative),
+            38 => Ok(Print),
+            39 => Ok(Assert),
+            40 => Ok(Exit),
+            41 => Ok(Length),
+            42 => Ok(StringEqual),
+            43 => Ok(Concat),
             _ => Err(()),
         }
     }
@@ -1,11 +1,53 @@
 use chunk::Chunk;
-use op::{OpCode, TryFrom};
+use heap::{Heap, ObjectKind};
+use native::{NativeFn, Value};
+use op::{OpCode, TryFrom, PRINT_BOOL, PRINT_CHAR, PRINT_INT, PRINT_STR};
+use std::collections::HashMap;
+use syntax::ast::Sign;
+
+/// The number of bytes each local slot takes up
+const SLOT_SIZE: usize = 8;
+
+/// The default number of bytes the stack and locals can grow to
+pub const STACK_SIZE: usize = 1 << 20;
+
+/// The deepest calls can be nested
+const MAX_FRAMES: usize = 1 << 16;
 
 pub struct VM<'a> {
     pub code: &'a mut Chunk,
-    stack: [u8; 256],
+    stack: Vec<u8>,
     stack_top: usize,
+    /// The number of bytes the stack and locals together can grow to
+    max_stack: usize,
     ip: usize,
+    frames: Vec<CallFrame>,
+    /// The local slots of every active call frame
+    locals: Vec<u8>,
+    pub heap: Heap,
+    /// Upvalues that still point at a local slot, by the slot's offset in `locals`
+    open_upvalues: Vec<(usize, u64)>,
+    /// The host functions that can be called, with their arity
+    natives: HashMap<String, (usize, NativeFn)>,
+    /// The host function for each of the chunk's natives, once linked
+    linked: Vec<NativeFn>,
+    /// The code the program asked to exit with
+    pub exit: Option<i32>,
+    /// Print the disassembly of the chunk before running it, when built with `debug`
+    pub disassemble: bool,
+}
+
+/// An active function call
+#[derive(Debug, Clone, Copy)]
+struct CallFrame {
+    /// The index of the function being run
+    function: usize,
+    /// Where to resume the caller once this function returns
+    ip: usize,
+    /// The offset of the frame's first slot in `VM::locals`
+    base: usize,
+    /// The closure being run, if the function was called through one
+    closure: Option<u64>,
 }
 
 /// Converts a slice of n length to type
@@ -15,6 +57,10 @@ macro_rules! to_num {
         use std::default;
         use std::mem;
 
+        if $top < mem::size_of::<$type>() {
+            return Err(VMError::StackUnderflow);
+        }
+
         $top -= mem::size_of::<$type>();
 
         let mut b: [u8; mem::size_of::<$type>()] = default::Default::default();
@@ -26,7 +72,11 @@ macro_rules! to_num {
 
 macro_rules! push {
     ($bytes:expr => $stack:expr,[$from:expr, $to:expr]) => {{
-        let mut b = &mut$stack[$from..($from + $to)];
+        if $stack.len() < $from + $to {
+            $stack.resize($from + $to, 0);
+        }
+
+        let b = &mut $stack[$from..($from + $to)];
 
         b.copy_from_slice($bytes);
 
@@ -34,10 +84,6 @@ macro_rules! push {
     }};
 }
 
-macro_rules! debug {
-    ($($p:tt)*) => {if cfg!(feature = "debug") { println!($($p)*) } else { }}
-}
-
 macro_rules! to_bytes {
     ($expr:expr => $type:ty) => {{
         use std::mem;
@@ -45,34 +91,91 @@ macro_rules! to_bytes {
     }};
 }
 
+/// Pops two numbers of `$type`, applies `$op` and pushes the result.
+/// When `$checked` is set a zero right hand side is reported as a runtime error
+macro_rules! arithmetic {
+    ($op:ident, $_self:ident, $type:ty, $checked:expr) => {{
+        use std::mem;
+
+        let b = to_num!([&$_self.stack,$_self.stack_top] => $type);
+        let a = to_num!([&$_self.stack,$_self.stack_top] => $type);
+
+        if $checked && b == 0 {
+            return Err(VMError::RuntimeError("Attempted to divide by zero".into()));
+        }
+
+        push!(&to_bytes!(a.$op(b) => $type) => $_self.stack,[$_self.stack_top,mem::size_of::<$type>()]);
+    }};
+}
+
+/// Arithmetic wraps on overflow in the width of the operands
 macro_rules! binary_op {
-    ($op:tt, $_self:ident) => {{
-        $_self.ip += 1;
+    ($op:ident, $_self:ident) => {{
+        binary_op!($op, $_self, false)
+    }};
 
-        let size = $_self.code[$_self.ip] as usize;
+    ($op:ident, $_self:ident, $checked:expr) => {{
+        let size = $_self.read_byte() as usize;
+        let signed = $_self.read_byte() != 0;
 
-        $_self.ip += 1;
+        match (size, signed) {
+            (1, true) => arithmetic!($op, $_self, i8, $checked),
+            (1, false) => arithmetic!($op, $_self, u8, $checked),
+            (4, true) => arithmetic!($op, $_self, i32, $checked),
+            (4, false) => arithmetic!($op, $_self, u32, $checked),
+            (8, true) => arithmetic!($op, $_self, i64, $checked),
+            (8, false) => arithmetic!($op, $_self, u64, $checked),
+            _ => unreachable!(),
+        };
+    }};
+}
 
-        match size {
-            1 => {
-                let a = to_num!([&$_self.stack,$_self.stack_top] => i8);
-                let b = to_num!([&$_self.stack,$_self.stack_top] => i8);
-                push!( &to_bytes!(a $op b => i8)     => $_self.stack,[$_self.stack_top,size]);
-            }
+/// Pops two numbers of `$type` and compares them
+macro_rules! compare {
+    ($op:tt, $_self:ident, $type:ty) => {{
+        let b = to_num!([&$_self.stack,$_self.stack_top] => $type);
+        let a = to_num!([&$_self.stack,$_self.stack_top] => $type);
+        a $op b
+    }};
+}
 
-            4 => {
-                let a = to_num!([&$_self.stack,$_self.stack_top] => i32);
-                let b = to_num!([&$_self.stack,$_self.stack_top] => i32);
-                push!( &to_bytes!(a $op b => i32)     => $_self.stack,[$_self.stack_top,size]);
-            }
+/// Compares the top two values and pushes the result as a single byte
+macro_rules! compare_op {
+    ($op:tt, $_self:ident) => {{
+        let size = $_self.read_byte() as usize;
+        let signed = $_self.read_byte() != 0;
 
-            8 => {
-                let a = to_num!([&$_self.stack,$_self.stack_top] => i64);
-                let b = to_num!([&$_self.stack,$_self.stack_top] => i64);
-                push!( &to_bytes!(a $op b => i64)     => $_self.stack,[$_self.stack_top,size]);
-            }
+        let result = match (size, signed) {
+            (1, true) => compare!($op, $_self, i8),
+            (1, false) => compare!($op, $_self, u8),
+            (4, true) => compare!($op, $_self, i32),
+            (4, false) => compare!($op, $_self, u32),
+            (8, true) => compare!($op, $_self, i64),
+            (8, false) => compare!($op, $_self, u64),
             _ => unreachable!(),
         };
+
+        push!(&[result as u8] => $_self.stack,[$_self.stack_top,1]);
+    }};
+}
+
+/// Pops a number of `$type` and pushes its negation
+macro_rules! negate {
+    ($_self:ident, $type:ty) => {{
+        use std::mem;
+
+        let a = to_num!([&$_self.stack,$_self.stack_top] => $type);
+        push!(&to_bytes!(a.wrapping_neg() => $type) => $_self.stack,[$_self.stack_top,mem::size_of::<$type>()]);
+    }};
+}
+
+/// Pops two bools and pushes the result as a single byte
+macro_rules! logical_op {
+    ($op:tt, $_self:ident) => {{
+        let b = to_num!([&$_self.stack,$_self.stack_top] => u8) != 0;
+        let a = to_num!([&$_self.stack,$_self.stack_top] => u8) != 0;
+
+        push!(&[(a $op b) as u8] => $_self.stack,[$_self.stack_top,1]);
     }};
 }
 
@@ -80,30 +183,102 @@ type VMResult = Result<(), VMError>;
 
 #[derive(Debug)]
 pub enum VMError {
-    CompilerError,
-    RuntimeError,
+    CompilerError(String),
+    RuntimeError(String),
+    /// The stack grew past its maximum size.
+    /// Contains the functions that were being called, innermost first
+    StackOverflow(Vec<String>),
+    /// An instruction tried to pop more than was on the stack
+    StackUnderflow,
 }
 
 impl<'a> VM<'a> {
-    
     pub fn new(code: &'a mut Chunk) -> Self {
+        VM::with_stack_size(code, STACK_SIZE)
+    }
+
+    /// A vm whose stack and locals can grow to at most `max_stack` bytes
+    pub fn with_stack_size(code: &'a mut Chunk, max_stack: usize) -> Self {
         VM {
             ip: 0,
-            stack_top: 1,
-            stack: [0; 256],
+            stack_top: 0,
+            stack: Vec::new(),
+            max_stack,
             code,
+            frames: Vec::new(),
+            locals: Vec::new(),
+            open_upvalues: Vec::new(),
+            heap: Heap::new(),
+            natives: HashMap::new(),
+            linked: Vec::new(),
+            exit: None,
+            disassemble: cfg!(feature = "debug"),
+        }
+    }
+
+    /// Makes a host function available to `external fn` declarations of the same name
+    pub fn register_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.natives.insert(name.into(), (arity, function));
+    }
+
+    /// Binds every external function the chunk calls to a registered native
+    pub fn link(&mut self) -> VMResult {
+        self.linked.clear();
+
+        for native in &self.code.natives {
+            match self.natives.get(&native.name) {
+                Some(&(arity, function)) if arity == native.params.len() => {
+                    self.linked.push(function)
+                }
+                Some(&(arity, _)) => {
+                    return Err(VMError::CompilerError(format!(
+                        "External function `{}` takes {} arguments but the native registered for it takes {}",
+                        native.name,
+                        native.params.len(),
+                        arity
+                    )))
+                }
+                None => {
+                    return Err(VMError::CompilerError(format!(
+                        "No native function registered for external function `{}`",
+                        native.name
+                    )))
+                }
+            }
         }
+
+        Ok(())
+    }
+
+    /// The bytes of the value of `size` on top of the stack
+    pub fn peek(&self, size: usize) -> &[u8] {
+        &self.stack[self.stack_top - size..self.stack_top]
     }
 
     pub fn run(&mut self) -> VMResult {
-        debug!("{:?}", self.code.dissassemble("test"));
+        #[cfg(feature = "debug")]
+        {
+            if self.disassemble {
+                self.code.dissassemble("test");
+            }
+        }
+
+        self.link()?;
+
+        if let Some(entry) = self.code.entry {
+            self.call(entry)?;
+        }
 
         loop {
+            if self.stack_top + self.locals.len() > self.max_stack {
+                return Err(self.overflow());
+            }
+
             if cfg!(feature = "stack") {
                 println!("[");
 
-                for (i, byte) in self.stack.iter().enumerate() {
-                    if i + 1 == self.stack.len() {
+                for (i, byte) in self.stack[..self.stack_top].iter().enumerate() {
+                    if i + 1 == self.stack_top {
                         print!("{}", byte);
                     } else {
                         print!("{},", byte);
@@ -113,94 +288,1089 @@ impl<'a> VM<'a> {
                 println!("]");
             }
 
-            match OpCode::try_from(self.code[self.ip]) {
+            match OpCode::try_from(self.read_byte()) {
                 Ok(OpCode::Return) => {
-                    self.ip += 1;
+                    let size = self.read_byte() as usize;
 
-                    let size = self.code[self.ip] as usize;
+                    if let Some(frame) = self.frames.pop() {
+                        self.close_upvalues(frame.base);
+                        self.locals.truncate(frame.base);
+                        self.ip = frame.ip;
+                    }
 
-                    match size {
-                        1 => println!("{}", to_num!([&self.stack,self.stack_top] => i8)),
+                    // The returned value is left on top of the stack for the caller
+                    if !self.frames.is_empty() {
+                        continue;
+                    }
 
-                        4 => {
-                            println!("{}", to_num!([&self.stack,self.stack_top] => i32));
-                        }
+                    // The entry point's value is left on the stack for the host
+                    if self.stack_top < size {
+                        return Err(VMError::StackUnderflow);
+                    }
 
-                        8 => {
-                            println!("{}", to_num!([&self.stack,self.stack_top] => i64));
-                        }
+                    return Ok(());
+                }
+                Ok(OpCode::Constant8) => {
+                    let index = self.read_byte() as usize;
+                    self.constant(index, 1);
+                }
+                Ok(OpCode::Constant32) => {
+                    let index = self.read_byte() as usize;
+                    self.constant(index, 4);
+                }
+                Ok(OpCode::Constant64) => {
+                    let index = self.read_byte() as usize;
+                    self.constant(index, 8);
+                }
+                Ok(OpCode::Constant8Long) => {
+                    let index = self.read_u24();
+                    self.constant(index, 1);
+                }
+                Ok(OpCode::Constant32Long) => {
+                    let index = self.read_u24();
+                    self.constant(index, 4);
+                }
+                Ok(OpCode::Constant64Long) => {
+                    let index = self.read_u24();
+                    self.constant(index, 8);
+                }
+
+                Ok(OpCode::Neg) => {
+                    let size = self.read_byte() as usize;
+                    let signed = self.read_byte() != 0;
+
+                    match (size, signed) {
+                        (1, true) => negate!(self, i8),
+                        (1, false) => negate!(self, u8),
+                        (4, true) => negate!(self, i32),
+                        (4, false) => negate!(self, u32),
+                        (8, true) => negate!(self, i64),
+                        (8, false) => negate!(self, u64),
                         _ => unreachable!(),
                     };
+                }
 
-                    return Ok(());
+                Ok(OpCode::Add) => binary_op!(wrapping_add, self),
+                Ok(OpCode::Divide) => binary_op!(wrapping_div, self, true),
+                Ok(OpCode::Multiply) => binary_op!(wrapping_mul, self),
+                Ok(OpCode::Subtract) => binary_op!(wrapping_sub, self),
+
+                Ok(OpCode::Not) => {
+                    let a = to_num!([&self.stack,self.stack_top] => u8);
+                    push!(&[(a == 0) as u8] => self.stack,[self.stack_top,1]);
                 }
-                Ok(OpCode::Constant8) => {
-                    self.ip += 1;
 
-                    let index = self.code[self.ip] as usize;
+                Ok(OpCode::Equal) => compare_op!(==,self),
+                Ok(OpCode::NotEqual) => compare_op!(!=,self),
+                Ok(OpCode::Less) => compare_op!(<,self),
+                Ok(OpCode::LessEqual) => compare_op!(<=,self),
+                Ok(OpCode::Greater) => compare_op!(>,self),
+                Ok(OpCode::GreaterEqual) => compare_op!(>=,self),
 
-                    push!(&self.code.constants[index..index+1] => self.stack,[self.stack_top,1]);
-                    self.ip += 1;
+                Ok(OpCode::And) => logical_op!(&&,self),
+                Ok(OpCode::Or) => logical_op!(||,self),
 
-                    // break;
+                Ok(OpCode::Jump) => {
+                    let offset = self.read_u16() as i16;
+                    self.jump(offset);
                 }
-                Ok(OpCode::Constant32) => {
-                    self.ip += 1;
 
-                    let index = self.code[self.ip] as usize;
+                Ok(OpCode::JumpIfFalse) => {
+                    let offset = self.read_u16() as i16;
 
-                    push!(&self.code.constants[index..index+4] => self.stack,[self.stack_top,4]);
-                    self.ip += 1;
+                    if to_num!([&self.stack,self.stack_top] => u8) == 0 {
+                        self.jump(offset);
+                    }
+                }
 
-                    // break;
+                Ok(OpCode::Call) => {
+                    let function = self.read_u16() as usize;
+                    self.call(function)?;
+                }
+
+                Ok(OpCode::GetLocal) => {
+                    let slot = self.slot();
+                    let size = self.read_byte() as usize;
+
+                    push!(&self.locals[slot..slot + size] => self.stack,[self.stack_top,size]);
                 }
-                Ok(OpCode::Constant64) => {
-                    self.ip += 1;
 
-                    let index = self.code[self.ip] as usize;
+                Ok(OpCode::SetLocal) => {
+                    let slot = self.slot();
+                    let size = self.read_byte() as usize;
 
-                    push!(&self.code.constants[index..index+8] => self.stack,[self.stack_top,8]);
-                    self.ip += 1;
+                    if self.stack_top < size {
+                        return Err(VMError::StackUnderflow);
+                    }
 
-                    // break;
+                    self.stack_top -= size;
+                    self.locals[slot..slot + size]
+                        .copy_from_slice(&self.stack[self.stack_top..self.stack_top + size]);
                 }
 
-                Ok(OpCode::Neg) => {
-                    self.ip += 1;
+                Ok(OpCode::Cast) => {
+                    let from = self.read_byte() as usize;
+                    let to = self.read_byte() as usize;
+                    let signed = self.read_byte() != 0;
 
-                    let size = self.code[self.ip] as usize;
-                    self.ip += 1;
+                    let value = match (from, signed) {
+                        (1, true) => i64::from(to_num!([&self.stack,self.stack_top] => i8)),
+                        (4, true) => i64::from(to_num!([&self.stack,self.stack_top] => i32)),
+                        (1, false) => i64::from(to_num!([&self.stack,self.stack_top] => u8)),
+                        (4, false) => i64::from(to_num!([&self.stack,self.stack_top] => u32)),
+                        (8, _) => to_num!([&self.stack,self.stack_top] => i64),
+                        _ => unreachable!(),
+                    };
 
-                    match size {
-                        1 => {
-                            let a = to_num!([&self.stack,self.stack_top] => i8);
-                            push!( &to_bytes!(-a => i8)     => self.stack,[self.stack_top,size]);
-                        }
+                    push!(&to_bytes!(value => i64)[..to] => self.stack,[self.stack_top,to]);
+                }
+
+                Ok(OpCode::String) => {
+                    let offset = self.read_u24();
+
+                    self.collect_garbage();
+
+                    let mut len = [0u8; 4];
+                    len.copy_from_slice(&self.code.constants[offset..offset + 4]);
+                    let len = u32::from_le_bytes(len) as usize;
+                    let bytes = self.code.constants[offset + 4..offset + 4 + len].to_vec();
+
+                    let reference = self.heap.alloc(ObjectKind::String, bytes);
+                    self.push_ref(reference);
+                }
+
+                Ok(OpCode::Array) => {
+                    let count = self.read_u24();
+                    let size = self.read_byte() as usize;
+
+                    self.collect_garbage();
+
+                    let elements = self.pop_bytes(count * size)?;
+                    let reference = self.heap.alloc(ObjectKind::Array, elements);
+                    self.push_ref(reference);
+                }
+
+                Ok(OpCode::Struct) => {
+                    let size = self.read_u16() as usize;
+
+                    self.collect_garbage();
+
+                    let fields = self.pop_bytes(size)?;
+                    let reference = self.heap.alloc(ObjectKind::Struct, fields);
+                    self.push_ref(reference);
+                }
+
+                Ok(OpCode::GetField) => {
+                    let offset = self.read_u16() as usize;
+                    let size = self.read_byte() as usize;
+                    let reference = to_num!([&self.stack,self.stack_top] => u64);
+
+                    self.load(reference, offset, size)?;
+                }
+
+                Ok(OpCode::SetField) => {
+                    let offset = self.read_u16() as usize;
+                    let size = self.read_byte() as usize;
+                    let value = self.pop_bytes(size)?;
+                    let reference = to_num!([&self.stack,self.stack_top] => u64);
+
+                    self.store(reference, offset, &value)?;
+                }
+
+                Ok(OpCode::Closure) => {
+                    let function = self.read_u16() as u64;
+                    let count = self.read_byte() as usize;
+
+                    self.collect_garbage();
+
+                    let mut data = Vec::with_capacity(8 + count * 8);
+                    data.extend_from_slice(&function.to_le_bytes());
+
+                    for _ in 0..count {
+                        let local = self.read_byte() != 0;
+
+                        let upvalue = if local {
+                            let slot = self.slot();
+                            self.capture(slot)
+                        } else {
+                            let index = self.read_u16() as usize;
+                            self.upvalue(index)?
+                        };
 
-                        4 => {
-                            let a = to_num!([&self.stack,self.stack_top] => i32);
-                            push!( &to_bytes!(-a => i32)     => self.stack,[self.stack_top,size]);
+                        data.extend_from_slice(&upvalue.to_le_bytes());
+                    }
+
+                    let reference = self.heap.alloc(ObjectKind::Closure, data);
+                    self.push_ref(reference);
+                }
+
+                Ok(OpCode::GetUpvalue) => {
+                    let index = self.read_u16() as usize;
+                    let size = self.read_byte() as usize;
+                    let upvalue = self.upvalue(index)?;
+
+                    match self.open_upvalues.iter().find(|&&(_, u)| u == upvalue) {
+                        Some(&(slot, _)) => {
+                            push!(&self.locals[slot..slot + size] => self.stack,[self.stack_top,size]);
                         }
+                        None => self.load(upvalue, 0, size)?,
+                    }
+                }
+
+                Ok(OpCode::SetUpvalue) => {
+                    let index = self.read_u16() as usize;
+                    let size = self.read_byte() as usize;
+                    let value = self.pop_bytes(size)?;
+                    let upvalue = self.upvalue(index)?;
+
+                    match self.open_upvalues.iter().find(|&&(_, u)| u == upvalue) {
+                        Some(&(slot, _)) => self.locals[slot..slot + size].copy_from_slice(&value),
+                        None => self.store(upvalue, 0, &value)?,
+                    }
+                }
+
+                Ok(OpCode::CloseUpvalue) => {
+                    let slot = self.slot();
+
+                    if let Some(pos) = self.open_upvalues.iter().position(|&(s, _)| s == slot) {
+                        let (_, upvalue) = self.open_upvalues.remove(pos);
+                        let value = self.locals[slot..slot + SLOT_SIZE].to_vec();
 
-                        8 => {
-                            let a = to_num!([&self.stack,self.stack_top] => i64);
-                            push!( &to_bytes!(-a => i64)     => self.stack,[self.stack_top,size]);
+                        self.store(upvalue, 0, &value)?;
+                    }
+                }
+
+                Ok(OpCode::CallClosure) => {
+                    let reference = to_num!([&self.stack,self.stack_top] => u64);
+
+                    let function = match self.heap.get(reference) {
+                        Some(object) if object.kind == ObjectKind::Closure => {
+                            let mut function = [0u8; 8];
+                            function.copy_from_slice(&object.data[..8]);
+                            u64::from_le_bytes(function) as usize
                         }
-                        _ => unreachable!(),
+                        _ => return Err(invalid_reference(reference)),
                     };
+
+                    self.call(function)?;
+
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.closure = Some(reference);
+                    }
                 }
 
-                Ok(OpCode::Add) => binary_op!(+,self),
-                Ok(OpCode::Divide) => binary_op!(/,self),
-                Ok(OpCode::Multiply) => binary_op!(*,self),
-                Ok(OpCode::Subtract) => binary_op!(-,self),
+                Ok(OpCode::CallNative) => {
+                    let index = self.read_u16() as usize;
+                    let native = self.code.natives[index].clone();
+
+                    let mut args = Vec::with_capacity(native.params.len());
+
+                    for &(sign, size) in native.params.iter().rev() {
+                        let bytes = self.pop_bytes(size.size() as usize)?;
+                        args.push(Value::from_bytes(&bytes, sign));
+                    }
+
+                    args.reverse();
+
+                    let (_, returns) = native.returns;
+                    let value = (self.linked[index])(&args)?.to_bytes(returns);
+
+                    push!(&value => self.stack,[self.stack_top,value.len()]);
+                }
+
+                Ok(OpCode::Print) => {
+                    let kind = self.read_byte();
+                    let size = self.read_byte() as usize;
+                    let signed = self.read_byte() != 0;
+
+                    let bytes = self.pop_bytes(size)?;
+
+                    match kind {
+                        PRINT_INT => match Value::from_bytes(&bytes, sign(signed)) {
+                            Value::Int(value) => println!("{}", value),
+                            Value::UInt(value) => println!("{}", value),
+                        },
+                        PRINT_BOOL => println!("{}", bytes[0] != 0),
+                        PRINT_CHAR => println!("{}", bytes[0] as char),
+                        PRINT_STR => {
+                            let mut reference = [0u8; 8];
+                            reference.copy_from_slice(&bytes);
+                            let reference = u64::from_le_bytes(reference);
+
+                            match self.heap.get(reference) {
+                                Some(object) if object.kind == ObjectKind::String => {
+                                    println!("{}", String::from_utf8_lossy(&object.data))
+                                }
+                                _ => return Err(invalid_reference(reference)),
+                            }
+                        }
+                        _ => {
+                            return Err(VMError::RuntimeError(format!(
+                                "Unknown print kind {}",
+                                kind
+                            )))
+                        }
+                    }
+                }
+
+                Ok(OpCode::Assert) => {
+                    if to_num!([&self.stack,self.stack_top] => u8) == 0 {
+                        return Err(VMError::RuntimeError("Assertion failed".into()));
+                    }
+                }
+
+                Ok(OpCode::Exit) => {
+                    self.exit = Some(to_num!([&self.stack,self.stack_top] => i32));
+                    return Ok(());
+                }
+
+                Ok(OpCode::Length) => {
+                    let reference = to_num!([&self.stack,self.stack_top] => u64);
+
+                    let len = self.string(reference)?.len();
+
+                    push!(&(len as i32).to_le_bytes() => self.stack,[self.stack_top,4]);
+                }
+
+                Ok(OpCode::StringEqual) => {
+                    let rhs = to_num!([&self.stack,self.stack_top] => u64);
+                    let lhs = to_num!([&self.stack,self.stack_top] => u64);
+
+                    let equal = self.string(lhs)? == self.string(rhs)?;
+
+                    push!(&[equal as u8] => self.stack,[self.stack_top,1]);
+                }
+
+                Ok(OpCode::Concat) => {
+                    // Both strings are still on the stack so they survive the collection
+                    self.collect_garbage();
+
+                    let rhs = to_num!([&self.stack,self.stack_top] => u64);
+                    let lhs = to_num!([&self.stack,self.stack_top] => u64);
+
+                    let mut bytes = self.string(lhs)?.to_vec();
+                    bytes.extend_from_slice(self.string(rhs)?);
+
+                    let reference = self.heap.alloc(ObjectKind::String, bytes);
+                    self.push_ref(reference);
+                }
 
-                // _ => unimplemented!(),
                 Err(_) => {
-                    println!("{:?}", self.code[self.ip]);
-                    return Err(VMError::RuntimeError);
+                    return Err(VMError::RuntimeError(format!(
+                        "Unknown opcode {}",
+                        self.code[self.ip - 1]
+                    )))
                 }
             }
         }
     }
+
+    fn read_byte(&mut self) -> u8 {
+        let byte = self.code[self.ip];
+        self.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let low = u16::from(self.read_byte());
+        let high = u16::from(self.read_byte());
+        low | high << 8
+    }
+
+    fn read_u24(&mut self) -> usize {
+        let low = self.read_u16() as usize;
+        let high = self.read_byte() as usize;
+        low | high << 16
+    }
+
+    /// Pushes the constant of `size` bytes stored at `index`
+    fn constant(&mut self, index: usize, size: usize) {
+        push!(&self.code.constants[index..index + size] => self.stack,[self.stack_top,size]);
+    }
+
+    fn jump(&mut self, offset: i16) {
+        self.ip = (self.ip as isize + offset as isize) as usize;
+    }
+
+    /// Reads a slot operand and returns its offset in `locals`
+    fn slot(&mut self) -> usize {
+        let slot = self.read_u16() as usize;
+        let base = self.frames.last().map(|frame| frame.base).unwrap_or(0);
+
+        base + slot * SLOT_SIZE
+    }
+
+    /// Pushes a new frame for `function` and jumps to its first instruction
+    fn call(&mut self, function: usize) -> VMResult {
+        if self.frames.len() == MAX_FRAMES {
+            return Err(self.overflow());
+        }
+
+        let base = self.locals.len();
+        let locals = self.code.functions[function].locals;
+
+        self.frames.push(CallFrame {
+            function,
+            ip: self.ip,
+            base,
+            closure: None,
+        });
+
+        self.locals.resize(base + locals * SLOT_SIZE, 0);
+        self.ip = self.code.functions[function].offset;
+
+        Ok(())
+    }
+
+    /// Returns the upvalue for a local slot, creating one if it hasn't been captured yet
+    fn capture(&mut self, slot: usize) -> u64 {
+        if let Some(&(_, upvalue)) = self.open_upvalues.iter().find(|&&(s, _)| s == slot) {
+            return upvalue;
+        }
+
+        let upvalue = self.heap.alloc(ObjectKind::Upvalue, vec![0; SLOT_SIZE]);
+        self.open_upvalues.push((slot, upvalue));
+
+        upvalue
+    }
+
+    /// The reference to an upvalue of the running closure
+    fn upvalue(&self, index: usize) -> Result<u64, VMError> {
+        let closure = match self.frames.last().and_then(|frame| frame.closure) {
+            Some(closure) => closure,
+            None => {
+                return Err(VMError::RuntimeError(
+                    "Upvalues can only be used inside a closure".into(),
+                ))
+            }
+        };
+
+        let start = 8 + index * 8;
+
+        match self.heap.get(closure) {
+            Some(object) if start + 8 <= object.data.len() => {
+                let mut upvalue = [0u8; 8];
+                upvalue.copy_from_slice(&object.data[start..start + 8]);
+                Ok(u64::from_le_bytes(upvalue))
+            }
+            Some(object) => Err(out_of_bounds(start, object.data.len())),
+            None => Err(invalid_reference(closure)),
+        }
+    }
+
+    /// Copies every local at or above `base` that a closure captured into its upvalue
+    fn close_upvalues(&mut self, base: usize) {
+        let locals = &self.locals;
+        let heap = &mut self.heap;
+
+        self.open_upvalues.retain(|&(slot, upvalue)| {
+            if slot < base {
+                return true;
+            }
+
+            if let Some(object) = heap.get_mut(upvalue) {
+                object.data.copy_from_slice(&locals[slot..slot + SLOT_SIZE]);
+            }
+
+            false
+        });
+    }
+
+    /// Collects garbage if the heap asks for it. This has to run before an
+    /// allocation pops its values so that any references in them are still rooted
+    fn collect_garbage(&mut self) {
+        if self.heap.should_collect() {
+            // The running closures and open upvalues aren't stored anywhere else
+            let mut references = Vec::new();
+
+            for frame in &self.frames {
+                if let Some(closure) = frame.closure {
+                    references.extend_from_slice(&closure.to_le_bytes());
+                }
+            }
+
+            for &(_, upvalue) in &self.open_upvalues {
+                references.extend_from_slice(&upvalue.to_le_bytes());
+            }
+
+            self.heap.collect(&[
+                &self.stack[..self.stack_top],
+                &self.locals,
+                &references,
+            ]);
+        }
+    }
+
+    fn pop_bytes(&mut self, size: usize) -> Result<Vec<u8>, VMError> {
+        if self.stack_top < size {
+            return Err(VMError::StackUnderflow);
+        }
+
+        self.stack_top -= size;
+
+        Ok(self.stack[self.stack_top..self.stack_top + size].to_vec())
+    }
+
+    fn push_ref(&mut self, reference: u64) {
+        push!(&to_bytes!(reference => u64) => self.stack,[self.stack_top,8]);
+    }
+
+    /// The bytes of the string behind a reference
+    fn string(&self, reference: u64) -> Result<&[u8], VMError> {
+        match self.heap.get(reference) {
+            Some(object) if object.kind == ObjectKind::String => Ok(&object.data),
+            _ => Err(invalid_reference(reference)),
+        }
+    }
+
+    /// Pushes `size` bytes read from `offset` within an object
+    fn load(&mut self, reference: u64, offset: usize, size: usize) -> VMResult {
+        let mut value = [0u8; 8];
+
+        match self.heap.get(reference) {
+            Some(object) if offset + size <= object.data.len() => {
+                value[..size].copy_from_slice(&object.data[offset..offset + size])
+            }
+            Some(object) => return Err(out_of_bounds(offset, object.data.len())),
+            None => return Err(invalid_reference(reference)),
+        }
+
+        push!(&value[..size] => self.stack,[self.stack_top,size]);
+
+        Ok(())
+    }
+
+    /// Writes `value` at `offset` within an object
+    fn store(&mut self, reference: u64, offset: usize, value: &[u8]) -> VMResult {
+        match self.heap.get_mut(reference) {
+            Some(ref mut object) if offset + value.len() <= object.data.len() => {
+                object.data[offset..offset + value.len()].copy_from_slice(value);
+                Ok(())
+            }
+            Some(object) => Err(out_of_bounds(offset, object.data.len())),
+            None => Err(invalid_reference(reference)),
+        }
+    }
+
+    fn overflow(&self) -> VMError {
+        VMError::StackOverflow(
+            self.frames
+                .iter()
+                .rev()
+                .map(|frame| self.code.functions[frame.function].name.clone())
+                .collect(),
+        )
+    }
+}
+
+fn sign(signed: bool) -> Sign {
+    if signed {
+        Sign::Signed
+    } else {
+        Sign::Unsigned
+    }
+}
+
+fn out_of_bounds(offset: usize, len: usize) -> VMError {
+    VMError::RuntimeError(format!(
+        "Offset {} is out of bounds for an object of {} bytes",
+        offset, len
+    ))
+}
+
+fn invalid_reference(reference: u64) -> VMError {
+    VMError::RuntimeError(format!("{:#x} is not a reference to a live object", reference))
+}
+
+#[cfg(test)]
+mod test {
+    use chunk::{Chunk, Function, Native};
+    use heap::ObjectKind;
+    use native::Value;
+    use op::OpCode;
+    use syntax::ast::{Sign, Size};
+    use vm::{VMError, VM};
+
+    fn local(chunk: &mut Chunk, op: OpCode, slot: u16) {
+        chunk.write(op, 1);
+        chunk.write_u16(slot, 1);
+        chunk.write(4, 1);
+    }
+
+    #[test]
+    fn calls_and_loops() {
+        let mut chunk = Chunk::new();
+
+        let zero = chunk.add_constant(&[0, 0, 0, 0], 1);
+        let one = chunk.add_constant(&[1, 0, 0, 0], 1);
+        let five = chunk.add_constant(&[5, 0, 0, 0], 1);
+
+        // fn sum(n) { let total = 0; while n > 0 { total = total + n; n = n - 1; } total }
+        local(&mut chunk, OpCode::SetLocal, 0);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(zero as u8, 1);
+        local(&mut chunk, OpCode::SetLocal, 1);
+
+        let start = chunk.len();
+        local(&mut chunk, OpCode::GetLocal, 0);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(zero as u8, 1);
+        chunk.write(OpCode::Greater, 1);
+        chunk.write(4, 1);
+        chunk.write(1, 1);
+        chunk.write(OpCode::JumpIfFalse, 1);
+        let exit = chunk.len();
+        chunk.write_u16(0, 1);
+
+        local(&mut chunk, OpCode::GetLocal, 1);
+        local(&mut chunk, OpCode::GetLocal, 0);
+        chunk.write(OpCode::Add, 1);
+        chunk.write(4, 1);
+        chunk.write(1, 1);
+        local(&mut chunk, OpCode::SetLocal, 1);
+        local(&mut chunk, OpCode::GetLocal, 0);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(one as u8, 1);
+        chunk.write(OpCode::Subtract, 1);
+        chunk.write(4, 1);
+        chunk.write(1, 1);
+        local(&mut chunk, OpCode::SetLocal, 0);
+        chunk.write(OpCode::Jump, 1);
+        let back = start as isize - (chunk.len() as isize + 2);
+        chunk.write_u16(back as i16 as u16, 1);
+
+        let forward = chunk.len() - (exit + 2);
+        chunk.patch(exit, forward as u8);
+
+        local(&mut chunk, OpCode::GetLocal, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        let main = chunk.len();
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(five as u8, 1);
+        chunk.write(OpCode::Call, 1);
+        chunk.write_u16(0, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        chunk.functions.push(Function {
+            name: "sum".into(),
+            offset: 0,
+            locals: 2,
+        });
+        chunk.functions.push(Function {
+            name: "main".into(),
+            offset: main,
+            locals: 0,
+        });
+        chunk.entry = Some(1);
+
+        let mut vm = VM::new(&mut chunk);
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(4), &[15, 0, 0, 0]);
+    }
+
+    fn binary(op: OpCode, lhs: &[u8], rhs: &[u8], signed: bool) -> Result<Vec<u8>, VMError> {
+        let mut chunk = Chunk::new();
+
+        let lhs_index = chunk.add_constant(lhs, 1);
+        let rhs_index = chunk.add_constant(rhs, 1);
+
+        chunk.write(OpCode::Constant8, 1);
+        chunk.write(lhs_index as u8, 1);
+        chunk.write(OpCode::Constant8, 1);
+        chunk.write(rhs_index as u8, 1);
+        chunk.write(op, 1);
+        chunk.write(1, 1);
+        chunk.write(signed as u8, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(1, 1);
+
+        let mut vm = VM::new(&mut chunk);
+
+        vm.run()?;
+
+        Ok(vm.peek(1).to_vec())
+    }
+
+    #[test]
+    fn width_and_sign() {
+        assert_eq!(binary(OpCode::Add, &[200], &[100], false).unwrap(), vec![44]);
+        assert_eq!(binary(OpCode::Divide, &[200], &[2], false).unwrap(), vec![100]);
+        assert_eq!(binary(OpCode::Divide, &[200], &[2], true).unwrap(), vec![-28i8 as u8]);
+        assert_eq!(binary(OpCode::Less, &[200], &[2], false).unwrap(), vec![0]);
+        assert_eq!(binary(OpCode::Less, &[200], &[2], true).unwrap(), vec![1]);
+        assert_eq!(binary(OpCode::Multiply, &[128], &[255], true).unwrap(), vec![128]);
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        match binary(OpCode::Divide, &[1], &[0], true) {
+            Err(VMError::RuntimeError(_)) => (),
+            e => panic!("Expected a runtime error got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn thousands_of_constants() {
+        let mut chunk = Chunk::new();
+
+        chunk.write_constant(&[0, 0, 0, 0], 1);
+
+        for i in 1..5000u32 {
+            chunk.write_constant(&to_bytes!(i => u32), 1);
+            chunk.write(OpCode::Add, 1);
+            chunk.write(4, 1);
+            chunk.write(0, 1);
+        }
+
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        assert!(chunk.constants.len() > 256 * 4);
+
+        let mut vm = VM::new(&mut chunk);
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(4), &to_bytes!((1..5000u32).sum::<u32>() => u32));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut chunk = Chunk::new();
+
+        // fn forever() { forever() }
+        chunk.write(OpCode::Call, 1);
+        chunk.write_u16(0, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(1, 1);
+
+        chunk.functions.push(Function {
+            name: "forever".into(),
+            offset: 0,
+            locals: 1,
+        });
+        chunk.entry = Some(0);
+
+        let mut vm = VM::with_stack_size(&mut chunk, 1024);
+
+        match vm.run() {
+            Err(VMError::StackOverflow(ref calls)) => {
+                assert_eq!(calls.len(), 1024 / 8 + 1);
+                assert!(calls.iter().all(|name| name == "forever"))
+            }
+            e => panic!("Expected a stack overflow got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let mut chunk = Chunk::new();
+
+        chunk.write(OpCode::Not, 1);
+
+        let mut vm = VM::new(&mut chunk);
+
+        match vm.run() {
+            Err(VMError::StackUnderflow) => (),
+            e => panic!("Expected a stack underflow got {:?}", e),
+        }
+    }
+
+    fn string(chunk: &mut Chunk, string: &str) {
+        let offset = chunk.add_constant(&(string.len() as u32).to_le_bytes(), 1);
+        chunk.add_constant(string.as_bytes(), 1);
+
+        chunk.write(OpCode::String, 1);
+        chunk.write(offset as u8, 1);
+        chunk.write((offset >> 8) as u8, 1);
+        chunk.write((offset >> 16) as u8, 1);
+    }
+
+    fn reference(chunk: &mut Chunk, op: OpCode, slot: u16) {
+        chunk.write(op, 1);
+        chunk.write_u16(slot, 1);
+        chunk.write(8, 1);
+    }
+
+    fn main(chunk: &mut Chunk, locals: usize) {
+        chunk.functions.push(Function {
+            name: "main".into(),
+            offset: 0,
+            locals,
+        });
+        chunk.entry = Some(0);
+    }
+
+    #[test]
+    fn collects_unreachable_objects() {
+        let mut chunk = Chunk::new();
+
+        for _ in 0..100 {
+            string(&mut chunk, "garbage");
+            reference(&mut chunk, OpCode::SetLocal, 0);
+        }
+
+        reference(&mut chunk, OpCode::GetLocal, 0);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(8, 1);
+
+        main(&mut chunk, 1);
+
+        let mut vm = VM::new(&mut chunk);
+        vm.heap.stress = true;
+
+        vm.run().unwrap();
+
+        // Only the string in the slot and the one being allocated are ever live
+        assert!(vm.heap.len() <= 2);
+    }
+
+    #[test]
+    fn reachable_objects_survive() {
+        let mut chunk = Chunk::new();
+
+        string(&mut chunk, "hello");
+        string(&mut chunk, "world");
+        chunk.write(OpCode::Array, 1);
+        chunk.write(2, 1);
+        chunk.write(0, 1);
+        chunk.write(0, 1);
+        chunk.write(8, 1);
+        reference(&mut chunk, OpCode::SetLocal, 0);
+
+        for _ in 0..10 {
+            string(&mut chunk, "garbage");
+            reference(&mut chunk, OpCode::SetLocal, 1);
+        }
+
+        // The second element
+        reference(&mut chunk, OpCode::GetLocal, 0);
+        chunk.write(OpCode::GetField, 1);
+        chunk.write_u16(8, 1);
+        chunk.write(8, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(8, 1);
+
+        main(&mut chunk, 2);
+
+        let mut vm = VM::new(&mut chunk);
+        vm.heap.stress = true;
+
+        vm.run().unwrap();
+
+        let mut world = [0u8; 8];
+        world.copy_from_slice(vm.peek(8));
+
+        let object = vm.heap.get(u64::from_le_bytes(world)).unwrap();
+
+        assert_eq!(object.kind, ObjectKind::String);
+        assert_eq!(object.data, b"world");
+    }
+
+    #[test]
+    fn closures_keep_captured_locals() {
+        let mut chunk = Chunk::new();
+
+        let ten = chunk.add_constant(&10i32.to_le_bytes(), 1);
+        let ninety_nine = chunk.add_constant(&99i32.to_le_bytes(), 1);
+
+        // let v = 10; let add = || { v = v + 10; v }; and v leaves scope
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(ten as u8, 1);
+        local(&mut chunk, OpCode::SetLocal, 0);
+
+        chunk.write(OpCode::Closure, 1);
+        chunk.write_u16(1, 1);
+        chunk.write(1, 1);
+        chunk.write(1, 1);
+        chunk.write_u16(0, 1);
+        reference(&mut chunk, OpCode::SetLocal, 1);
+
+        chunk.write(OpCode::CloseUpvalue, 1);
+        chunk.write_u16(0, 1);
+
+        // The slot is reused once v is out of scope
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(ninety_nine as u8, 1);
+        local(&mut chunk, OpCode::SetLocal, 0);
+
+        reference(&mut chunk, OpCode::GetLocal, 1);
+        chunk.write(OpCode::CallClosure, 1);
+        local(&mut chunk, OpCode::SetLocal, 2);
+
+        reference(&mut chunk, OpCode::GetLocal, 1);
+        chunk.write(OpCode::CallClosure, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        let add = chunk.len();
+        local(&mut chunk, OpCode::GetUpvalue, 0);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(ten as u8, 1);
+        chunk.write(OpCode::Add, 1);
+        chunk.write(4, 1);
+        chunk.write(1, 1);
+        local(&mut chunk, OpCode::SetUpvalue, 0);
+        local(&mut chunk, OpCode::GetUpvalue, 0);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        main(&mut chunk, 3);
+        chunk.functions.push(Function {
+            name: "add".into(),
+            offset: add,
+            locals: 0,
+        });
+
+        let mut vm = VM::new(&mut chunk);
+        vm.heap.stress = true;
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(4), &30i32.to_le_bytes());
+    }
+
+    fn sum(args: &[Value]) -> Result<Value, VMError> {
+        let mut total = 0;
+
+        for arg in args {
+            match *arg {
+                Value::Int(value) => total += value,
+                Value::UInt(value) => total += value as i64,
+            }
+        }
+
+        Ok(Value::Int(total))
+    }
+
+    fn native_call() -> Chunk {
+        let mut chunk = Chunk::new();
+
+        let two = chunk.add_constant(&[2], 1);
+        let minus_five = chunk.add_constant(&(-5i32).to_le_bytes(), 1);
+
+        chunk.write(OpCode::Constant8, 1);
+        chunk.write(two as u8, 1);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(minus_five as u8, 1);
+        chunk.write(OpCode::CallNative, 1);
+        chunk.write_u16(0, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(4, 1);
+
+        chunk.natives.push(Native {
+            name: "sum".into(),
+            params: vec![(Sign::Unsigned, Size::Bit8), (Sign::Signed, Size::Bit32)],
+            returns: (Sign::Signed, Size::Bit32),
+        });
+
+        main(&mut chunk, 0);
+
+        chunk
+    }
+
+    #[test]
+    fn natives() {
+        let mut chunk = native_call();
+        let mut vm = VM::new(&mut chunk);
+
+        vm.register_native("sum", 2, sum);
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(4), &(-3i32).to_le_bytes());
+    }
+
+    #[test]
+    fn unregistered_natives() {
+        let mut chunk = native_call();
+
+        match VM::new(&mut chunk).run() {
+            Err(VMError::CompilerError(_)) => (),
+            e => panic!("Expected a link error got {:?}", e),
+        }
+
+        let mut chunk = native_call();
+        let mut vm = VM::new(&mut chunk);
+
+        vm.register_native("sum", 3, sum);
+
+        match vm.run() {
+            Err(VMError::CompilerError(_)) => (),
+            e => panic!("Expected an arity mismatch got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn intrinsics() {
+        // assert(len("four") == 4); exit(7)
+        let mut chunk = Chunk::new();
+
+        string(&mut chunk, "four");
+        chunk.write(OpCode::Length, 1);
+
+        let four = chunk.add_constant(&4i32.to_le_bytes(), 1);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(four as u8, 1);
+        chunk.write(OpCode::Equal, 1);
+        chunk.write(4, 1);
+        chunk.write(1, 1);
+        chunk.write(OpCode::Assert, 1);
+
+        let seven = chunk.add_constant(&7i32.to_le_bytes(), 1);
+        chunk.write(OpCode::Constant32, 1);
+        chunk.write(seven as u8, 1);
+        chunk.write(OpCode::Exit, 1);
+
+        main(&mut chunk, 0);
+
+        let mut vm = VM::new(&mut chunk);
+        vm.run().unwrap();
+
+        assert_eq!(vm.exit, Some(7));
+
+        let mut chunk = Chunk::new();
+
+        let false_ = chunk.add_constant(&[0], 1);
+        chunk.write(OpCode::Constant8, 1);
+        chunk.write(false_ as u8, 1);
+        chunk.write(OpCode::Assert, 1);
+        chunk.write(OpCode::Return, 1);
+        chunk.write(0, 1);
+        main(&mut chunk, 0);
+
+        match VM::new(&mut chunk).run() {
+            Err(VMError::RuntimeError(_)) => (),
+            e => panic!("Expected a failed assertion got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn strings() {
+        // assert("ab" + "cd" == "abcd"); exit(len("ab" + "cd"))
+        let mut chunk = Chunk::new();
+
+        string(&mut chunk, "ab");
+        string(&mut chunk, "cd");
+        chunk.write(OpCode::Concat, 1);
+        string(&mut chunk, "abcd");
+        chunk.write(OpCode::StringEqual, 1);
+        chunk.write(OpCode::Assert, 1);
+
+        string(&mut chunk, "ab");
+        string(&mut chunk, "cd");
+        chunk.write(OpCode::Concat, 1);
+        chunk.write(OpCode::Length, 1);
+        chunk.write(OpCode::Exit, 1);
+        main(&mut chunk, 0);
+
+        let mut vm = VM::new(&mut chunk);
+        vm.run().unwrap();
+
+        assert_eq!(vm.exit, Some(4));
+    }
 }
@@ -0,0 +1,64 @@
+// Runs a module the wasm backend emitted with `--emit wasm` under node, giving it the
+// prelude, the runtime errors and the libc functions the vm has natives for.
+//
+//     node host.js program.wasm
+"use strict";
+
+const fs = require("fs");
+
+const bytes = fs.readFileSync(process.argv[2]);
+let memory;
+
+function write(text) {
+    fs.writeSync(1, text);
+}
+
+// Strings are their eight byte length followed by their bytes
+function string(address) {
+    const view = new DataView(memory.buffer);
+    const length = Number(view.getBigUint64(address, true));
+
+    return Buffer.from(memory.buffer, address + 8, length);
+}
+
+function fail(message) {
+    fs.writeSync(2, `${message}\n`);
+    process.exit(70);
+}
+
+const imports = {
+    underscore: {
+        print_int: (value) => write(`${value}\n`),
+        print_uint: (value) => write(`${BigInt.asUintN(64, value)}\n`),
+        print_bool: (value) => write(`${value !== 0}\n`),
+        print_char: (value) => write(`${String.fromCharCode(value & 0xff)}\n`),
+        print_str: (address) => {
+            write(string(address));
+            write("\n");
+        },
+        assert: (value) => {
+            if (value === 0) {
+                fail("Assertion failed");
+            }
+        },
+        exit: (code) => process.exit(code),
+        error: (message) => fail(string(message).toString()),
+    },
+    env: {
+        abs: (value) => Math.abs(value) | 0,
+        labs: (value) => BigInt.asIntN(64, value < 0n ? -value : value),
+        putchar: (value) => {
+            write(Buffer.from([value & 0xff]));
+            return value;
+        },
+    },
+};
+
+WebAssembly.instantiate(bytes, imports).then(({ instance }) => {
+    memory = instance.exports.memory;
+
+    // A program without a main does nothing
+    if (instance.exports.main) {
+        instance.exports.main();
+    }
+});
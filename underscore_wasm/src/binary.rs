@@ -0,0 +1,318 @@
+//! Encodes a module in the WebAssembly binary format, which is what runtimes load.
+//! The module has to have been validated, as calls are looked up by id.
+use std::collections::HashMap;
+use wat::{Instr, Module, Numeric, ValType};
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+
+/// The block type of blocks, loops and ifs that don't leave a value
+const EMPTY: u8 = 0x40;
+const END: u8 = 0x0b;
+
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut types: Vec<(&[ValType], Option<ValType>)> = vec![];
+    let mut indices = HashMap::new();
+
+    let imports = module
+        .imports
+        .iter()
+        .map(|import| (&import.id, &import.params[..], import.result));
+    let functions = module
+        .functions
+        .iter()
+        .map(|function| (&function.id, &function.params[..], function.result));
+
+    // Every signature gets one type, whichever functions share it
+    let mut signatures = vec![];
+
+    for (index, (id, params, result)) in imports.chain(functions).enumerate() {
+        let signature = match types.iter().position(|&ty| ty == (params, result)) {
+            Some(signature) => signature,
+            None => {
+                types.push((params, result));
+                types.len() - 1
+            }
+        };
+
+        indices.insert(id.as_str(), index as u32);
+        signatures.push(signature as u32);
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(VERSION);
+
+    section(&mut bytes, TYPE_SECTION, types.len(), |out| {
+        for &(params, result) in &types {
+            out.push(0x60);
+            unsigned(out, params.len() as u64);
+            out.extend(params.iter().map(|&ty| val_type(ty)));
+            unsigned(out, result.iter().count() as u64);
+            out.extend(result.map(val_type));
+        }
+    });
+
+    section(&mut bytes, IMPORT_SECTION, module.imports.len(), |out| {
+        for (import, &signature) in module.imports.iter().zip(&signatures) {
+            name(out, &import.module);
+            name(out, &import.name);
+            out.push(0x00);
+            unsigned(out, u64::from(signature));
+        }
+    });
+
+    section(
+        &mut bytes,
+        FUNCTION_SECTION,
+        module.functions.len(),
+        |out| {
+            for &signature in &signatures[module.imports.len()..] {
+                unsigned(out, u64::from(signature));
+            }
+        },
+    );
+
+    section(&mut bytes, MEMORY_SECTION, 1, |out| {
+        out.push(0x00);
+        unsigned(out, u64::from(module.memory));
+    });
+
+    let exports: Vec<(&str, u32)> = module
+        .functions
+        .iter()
+        .filter_map(|function| {
+            function
+                .export
+                .as_ref()
+                .map(|export| (export.as_str(), indices[function.id.as_str()]))
+        })
+        .collect();
+
+    section(&mut bytes, EXPORT_SECTION, exports.len() + 1, |out| {
+        name(out, "memory");
+        out.extend_from_slice(&[0x02, 0x00]);
+
+        for &(export, index) in &exports {
+            name(out, export);
+            out.push(0x00);
+            unsigned(out, u64::from(index));
+        }
+    });
+
+    section(&mut bytes, CODE_SECTION, module.functions.len(), |out| {
+        for function in &module.functions {
+            let mut code = vec![];
+
+            // Locals are declared in runs of the same type
+            let mut runs: Vec<(u32, ValType)> = vec![];
+
+            for &local in &function.locals {
+                match runs.last_mut() {
+                    Some(&mut (ref mut count, ty)) if ty == local => *count += 1,
+                    _ => runs.push((1, local)),
+                }
+            }
+
+            unsigned(&mut code, runs.len() as u64);
+
+            for &(count, ty) in &runs {
+                unsigned(&mut code, u64::from(count));
+                code.push(val_type(ty));
+            }
+
+            body(&mut code, &function.body, &indices);
+            code.push(END);
+
+            unsigned(out, code.len() as u64);
+            out.extend(code);
+        }
+    });
+
+    section(&mut bytes, DATA_SECTION, module.data.len(), |out| {
+        for &(offset, ref data) in &module.data {
+            out.push(0x00);
+            out.push(0x41);
+            signed(out, i64::from(offset as i32));
+            out.push(END);
+            unsigned(out, data.len() as u64);
+            out.extend_from_slice(data);
+        }
+    });
+
+    bytes
+}
+
+/// Writes a section holding `count` entries, leaving it out when there are none
+fn section<F: FnOnce(&mut Vec<u8>)>(bytes: &mut Vec<u8>, id: u8, count: usize, entries: F) {
+    if count == 0 {
+        return;
+    }
+
+    let mut contents = vec![];
+    unsigned(&mut contents, count as u64);
+    entries(&mut contents);
+
+    bytes.push(id);
+    unsigned(bytes, contents.len() as u64);
+    bytes.extend(contents);
+}
+
+fn body(out: &mut Vec<u8>, body: &[Instr], indices: &HashMap<&str, u32>) {
+    for instr in body {
+        match *instr {
+            Instr::Block(ref body) => {
+                out.extend_from_slice(&[0x02, EMPTY]);
+                self::body(out, body, indices);
+                out.push(END);
+            }
+            Instr::Loop(ref body) => {
+                out.extend_from_slice(&[0x03, EMPTY]);
+                self::body(out, body, indices);
+                out.push(END);
+            }
+            Instr::If(ref then, ref otherwise) => {
+                out.extend_from_slice(&[0x04, EMPTY]);
+                self::body(out, then, indices);
+
+                if !otherwise.is_empty() {
+                    out.push(0x05);
+                    self::body(out, otherwise, indices);
+                }
+
+                out.push(END);
+            }
+            Instr::Br(depth) => {
+                out.push(0x0c);
+                unsigned(out, u64::from(depth));
+            }
+            Instr::Return => out.push(0x0f),
+            Instr::Unreachable => out.push(0x00),
+            Instr::Call(ref id) => {
+                out.push(0x10);
+                unsigned(out, u64::from(indices[id.as_str()]));
+            }
+            Instr::LocalGet(index) => {
+                out.push(0x20);
+                unsigned(out, u64::from(index));
+            }
+            Instr::LocalSet(index) => {
+                out.push(0x21);
+                unsigned(out, u64::from(index));
+            }
+            Instr::I32Const(value) => {
+                out.push(0x41);
+                signed(out, i64::from(value));
+            }
+            Instr::I64Const(value) => {
+                out.push(0x42);
+                signed(out, value);
+            }
+            // Aligned to eight bytes at no offset
+            Instr::I64Load => out.extend_from_slice(&[0x29, 0x03, 0x00]),
+            Instr::Numeric(numeric) => out.push(opcode(numeric)),
+        }
+    }
+}
+
+fn opcode(numeric: Numeric) -> u8 {
+    match numeric {
+        Numeric::I32Eqz => 0x45,
+        Numeric::I32WrapI64 => 0xa7,
+        Numeric::I64Eqz => 0x50,
+        Numeric::I64Eq => 0x51,
+        Numeric::I64Ne => 0x52,
+        Numeric::I64LtS => 0x53,
+        Numeric::I64LtU => 0x54,
+        Numeric::I64GtS => 0x55,
+        Numeric::I64GtU => 0x56,
+        Numeric::I64LeS => 0x57,
+        Numeric::I64LeU => 0x58,
+        Numeric::I64GeS => 0x59,
+        Numeric::I64GeU => 0x5a,
+        Numeric::I64Add => 0x7c,
+        Numeric::I64Sub => 0x7d,
+        Numeric::I64Mul => 0x7e,
+        Numeric::I64DivS => 0x7f,
+        Numeric::I64DivU => 0x80,
+        Numeric::I64And => 0x83,
+        Numeric::I64Or => 0x84,
+        Numeric::I64Extend8S => 0xc2,
+        Numeric::I64Extend32S => 0xc4,
+        Numeric::I64ExtendI32S => 0xac,
+        Numeric::I64ExtendI32U => 0xad,
+    }
+}
+
+fn val_type(ty: ValType) -> u8 {
+    match ty {
+        ValType::I32 => 0x7f,
+        ValType::I64 => 0x7e,
+    }
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Writes a number in unsigned LEB128
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a number in signed LEB128
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+
+        if done {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{signed, unsigned};
+
+    #[test]
+    fn numbers_are_leb128() {
+        let encode = |write: &dyn Fn(&mut Vec<u8>)| {
+            let mut out = vec![];
+            write(&mut out);
+            out
+        };
+
+        assert_eq!(
+            encode(&|out| unsigned(out, 624_485)),
+            vec![0xe5, 0x8e, 0x26]
+        );
+        assert_eq!(encode(&|out| signed(out, -123_456)), vec![0xc0, 0xbb, 0x78]);
+        assert_eq!(encode(&|out| signed(out, 64)), vec![0xc0, 0x00]);
+        assert_eq!(encode(&|out| signed(out, -1)), vec![0x7f]);
+    }
+}
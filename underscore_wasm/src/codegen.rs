@@ -0,0 +1,966 @@
+//! Lowers an `ir::Program` to a WebAssembly module.
+//! Every temp is an `i64` local holding its value sign or zero extended to 64 bits,
+//! the same way the x86 backend keeps them in registers. Values only take the `i32`
+//! or `i64` type of their width when they are passed to or returned from a function.
+//! Strings are stored in linear memory as their eight byte length followed by their
+//! bytes, and a temp holding one holds its address.
+use ir::ir::{self, BinOp, CmpOp, Instruction, Intrinsic, Temp, UnOp, Value};
+use std::collections::HashMap;
+use structure::{Exit, Graph};
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::{Symbol, Symbols};
+use wat::{Func, Import, Instr, Module, Numeric, ValType};
+
+/// The type given to a temp that is read before anything was stored in it
+const DEFAULT_TYPE: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+/// Where the first string is stored, so that no string is at address zero
+const DATA_START: u32 = 8;
+
+/// The size of a page of memory
+const PAGE_SIZE: u32 = 65536;
+
+/// The module the prelude and runtime errors are imported from
+pub const RUNTIME: &str = "underscore";
+
+/// The module external functions are imported from
+pub const EXTERNALS: &str = "env";
+
+#[derive(Debug)]
+pub enum CodegenError {
+    /// The program uses something the backend can't generate code for yet
+    Unsupported(String),
+}
+
+/// What a branch can target
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Context {
+    /// The end of a block, which the given basic block follows
+    BlockFollowedBy(usize),
+    /// The start of a loop, which the given basic block heads
+    LoopHeadedBy(usize),
+    /// An if, which can't be the target of a branch but is a level that they skip
+    IfThenElse,
+}
+
+pub struct Codegen {
+    module: Module,
+    /// Maps a function to its id and the types of its params and result
+    functions: HashMap<Symbol, (String, Vec<(Sign, Size)>, (Sign, Size))>,
+    /// The addresses of the messages passed to the runtime
+    messages: HashMap<&'static str, u32>,
+    /// Where the next string will be stored
+    data_end: u32,
+    /// Maps a temp to its local
+    locals: HashMap<Temp, u32>,
+    /// The number of params of the function being generated
+    params: u32,
+    /// The type the function being generated returns
+    returns: (Sign, Size),
+    /// The type of the first operand of each instruction of the function being generated
+    types: Vec<(Sign, Size)>,
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Codegen {
+            module: Module::default(),
+            functions: HashMap::new(),
+            messages: HashMap::new(),
+            data_end: DATA_START,
+            locals: HashMap::new(),
+            params: 0,
+            returns: DEFAULT_TYPE,
+            types: Vec::new(),
+        }
+    }
+
+    pub fn compile<T: Clone>(
+        mut self,
+        program: &ir::Program,
+        symbols: &Symbols<T>,
+    ) -> Result<Module, CodegenError> {
+        // Every instantiation of a generic function at the same types has the same name
+        let mut functions = Vec::new();
+
+        for function in &program.functions {
+            if self.functions.contains_key(&function.name) {
+                continue;
+            }
+
+            let name = symbols.name(function.name);
+
+            let id = match function.linkage {
+                Linkage::External => {
+                    self.module.imports.push(Import {
+                        module: EXTERNALS.into(),
+                        name: name.clone(),
+                        id: escape(&name),
+                        params: function
+                            .param_types
+                            .iter()
+                            .map(|&ty| val_type(ty))
+                            .collect(),
+                        result: Some(val_type(function.returns)),
+                    });
+
+                    escape(&name)
+                }
+                Linkage::Normal => {
+                    functions.push(function);
+                    escape(&name)
+                }
+            };
+
+            self.functions.insert(
+                function.name,
+                (id, function.param_types.clone(), function.returns),
+            );
+        }
+
+        for function in functions {
+            let name = symbols.name(function.name);
+            self.compile_function(function, name)?;
+        }
+
+        self.module.memory = self.data_end.div_ceil(PAGE_SIZE);
+
+        Ok(self.module)
+    }
+
+    fn compile_function(
+        &mut self,
+        function: &ir::Function,
+        name: String,
+    ) -> Result<(), CodegenError> {
+        let graph = Graph::new(&function.body).map_err(CodegenError::Unsupported)?;
+
+        self.locals.clear();
+        self.params = function.params.len() as u32;
+        self.returns = function.returns;
+        self.types = self.operand_types(function);
+
+        let mut body = Vec::new();
+
+        // Params arrive at their width and are extended into the temps they are bound to
+        for (i, (&param, &ty)) in function
+            .params
+            .iter()
+            .zip(&function.param_types)
+            .enumerate()
+        {
+            body.push(Instr::LocalGet(i as u32));
+            body.extend(from_wasm(ty));
+            body.push(Instr::LocalSet(self.local(param)));
+        }
+
+        body.extend(self.tree(&graph, 0, &mut vec![], &function.body)?);
+
+        // Every path through the body ends in a branch or a return
+        body.push(Instr::Unreachable);
+
+        let locals = vec![ValType::I64; self.locals.len()];
+
+        self.module.functions.push(Func {
+            id: self.functions[&function.name].0.clone(),
+            // The memory is exported under that name already
+            export: if name == "memory" { None } else { Some(name) },
+            params: function
+                .param_types
+                .iter()
+                .map(|&ty| val_type(ty))
+                .collect(),
+            result: Some(val_type(function.returns)),
+            locals,
+            body,
+        });
+
+        Ok(())
+    }
+
+    /// The type of the temp each instruction reads first, which decides the sign and
+    /// width of what it computes. Temps take the type last stored in them in the
+    /// order of the body, just like in the x86 backend, and params start with the type
+    /// they are declared with
+    fn operand_types(&self, function: &ir::Function) -> Vec<(Sign, Size)> {
+        let mut types: HashMap<Temp, (Sign, Size)> = function
+            .params
+            .iter()
+            .cloned()
+            .zip(function.param_types.iter().cloned())
+            .collect();
+        let mut operands = Vec::with_capacity(function.body.len());
+
+        for instruction in &function.body {
+            let ty_of = |temp: &Temp| *types.get(temp).unwrap_or(&DEFAULT_TYPE);
+
+            let operand = match *instruction {
+                Instruction::BinOp(lhs, _, _, _) | Instruction::CJump(lhs, _, _, _, _) => {
+                    ty_of(&lhs)
+                }
+                Instruction::UnOp(_, _, from) | Instruction::Copy(_, from) => ty_of(&from),
+                Instruction::Store(_, Value::Temp(from)) => ty_of(&from),
+                Instruction::Intrinsic(_, _, ref args) => {
+                    args.first().map(ty_of).unwrap_or(DEFAULT_TYPE)
+                }
+                _ => DEFAULT_TYPE,
+            };
+
+            operands.push(operand);
+
+            let stored = match *instruction {
+                Instruction::Store(temp, Value::Const(_, sign, size)) => Some((temp, (sign, size))),
+                Instruction::Store(temp, Value::Temp(_)) | Instruction::Copy(temp, _) => {
+                    Some((temp, operand))
+                }
+                Instruction::Store(temp, _) => Some((temp, (Sign::Unsigned, Size::Bit64))),
+                Instruction::BinOp(_, BinOp::And, _, temp)
+                | Instruction::BinOp(_, BinOp::Or, _, temp) => {
+                    Some((temp, (Sign::Unsigned, Size::Bit8)))
+                }
+                Instruction::BinOp(_, _, _, temp) => Some((temp, operand)),
+                Instruction::UnOp(temp, UnOp::Minus, _) => Some((temp, operand)),
+                Instruction::UnOp(temp, UnOp::Bang, _) => {
+                    Some((temp, (Sign::Unsigned, Size::Bit8)))
+                }
+                Instruction::Cast(temp, _, sign, size) => Some((temp, (sign, size))),
+                Instruction::Call(temp, callee, _) => self
+                    .functions
+                    .get(&callee)
+                    .map(|function| (temp, function.2)),
+                Instruction::Intrinsic(temp, Intrinsic::StrLen, _) => {
+                    Some((temp, (Sign::Signed, Size::Bit32)))
+                }
+                _ => None,
+            };
+
+            if let Some((temp, ty)) = stored {
+                types.insert(temp, ty);
+            }
+        }
+
+        operands
+    }
+
+    /// The code for a block and the blocks it dominates. A loop header is wrapped
+    /// in a loop, and each block it dominates that can be reached in more than one
+    /// way follows a block that it can be branched to the end of
+    fn tree(
+        &mut self,
+        graph: &Graph,
+        block: usize,
+        context: &mut Vec<Context>,
+        body: &[Instruction],
+    ) -> Result<Vec<Instr>, CodegenError> {
+        let merges = graph.merge_children(block);
+
+        if graph.is_loop_header(block) {
+            context.push(Context::LoopHeadedBy(block));
+            let code = self.within(graph, block, &merges, context, body);
+            context.pop();
+
+            Ok(vec![Instr::Loop(code?)])
+        } else {
+            self.within(graph, block, &merges, context, body)
+        }
+    }
+
+    fn within(
+        &mut self,
+        graph: &Graph,
+        block: usize,
+        merges: &[usize],
+        context: &mut Vec<Context>,
+        body: &[Instruction],
+    ) -> Result<Vec<Instr>, CodegenError> {
+        let (&follower, rest) = match merges.split_first() {
+            Some(split) => split,
+            None => return self.block(graph, block, context, body),
+        };
+
+        context.push(Context::BlockFollowedBy(follower));
+        let inner = self.within(graph, block, rest, context, body);
+        context.pop();
+
+        let mut code = vec![Instr::Block(inner?)];
+        code.extend(self.tree(graph, follower, context, body)?);
+
+        Ok(code)
+    }
+
+    /// The instructions of a basic block followed by how it exits
+    fn block(
+        &mut self,
+        graph: &Graph,
+        block: usize,
+        context: &mut Vec<Context>,
+        body: &[Instruction],
+    ) -> Result<Vec<Instr>, CodegenError> {
+        let mut code = Vec::new();
+        let (start, end) = (graph.blocks[block].start, graph.blocks[block].end);
+
+        let types = self.types[start..end].to_vec();
+
+        for (instruction, ty) in body[start..end].iter().zip(types) {
+            self.instruction(instruction, ty, &mut code)?;
+        }
+
+        match graph.blocks[block].exit {
+            Exit::Jump(to) => code.extend(self.branch(graph, block, to, context, body)?),
+
+            Exit::Branch(i, ltrue, lfalse) => {
+                if let Instruction::CJump(lhs, ref op, rhs, _, _) = body[i] {
+                    code.push(Instr::LocalGet(self.local(lhs)));
+                    code.push(Instr::LocalGet(self.local(rhs)));
+                    code.push(Instr::Numeric(compare(op, self.types[i].0)));
+                }
+
+                context.push(Context::IfThenElse);
+                let then = self.branch(graph, block, ltrue, context, body);
+                let otherwise = self.branch(graph, block, lfalse, context, body);
+                context.pop();
+
+                code.push(Instr::If(then?, otherwise?));
+            }
+
+            Exit::Return(i) => {
+                if let Instruction::Return(temp) = body[i] {
+                    code.push(Instr::LocalGet(self.local(temp)));
+                    code.extend(extend(self.returns));
+                    code.extend(to_wasm(self.returns));
+                    code.push(Instr::Return);
+                }
+            }
+
+            // Falling of the end of a function returns zero
+            Exit::FallOff => {
+                code.push(match val_type(self.returns) {
+                    ValType::I32 => Instr::I32Const(0),
+                    ValType::I64 => Instr::I64Const(0),
+                });
+                code.push(Instr::Return);
+            }
+        }
+
+        Ok(code)
+    }
+
+    /// Branches back to a loop or forward to a merge node, otherwise the target is
+    /// only reached from here so its code goes here
+    fn branch(
+        &mut self,
+        graph: &Graph,
+        from: usize,
+        to: usize,
+        context: &mut Vec<Context>,
+        body: &[Instruction],
+    ) -> Result<Vec<Instr>, CodegenError> {
+        let target = if graph.is_backward(from, to) {
+            Context::LoopHeadedBy(to)
+        } else if graph.is_merge(to) {
+            Context::BlockFollowedBy(to)
+        } else {
+            return self.tree(graph, to, context, body);
+        };
+
+        match context.iter().rev().position(|&level| level == target) {
+            Some(depth) => Ok(vec![Instr::Br(depth as u32)]),
+            None => Err(CodegenError::Unsupported(
+                "A branch that can't be structured".into(),
+            )),
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        instruction: &Instruction,
+        operand: (Sign, Size),
+        code: &mut Vec<Instr>,
+    ) -> Result<(), CodegenError> {
+        match *instruction {
+            Instruction::Store(temp, ref value) => {
+                match *value {
+                    Value::Const(value, sign, size) => {
+                        code.push(Instr::I64Const(extend_const(value, (sign, size))))
+                    }
+                    Value::Temp(from) => code.push(Instr::LocalGet(self.local(from))),
+                    Value::Mem(ref bytes) => {
+                        let address = self.string(bytes);
+                        code.push(Instr::I64Const(i64::from(address)))
+                    }
+                    Value::Name(_) => {
+                        return Err(CodegenError::Unsupported(format!(
+                            "`{}` is not supported by the wasm backend",
+                            instruction
+                        )))
+                    }
+                }
+
+                code.push(Instr::LocalSet(self.local(temp)));
+            }
+
+            Instruction::Copy(to, from) => {
+                code.push(Instr::LocalGet(self.local(from)));
+                code.push(Instr::LocalSet(self.local(to)));
+            }
+
+            Instruction::BinOp(lhs, ref op, rhs, to) => {
+                let (sign, _) = operand;
+
+                if let BinOp::Div = *op {
+                    self.check_divisor(rhs, code);
+                }
+
+                code.push(Instr::LocalGet(self.local(lhs)));
+                code.push(Instr::LocalGet(self.local(rhs)));
+
+                let ty = match *op {
+                    BinOp::Plus => {
+                        code.push(Instr::Numeric(Numeric::I64Add));
+                        operand
+                    }
+                    BinOp::Minus => {
+                        code.push(Instr::Numeric(Numeric::I64Sub));
+                        operand
+                    }
+                    BinOp::Mul => {
+                        code.push(Instr::Numeric(Numeric::I64Mul));
+                        operand
+                    }
+                    BinOp::Div => {
+                        code.push(Instr::Numeric(match sign {
+                            Sign::Signed => Numeric::I64DivS,
+                            Sign::Unsigned => Numeric::I64DivU,
+                        }));
+                        operand
+                    }
+                    BinOp::And => {
+                        code.push(Instr::Numeric(Numeric::I64And));
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                    BinOp::Or => {
+                        code.push(Instr::Numeric(Numeric::I64Or));
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                };
+
+                code.extend(extend(ty));
+                code.push(Instr::LocalSet(self.local(to)));
+            }
+
+            Instruction::UnOp(to, ref op, from) => {
+                match *op {
+                    UnOp::Minus => {
+                        code.push(Instr::I64Const(0));
+                        code.push(Instr::LocalGet(self.local(from)));
+                        code.push(Instr::Numeric(Numeric::I64Sub));
+                        code.extend(extend(operand));
+                    }
+                    UnOp::Bang => {
+                        code.push(Instr::LocalGet(self.local(from)));
+                        code.push(Instr::Numeric(Numeric::I64Eqz));
+                        code.push(Instr::Numeric(Numeric::I64ExtendI32U));
+                    }
+                }
+
+                code.push(Instr::LocalSet(self.local(to)));
+            }
+
+            Instruction::Cast(to, from, sign, size) => {
+                code.push(Instr::LocalGet(self.local(from)));
+                code.extend(extend((sign, size)));
+                code.push(Instr::LocalSet(self.local(to)));
+            }
+
+            Instruction::Call(to, callee, ref args) => {
+                let (id, params, returns) = match self.functions.get(&callee) {
+                    Some(function) => function.clone(),
+                    None => {
+                        return Err(CodegenError::Unsupported(format!(
+                            "Call to undefined function `{}`",
+                            callee
+                        )))
+                    }
+                };
+
+                for (arg, ty) in args
+                    .iter()
+                    .zip(params.into_iter().chain(::std::iter::repeat(DEFAULT_TYPE)))
+                {
+                    code.push(Instr::LocalGet(self.local(*arg)));
+                    code.extend(to_wasm(ty));
+                }
+
+                code.push(Instr::Call(id));
+                code.extend(from_wasm(returns));
+                code.push(Instr::LocalSet(self.local(to)));
+            }
+
+            Instruction::Intrinsic(to, intrinsic, ref args) => {
+                self.intrinsic(to, intrinsic, args, operand, code)?
+            }
+
+            Instruction::Value(_) | Instruction::Label(_) => (),
+
+            // Structs and arrays live on a heap, which modules don't have
+            Instruction::Alloc(_, _)
+            | Instruction::LoadAt(_, _, _, _, _)
+            | Instruction::StoreAt(_, _, _, _, _) => {
+                return Err(CodegenError::Unsupported(format!(
+                    "Structs and arrays are not supported by the wasm backend, `{}` needs a heap",
+                    instruction
+                )))
+            }
+
+            Instruction::Jump(_)
+            | Instruction::CJump(_, _, _, _, _)
+            | Instruction::Return(_)
+            | Instruction::Load(_)
+            | Instruction::Block(_, _)
+            | Instruction::Closure(_, _, _)
+            | Instruction::GetUpvalue(_, _)
+            | Instruction::SetUpvalue(_, _)
+            | Instruction::CloseUpvalue(_)
+            | Instruction::CallClosure(_, _, _, _)
+            | Instruction::Phi(_, _) => {
+                return Err(CodegenError::Unsupported(format!(
+                    "`{}` is not supported by the wasm backend",
+                    instruction
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn intrinsic(
+        &mut self,
+        to: Temp,
+        intrinsic: Intrinsic,
+        args: &[Temp],
+        (sign, _): (Sign, Size),
+        code: &mut Vec<Instr>,
+    ) -> Result<(), CodegenError> {
+        // Comparing and joining strings needs a heap, which modules don't have
+        if intrinsic == Intrinsic::StrEq || intrinsic == Intrinsic::StrConcat {
+            return Err(CodegenError::Unsupported(format!(
+                "`{}` is not supported by the wasm backend",
+                intrinsic
+            )));
+        }
+
+        let arg = match args.first() {
+            Some(arg) => *arg,
+            None => {
+                return Err(CodegenError::Unsupported(format!(
+                    "`{}` needs an argument",
+                    intrinsic
+                )))
+            }
+        };
+
+        code.push(Instr::LocalGet(self.local(arg)));
+
+        if intrinsic == Intrinsic::StrLen {
+            code.push(Instr::Numeric(Numeric::I32WrapI64));
+            code.push(Instr::I64Load);
+            code.extend(extend((Sign::Signed, Size::Bit32)));
+            code.push(Instr::LocalSet(self.local(to)));
+
+            return Ok(());
+        }
+
+        let name = match intrinsic {
+            Intrinsic::PrintInt if sign == Sign::Unsigned => "print_uint",
+            Intrinsic::PrintInt => "print_int",
+            Intrinsic::PrintBool => "print_bool",
+            Intrinsic::PrintChar => "print_char",
+            Intrinsic::PrintStr => "print_str",
+            Intrinsic::Assert => "assert",
+            Intrinsic::Exit => "exit",
+            Intrinsic::StrLen | Intrinsic::StrEq | Intrinsic::StrConcat => unreachable!(),
+        };
+
+        // Integers are printed at their full width, everything else fits in an i32
+        let param = match intrinsic {
+            Intrinsic::PrintInt => ValType::I64,
+            _ => {
+                code.push(Instr::Numeric(Numeric::I32WrapI64));
+                ValType::I32
+            }
+        };
+
+        code.push(Instr::Call(self.import(name, param)));
+
+        if intrinsic == Intrinsic::Exit {
+            code.push(Instr::Unreachable);
+        }
+
+        Ok(())
+    }
+
+    /// Stops with a runtime error the same way the vm reports it when dividing by zero
+    fn check_divisor(&mut self, divisor: Temp, code: &mut Vec<Instr>) {
+        let message = self.message("Attempted to divide by zero");
+        let error = self.import("error", ValType::I32);
+
+        code.push(Instr::LocalGet(self.local(divisor)));
+        code.push(Instr::Numeric(Numeric::I64Eqz));
+        code.push(Instr::If(
+            vec![
+                Instr::I32Const(message as i32),
+                Instr::Call(error),
+                Instr::Unreachable,
+            ],
+            vec![],
+        ));
+    }
+
+    /// Imports a function of the runtime the first time it is used
+    fn import(&mut self, name: &str, param: ValType) -> String {
+        let id = format!("{}.{}", RUNTIME, name);
+
+        if !self.module.imports.iter().any(|import| import.id == id) {
+            self.module.imports.push(Import {
+                module: RUNTIME.into(),
+                name: name.into(),
+                id: id.clone(),
+                params: vec![param],
+                result: None,
+            });
+        }
+
+        id
+    }
+
+    /// Stores a string in memory, returning its address. Every literal gets its own copy
+    /// as strings are compared by address
+    fn string(&mut self, bytes: &[u8]) -> u32 {
+        let address = self.data_end;
+
+        let mut data = (bytes.len() as u64).to_le_bytes().to_vec();
+        data.extend_from_slice(bytes);
+        data.push(0);
+
+        // Keep the lengths aligned
+        self.data_end = (address + data.len() as u32 + 7) & !7;
+
+        self.module.data.push((address, data));
+
+        address
+    }
+
+    fn message(&mut self, message: &'static str) -> u32 {
+        if let Some(&address) = self.messages.get(message) {
+            return address;
+        }
+
+        let address = self.string(message.as_bytes());
+        self.messages.insert(message, address);
+
+        address
+    }
+
+    /// The local a temp lives in, temps get theirs the first time they are used
+    fn local(&mut self, temp: Temp) -> u32 {
+        let next = self.params + self.locals.len() as u32;
+
+        *self.locals.entry(temp).or_insert(next)
+    }
+}
+
+impl Default for Codegen {
+    fn default() -> Self {
+        Codegen::new()
+    }
+}
+
+/// Values up to 32 bits wide are passed as an `i32`
+fn val_type((_, size): (Sign, Size)) -> ValType {
+    match size {
+        Size::Bit64 => ValType::I64,
+        Size::Bit8 | Size::Bit32 => ValType::I32,
+    }
+}
+
+/// Narrows the value on top of the stack to the type it is passed as
+fn to_wasm(ty: (Sign, Size)) -> Vec<Instr> {
+    match val_type(ty) {
+        ValType::I32 => vec![Instr::Numeric(Numeric::I32WrapI64)],
+        ValType::I64 => vec![],
+    }
+}
+
+/// Extends a value that was passed as its type back to 64 bits
+fn from_wasm((sign, size): (Sign, Size)) -> Vec<Instr> {
+    match (val_type((sign, size)), sign) {
+        (ValType::I64, _) => vec![],
+        (ValType::I32, Sign::Signed) => vec![Instr::Numeric(Numeric::I64ExtendI32S)],
+        (ValType::I32, Sign::Unsigned) => vec![Instr::Numeric(Numeric::I64ExtendI32U)],
+    }
+}
+
+/// Truncates the value on top of the stack to the width of `ty` and extends it back
+fn extend((sign, size): (Sign, Size)) -> Vec<Instr> {
+    match (sign, size) {
+        (_, Size::Bit64) => vec![],
+        (Sign::Signed, Size::Bit8) => vec![Instr::Numeric(Numeric::I64Extend8S)],
+        (Sign::Signed, Size::Bit32) => vec![Instr::Numeric(Numeric::I64Extend32S)],
+        (Sign::Unsigned, Size::Bit8) => {
+            vec![Instr::I64Const(0xff), Instr::Numeric(Numeric::I64And)]
+        }
+        (Sign::Unsigned, Size::Bit32) => vec![
+            Instr::I64Const(0xffff_ffff),
+            Instr::Numeric(Numeric::I64And),
+        ],
+    }
+}
+
+fn extend_const(value: u64, (sign, size): (Sign, Size)) -> i64 {
+    match (sign, size) {
+        (_, Size::Bit64) => value as i64,
+        (Sign::Signed, Size::Bit8) => i64::from(value as i8),
+        (Sign::Signed, Size::Bit32) => i64::from(value as i32),
+        (Sign::Unsigned, Size::Bit8) => i64::from(value as u8),
+        (Sign::Unsigned, Size::Bit32) => i64::from(value as u32),
+    }
+}
+
+fn compare(op: &CmpOp, sign: Sign) -> Numeric {
+    let signed = sign == Sign::Signed;
+
+    match *op {
+        CmpOp::EQ => Numeric::I64Eq,
+        CmpOp::NE => Numeric::I64Ne,
+        CmpOp::LT if signed => Numeric::I64LtS,
+        CmpOp::LT => Numeric::I64LtU,
+        CmpOp::LTE if signed => Numeric::I64LeS,
+        CmpOp::LTE => Numeric::I64LeU,
+        CmpOp::GT if signed => Numeric::I64GtS,
+        CmpOp::GT => Numeric::I64GtU,
+        CmpOp::GTE if signed => Numeric::I64GeS,
+        CmpOp::GTE => Numeric::I64GeU,
+    }
+}
+
+/// Hex escapes anything that isn't allowed in an id, which leaves `.` free to
+/// separate the runtime's imports from the program's functions
+fn escape(name: &str) -> String {
+    let mut id = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            id.push(c);
+        } else {
+            id.push_str(&format!("-{:x}", c as u32));
+        }
+    }
+
+    id
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codegen, CodegenError};
+    use ir::ir::{BinOp, CmpOp, Function, Instruction, Intrinsic, Program, Temp, Value};
+    use parse::parse;
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+    use validate::validate;
+    use wat::Instr;
+
+    const I32: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+    fn function(
+        name: &str,
+        params: Vec<Temp>,
+        body: Vec<Instruction>,
+        linkage: Linkage,
+        symbols: &mut Symbols<()>,
+    ) -> Function {
+        Function {
+            name: symbols.symbol(name),
+            param_types: params.iter().map(|_| I32).collect(),
+            params,
+            returns: I32,
+            upvalues: vec![],
+            body,
+            linkage,
+        }
+    }
+
+    /// Counts up to `n` skipping 3, then branches on the result and calls out
+    fn program(symbols: &mut Symbols<()>) -> Program {
+        let (n, i, total, one, three) = (
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+        );
+        let (top, body, add, end) = (
+            symbols.symbol("top"),
+            symbols.symbol("body"),
+            symbols.symbol("add"),
+            symbols.symbol("end"),
+        );
+
+        let sum = function(
+            "sum",
+            vec![n],
+            vec![
+                Instruction::Store(total, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Store(i, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Label(top),
+                Instruction::CJump(i, CmpOp::LT, n, body, end),
+                Instruction::Label(body),
+                Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+                Instruction::BinOp(i, BinOp::Plus, one, i),
+                Instruction::Store(three, Value::Const(3, Sign::Signed, Size::Bit32)),
+                Instruction::CJump(i, CmpOp::EQ, three, top, add),
+                Instruction::Label(add),
+                Instruction::BinOp(total, BinOp::Plus, i, total),
+                Instruction::Jump(top),
+                Instruction::Label(end),
+                Instruction::Return(total),
+            ],
+            Linkage::Normal,
+            symbols,
+        );
+
+        let (result, limit, string, quotient, x) = (
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+        );
+        let (big, small, join) = (
+            symbols.symbol("big"),
+            symbols.symbol("small"),
+            symbols.symbol("join"),
+        );
+
+        let main = function(
+            "main",
+            vec![],
+            vec![
+                Instruction::Store(limit, Value::Const(5, Sign::Signed, Size::Bit32)),
+                Instruction::Call(result, symbols.symbol("sum"), vec![limit]),
+                Instruction::CJump(result, CmpOp::GT, limit, big, small),
+                Instruction::Label(big),
+                Instruction::Store(string, Value::Mem(b"big".to_vec())),
+                Instruction::Jump(join),
+                Instruction::Label(small),
+                Instruction::Store(string, Value::Mem(b"small".to_vec())),
+                Instruction::Label(join),
+                Instruction::Intrinsic(x, Intrinsic::PrintStr, vec![string]),
+                Instruction::BinOp(result, BinOp::Div, limit, quotient),
+                Instruction::Call(x, symbols.symbol("abs"), vec![quotient]),
+                Instruction::Intrinsic(x, Intrinsic::PrintInt, vec![x]),
+            ],
+            Linkage::Normal,
+            symbols,
+        );
+
+        let abs = function("abs", vec![Temp::new()], vec![], Linkage::External, symbols);
+
+        Program {
+            functions: vec![sum, main, abs],
+        }
+    }
+
+    #[test]
+    fn control_flow_is_structured() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let program = program(&mut symbols);
+
+        let module = Codegen::new().compile(&program, &symbols).unwrap();
+
+        validate(&module).unwrap();
+
+        // The loop header is entered from the top and from both the continue and the
+        // end of the body, while the join of the if in main follows a block
+        let sum = &module.functions[0];
+        assert!(sum
+            .body
+            .iter()
+            .any(|instr| matches!(*instr, Instr::Loop(_))));
+
+        let main = &module.functions[1];
+        assert!(main
+            .body
+            .iter()
+            .any(|instr| matches!(*instr, Instr::Block(_))));
+
+        assert_eq!(module.imports[0].module, "env");
+        assert_eq!(module.imports[0].name, "abs");
+        assert_eq!(module.data.len(), 3);
+    }
+
+    #[test]
+    fn modules_round_trip() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let program = program(&mut symbols);
+
+        let module = Codegen::new().compile(&program, &symbols).unwrap();
+        let text = module.to_string();
+        let parsed = parse(&text).unwrap();
+
+        assert_eq!(parsed, module);
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn loops_with_two_entries_are_rejected() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let (a, b) = (symbols.symbol("a"), symbols.symbol("b"));
+        let x = Temp::new();
+
+        let program = Program {
+            functions: vec![function(
+                "main",
+                vec![x],
+                vec![
+                    Instruction::CJump(x, CmpOp::EQ, x, a, b),
+                    Instruction::Label(a),
+                    Instruction::Jump(b),
+                    Instruction::Label(b),
+                    Instruction::Jump(a),
+                ],
+                Linkage::Normal,
+                &mut symbols,
+            )],
+        };
+
+        assert!(Codegen::new().compile(&program, &symbols).is_err());
+    }
+
+    #[test]
+    fn structs_are_rejected() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let (object, field) = (Temp::new(), Temp::new());
+
+        let program = Program {
+            functions: vec![function(
+                "main",
+                vec![],
+                vec![
+                    Instruction::Alloc(object, 8),
+                    Instruction::LoadAt(field, object, 0, Sign::Signed, Size::Bit32),
+                    Instruction::Return(field),
+                ],
+                Linkage::Normal,
+                &mut symbols,
+            )],
+        };
+
+        match Codegen::new().compile(&program, &symbols) {
+            Err(CodegenError::Unsupported(message)) => {
+                assert!(message.starts_with("Structs and arrays are not supported"))
+            }
+            Ok(module) => panic!("{}", module),
+        }
+    }
+}
@@ -0,0 +1,16 @@
+extern crate underscore_ir as ir;
+extern crate underscore_syntax as syntax;
+extern crate underscore_util as util;
+
+mod binary;
+mod codegen;
+mod parse;
+mod structure;
+mod validate;
+mod wat;
+
+pub use binary::encode;
+pub use codegen::{Codegen, CodegenError, EXTERNALS, RUNTIME};
+pub use parse::{parse, ParseError};
+pub use validate::{validate, ValidationError};
+pub use wat::{Func, Import, Instr, Module, Numeric, ValType};
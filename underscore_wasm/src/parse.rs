@@ -0,0 +1,381 @@
+//! Reads back the text format that `Module` prints, so a module can be round tripped
+//! and checked without any tools outside of the repo.
+use wat::{Func, Import, Instr, Module, Numeric, ValType};
+
+#[derive(Debug)]
+pub enum ParseError {
+    Syntax(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Atom(String),
+    Str(Vec<u8>),
+}
+
+pub fn parse(input: &str) -> Result<Module, ParseError> {
+    let mut parser = Parser {
+        tokens: lex(input)?,
+        current: 0,
+    };
+
+    let module = parser.module()?;
+
+    if parser.current != parser.tokens.len() {
+        return Err(parser.error("the end of the input"));
+    }
+
+    Ok(module)
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let bytes = input.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            b')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            b';' if bytes.get(i + 1) == Some(&b';') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' => {
+                let mut string = Vec::new();
+                i += 1;
+
+                loop {
+                    match bytes.get(i) {
+                        None => return Err(ParseError::Syntax("Unterminated string".into())),
+                        Some(&b'"') => break,
+                        Some(&b'\\') => {
+                            let escape = &bytes[i + 1..(i + 3).min(bytes.len())];
+
+                            if escape.len() == 2 && escape.iter().all(u8::is_ascii_hexdigit) {
+                                let hex = String::from_utf8_lossy(escape);
+                                string.push(u8::from_str_radix(&hex, 16).unwrap());
+                                i += 3;
+                            } else if escape.first() == Some(&b'"')
+                                || escape.first() == Some(&b'\\')
+                            {
+                                string.push(escape[0]);
+                                i += 2;
+                            } else {
+                                return Err(ParseError::Syntax("Invalid escape in string".into()));
+                            }
+                        }
+                        Some(&byte) => {
+                            string.push(byte);
+                            i += 1;
+                        }
+                    }
+                }
+
+                tokens.push(Token::Str(string));
+                i += 1;
+            }
+            byte if byte.is_ascii_whitespace() => i += 1,
+            _ => {
+                let start = i;
+
+                while i < bytes.len()
+                    && !bytes[i].is_ascii_whitespace()
+                    && bytes[i] != b'('
+                    && bytes[i] != b')'
+                    && bytes[i] != b'"'
+                {
+                    i += 1;
+                }
+
+                tokens.push(Token::Atom(input[start..i].into()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl Parser {
+    fn module(&mut self) -> Result<Module, ParseError> {
+        self.expect(Token::LParen)?;
+        self.keyword("module")?;
+
+        let mut module = Module::default();
+
+        while !self.eat(&Token::RParen) {
+            self.expect(Token::LParen)?;
+
+            match self.atom()?.as_str() {
+                "import" => {
+                    let module_name = self.string()?;
+                    let name = self.string()?;
+
+                    self.expect(Token::LParen)?;
+                    self.keyword("func")?;
+
+                    let id = self.id()?;
+                    let params = self.types("param")?;
+                    let result = self.result()?;
+
+                    self.expect(Token::RParen)?;
+                    self.expect(Token::RParen)?;
+
+                    module.imports.push(Import {
+                        module: module_name,
+                        name,
+                        id,
+                        params,
+                        result,
+                    });
+                }
+
+                "memory" => {
+                    self.export()?;
+                    module.memory = self.number()?;
+                    self.expect(Token::RParen)?;
+                }
+
+                "data" => {
+                    self.expect(Token::LParen)?;
+                    self.keyword("i32.const")?;
+                    let offset = self.number()?;
+                    self.expect(Token::RParen)?;
+
+                    let mut bytes = Vec::new();
+
+                    while let Some(Token::Str(string)) = self.tokens.get(self.current) {
+                        bytes.extend_from_slice(string);
+                        self.current += 1;
+                    }
+
+                    self.expect(Token::RParen)?;
+
+                    module.data.push((offset, bytes));
+                }
+
+                "func" => {
+                    let id = self.id()?;
+                    let export = self.export()?;
+                    let params = self.types("param")?;
+                    let result = self.result()?;
+                    let locals = self.types("local")?;
+
+                    let (body, end) = self.body()?;
+
+                    if end != ")" {
+                        return Err(ParseError::Syntax(format!("Unexpected `{}`", end)));
+                    }
+
+                    module.functions.push(Func {
+                        id,
+                        export,
+                        params,
+                        result,
+                        locals,
+                        body,
+                    });
+                }
+
+                field => return Err(ParseError::Syntax(format!("Unknown field `{}`", field))),
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Reads instructions up to an `end`, `else` or the closing paren of the function,
+    /// returning which one it stopped at
+    fn body(&mut self) -> Result<(Vec<Instr>, String), ParseError> {
+        let mut body = Vec::new();
+
+        loop {
+            if self.eat(&Token::RParen) {
+                return Ok((body, ")".into()));
+            }
+
+            let atom = self.atom()?;
+
+            let instr = match atom.as_str() {
+                "end" | "else" => return Ok((body, atom)),
+                "block" => Instr::Block(self.block()?),
+                "loop" => Instr::Loop(self.block()?),
+                "if" => {
+                    let (then, end) = self.body()?;
+
+                    let otherwise = match end.as_str() {
+                        "else" => self.block()?,
+                        "end" => vec![],
+                        _ => return Err(self.error("`else` or `end`")),
+                    };
+
+                    Instr::If(then, otherwise)
+                }
+                "br" => Instr::Br(self.number()?),
+                "return" => Instr::Return,
+                "unreachable" => Instr::Unreachable,
+                "call" => Instr::Call(self.id()?),
+                "local.get" => Instr::LocalGet(self.number()?),
+                "local.set" => Instr::LocalSet(self.number()?),
+                "i32.const" => Instr::I32Const(self.number()?),
+                "i64.const" => Instr::I64Const(self.number()?),
+                "i64.load" => Instr::I64Load,
+                name => match Numeric::ALL.iter().find(|numeric| numeric.name() == name) {
+                    Some(numeric) => Instr::Numeric(*numeric),
+                    None => {
+                        return Err(ParseError::Syntax(format!(
+                            "Unknown instruction `{}`",
+                            name
+                        )))
+                    }
+                },
+            };
+
+            body.push(instr);
+        }
+    }
+
+    /// The body of a block, loop or else arm, which has to finish with `end`
+    fn block(&mut self) -> Result<Vec<Instr>, ParseError> {
+        match self.body()? {
+            (body, ref end) if end == "end" => Ok(body),
+            _ => Err(self.error("`end`")),
+        }
+    }
+
+    /// Any number of `(param ...)` or `(local ...)` groups
+    fn types(&mut self, kind: &str) -> Result<Vec<ValType>, ParseError> {
+        let mut types = Vec::new();
+
+        while self.peek_field(kind) {
+            self.current += 2;
+
+            while !self.eat(&Token::RParen) {
+                types.push(self.val_type()?);
+            }
+        }
+
+        Ok(types)
+    }
+
+    fn result(&mut self) -> Result<Option<ValType>, ParseError> {
+        if !self.peek_field("result") {
+            return Ok(None);
+        }
+
+        self.current += 2;
+
+        let ty = self.val_type()?;
+        self.expect(Token::RParen)?;
+
+        Ok(Some(ty))
+    }
+
+    fn export(&mut self) -> Result<Option<String>, ParseError> {
+        if !self.peek_field("export") {
+            return Ok(None);
+        }
+
+        self.current += 2;
+
+        let name = self.string()?;
+        self.expect(Token::RParen)?;
+
+        Ok(Some(name))
+    }
+
+    fn val_type(&mut self) -> Result<ValType, ParseError> {
+        match self.atom()?.as_str() {
+            "i32" => Ok(ValType::I32),
+            "i64" => Ok(ValType::I64),
+            ty => Err(ParseError::Syntax(format!("Unknown type `{}`", ty))),
+        }
+    }
+
+    fn peek_field(&self, name: &str) -> bool {
+        self.tokens.get(self.current) == Some(&Token::LParen)
+            && self.tokens.get(self.current + 1) == Some(&Token::Atom(name.into()))
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.current) == Some(token) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("{:?}", token)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.expect(Token::Atom(keyword.into()))
+    }
+
+    fn atom(&mut self) -> Result<String, ParseError> {
+        match self.tokens.get(self.current).cloned() {
+            Some(Token::Atom(atom)) => {
+                self.current += 1;
+                Ok(atom)
+            }
+            _ => Err(self.error("a keyword")),
+        }
+    }
+
+    fn id(&mut self) -> Result<String, ParseError> {
+        let atom = self.atom()?;
+
+        if atom.starts_with('$') && atom.len() > 1 {
+            Ok(atom[1..].into())
+        } else {
+            Err(ParseError::Syntax(format!(
+                "Expected an id found `{}`",
+                atom
+            )))
+        }
+    }
+
+    fn number<T: ::std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        let atom = self.atom()?;
+
+        atom.parse()
+            .map_err(|_| ParseError::Syntax(format!("Expected a number found `{}`", atom)))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        match self.tokens.get(self.current).cloned() {
+            Some(Token::Str(bytes)) => {
+                self.current += 1;
+                String::from_utf8(bytes).map_err(|_| ParseError::Syntax("Invalid utf-8".into()))
+            }
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    fn error(&self, expected: &str) -> ParseError {
+        match self.tokens.get(self.current) {
+            Some(token) => ParseError::Syntax(format!("Expected {} found {:?}", expected, token)),
+            None => ParseError::Syntax(format!("Expected {} found the end of the input", expected)),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Finds the dominator tree of a function's control flow graph, which is what
+//! structured control flow is recovered from. The blocks are numbered as in the
+//! `Cfg`, so the entry is always block 0.
+use ir::cfg::{Cfg, CfgError};
+use ir::dominators::Dominators;
+use ir::ir::Instruction;
+
+#[derive(Debug)]
+pub struct Block {
+    /// The instructions of the block, apart from the one it exits with
+    pub start: usize,
+    pub end: usize,
+    pub exit: Exit,
+}
+
+/// How control leaves a block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Jump(usize),
+    /// The `CJump` at an index of the body, with the blocks it goes to when true and false
+    Branch(usize, usize, usize),
+    /// The `Return` at an index of the body
+    Return(usize),
+    /// The end of the function
+    FallOff,
+}
+
+#[derive(Debug)]
+pub struct Graph {
+    pub blocks: Vec<Block>,
+    /// The position of each block in reverse postorder, `None` if it can't be reached
+    order: Vec<Option<usize>>,
+    dominators: Dominators,
+    /// The number of edges into each block from blocks earlier in reverse postorder
+    forward_edges: Vec<usize>,
+    /// Whether a block is the target of an edge from a later block
+    loop_header: Vec<bool>,
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Jump(to) => vec![to],
+            Exit::Branch(_, ltrue, lfalse) => vec![ltrue, lfalse],
+            Exit::Return(_) | Exit::FallOff => vec![],
+        }
+    }
+}
+
+impl Graph {
+    pub fn new(body: &[Instruction]) -> Result<Self, String> {
+        let cfg = Cfg::new(body).map_err(|e| match e {
+            CfgError::UndefinedLabel(label) => format!("Jump to undefined label `{}`", label),
+        })?;
+
+        let blocks: Vec<Block> = cfg
+            .blocks
+            .iter()
+            .map(|block| {
+                // The CFG only has one edge for a `CJump` to the same label twice
+                let exit = match body[block.start..block.end].last() {
+                    Some(&Instruction::Jump(_)) => Exit::Jump(block.successors[0]),
+                    Some(&Instruction::CJump(_, _, _, _, _)) => Exit::Branch(
+                        block.end - 1,
+                        block.successors[0],
+                        *block.successors.last().unwrap(),
+                    ),
+                    Some(&Instruction::Return(_)) => Exit::Return(block.end - 1),
+                    // Blocks without an exit fall through to the next one
+                    _ => match block.successors.first() {
+                        Some(&next) => Exit::Jump(next),
+                        None => Exit::FallOff,
+                    },
+                };
+
+                let end = match body[block.start..block.end].last() {
+                    Some(&Instruction::Jump(_))
+                    | Some(&Instruction::CJump(_, _, _, _, _))
+                    | Some(&Instruction::Return(_)) => block.end - 1,
+                    _ => block.end,
+                };
+
+                Block {
+                    start: block.start,
+                    end,
+                    exit,
+                }
+            })
+            .collect();
+
+        let mut graph = Graph {
+            order: vec![None; blocks.len()],
+            dominators: Dominators::new(&cfg),
+            forward_edges: vec![0; blocks.len()],
+            loop_header: vec![false; blocks.len()],
+            blocks,
+        };
+
+        for (i, block) in cfg.reverse_postorder().into_iter().enumerate() {
+            graph.order[block] = Some(i);
+        }
+
+        graph.classify_edges()?;
+
+        Ok(graph)
+    }
+
+    /// Finds the merge nodes and loop headers, failing if a loop has two entries
+    fn classify_edges(&mut self) -> Result<(), String> {
+        for block in 0..self.blocks.len() {
+            if self.order[block].is_none() {
+                continue;
+            }
+
+            for successor in self.blocks[block].exit.successors() {
+                if self.is_backward(block, successor) {
+                    if !self.dominates(successor, block) {
+                        return Err("Loops with more than one entry".into());
+                    }
+
+                    self.loop_header[successor] = true;
+                } else {
+                    self.forward_edges[successor] += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        self.dominators.dominates(a, b)
+    }
+
+    /// Whether an edge goes back to a block at or before where it started
+    pub fn is_backward(&self, from: usize, to: usize) -> bool {
+        self.order[to] <= self.order[from]
+    }
+
+    pub fn is_loop_header(&self, block: usize) -> bool {
+        self.loop_header[block]
+    }
+
+    /// A block that can be reached from more than one place without looping
+    pub fn is_merge(&self, block: usize) -> bool {
+        self.forward_edges[block] > 1
+    }
+
+    /// The merge nodes that a block immediately dominates, latest first
+    pub fn merge_children(&self, block: usize) -> Vec<usize> {
+        let mut children: Vec<usize> = self
+            .dominators
+            .children(block)
+            .iter()
+            .cloned()
+            .filter(|&child| self.is_merge(child))
+            .collect();
+
+        children.sort_by_key(|&child| ::std::cmp::Reverse(self.order[child]));
+        children
+    }
+}
@@ -0,0 +1,318 @@
+//! Type checks a module with the validation algorithm from the WebAssembly spec,
+//! so a generated module can be checked to load in any runtime without running one.
+use std::collections::{HashMap, HashSet};
+use wat::{Instr, Module, ValType};
+
+/// The size of a page of memory
+const PAGE_SIZE: u64 = 65536;
+
+#[derive(Debug)]
+pub enum ValidationError {
+    Invalid(String),
+}
+
+pub fn validate(module: &Module) -> Result<(), ValidationError> {
+    let mut signatures = HashMap::new();
+
+    let imports = module
+        .imports
+        .iter()
+        .map(|import| (&import.id, &import.params, import.result));
+    let functions = module
+        .functions
+        .iter()
+        .map(|function| (&function.id, &function.params, function.result));
+
+    for (id, params, result) in imports.chain(functions) {
+        if signatures
+            .insert(id.as_str(), (params.as_slice(), result))
+            .is_some()
+        {
+            return Err(invalid(format!("The function `${}` is defined twice", id)));
+        }
+    }
+
+    let mut exports = HashSet::new();
+    exports.insert("memory");
+
+    for function in &module.functions {
+        if let Some(ref export) = function.export {
+            if !exports.insert(export.as_str()) {
+                return Err(invalid(format!("`{}` is exported twice", export)));
+            }
+        }
+    }
+
+    for &(offset, ref bytes) in &module.data {
+        if u64::from(offset) + bytes.len() as u64 > u64::from(module.memory) * PAGE_SIZE {
+            return Err(invalid(format!(
+                "The data at {} doesn't fit in memory",
+                offset
+            )));
+        }
+    }
+
+    for function in &module.functions {
+        let mut locals = function.params.clone();
+        locals.extend_from_slice(&function.locals);
+
+        let mut validator = Validator {
+            signatures: &signatures,
+            locals,
+            result: function.result,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        };
+
+        validator
+            .function(&function.body)
+            .map_err(|e| invalid(format!("In `${}`: {}", function.id, e)))?;
+    }
+
+    Ok(())
+}
+
+fn invalid(message: String) -> ValidationError {
+    ValidationError::Invalid(message)
+}
+
+/// A block, loop or if that is being checked
+struct Frame {
+    /// The height of the operand stack when the frame was entered
+    height: usize,
+    /// The types that a branch to the frame has to leave on the stack
+    labels: Vec<ValType>,
+    /// Set once the rest of the frame can't be reached
+    unreachable: bool,
+}
+
+struct Validator<'a> {
+    signatures: &'a HashMap<&'a str, (&'a [ValType], Option<ValType>)>,
+    locals: Vec<ValType>,
+    result: Option<ValType>,
+    /// `None` is a value of any type, left by code that can't be reached
+    stack: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Validator<'a> {
+    fn function(&mut self, body: &[Instr]) -> Result<(), String> {
+        let results: Vec<ValType> = self.result.into_iter().collect();
+
+        self.block(body, results.clone(), results)
+    }
+
+    /// Checks a body that leaves `results` on the stack when it ends
+    fn block(
+        &mut self,
+        body: &[Instr],
+        labels: Vec<ValType>,
+        results: Vec<ValType>,
+    ) -> Result<(), String> {
+        self.frames.push(Frame {
+            height: self.stack.len(),
+            labels,
+            unreachable: false,
+        });
+
+        for instr in body {
+            self.instr(instr)?;
+        }
+
+        for ty in results.iter().rev() {
+            self.pop(Some(*ty))?;
+        }
+
+        let frame = self.frames.pop().unwrap();
+
+        if self.stack.len() != frame.height {
+            return Err("Values are left on the stack at the end of a block".into());
+        }
+
+        self.stack.extend(results.into_iter().map(Some));
+
+        Ok(())
+    }
+
+    fn instr(&mut self, instr: &Instr) -> Result<(), String> {
+        match *instr {
+            Instr::Block(ref body) => self.block(body, vec![], vec![])?,
+
+            Instr::Loop(ref body) => self.block(body, vec![], vec![])?,
+
+            Instr::If(ref then, ref otherwise) => {
+                self.pop(Some(ValType::I32))?;
+                self.block(then, vec![], vec![])?;
+                self.block(otherwise, vec![], vec![])?;
+            }
+
+            Instr::Br(depth) => {
+                let labels = match self.frames.len().checked_sub(depth as usize + 1) {
+                    Some(index) => self.frames[index].labels.clone(),
+                    None => return Err(format!("There is no block {} levels out", depth)),
+                };
+
+                for ty in labels.iter().rev() {
+                    self.pop(Some(*ty))?;
+                }
+
+                self.unreachable();
+            }
+
+            Instr::Return => {
+                if let Some(result) = self.result {
+                    self.pop(Some(result))?;
+                }
+
+                self.unreachable();
+            }
+
+            Instr::Unreachable => self.unreachable(),
+
+            Instr::Call(ref id) => {
+                let (params, result) = match self.signatures.get(id.as_str()) {
+                    Some(&signature) => signature,
+                    None => return Err(format!("Call to undefined function `${}`", id)),
+                };
+
+                for ty in params.iter().rev() {
+                    self.pop(Some(*ty))?;
+                }
+
+                if let Some(result) = result {
+                    self.stack.push(Some(result));
+                }
+            }
+
+            Instr::LocalGet(index) => {
+                let ty = self.local(index)?;
+                self.stack.push(Some(ty));
+            }
+
+            Instr::LocalSet(index) => {
+                let ty = self.local(index)?;
+                self.pop(Some(ty))?;
+            }
+
+            Instr::I32Const(_) => self.stack.push(Some(ValType::I32)),
+
+            Instr::I64Const(_) => self.stack.push(Some(ValType::I64)),
+
+            Instr::I64Load => {
+                self.pop(Some(ValType::I32))?;
+                self.stack.push(Some(ValType::I64));
+            }
+
+            Instr::Numeric(numeric) => {
+                let (params, result) = numeric.signature();
+
+                for ty in params.iter().rev() {
+                    self.pop(Some(*ty))?;
+                }
+
+                self.stack.push(Some(result));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn local(&self, index: u32) -> Result<ValType, String> {
+        self.locals
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| format!("There is no local {}", index))
+    }
+
+    /// Pops a value, which has to be of the expected type unless that is `None`
+    fn pop(&mut self, expected: Option<ValType>) -> Result<Option<ValType>, String> {
+        let frame = self.frames.last().unwrap();
+
+        let actual = if self.stack.len() == frame.height {
+            if frame.unreachable {
+                None
+            } else {
+                return Err("Popped a value from an empty stack".into());
+            }
+        } else {
+            self.stack.pop().unwrap()
+        };
+
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => Err(format!(
+                "Expected a value of type {} found {}",
+                expected, actual
+            )),
+            (Some(actual), _) => Ok(Some(actual)),
+            (None, expected) => Ok(expected),
+        }
+    }
+
+    /// The rest of the frame can't be reached so its stack can hold anything
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::validate;
+    use wat::{Func, Instr, Module, Numeric, ValType};
+
+    fn module(body: Vec<Instr>) -> Module {
+        Module {
+            imports: vec![],
+            memory: 1,
+            data: vec![],
+            functions: vec![Func {
+                id: "f".into(),
+                export: None,
+                params: vec![ValType::I32],
+                result: Some(ValType::I64),
+                locals: vec![ValType::I64],
+                body,
+            }],
+        }
+    }
+
+    #[test]
+    fn stacks_are_type_checked() {
+        let valid = vec![
+            Instr::LocalGet(0),
+            Instr::Numeric(Numeric::I64ExtendI32S),
+            Instr::LocalSet(1),
+            Instr::Loop(vec![
+                Instr::LocalGet(1),
+                Instr::Numeric(Numeric::I64Eqz),
+                Instr::If(vec![Instr::Br(1)], vec![]),
+                Instr::LocalGet(1),
+                Instr::Return,
+            ]),
+            Instr::Unreachable,
+        ];
+
+        assert!(validate(&module(valid)).is_ok());
+
+        let invalid = vec![
+            // An i32 where an i64 is expected
+            vec![Instr::LocalGet(0), Instr::Return],
+            // Nothing to return
+            vec![Instr::Block(vec![Instr::Return])],
+            // A value left at the end of a block
+            vec![Instr::Block(vec![Instr::I64Const(1)]), Instr::Unreachable],
+            // Out of range
+            vec![Instr::Block(vec![Instr::Br(2)]), Instr::Unreachable],
+            vec![Instr::LocalGet(2), Instr::Return],
+            vec![Instr::Call("g".into()), Instr::Return],
+            // The function falls off its end without a result
+            vec![],
+        ];
+
+        for body in invalid {
+            assert!(validate(&module(body.clone())).is_err(), "{:?}", body);
+        }
+    }
+}
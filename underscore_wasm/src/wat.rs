@@ -0,0 +1,329 @@
+//! The subset of WebAssembly that the code generator targets.
+//! Everything prints in the text format, with instructions written out flat rather
+//! than folded, so the output can be read back in by `parse`.
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    /// The size of the exported memory in 64KiB pages
+    pub memory: u32,
+    /// Bytes copied into memory at an offset when the module is instantiated
+    pub data: Vec<(u32, Vec<u8>)>,
+    pub functions: Vec<Func>,
+}
+
+/// A function the host provides
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub id: String,
+    pub params: Vec<ValType>,
+    pub result: Option<ValType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Func {
+    pub id: String,
+    /// The name the host can call the function by
+    pub export: Option<String>,
+    pub params: Vec<ValType>,
+    pub result: Option<ValType>,
+    /// Locals are numbered after the params
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+/// Blocks, loops and ifs never take or leave values on the stack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Block(Vec<Instr>),
+    Loop(Vec<Instr>),
+    /// The then and else arms
+    If(Vec<Instr>, Vec<Instr>),
+    /// Branch to the end of the enclosing block or if, or the start of the enclosing
+    /// loop, that is the given number of levels out
+    Br(u32),
+    Return,
+    Unreachable,
+    Call(String),
+    LocalGet(u32),
+    LocalSet(u32),
+    I32Const(i32),
+    I64Const(i64),
+    /// Load the eight bytes at an address
+    I64Load,
+    Numeric(Numeric),
+}
+
+/// Instructions that pop their operands and push a result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Numeric {
+    I32Eqz,
+    I32WrapI64,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64And,
+    I64Or,
+    I64Extend8S,
+    I64Extend32S,
+    I64ExtendI32S,
+    I64ExtendI32U,
+}
+
+impl Numeric {
+    pub const ALL: [Numeric; 24] = [
+        Numeric::I32Eqz,
+        Numeric::I32WrapI64,
+        Numeric::I64Eqz,
+        Numeric::I64Eq,
+        Numeric::I64Ne,
+        Numeric::I64LtS,
+        Numeric::I64LtU,
+        Numeric::I64GtS,
+        Numeric::I64GtU,
+        Numeric::I64LeS,
+        Numeric::I64LeU,
+        Numeric::I64GeS,
+        Numeric::I64GeU,
+        Numeric::I64Add,
+        Numeric::I64Sub,
+        Numeric::I64Mul,
+        Numeric::I64DivS,
+        Numeric::I64DivU,
+        Numeric::I64And,
+        Numeric::I64Or,
+        Numeric::I64Extend8S,
+        Numeric::I64Extend32S,
+        Numeric::I64ExtendI32S,
+        Numeric::I64ExtendI32U,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Numeric::I32Eqz => "i32.eqz",
+            Numeric::I32WrapI64 => "i32.wrap_i64",
+            Numeric::I64Eqz => "i64.eqz",
+            Numeric::I64Eq => "i64.eq",
+            Numeric::I64Ne => "i64.ne",
+            Numeric::I64LtS => "i64.lt_s",
+            Numeric::I64LtU => "i64.lt_u",
+            Numeric::I64GtS => "i64.gt_s",
+            Numeric::I64GtU => "i64.gt_u",
+            Numeric::I64LeS => "i64.le_s",
+            Numeric::I64LeU => "i64.le_u",
+            Numeric::I64GeS => "i64.ge_s",
+            Numeric::I64GeU => "i64.ge_u",
+            Numeric::I64Add => "i64.add",
+            Numeric::I64Sub => "i64.sub",
+            Numeric::I64Mul => "i64.mul",
+            Numeric::I64DivS => "i64.div_s",
+            Numeric::I64DivU => "i64.div_u",
+            Numeric::I64And => "i64.and",
+            Numeric::I64Or => "i64.or",
+            Numeric::I64Extend8S => "i64.extend8_s",
+            Numeric::I64Extend32S => "i64.extend32_s",
+            Numeric::I64ExtendI32S => "i64.extend_i32_s",
+            Numeric::I64ExtendI32U => "i64.extend_i32_u",
+        }
+    }
+
+    /// The types of the operands and of the result
+    pub fn signature(self) -> (&'static [ValType], ValType) {
+        use self::ValType::{I32, I64};
+
+        match self {
+            Numeric::I32Eqz => (&[I32], I32),
+            Numeric::I32WrapI64 | Numeric::I64Eqz => (&[I64], I32),
+            Numeric::I64Eq
+            | Numeric::I64Ne
+            | Numeric::I64LtS
+            | Numeric::I64LtU
+            | Numeric::I64GtS
+            | Numeric::I64GtU
+            | Numeric::I64LeS
+            | Numeric::I64LeU
+            | Numeric::I64GeS
+            | Numeric::I64GeU => (&[I64, I64], I32),
+            Numeric::I64Add
+            | Numeric::I64Sub
+            | Numeric::I64Mul
+            | Numeric::I64DivS
+            | Numeric::I64DivU
+            | Numeric::I64And
+            | Numeric::I64Or => (&[I64, I64], I64),
+            Numeric::I64Extend8S | Numeric::I64Extend32S => (&[I64], I64),
+            Numeric::I64ExtendI32S | Numeric::I64ExtendI32U => (&[I32], I64),
+        }
+    }
+}
+
+impl Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "(module")?;
+
+        for import in &self.imports {
+            write!(
+                f,
+                "  (import {} {} (func ${}",
+                quote(import.module.as_bytes()),
+                quote(import.name.as_bytes()),
+                import.id
+            )?;
+            write_signature(f, &import.params, import.result)?;
+            writeln!(f, "))")?;
+        }
+
+        writeln!(f, "  (memory (export \"memory\") {})", self.memory)?;
+
+        for &(offset, ref bytes) in &self.data {
+            writeln!(f, "  (data (i32.const {}) {})", offset, quote(bytes))?;
+        }
+
+        for function in &self.functions {
+            write!(f, "  (func ${}", function.id)?;
+
+            if let Some(ref export) = function.export {
+                write!(f, " (export {})", quote(export.as_bytes()))?;
+            }
+
+            write_signature(f, &function.params, function.result)?;
+            write_types(f, "local", &function.locals)?;
+            writeln!(f)?;
+
+            write_body(f, &function.body, 2)?;
+
+            writeln!(f, "  )")?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+fn write_signature(
+    f: &mut fmt::Formatter,
+    params: &[ValType],
+    result: Option<ValType>,
+) -> fmt::Result {
+    write_types(f, "param", params)?;
+
+    if let Some(result) = result {
+        write!(f, " (result {})", result)?;
+    }
+
+    Ok(())
+}
+
+fn write_types(f: &mut fmt::Formatter, kind: &str, types: &[ValType]) -> fmt::Result {
+    if types.is_empty() {
+        return Ok(());
+    }
+
+    write!(f, " ({}", kind)?;
+
+    for ty in types {
+        write!(f, " {}", ty)?;
+    }
+
+    write!(f, ")")
+}
+
+fn write_body(f: &mut fmt::Formatter, body: &[Instr], depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+
+    for instr in body {
+        match *instr {
+            Instr::Block(ref body) => {
+                writeln!(f, "{}block", indent)?;
+                write_body(f, body, depth + 1)?;
+                writeln!(f, "{}end", indent)?;
+            }
+            Instr::Loop(ref body) => {
+                writeln!(f, "{}loop", indent)?;
+                write_body(f, body, depth + 1)?;
+                writeln!(f, "{}end", indent)?;
+            }
+            Instr::If(ref then, ref otherwise) => {
+                writeln!(f, "{}if", indent)?;
+                write_body(f, then, depth + 1)?;
+
+                if !otherwise.is_empty() {
+                    writeln!(f, "{}else", indent)?;
+                    write_body(f, otherwise, depth + 1)?;
+                }
+
+                writeln!(f, "{}end", indent)?;
+            }
+            ref instr => writeln!(f, "{}{}", indent, instr)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a single instruction, blocks only print their opening keyword
+impl Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instr::Block(_) => write!(f, "block"),
+            Instr::Loop(_) => write!(f, "loop"),
+            Instr::If(_, _) => write!(f, "if"),
+            Instr::Br(depth) => write!(f, "br {}", depth),
+            Instr::Return => write!(f, "return"),
+            Instr::Unreachable => write!(f, "unreachable"),
+            Instr::Call(ref id) => write!(f, "call ${}", id),
+            Instr::LocalGet(index) => write!(f, "local.get {}", index),
+            Instr::LocalSet(index) => write!(f, "local.set {}", index),
+            Instr::I32Const(value) => write!(f, "i32.const {}", value),
+            Instr::I64Const(value) => write!(f, "i64.const {}", value),
+            Instr::I64Load => write!(f, "i64.load"),
+            Instr::Numeric(numeric) => write!(f, "{}", numeric.name()),
+        }
+    }
+}
+
+/// A string literal, escaping anything that isn't printable ascii as hex
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => quoted.push_str(&format!("\\{}", byte as char)),
+            b' '..=b'~' => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,92 @@
+/*
+ * The runtime natively compiled programs are linked against.
+ * It provides the same intrinsics as the prelude of the vm and starts the program
+ * by calling its main function, which returns the exit code.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* The exit code a program stops with after a runtime error, the same as the vm */
+#define RUNTIME_ERROR 70
+
+/* String literals are stored as their length followed by their bytes */
+struct us_string {
+    uint64_t length;
+    char bytes[];
+};
+
+/* The main function of the program */
+int64_t _us_main(void);
+
+/* Stops the program the same way the vm reports a runtime error */
+void __us_rt_error(const char *message) {
+    printf("RuntimeError(\"%s\")\n", message);
+    exit(RUNTIME_ERROR);
+}
+
+void __us_rt_print_int(int64_t value) {
+    printf("%lld\n", (long long)value);
+}
+
+void __us_rt_print_uint(uint64_t value) {
+    printf("%llu\n", (unsigned long long)value);
+}
+
+void __us_rt_print_bool(int64_t value) {
+    puts(value ? "true" : "false");
+}
+
+void __us_rt_print_char(int64_t value) {
+    printf("%c\n", (char)value);
+}
+
+void __us_rt_print_str(const struct us_string *string) {
+    printf("%.*s\n", (int)string->length, string->bytes);
+}
+
+void __us_rt_assert(int64_t value) {
+    if (!value) {
+        __us_rt_error("Assertion failed");
+    }
+}
+
+void __us_rt_exit(int64_t code) {
+    exit((int32_t)code);
+}
+
+int64_t __us_rt_len(const struct us_string *string) {
+    return (int32_t)string->length;
+}
+
+/* Allocates zeroed memory for structs and arrays, which is never freed */
+void *__us_rt_alloc(uint64_t size) {
+    void *memory = calloc(1, size ? size : 1);
+
+    if (memory == NULL) {
+        __us_rt_error("Out of memory");
+    }
+
+    return memory;
+}
+
+int64_t __us_rt_str_eq(const struct us_string *lhs, const struct us_string *rhs) {
+    return lhs->length == rhs->length && memcmp(lhs->bytes, rhs->bytes, lhs->length) == 0;
+}
+
+/* Joins two strings into a new one on the heap */
+struct us_string *__us_rt_concat(const struct us_string *lhs, const struct us_string *rhs) {
+    uint64_t length = lhs->length + rhs->length;
+    struct us_string *string = __us_rt_alloc(sizeof(struct us_string) + length);
+
+    string->length = length;
+    memcpy(string->bytes, lhs->bytes, lhs->length);
+    memcpy(string->bytes + lhs->length, rhs->bytes, rhs->length);
+
+    return string;
+}
+
+int main(void) {
+    return (int)_us_main();
+}
@@ -0,0 +1,334 @@
+//! A small subset of x86-64 that the code generator targets.
+//! Everything prints in AT&T syntax so it can be fed straight to `as`.
+use std::fmt::{self, Display};
+use syntax::ast::{Sign, Size};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+/// The registers integer arguments are passed in, in order
+pub const ARGUMENT_REGISTERS: [Reg; 6] = [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    /// `disp(base)`
+    Mem(Reg, i32),
+    /// The address of a symbol relative to the instruction pointer
+    Rip(String),
+}
+
+/// Condition codes for `jcc` and `setcc`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cond {
+    E,
+    Ne,
+    /// Signed comparisons
+    L,
+    Le,
+    G,
+    Ge,
+    /// Unsigned comparisons
+    B,
+    Be,
+    A,
+    Ae,
+}
+
+/// Operands are in AT&T order, source first.
+/// Unless noted otherwise instructions operate on all 64 bits
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Label(String),
+    Mov(Operand, Operand),
+    MovAbs(i64, Reg),
+    /// Sign extend the low bits of the first register into the second
+    Movsx(Size, Reg, Reg),
+    /// Zero extend the low bits of the first register into the second
+    Movzx(Size, Reg, Reg),
+    /// Read a value of the given width from memory, extending it by its sign into the
+    /// register
+    Load(Sign, Size, Operand, Reg),
+    /// Write the low bits of the register to memory
+    Store(Size, Reg, Operand),
+    Lea(Operand, Reg),
+    Add(Operand, Reg),
+    Sub(Operand, Reg),
+    Imul(Operand, Reg),
+    And(Operand, Reg),
+    Or(Operand, Reg),
+    Xor(Operand, Reg),
+    Neg(Reg),
+    /// Sign extend `rax` into `rdx`
+    Cqo,
+    /// Divide `rdx:rax`, leaving the quotient in `rax`
+    Idiv(Reg),
+    Div(Reg),
+    Cmp(Operand, Reg),
+    Test(Reg, Reg),
+    /// Set the low byte of the register
+    Set(Cond, Reg),
+    Jmp(String),
+    Jcc(Cond, String),
+    Call(String),
+    /// Call the address held in a register
+    CallReg(Reg),
+    Push(Reg),
+    Pop(Reg),
+    Ret,
+}
+
+/// A function ready to be printed or encoded
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    /// Whether other objects can see the function
+    pub global: bool,
+    pub body: Vec<Instr>,
+}
+
+/// Everything that ends up in the object file
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub functions: Vec<Function>,
+    /// Read only data and the label that points at it
+    pub data: Vec<(String, Vec<u8>)>,
+    /// Functions that are defined in another object
+    pub externs: Vec<String>,
+}
+
+impl Reg {
+    /// The name of the register when `size` bytes of it are used
+    pub fn name(self, size: Size) -> &'static str {
+        use self::Reg::*;
+
+        match size {
+            Size::Bit64 => match self {
+                Rax => "rax",
+                Rcx => "rcx",
+                Rdx => "rdx",
+                Rbx => "rbx",
+                Rsp => "rsp",
+                Rbp => "rbp",
+                Rsi => "rsi",
+                Rdi => "rdi",
+                R8 => "r8",
+                R9 => "r9",
+                R10 => "r10",
+                R11 => "r11",
+                R12 => "r12",
+                R13 => "r13",
+                R14 => "r14",
+                R15 => "r15",
+            },
+            Size::Bit32 => match self {
+                Rax => "eax",
+                Rcx => "ecx",
+                Rdx => "edx",
+                Rbx => "ebx",
+                Rsp => "esp",
+                Rbp => "ebp",
+                Rsi => "esi",
+                Rdi => "edi",
+                R8 => "r8d",
+                R9 => "r9d",
+                R10 => "r10d",
+                R11 => "r11d",
+                R12 => "r12d",
+                R13 => "r13d",
+                R14 => "r14d",
+                R15 => "r15d",
+            },
+            Size::Bit8 => match self {
+                Rax => "al",
+                Rcx => "cl",
+                Rdx => "dl",
+                Rbx => "bl",
+                Rsp => "spl",
+                Rbp => "bpl",
+                Rsi => "sil",
+                Rdi => "dil",
+                R8 => "r8b",
+                R9 => "r9b",
+                R10 => "r10b",
+                R11 => "r11b",
+                R12 => "r12b",
+                R13 => "r13b",
+                R14 => "r14b",
+                R15 => "r15b",
+            },
+        }
+    }
+}
+
+impl Cond {
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Cond::E => "e",
+            Cond::Ne => "ne",
+            Cond::L => "l",
+            Cond::Le => "le",
+            Cond::G => "g",
+            Cond::Ge => "ge",
+            Cond::B => "b",
+            Cond::Be => "be",
+            Cond::A => "a",
+            Cond::Ae => "ae",
+        }
+    }
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.name(Size::Bit64))
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Imm(value) => write!(f, "${}", value),
+            Operand::Mem(base, 0) => write!(f, "({})", base),
+            Operand::Mem(base, disp) => write!(f, "{}({})", disp, base),
+            Operand::Rip(ref label) => write!(f, "{}(%rip)", label),
+        }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instr::Label(ref label) => write!(f, "{}:", label),
+            Instr::Mov(ref src, ref dst) => write!(f, "    movq {}, {}", src, dst),
+            Instr::MovAbs(value, dst) => write!(f, "    movabsq ${}, {}", value, dst),
+            Instr::Movsx(Size::Bit8, src, dst) => {
+                write!(f, "    movsbq %{}, {}", src.name(Size::Bit8), dst)
+            }
+            Instr::Movsx(Size::Bit32, src, dst) => {
+                write!(f, "    movslq %{}, {}", src.name(Size::Bit32), dst)
+            }
+            Instr::Movsx(Size::Bit64, src, dst) | Instr::Movzx(Size::Bit64, src, dst) => {
+                write!(f, "    movq {}, {}", src, dst)
+            }
+            Instr::Movzx(Size::Bit8, src, dst) => {
+                write!(f, "    movzbq %{}, {}", src.name(Size::Bit8), dst)
+            }
+            // Writing a 32 bit register clears the upper half
+            Instr::Movzx(Size::Bit32, src, dst) => write!(
+                f,
+                "    movl %{}, %{}",
+                src.name(Size::Bit32),
+                dst.name(Size::Bit32)
+            ),
+            Instr::Load(Sign::Signed, Size::Bit8, ref src, dst) => {
+                write!(f, "    movsbq {}, {}", src, dst)
+            }
+            Instr::Load(Sign::Signed, Size::Bit32, ref src, dst) => {
+                write!(f, "    movslq {}, {}", src, dst)
+            }
+            Instr::Load(Sign::Unsigned, Size::Bit8, ref src, dst) => {
+                write!(f, "    movzbq {}, {}", src, dst)
+            }
+            Instr::Load(Sign::Unsigned, Size::Bit32, ref src, dst) => {
+                write!(f, "    movl {}, %{}", src, dst.name(Size::Bit32))
+            }
+            Instr::Load(_, Size::Bit64, ref src, dst) => write!(f, "    movq {}, {}", src, dst),
+            Instr::Store(Size::Bit8, src, ref dst) => {
+                write!(f, "    movb %{}, {}", src.name(Size::Bit8), dst)
+            }
+            Instr::Store(Size::Bit32, src, ref dst) => {
+                write!(f, "    movl %{}, {}", src.name(Size::Bit32), dst)
+            }
+            Instr::Store(Size::Bit64, src, ref dst) => write!(f, "    movq {}, {}", src, dst),
+            Instr::Lea(ref src, dst) => write!(f, "    leaq {}, {}", src, dst),
+            Instr::Add(ref src, dst) => write!(f, "    addq {}, {}", src, dst),
+            Instr::Sub(ref src, dst) => write!(f, "    subq {}, {}", src, dst),
+            Instr::Imul(ref src, dst) => write!(f, "    imulq {}, {}", src, dst),
+            Instr::And(ref src, dst) => write!(f, "    andq {}, {}", src, dst),
+            Instr::Or(ref src, dst) => write!(f, "    orq {}, {}", src, dst),
+            Instr::Xor(ref src, dst) => write!(f, "    xorq {}, {}", src, dst),
+            Instr::Neg(reg) => write!(f, "    negq {}", reg),
+            Instr::Cqo => write!(f, "    cqto"),
+            Instr::Idiv(reg) => write!(f, "    idivq {}", reg),
+            Instr::Div(reg) => write!(f, "    divq {}", reg),
+            Instr::Cmp(ref src, dst) => write!(f, "    cmpq {}, {}", src, dst),
+            Instr::Test(src, dst) => write!(f, "    testq {}, {}", src, dst),
+            Instr::Set(cond, reg) => {
+                write!(f, "    set{} %{}", cond.suffix(), reg.name(Size::Bit8))
+            }
+            Instr::Jmp(ref label) => write!(f, "    jmp {}", label),
+            Instr::Jcc(cond, ref label) => write!(f, "    j{} {}", cond.suffix(), label),
+            Instr::Call(ref label) => write!(f, "    call {}", label),
+            Instr::CallReg(reg) => write!(f, "    call *{}", reg),
+            Instr::Push(reg) => write!(f, "    pushq {}", reg),
+            Instr::Pop(reg) => write!(f, "    popq {}", reg),
+            Instr::Ret => write!(f, "    ret"),
+        }
+    }
+}
+
+impl Display for Assembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    .text")?;
+
+        for function in &self.functions {
+            writeln!(f)?;
+
+            if function.global {
+                writeln!(f, "    .globl {}", function.name)?;
+            }
+
+            writeln!(f, "    .type {}, @function", function.name)?;
+            writeln!(f, "{}:", function.name)?;
+
+            for instr in &function.body {
+                writeln!(f, "{}", instr)?;
+            }
+        }
+
+        if !self.data.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "    .section .rodata")?;
+
+            for (label, bytes) in &self.data {
+                writeln!(f, "{}:", label)?;
+
+                if bytes.is_empty() {
+                    continue;
+                }
+
+                write!(f, "    .byte ")?;
+
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i + 1 == bytes.len() {
+                        writeln!(f, "{}", byte)?;
+                    } else {
+                        write!(f, "{},", byte)?;
+                    }
+                }
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "    .section .note.GNU-stack,\"\",@progbits")
+    }
+}
@@ -0,0 +1,864 @@
+//! Lowers an `ir::Program` to x86-64 following the System V calling convention.
+//! Temps live in the register they were allocated or, when spilled, in their own
+//! eight byte stack slot below the frame pointer. Either way a temp holds its value
+//! sign or zero extended to 64 bits, so instructions only have to truncate their
+//! results back to the width of their type.
+use asm::{Assembly, Cond, Function, Instr, Operand, Reg, ARGUMENT_REGISTERS};
+use ir::ir::{self, BinOp, CmpOp, Instruction, Intrinsic, Label, Temp, UnOp, Value};
+use regalloc;
+use runtime;
+use std::collections::HashMap;
+use syntax::ast::{Linkage, Sign, Size};
+use util::symbol::{Symbol, Symbols};
+
+/// The type given to a temp that is read before anything was stored in it
+const DEFAULT_TYPE: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+#[derive(Debug)]
+pub enum CodegenError {
+    /// The program uses something the backend can't generate code for yet
+    Unsupported(String),
+    /// The system assembler or linker failed
+    Toolchain(String),
+}
+
+pub struct Codegen {
+    asm: Assembly,
+    /// Maps a function to the symbol it is emitted as and the type it returns
+    functions: HashMap<Symbol, (String, (Sign, Size))>,
+    /// The labels of the C strings passed to the runtime
+    messages: HashMap<&'static str, String>,
+    /// The body of the function being generated
+    body: Vec<Instr>,
+    /// The name of the function being generated, used to make its labels unique
+    prefix: String,
+    /// The type the function being generated returns
+    returns: (Sign, Size),
+    /// Maps a temp to the register it was allocated
+    registers: HashMap<Temp, Reg>,
+    /// The callee saved registers the function being generated pushes in its prologue
+    saved: Vec<Reg>,
+    /// Maps a spilled temp to its offset from the frame pointer
+    slots: HashMap<Temp, i32>,
+    /// The sign and width of the value last stored in a temp
+    types: HashMap<Temp, (Sign, Size)>,
+    /// Used to make labels that aren't in the ir
+    labels: usize,
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Codegen {
+            asm: Assembly::default(),
+            functions: HashMap::new(),
+            messages: HashMap::new(),
+            body: Vec::new(),
+            prefix: String::new(),
+            returns: DEFAULT_TYPE,
+            registers: HashMap::new(),
+            saved: Vec::new(),
+            slots: HashMap::new(),
+            types: HashMap::new(),
+            labels: 0,
+        }
+    }
+
+    pub fn compile<T: Clone>(
+        mut self,
+        program: &ir::Program,
+        symbols: &Symbols<T>,
+    ) -> Result<Assembly, CodegenError> {
+        for function in &program.functions {
+            let name = symbols.name(function.name);
+
+            let symbol = match function.linkage {
+                Linkage::External => {
+                    self.asm.externs.push(name.clone());
+                    name
+                }
+                Linkage::Normal => mangle(&name),
+            };
+
+            self.functions
+                .insert(function.name, (symbol, function.returns));
+        }
+
+        for function in &program.functions {
+            if function.linkage == Linkage::External {
+                continue;
+            }
+
+            self.compile_function(function, symbols)?;
+        }
+
+        // A program without a main does nothing, the same as the C translation of it
+        if !self
+            .asm
+            .functions
+            .iter()
+            .any(|function| function.name == runtime::ENTRY)
+        {
+            self.asm.functions.push(Function {
+                name: runtime::ENTRY.into(),
+                global: true,
+                body: vec![Instr::Xor(Operand::Reg(Reg::Rax), Reg::Rax), Instr::Ret],
+            });
+        }
+
+        Ok(self.asm)
+    }
+
+    fn compile_function<T: Clone>(
+        &mut self,
+        function: &ir::Function,
+        symbols: &Symbols<T>,
+    ) -> Result<(), CodegenError> {
+        self.slots.clear();
+        self.types.clear();
+
+        let allocation = regalloc::allocate(function);
+
+        self.registers = allocation.registers;
+        self.saved = allocation.saved;
+
+        let name = self.functions[&function.name].0.clone();
+
+        self.prefix = name.clone();
+        self.returns = function.returns;
+
+        self.body = vec![
+            Instr::Push(Reg::Rbp),
+            Instr::Mov(Operand::Reg(Reg::Rsp), Operand::Reg(Reg::Rbp)),
+        ];
+
+        for reg in self.saved.clone() {
+            self.body.push(Instr::Push(reg));
+        }
+
+        // The size of the frame is patched in once every spilled temp has a slot
+        let frame_size = self.body.len();
+        self.body.push(Instr::Sub(Operand::Imm(0), Reg::Rsp));
+
+        let params: Vec<(Temp, (Sign, Size))> = function
+            .params
+            .iter()
+            .cloned()
+            .zip(function.param_types.iter().cloned())
+            .collect();
+
+        // A parameter may have been allocated the register another one arrives in,
+        // so they are all pushed before any of them are moved
+        let in_registers = params.len().min(ARGUMENT_REGISTERS.len());
+
+        for reg in &ARGUMENT_REGISTERS[..in_registers] {
+            self.body.push(Instr::Push(*reg));
+        }
+
+        for &(param, ty) in params[..in_registers].iter().rev() {
+            self.body.push(Instr::Pop(Reg::Rax));
+            self.extend(Reg::Rax, ty);
+            self.store(param, ty);
+        }
+
+        // The rest were pushed by the caller above the return address
+        for (i, &(param, ty)) in params.iter().enumerate().skip(in_registers) {
+            let offset = 16 + 8 * (i - ARGUMENT_REGISTERS.len()) as i32;
+
+            self.body.push(Instr::Mov(
+                Operand::Mem(Reg::Rbp, offset),
+                Operand::Reg(Reg::Rax),
+            ));
+            self.extend(Reg::Rax, ty);
+            self.store(param, ty);
+        }
+
+        for instruction in &function.body {
+            self.compile_instruction(instruction, symbols)?;
+        }
+
+        // Falling of the end of a function returns zero
+        let epilogue = self.epilogue();
+
+        self.body.push(Instr::Xor(Operand::Reg(Reg::Rax), Reg::Rax));
+        self.body.push(Instr::Label(epilogue));
+
+        let saved = 8 * self.saved.len() as i64;
+
+        if saved == 0 {
+            self.body
+                .push(Instr::Mov(Operand::Reg(Reg::Rbp), Operand::Reg(Reg::Rsp)));
+        } else {
+            self.body
+                .push(Instr::Lea(Operand::Mem(Reg::Rbp, -saved as i32), Reg::Rsp));
+
+            for reg in self.saved.clone().into_iter().rev() {
+                self.body.push(Instr::Pop(reg));
+            }
+        }
+
+        self.body.push(Instr::Pop(Reg::Rbp));
+        self.body.push(Instr::Ret);
+
+        // Keep the stack aligned to 16 bytes at calls
+        let frame = ((saved + self.slots.len() as i64 * 8 + 15) & !15) - saved;
+        self.body[frame_size] = Instr::Sub(Operand::Imm(frame), Reg::Rsp);
+
+        self.asm.functions.push(Function {
+            name: name.clone(),
+            global: name == runtime::ENTRY,
+            body: ::std::mem::take(&mut self.body),
+        });
+
+        Ok(())
+    }
+
+    fn compile_instruction<T: Clone>(
+        &mut self,
+        instruction: &Instruction,
+        symbols: &Symbols<T>,
+    ) -> Result<(), CodegenError> {
+        match *instruction {
+            Instruction::Store(temp, ref value) => {
+                let ty = match *value {
+                    Value::Const(value, sign, size) => {
+                        self.body.push(Instr::MovAbs(value as i64, Reg::Rax));
+                        self.extend(Reg::Rax, (sign, size));
+                        (sign, size)
+                    }
+
+                    Value::Temp(from) => self.load(from, Reg::Rax),
+
+                    Value::Mem(ref bytes) => {
+                        let label = self.string(bytes);
+                        self.body.push(Instr::Lea(Operand::Rip(label), Reg::Rax));
+                        (Sign::Unsigned, Size::Bit64)
+                    }
+
+                    Value::Name(ref label) => {
+                        let name = self.function(*label)?.0;
+                        self.body.push(Instr::Lea(Operand::Rip(name), Reg::Rax));
+                        (Sign::Unsigned, Size::Bit64)
+                    }
+                };
+
+                self.store(temp, ty);
+            }
+
+            Instruction::Copy(to, from) => {
+                let ty = self.load(from, Reg::Rax);
+                self.store(to, ty);
+            }
+
+            Instruction::BinOp(lhs, ref op, rhs, to) => {
+                let (sign, size) = self.load(lhs, Reg::Rax);
+                self.load(rhs, Reg::Rcx);
+
+                let rcx = Operand::Reg(Reg::Rcx);
+
+                let ty = match *op {
+                    BinOp::Plus => {
+                        self.body.push(Instr::Add(rcx, Reg::Rax));
+                        (sign, size)
+                    }
+                    BinOp::Minus => {
+                        self.body.push(Instr::Sub(rcx, Reg::Rax));
+                        (sign, size)
+                    }
+                    BinOp::Mul => {
+                        self.body.push(Instr::Imul(rcx, Reg::Rax));
+                        (sign, size)
+                    }
+                    BinOp::Div => {
+                        self.check_divisor(Reg::Rcx);
+
+                        if sign == Sign::Signed {
+                            self.body.push(Instr::Cqo);
+                            self.body.push(Instr::Idiv(Reg::Rcx));
+                        } else {
+                            self.body.push(Instr::Xor(Operand::Reg(Reg::Rdx), Reg::Rdx));
+                            self.body.push(Instr::Div(Reg::Rcx));
+                        }
+
+                        (sign, size)
+                    }
+                    BinOp::And => {
+                        self.body.push(Instr::And(rcx, Reg::Rax));
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                    BinOp::Or => {
+                        self.body.push(Instr::Or(rcx, Reg::Rax));
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                };
+
+                self.extend(Reg::Rax, ty);
+                self.store(to, ty);
+            }
+
+            Instruction::UnOp(to, ref op, from) => {
+                let ty = self.load(from, Reg::Rax);
+
+                let ty = match *op {
+                    UnOp::Minus => {
+                        self.body.push(Instr::Neg(Reg::Rax));
+                        self.extend(Reg::Rax, ty);
+                        ty
+                    }
+                    UnOp::Bang => {
+                        self.body.push(Instr::Test(Reg::Rax, Reg::Rax));
+                        self.body.push(Instr::Set(Cond::E, Reg::Rax));
+                        self.extend(Reg::Rax, (Sign::Unsigned, Size::Bit8));
+                        (Sign::Unsigned, Size::Bit8)
+                    }
+                };
+
+                self.store(to, ty);
+            }
+
+            Instruction::Cast(to, from, sign, size) => {
+                self.load(from, Reg::Rax);
+                self.extend(Reg::Rax, (sign, size));
+                self.store(to, (sign, size));
+            }
+
+            Instruction::CJump(lhs, ref op, rhs, ltrue, lfalse) => {
+                let (sign, _) = self.load(lhs, Reg::Rax);
+                self.load(rhs, Reg::Rcx);
+
+                let signed = sign == Sign::Signed;
+
+                let cond = match *op {
+                    CmpOp::EQ => Cond::E,
+                    CmpOp::NE => Cond::Ne,
+                    CmpOp::LT if signed => Cond::L,
+                    CmpOp::LT => Cond::B,
+                    CmpOp::LTE if signed => Cond::Le,
+                    CmpOp::LTE => Cond::Be,
+                    CmpOp::GT if signed => Cond::G,
+                    CmpOp::GT => Cond::A,
+                    CmpOp::GTE if signed => Cond::Ge,
+                    CmpOp::GTE => Cond::Ae,
+                };
+
+                let (ltrue, lfalse) = (self.label(ltrue, symbols), self.label(lfalse, symbols));
+
+                self.body.push(Instr::Cmp(Operand::Reg(Reg::Rcx), Reg::Rax));
+                self.body.push(Instr::Jcc(cond, ltrue));
+                self.body.push(Instr::Jmp(lfalse));
+            }
+
+            Instruction::Jump(label) => {
+                let label = self.label(label, symbols);
+                self.body.push(Instr::Jmp(label));
+            }
+
+            Instruction::Label(label) => {
+                let label = self.label(label, symbols);
+                self.body.push(Instr::Label(label));
+            }
+
+            Instruction::Call(to, callee, ref args) => {
+                let (name, returns) = self.function(callee)?;
+
+                self.call(&name, args);
+                self.extend(Reg::Rax, returns);
+                self.store(to, returns);
+            }
+
+            Instruction::Return(temp) => {
+                let returns = self.returns;
+
+                self.load(temp, Reg::Rax);
+                self.extend(Reg::Rax, returns);
+
+                let epilogue = self.epilogue();
+                self.body.push(Instr::Jmp(epilogue));
+            }
+
+            Instruction::Intrinsic(to, intrinsic, ref args) => {
+                self.intrinsic(to, intrinsic, args)?
+            }
+
+            Instruction::Alloc(to, size) => {
+                self.body.push(Instr::Mov(
+                    Operand::Imm(size as i64),
+                    Operand::Reg(Reg::Rdi),
+                ));
+                self.body.push(Instr::Call(runtime::ALLOC.into()));
+                self.store(to, (Sign::Unsigned, Size::Bit64));
+            }
+
+            Instruction::LoadAt(to, base, offset, sign, size) => {
+                let field = self.field(Reg::Rcx, offset)?;
+
+                self.load(base, Reg::Rcx);
+                self.body.push(Instr::Load(sign, size, field, Reg::Rax));
+                self.store(to, (sign, size));
+            }
+
+            Instruction::StoreAt(base, offset, from, _, size) => {
+                let field = self.field(Reg::Rcx, offset)?;
+
+                self.load(base, Reg::Rcx);
+                self.load(from, Reg::Rax);
+                self.body.push(Instr::Store(size, Reg::Rax, field));
+            }
+
+            Instruction::Value(_) => (),
+
+            Instruction::Load(_)
+            | Instruction::Block(_, _)
+            | Instruction::Closure(_, _, _)
+            | Instruction::GetUpvalue(_, _)
+            | Instruction::SetUpvalue(_, _)
+            | Instruction::CloseUpvalue(_)
+            | Instruction::CallClosure(_, _, _, _)
+            | Instruction::Phi(_, _) => {
+                return Err(CodegenError::Unsupported(format!(
+                    "`{}` is not supported by the x86 backend",
+                    instruction
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn intrinsic(
+        &mut self,
+        to: Temp,
+        intrinsic: Intrinsic,
+        args: &[Temp],
+    ) -> Result<(), CodegenError> {
+        let arg = match args.first() {
+            Some(arg) => *arg,
+            None => {
+                return Err(CodegenError::Unsupported(format!(
+                    "`{}` needs an argument",
+                    intrinsic
+                )))
+            }
+        };
+
+        let (sign, _) = *self.types.get(&arg).unwrap_or(&DEFAULT_TYPE);
+
+        self.call(runtime::intrinsic(intrinsic, sign), args);
+
+        let returns = match intrinsic {
+            Intrinsic::StrLen => (Sign::Signed, Size::Bit32),
+            Intrinsic::StrEq => (Sign::Unsigned, Size::Bit8),
+            Intrinsic::StrConcat => (Sign::Unsigned, Size::Bit64),
+            _ => return Ok(()),
+        };
+
+        self.extend(Reg::Rax, returns);
+        self.store(to, returns);
+
+        Ok(())
+    }
+
+    /// Calls a System V function with the temps as its arguments, leaving the result in `rax`
+    fn call(&mut self, name: &str, args: &[Temp]) {
+        let stacked = args.len().saturating_sub(ARGUMENT_REGISTERS.len());
+        // Keep the stack aligned once the arguments have been pushed
+        let padding = stacked % 2;
+
+        if padding != 0 {
+            self.body.push(Instr::Sub(Operand::Imm(8), Reg::Rsp));
+        }
+
+        for arg in args.iter().skip(ARGUMENT_REGISTERS.len()).rev() {
+            self.load(*arg, Reg::Rax);
+            self.body.push(Instr::Push(Reg::Rax));
+        }
+
+        // An argument may live in the register an earlier one is passed in,
+        // so every argument is read before any of those registers are written
+        let in_registers = args.len().min(ARGUMENT_REGISTERS.len());
+
+        for arg in &args[..in_registers] {
+            self.load(*arg, Reg::Rax);
+            self.body.push(Instr::Push(Reg::Rax));
+        }
+
+        for reg in ARGUMENT_REGISTERS[..in_registers].iter().rev() {
+            self.body.push(Instr::Pop(*reg));
+        }
+
+        // Variadic C functions read the number of vector arguments from `al`
+        self.body.push(Instr::Xor(Operand::Reg(Reg::Rax), Reg::Rax));
+        self.body.push(Instr::Call(name.into()));
+
+        if stacked + padding != 0 {
+            self.body.push(Instr::Add(
+                Operand::Imm(8 * (stacked + padding) as i64),
+                Reg::Rsp,
+            ));
+        }
+    }
+
+    /// Stops the program with a runtime error the same way the vm reports it
+    fn fail(&mut self, message: &'static str) {
+        let label = self.message(message);
+
+        self.body.push(Instr::Lea(Operand::Rip(label), Reg::Rdi));
+        self.body.push(Instr::Call(runtime::ERROR.into()));
+    }
+
+    fn check_divisor(&mut self, divisor: Reg) {
+        let ok = self.local_label();
+
+        self.body.push(Instr::Test(divisor, divisor));
+        self.body.push(Instr::Jcc(Cond::Ne, ok.clone()));
+        self.fail("Attempted to divide by zero");
+        self.body.push(Instr::Label(ok));
+    }
+
+    /// The memory at an offset from the address in a register
+    fn field(&self, base: Reg, offset: usize) -> Result<Operand, CodegenError> {
+        if offset > i32::MAX as usize {
+            return Err(CodegenError::Unsupported(format!(
+                "Offset {} is too far from the start of a struct",
+                offset
+            )));
+        }
+
+        Ok(Operand::Mem(base, offset as i32))
+    }
+
+    /// Moves a temp into a register and returns its type
+    fn load(&mut self, temp: Temp, reg: Reg) -> (Sign, Size) {
+        let home = self.home(temp);
+
+        if home != Operand::Reg(reg) {
+            self.body.push(Instr::Mov(home, Operand::Reg(reg)));
+        }
+
+        *self.types.get(&temp).unwrap_or(&DEFAULT_TYPE)
+    }
+
+    /// Moves `rax` into a temp
+    fn store(&mut self, temp: Temp, ty: (Sign, Size)) {
+        let home = self.home(temp);
+
+        self.types.insert(temp, ty);
+        self.body.push(Instr::Mov(Operand::Reg(Reg::Rax), home));
+    }
+
+    /// Where the value of a temp is kept
+    fn home(&mut self, temp: Temp) -> Operand {
+        if let Some(reg) = self.registers.get(&temp) {
+            return Operand::Reg(*reg);
+        }
+
+        // Slots start below the callee saved registers
+        let next = -8 * (self.saved.len() + self.slots.len() + 1) as i32;
+
+        Operand::Mem(Reg::Rbp, *self.slots.entry(temp).or_insert(next))
+    }
+
+    /// Truncates a register to the width of `ty` and extends it back to 64 bits
+    fn extend(&mut self, reg: Reg, (sign, size): (Sign, Size)) {
+        if size == Size::Bit64 {
+            return;
+        }
+
+        match sign {
+            Sign::Signed => self.body.push(Instr::Movsx(size, reg, reg)),
+            Sign::Unsigned => self.body.push(Instr::Movzx(size, reg, reg)),
+        }
+    }
+
+    fn function(&self, function: Symbol) -> Result<(String, (Sign, Size)), CodegenError> {
+        match self.functions.get(&function) {
+            Some(function) => Ok(function.clone()),
+            None => Err(CodegenError::Unsupported(format!(
+                "Call to undefined function `{}`",
+                function
+            ))),
+        }
+    }
+
+    /// Labels are local to the function they are in
+    fn label<T: Clone>(&self, label: Label, symbols: &Symbols<T>) -> String {
+        format!(".L{}_{}", self.prefix, escape(&symbols.name(label)))
+    }
+
+    fn local_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}_{}", self.prefix, self.labels)
+    }
+
+    fn epilogue(&self) -> String {
+        format!(".L{}_return", self.prefix)
+    }
+
+    /// Adds a string literal, stored as its eight byte length followed by its bytes
+    fn string(&mut self, bytes: &[u8]) -> String {
+        let mut data = (bytes.len() as u64).to_le_bytes().to_vec();
+        data.extend_from_slice(bytes);
+        data.push(0);
+
+        self.data(data)
+    }
+
+    /// A nul terminated string that is only used to call into the runtime
+    fn message(&mut self, message: &'static str) -> String {
+        if let Some(label) = self.messages.get(message) {
+            return label.clone();
+        }
+
+        let label = self.data(message.as_bytes().iter().cloned().chain(Some(0)).collect());
+        self.messages.insert(message, label.clone());
+
+        label
+    }
+
+    fn data(&mut self, bytes: Vec<u8>) -> String {
+        let label = format!(".Ldata{}", self.asm.data.len());
+
+        self.asm.data.push((label.clone(), bytes));
+
+        label
+    }
+}
+
+impl Default for Codegen {
+    fn default() -> Self {
+        Codegen::new()
+    }
+}
+
+/// Gives a function a symbol that can't clash with the C library
+pub fn mangle(name: &str) -> String {
+    format!("_us_{}", escape(name))
+}
+
+/// Hex escapes anything the assembler wouldn't accept in a symbol
+fn escape(name: &str) -> String {
+    let mut symbol = String::new();
+
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            symbol.push(c);
+        } else {
+            symbol.push_str(&format!(".{:x}", c as u32));
+        }
+    }
+
+    symbol
+}
+
+#[cfg(test)]
+mod test {
+    use super::Codegen;
+    use ir::ir::{BinOp, CmpOp, Function, Instruction, Intrinsic, Program, Temp, Value};
+    use link::link;
+    use std::process::{Command, Output};
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    const I32: (Sign, Size) = (Sign::Signed, Size::Bit32);
+
+    fn run(name: &str, program: &Program, symbols: &Symbols<()>) -> Output {
+        let asm = Codegen::new().compile(program, symbols).unwrap();
+        let exe = ::std::env::temp_dir().join(format!(
+            "underscore_x86_{}_{}",
+            name,
+            ::std::process::id()
+        ));
+
+        link(&asm, &exe).unwrap();
+
+        let output = Command::new(&exe).output().unwrap();
+        let _ = ::std::fs::remove_file(&exe);
+
+        output
+    }
+
+    fn function(
+        name: &str,
+        params: Vec<Temp>,
+        body: Vec<Instruction>,
+        symbols: &mut Symbols<()>,
+    ) -> Function {
+        Function {
+            name: symbols.symbol(name),
+            param_types: params.iter().map(|_| I32).collect(),
+            params,
+            returns: I32,
+            upvalues: vec![],
+            body,
+            linkage: Linkage::Normal,
+        }
+    }
+
+    #[test]
+    fn loops_and_exit_codes() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let (start, body, end) = (
+            symbols.symbol("start"),
+            symbols.symbol("body"),
+            symbols.symbol("end"),
+        );
+        let (n, total, one, zero) = (Temp::new(), Temp::new(), Temp::new(), Temp::new());
+
+        // fn sum(n) { let total = 0; while n > 0 { total = total + n; n = n - 1; } total }
+        let sum = function(
+            "sum",
+            vec![n],
+            vec![
+                Instruction::Store(total, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Store(one, Value::Const(1, Sign::Signed, Size::Bit32)),
+                Instruction::Store(zero, Value::Const(0, Sign::Signed, Size::Bit32)),
+                Instruction::Label(start),
+                Instruction::CJump(n, CmpOp::GT, zero, body, end),
+                Instruction::Label(body),
+                Instruction::BinOp(total, BinOp::Plus, n, total),
+                Instruction::BinOp(n, BinOp::Minus, one, n),
+                Instruction::Jump(start),
+                Instruction::Label(end),
+                Instruction::Return(total),
+            ],
+            &mut symbols,
+        );
+
+        let (ten, result) = (Temp::new(), Temp::new());
+        let main = function(
+            "main",
+            vec![],
+            vec![
+                Instruction::Store(ten, Value::Const(10, Sign::Signed, Size::Bit32)),
+                Instruction::Call(result, sum.name, vec![ten]),
+                Instruction::Return(result),
+            ],
+            &mut symbols,
+        );
+
+        let program = Program {
+            functions: vec![sum, main],
+        };
+
+        assert_eq!(run("loops", &program, &symbols).status.code(), Some(55));
+    }
+
+    #[test]
+    fn programs_without_main_do_nothing() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let n = Temp::new();
+        let id = function("id", vec![n], vec![Instruction::Return(n)], &mut symbols);
+
+        let program = Program {
+            functions: vec![id],
+        };
+
+        let output = run("no_main", &program, &symbols);
+
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    #[test]
+    fn stack_arguments_and_printing() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+
+        // fn last(a, b, c, d, e, f, g, h) { g - h }
+        let params: Vec<Temp> = (0..8).map(|_| Temp::new()).collect();
+        let difference = Temp::new();
+        let last = function(
+            "last",
+            params.clone(),
+            vec![
+                Instruction::BinOp(params[6], BinOp::Minus, params[7], difference),
+                Instruction::Return(difference),
+            ],
+            &mut symbols,
+        );
+
+        let args: Vec<Temp> = (0..8).map(|_| Temp::new()).collect();
+        let (result, string, unit) = (Temp::new(), Temp::new(), Temp::new());
+
+        let mut body: Vec<Instruction> = args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                Instruction::Store(*arg, Value::Const(i as u64, Sign::Signed, Size::Bit32))
+            })
+            .collect();
+
+        body.push(Instruction::Call(result, last.name, args));
+        body.push(Instruction::Intrinsic(
+            unit,
+            Intrinsic::PrintInt,
+            vec![result],
+        ));
+        body.push(Instruction::Store(string, Value::Mem(b"native".to_vec())));
+        body.push(Instruction::Intrinsic(
+            unit,
+            Intrinsic::PrintStr,
+            vec![string],
+        ));
+        body.push(Instruction::Intrinsic(unit, Intrinsic::Exit, vec![result]));
+
+        let main = function("main", vec![], body, &mut symbols);
+
+        let output = run(
+            "arguments",
+            &Program {
+                functions: vec![last, main],
+            },
+            &symbols,
+        );
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "-1\nnative\n");
+        assert_eq!(output.status.code(), Some(255));
+    }
+
+    #[test]
+    fn intrinsics_behave_like_the_vm() {
+        let mut symbols = Symbols::new(Rc::new(SymbolMap::new()));
+        let (string, length, yes, letter, big, unit) = (
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+            Temp::new(),
+        );
+
+        let main = function(
+            "main",
+            vec![],
+            vec![
+                Instruction::Store(string, Value::Mem(b"runtime".to_vec())),
+                Instruction::Intrinsic(length, Intrinsic::StrLen, vec![string]),
+                Instruction::Intrinsic(unit, Intrinsic::PrintInt, vec![length]),
+                Instruction::Store(yes, Value::Const(1, Sign::Unsigned, Size::Bit8)),
+                Instruction::Intrinsic(unit, Intrinsic::PrintBool, vec![yes]),
+                Instruction::Store(letter, Value::Const(104, Sign::Unsigned, Size::Bit8)),
+                Instruction::Intrinsic(unit, Intrinsic::PrintChar, vec![letter]),
+                Instruction::Store(big, Value::Const(u64::MAX, Sign::Unsigned, Size::Bit64)),
+                Instruction::Intrinsic(unit, Intrinsic::PrintInt, vec![big]),
+                Instruction::Store(yes, Value::Const(0, Sign::Unsigned, Size::Bit8)),
+                Instruction::Intrinsic(unit, Intrinsic::Assert, vec![yes]),
+                Instruction::Return(length),
+            ],
+            &mut symbols,
+        );
+
+        let output = run(
+            "intrinsics",
+            &Program {
+                functions: vec![main],
+            },
+            &symbols,
+        );
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "7\ntrue\nh\n18446744073709551615\nRuntimeError(\"Assertion failed\")\n"
+        );
+        assert_eq!(output.status.code(), Some(70));
+    }
+}
@@ -0,0 +1,371 @@
+//! Writes a relocatable ELF64 object for x86-64.
+//! The object has the text of every function, the read only data and a symbol
+//! table with the functions, plus undefined symbols for everything they call that
+//! is defined somewhere else.
+use asm::Assembly;
+use codegen::CodegenError;
+use encode::{self, RelocationKind};
+use std::collections::HashMap;
+
+const EHDR_SIZE: usize = 64;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const EM_X86_64: u16 = 62;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+
+/// The index of each section in the header table
+const TEXT: u16 = 1;
+const RODATA: u16 = 2;
+const RELA_TEXT: u16 = 3;
+const SYMTAB: u16 = 4;
+const STRTAB: u16 = 5;
+const SHSTRTAB: u16 = 6;
+const NOTE_STACK: u16 = 7;
+const SECTIONS: u16 = 8;
+
+#[derive(Debug)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    section: u16,
+    value: u64,
+    size: u64,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+/// A string table, which starts with the empty string
+struct Strings(Vec<u8>);
+
+impl Strings {
+    fn new() -> Self {
+        Strings(vec![0])
+    }
+
+    fn add(&mut self, string: &str) -> u32 {
+        let offset = self.0.len() as u32;
+
+        self.0.extend_from_slice(string.as_bytes());
+        self.0.push(0);
+
+        offset
+    }
+}
+
+pub fn object(asm: &Assembly) -> Result<Vec<u8>, CodegenError> {
+    let text = encode::encode(asm)?;
+
+    let mut rodata = Vec::new();
+    let mut data = HashMap::new();
+
+    for (label, bytes) in &asm.data {
+        // Strings start with their length so keep them aligned for it
+        while !rodata.len().is_multiple_of(8) {
+            rodata.push(0);
+        }
+
+        data.insert(label.clone(), rodata.len());
+        rodata.extend_from_slice(bytes);
+    }
+
+    let mut strtab = Strings::new();
+    let mut symbols = vec![
+        Symbol {
+            name: 0,
+            info: 0,
+            section: 0,
+            value: 0,
+            size: 0,
+        },
+        // Data is referred to through its section
+        Symbol {
+            name: 0,
+            info: STB_LOCAL << 4 | STT_SECTION,
+            section: RODATA,
+            value: 0,
+            size: 0,
+        },
+    ];
+    let mut indices: HashMap<String, usize> = HashMap::new();
+
+    // Locals have to come before globals
+    for global in &[false, true] {
+        for &(ref name, is_global, offset, size) in &text.functions {
+            if is_global != *global {
+                continue;
+            }
+
+            indices.insert(name.clone(), symbols.len());
+            symbols.push(Symbol {
+                name: strtab.add(name),
+                info: (if is_global { STB_GLOBAL } else { STB_LOCAL }) << 4 | STT_FUNC,
+                section: TEXT,
+                value: offset as u64,
+                size: size as u64,
+            });
+        }
+    }
+
+    let first_global = symbols.len() - text.functions.iter().filter(|f| f.1).count();
+
+    let undefined = asm.externs.iter().chain(
+        text.relocations
+            .iter()
+            .filter(|relocation| !data.contains_key(&relocation.symbol))
+            .map(|relocation| &relocation.symbol),
+    );
+
+    for name in undefined {
+        if indices.contains_key(name) {
+            continue;
+        }
+
+        indices.insert(name.clone(), symbols.len());
+        symbols.push(Symbol {
+            name: strtab.add(name),
+            info: STB_GLOBAL << 4 | STT_NOTYPE,
+            section: 0,
+            value: 0,
+            size: 0,
+        });
+    }
+
+    let mut rela = Vec::with_capacity(text.relocations.len() * RELA_SIZE);
+
+    for relocation in &text.relocations {
+        let (symbol, addend) = match data.get(&relocation.symbol) {
+            Some(offset) => (1, relocation.addend + *offset as i64),
+            None => (indices[&relocation.symbol], relocation.addend),
+        };
+
+        let kind = match relocation.kind {
+            RelocationKind::Plt32 => R_X86_64_PLT32,
+            RelocationKind::Pc32 => R_X86_64_PC32,
+        };
+
+        rela.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+        rela.extend_from_slice(&((symbol as u64) << 32 | u64::from(kind)).to_le_bytes());
+        rela.extend_from_slice(&addend.to_le_bytes());
+    }
+
+    let mut symtab = Vec::with_capacity(symbols.len() * SYM_SIZE);
+
+    for symbol in &symbols {
+        symtab.extend_from_slice(&symbol.name.to_le_bytes());
+        symtab.push(symbol.info);
+        symtab.push(0);
+        symtab.extend_from_slice(&symbol.section.to_le_bytes());
+        symtab.extend_from_slice(&symbol.value.to_le_bytes());
+        symtab.extend_from_slice(&symbol.size.to_le_bytes());
+    }
+
+    let mut shstrtab = Strings::new();
+    let names = [
+        shstrtab.add(".text"),
+        shstrtab.add(".rodata"),
+        shstrtab.add(".rela.text"),
+        shstrtab.add(".symtab"),
+        shstrtab.add(".strtab"),
+        shstrtab.add(".shstrtab"),
+        shstrtab.add(".note.GNU-stack"),
+    ];
+
+    let mut out = vec![0; EHDR_SIZE];
+    let mut sections = Vec::new();
+
+    {
+        let section = |out: &mut Vec<u8>, bytes: &[u8], align: usize| {
+            while !out.len().is_multiple_of(align) {
+                out.push(0);
+            }
+
+            let offset = out.len() as u64;
+            out.extend_from_slice(bytes);
+
+            (offset, bytes.len() as u64)
+        };
+
+        let contents: [(&[u8], usize); 7] = [
+            (&text.code, 16),
+            (&rodata, 8),
+            (&rela, 8),
+            (&symtab, 8),
+            (&strtab.0, 1),
+            (&shstrtab.0, 1),
+            (&[], 1),
+        ];
+
+        for (i, &(bytes, align)) in contents.iter().enumerate() {
+            let (offset, size) = section(&mut out, bytes, align);
+            let index = i as u16 + 1;
+
+            let (kind, flags, link, info, entsize) = match index {
+                TEXT => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0, 0, 0),
+                RODATA => (SHT_PROGBITS, SHF_ALLOC, 0, 0, 0),
+                RELA_TEXT => (
+                    SHT_RELA,
+                    SHF_INFO_LINK,
+                    u32::from(SYMTAB),
+                    u32::from(TEXT),
+                    RELA_SIZE as u64,
+                ),
+                SYMTAB => (
+                    SHT_SYMTAB,
+                    0,
+                    u32::from(STRTAB),
+                    first_global as u32,
+                    SYM_SIZE as u64,
+                ),
+                STRTAB | SHSTRTAB => (SHT_STRTAB, 0, 0, 0, 0),
+                NOTE_STACK => (SHT_PROGBITS, 0, 0, 0, 0),
+                _ => unreachable!(),
+            };
+
+            sections.push(Section {
+                name: names[i],
+                kind,
+                flags,
+                offset,
+                size,
+                link,
+                info,
+                align: align as u64,
+                entsize,
+            });
+        }
+    }
+
+    while !out.len().is_multiple_of(8) {
+        out.push(0);
+    }
+
+    let shoff = out.len() as u64;
+
+    // The first section header is always empty
+    out.extend_from_slice(&[0; SHDR_SIZE]);
+
+    for section in &sections {
+        out.extend_from_slice(&section.name.to_le_bytes());
+        out.extend_from_slice(&section.kind.to_le_bytes());
+        out.extend_from_slice(&section.flags.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&section.offset.to_le_bytes());
+        out.extend_from_slice(&section.size.to_le_bytes());
+        out.extend_from_slice(&section.link.to_le_bytes());
+        out.extend_from_slice(&section.info.to_le_bytes());
+        out.extend_from_slice(&section.align.to_le_bytes());
+        out.extend_from_slice(&section.entsize.to_le_bytes());
+    }
+
+    let mut header = Vec::with_capacity(EHDR_SIZE);
+
+    // 64 bit, little endian, version 1, System V
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&ET_REL.to_le_bytes());
+    header.extend_from_slice(&EM_X86_64.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    // No entry point or program headers
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&shoff.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&SECTIONS.to_le_bytes());
+    header.extend_from_slice(&SHSTRTAB.to_le_bytes());
+
+    out[..EHDR_SIZE].copy_from_slice(&header);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::object;
+    use asm::{Assembly, Function, Instr, Operand, Reg};
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn symbols_and_relocations() {
+        let asm = Assembly {
+            functions: vec![
+                Function {
+                    name: "_us_helper".into(),
+                    global: false,
+                    body: vec![Instr::Call("sqrt".into()), Instr::Ret],
+                },
+                Function {
+                    name: "main".into(),
+                    global: true,
+                    body: vec![
+                        Instr::Lea(Operand::Rip(".Ldata0".into()), Reg::Rdi),
+                        Instr::Call("_us_helper".into()),
+                        Instr::Ret,
+                    ],
+                },
+            ],
+            data: vec![(".Ldata0".into(), b"hi\0".to_vec())],
+            externs: vec!["sqrt".into(), "unused".into()],
+        };
+
+        let path =
+            ::std::env::temp_dir().join(format!("underscore_elf_{}.o", ::std::process::id()));
+
+        fs::write(&path, object(&asm).unwrap()).unwrap();
+
+        let symbols = Command::new("nm").arg(&path).output().unwrap();
+        let relocations = Command::new("readelf")
+            .arg("-r")
+            .arg(&path)
+            .output()
+            .unwrap();
+
+        let _ = fs::remove_file(&path);
+
+        let symbols = String::from_utf8_lossy(&symbols.stdout);
+        let relocations = String::from_utf8_lossy(&relocations.stdout);
+
+        assert!(symbols.contains("t _us_helper"), "{}", symbols);
+        assert!(symbols.contains("T main"), "{}", symbols);
+        assert!(symbols.contains("U sqrt"), "{}", symbols);
+        assert!(symbols.contains("U unused"), "{}", symbols);
+
+        assert!(relocations.contains("R_X86_64_PLT32") && relocations.contains("sqrt"));
+        assert!(relocations.contains("R_X86_64_PLT32") && relocations.contains("_us_helper"));
+        assert!(relocations.contains("R_X86_64_PC32") && relocations.contains(".rodata"));
+    }
+}
@@ -0,0 +1,526 @@
+//! Encodes instructions into machine code.
+//! Jumps to labels within the text are resolved here, anything that refers to a
+//! function or to data is left as a relocation for the object file.
+use asm::{Assembly, Cond, Instr, Operand, Reg};
+use codegen::CodegenError;
+use std::collections::HashMap;
+use syntax::ast::{Sign, Size};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    /// A call to a function, which may go through the procedure linkage table
+    Plt32,
+    /// A 32 bit offset from the end of the instruction
+    Pc32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// Where the 32 bit value that needs patching is in the text
+    pub offset: usize,
+    /// The function or data label being referred to
+    pub symbol: String,
+    pub kind: RelocationKind,
+    pub addend: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct Text {
+    pub code: Vec<u8>,
+    /// The name, global flag, offset and size of each function
+    pub functions: Vec<(String, bool, usize, usize)>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Default)]
+struct Encoder {
+    code: Vec<u8>,
+    labels: HashMap<String, usize>,
+    /// Jumps whose label is patched in once every label has an offset
+    jumps: Vec<(usize, String)>,
+    relocations: Vec<Relocation>,
+}
+
+pub fn encode(asm: &Assembly) -> Result<Text, CodegenError> {
+    let mut encoder = Encoder::default();
+    let mut functions = Vec::new();
+
+    for function in &asm.functions {
+        let start = encoder.code.len();
+
+        for instr in &function.body {
+            encoder.instr(instr)?;
+        }
+
+        functions.push((
+            function.name.clone(),
+            function.global,
+            start,
+            encoder.code.len() - start,
+        ));
+    }
+
+    for (offset, label) in ::std::mem::take(&mut encoder.jumps) {
+        let target = match encoder.labels.get(&label) {
+            Some(target) => *target as i64,
+            None => {
+                return Err(CodegenError::Unsupported(format!(
+                    "Jump to undefined label `{}`",
+                    label
+                )))
+            }
+        };
+
+        let rel = (target - (offset as i64 + 4)) as i32;
+        encoder.code[offset..offset + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    Ok(Text {
+        code: encoder.code,
+        functions,
+        relocations: encoder.relocations,
+    })
+}
+
+/// The encoding of a condition in `jcc` and `setcc`
+fn condition(cond: Cond) -> u8 {
+    match cond {
+        Cond::B => 0x2,
+        Cond::Ae => 0x3,
+        Cond::E => 0x4,
+        Cond::Ne => 0x5,
+        Cond::Be => 0x6,
+        Cond::A => 0x7,
+        Cond::L => 0xc,
+        Cond::Ge => 0xd,
+        Cond::Le => 0xe,
+        Cond::G => 0xf,
+    }
+}
+
+fn code(reg: Reg) -> u8 {
+    reg as u8 & 7
+}
+
+/// Whether the register needs a REX prefix bit to be addressed
+fn extended(reg: Reg) -> bool {
+    reg as u8 >= 8
+}
+
+fn fits_i8(value: i64) -> bool {
+    value >= i64::from(i8::MIN) && value <= i64::from(i8::MAX)
+}
+
+fn fits_i32(value: i64) -> bool {
+    value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX)
+}
+
+impl Encoder {
+    fn instr(&mut self, instr: &Instr) -> Result<(), CodegenError> {
+        use self::Operand::*;
+
+        match *instr {
+            Instr::Label(ref label) => {
+                self.labels.insert(label.clone(), self.code.len());
+            }
+
+            Instr::Mov(Reg(src), ref dst) => self.op_rm(true, &[0x89], src, dst),
+            Instr::Mov(Imm(value), ref dst) if fits_i32(value) => {
+                self.op_rm(true, &[0xc7], extension(0), dst);
+                self.imm32(value);
+            }
+            Instr::Mov(Imm(value), Reg(dst)) => self.instr(&Instr::MovAbs(value, dst))?,
+            Instr::Mov(ref src, Reg(dst)) => self.op_rm(true, &[0x8b], dst, src),
+            Instr::Mov(ref src, ref dst) => {
+                return Err(CodegenError::Unsupported(format!(
+                    "Can't move {} to {}",
+                    src, dst
+                )))
+            }
+
+            Instr::MovAbs(value, dst) => {
+                self.rex(true, false, extended(dst));
+                self.code.push(0xb8 + code(dst));
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+
+            Instr::Movsx(Size::Bit8, src, dst) => self.op_rm(true, &[0x0f, 0xbe], dst, &Reg(src)),
+            Instr::Movsx(Size::Bit32, src, dst) => self.op_rm(true, &[0x63], dst, &Reg(src)),
+            Instr::Movzx(Size::Bit8, src, dst) => self.op_rm(true, &[0x0f, 0xb6], dst, &Reg(src)),
+            Instr::Movzx(Size::Bit32, src, dst) => self.op_rm(false, &[0x89], src, &Reg(dst)),
+            Instr::Movsx(Size::Bit64, src, dst) | Instr::Movzx(Size::Bit64, src, dst) => {
+                self.op_rm(true, &[0x89], src, &Reg(dst))
+            }
+
+            Instr::Load(Sign::Signed, Size::Bit8, ref src, dst) => {
+                self.op_rm(true, &[0x0f, 0xbe], dst, src)
+            }
+            Instr::Load(Sign::Signed, Size::Bit32, ref src, dst) => {
+                self.op_rm(true, &[0x63], dst, src)
+            }
+            Instr::Load(Sign::Unsigned, Size::Bit8, ref src, dst) => {
+                self.op_rm(true, &[0x0f, 0xb6], dst, src)
+            }
+            Instr::Load(Sign::Unsigned, Size::Bit32, ref src, dst) => {
+                self.op_rm(false, &[0x8b], dst, src)
+            }
+            Instr::Load(_, Size::Bit64, ref src, dst) => self.op_rm(true, &[0x8b], dst, src),
+
+            Instr::Store(Size::Bit8, src, ref dst) => {
+                let ext_base = match *dst {
+                    Reg(base) | Mem(base, _) => extended(base),
+                    _ => false,
+                };
+
+                // Without a prefix 4 to 7 would be the high bytes of the first registers
+                if src as u8 >= 4 || ext_base {
+                    self.rex(false, extended(src), ext_base);
+                }
+
+                self.code.push(0x88);
+                self.modrm(src, dst);
+            }
+            Instr::Store(Size::Bit32, src, ref dst) => self.op_rm(false, &[0x89], src, dst),
+            Instr::Store(Size::Bit64, src, ref dst) => self.op_rm(true, &[0x89], src, dst),
+
+            Instr::Lea(ref src, dst) => self.op_rm(true, &[0x8d], dst, src),
+
+            Instr::Add(ref src, dst) => self.arithmetic(0x01, 0, src, dst),
+            Instr::Or(ref src, dst) => self.arithmetic(0x09, 1, src, dst),
+            Instr::And(ref src, dst) => self.arithmetic(0x21, 4, src, dst),
+            Instr::Sub(ref src, dst) => self.arithmetic(0x29, 5, src, dst),
+            Instr::Xor(ref src, dst) => self.arithmetic(0x31, 6, src, dst),
+            Instr::Cmp(ref src, dst) => self.arithmetic(0x39, 7, src, dst),
+
+            Instr::Imul(Imm(value), dst) => {
+                if fits_i8(value) {
+                    self.op_rm(true, &[0x6b], dst, &Reg(dst));
+                    self.code.push(value as u8);
+                } else {
+                    self.op_rm(true, &[0x69], dst, &Reg(dst));
+                    self.imm32(value);
+                }
+            }
+            Instr::Imul(ref src, dst) => self.op_rm(true, &[0x0f, 0xaf], dst, src),
+
+            Instr::Neg(reg) => self.op_rm(true, &[0xf7], extension(3), &Reg(reg)),
+            Instr::Div(reg) => self.op_rm(true, &[0xf7], extension(6), &Reg(reg)),
+            Instr::Idiv(reg) => self.op_rm(true, &[0xf7], extension(7), &Reg(reg)),
+            Instr::Cqo => self.code.extend_from_slice(&[0x48, 0x99]),
+
+            Instr::Test(src, dst) => self.op_rm(true, &[0x85], src, &Reg(dst)),
+
+            Instr::Set(cond, reg) => {
+                // Without a prefix 4 to 7 would be the high bytes of the first registers
+                if reg as u8 >= 4 {
+                    self.rex(false, false, extended(reg));
+                }
+
+                self.code.extend_from_slice(&[0x0f, 0x90 + condition(cond)]);
+                self.code.push(0xc0 | code(reg));
+            }
+
+            Instr::Jmp(ref label) => {
+                self.code.push(0xe9);
+                self.jump(label);
+            }
+
+            Instr::Jcc(cond, ref label) => {
+                self.code.extend_from_slice(&[0x0f, 0x80 + condition(cond)]);
+                self.jump(label);
+            }
+
+            Instr::Call(ref function) => {
+                self.code.push(0xe8);
+                self.relocation(function, RelocationKind::Plt32);
+            }
+
+            Instr::CallReg(reg) => {
+                if extended(reg) {
+                    self.rex(false, false, true);
+                }
+
+                self.code.extend_from_slice(&[0xff, 0xd0 | code(reg)]);
+            }
+
+            Instr::Push(reg) => {
+                if extended(reg) {
+                    self.rex(false, false, true);
+                }
+
+                self.code.push(0x50 + code(reg));
+            }
+
+            Instr::Pop(reg) => {
+                if extended(reg) {
+                    self.rex(false, false, true);
+                }
+
+                self.code.push(0x58 + code(reg));
+            }
+
+            Instr::Ret => self.code.push(0xc3),
+        }
+
+        Ok(())
+    }
+
+    /// Arithmetic with a register destination. `op` is the form that takes a register
+    /// source and `ext` is the opcode extension of the form that takes an immediate
+    fn arithmetic(&mut self, op: u8, ext: u8, src: &Operand, dst: Reg) {
+        match *src {
+            Operand::Reg(src) => self.op_rm(true, &[op], src, &Operand::Reg(dst)),
+            Operand::Imm(value) => {
+                let reg = extension(ext);
+
+                if fits_i8(value) {
+                    self.op_rm(true, &[0x83], reg, &Operand::Reg(dst));
+                    self.code.push(value as u8);
+                } else if dst == Reg::Rax {
+                    // rax has a shorter form without a ModRM byte
+                    self.code.extend_from_slice(&[0x48, ext << 3 | 5]);
+                    self.imm32(value);
+                } else {
+                    self.op_rm(true, &[0x81], reg, &Operand::Reg(dst));
+                    self.imm32(value);
+                }
+            }
+            // The form that reads memory is two past the one that writes it
+            ref src => self.op_rm(true, &[op + 2], dst, src),
+        }
+    }
+
+    /// Emits an instruction with a ModRM byte where `reg` is the register field and
+    /// `rm` is the register or memory operand
+    fn op_rm(&mut self, wide: bool, opcode: &[u8], reg: Reg, rm: &Operand) {
+        let base = match *rm {
+            Operand::Reg(base) | Operand::Mem(base, _) => Some(base),
+            _ => None,
+        };
+
+        let ext_base = base.map(extended).unwrap_or(false);
+
+        if wide || extended(reg) || ext_base {
+            self.rex(wide, extended(reg), ext_base);
+        }
+
+        self.code.extend_from_slice(opcode);
+        self.modrm(reg, rm);
+    }
+
+    /// The ModRM byte and any displacement that follows it
+    fn modrm(&mut self, reg: Reg, rm: &Operand) {
+        let reg = code(reg) << 3;
+
+        match *rm {
+            Operand::Reg(rm) => self.code.push(0xc0 | reg | code(rm)),
+
+            Operand::Mem(base, disp) => {
+                // rbp and r13 can't be used without a displacement
+                let (mode, size) = if disp == 0 && code(base) != 5 {
+                    (0x00, 0)
+                } else if fits_i8(i64::from(disp)) {
+                    (0x40, 1)
+                } else {
+                    (0x80, 4)
+                };
+
+                self.code.push(mode | reg | code(base));
+
+                // rsp and r12 need a scale index byte
+                if code(base) == 4 {
+                    self.code.push(0x24);
+                }
+
+                match size {
+                    1 => self.code.push(disp as u8),
+                    4 => self.code.extend_from_slice(&disp.to_le_bytes()),
+                    _ => (),
+                }
+            }
+
+            Operand::Rip(ref label) => {
+                self.code.push(reg | 0x05);
+                self.relocation(label, RelocationKind::Pc32);
+            }
+
+            Operand::Imm(_) => unreachable!("Immediates aren't encoded with a ModRM byte"),
+        }
+    }
+
+    fn rex(&mut self, wide: bool, reg: bool, base: bool) {
+        self.code
+            .push(0x40 | (wide as u8) << 3 | (reg as u8) << 2 | base as u8);
+    }
+
+    fn imm32(&mut self, value: i64) {
+        self.code.extend_from_slice(&(value as i32).to_le_bytes());
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.jumps.push((self.code.len(), label.into()));
+        self.code.extend_from_slice(&[0; 4]);
+    }
+
+    /// The offset is relative to the end of the instruction, which is where the
+    /// four bytes being patched end for every instruction that is emitted
+    fn relocation(&mut self, symbol: &str, kind: RelocationKind) {
+        self.relocations.push(Relocation {
+            offset: self.code.len(),
+            symbol: symbol.into(),
+            kind,
+            addend: -4,
+        });
+        self.code.extend_from_slice(&[0; 4]);
+    }
+}
+
+/// The register whose number is an opcode extension
+fn extension(ext: u8) -> Reg {
+    use asm::Reg::*;
+
+    [Rax, Rcx, Rdx, Rbx, Rsp, Rbp, Rsi, Rdi][ext as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::encode;
+    use asm::{Assembly, Cond, Function, Instr, Operand};
+    use std::fs;
+    use std::process::Command;
+    use syntax::ast::{Sign, Size};
+
+    /// Assembles the instructions with `as` and returns the bytes of the text section
+    fn assemble(asm: &Assembly, name: &str) -> Vec<u8> {
+        let dir = ::std::env::temp_dir();
+        let id = format!("underscore_encode_{}_{}", name, ::std::process::id());
+        let (source, object, text) = (
+            dir.join(format!("{}.s", id)),
+            dir.join(format!("{}.o", id)),
+            dir.join(format!("{}.bin", id)),
+        );
+
+        fs::write(&source, asm.to_string()).unwrap();
+
+        assert!(Command::new("as")
+            .arg(&source)
+            .arg("-o")
+            .arg(&object)
+            .status()
+            .unwrap()
+            .success());
+        assert!(Command::new("objcopy")
+            .args(["-O", "binary", "--only-section=.text"])
+            .arg(&object)
+            .arg(&text)
+            .status()
+            .unwrap()
+            .success());
+
+        let bytes = fs::read(&text).unwrap();
+
+        for file in &[source, object, text] {
+            let _ = fs::remove_file(file);
+        }
+
+        bytes
+    }
+
+    fn function(body: Vec<Instr>) -> Assembly {
+        Assembly {
+            functions: vec![Function {
+                name: "f".into(),
+                global: true,
+                body,
+            }],
+            data: vec![],
+            externs: vec![],
+        }
+    }
+
+    #[test]
+    fn matches_the_system_assembler() {
+        use asm::Reg::*;
+
+        let regs = [Rax, Rcx, Rsp, Rbp, Rsi, Rdi, R8, R12, R13, R15];
+        let mut body = vec![];
+
+        for &src in &regs {
+            for &dst in &[Rax, Rbx, Rsp, Rbp, Rdi, R9, R12, R13] {
+                body.push(Instr::Mov(Operand::Reg(src), Operand::Reg(dst)));
+                body.push(Instr::Add(Operand::Reg(src), dst));
+                body.push(Instr::Cmp(Operand::Reg(src), dst));
+                body.push(Instr::Imul(Operand::Reg(src), dst));
+                body.push(Instr::Test(src, dst));
+            }
+
+            for &disp in &[0, -8, 16, -200, 4096] {
+                for &base in &[Rbp, Rsp, Rax, R12, R13] {
+                    body.push(Instr::Mov(Operand::Mem(base, disp), Operand::Reg(src)));
+                    body.push(Instr::Mov(Operand::Reg(src), Operand::Mem(base, disp)));
+                    body.push(Instr::Lea(Operand::Mem(base, disp), src));
+                    body.push(Instr::Sub(Operand::Mem(base, disp), src));
+                }
+            }
+
+            for &value in &[0, 1, -1, 127, 128, -129, 70000, 1 << 40] {
+                body.push(Instr::Mov(Operand::Imm(value), Operand::Reg(src)));
+                body.push(Instr::MovAbs(value, src));
+
+                if value < 1 << 31 {
+                    body.push(Instr::And(Operand::Imm(value), src));
+                    body.push(Instr::Imul(Operand::Imm(value), src));
+                }
+            }
+
+            for &size in &[Size::Bit8, Size::Bit32, Size::Bit64] {
+                body.push(Instr::Movsx(size, src, Rax));
+                body.push(Instr::Movzx(size, src, R10));
+                body.push(Instr::Movzx(size, Rsi, src));
+            }
+
+            for &base in &[Rbp, Rsp, Rax, R12, R13] {
+                for &size in &[Size::Bit8, Size::Bit32, Size::Bit64] {
+                    body.push(Instr::Load(Sign::Signed, size, Operand::Mem(base, 12), src));
+                    body.push(Instr::Load(Sign::Unsigned, size, Operand::Mem(base, 0), src));
+                    body.push(Instr::Store(size, src, Operand::Mem(base, -300)));
+                }
+            }
+
+            body.push(Instr::Mov(Operand::Imm(-5), Operand::Mem(src, 8)));
+            body.push(Instr::Set(Cond::Le, src));
+            body.push(Instr::Neg(src));
+            body.push(Instr::Idiv(src));
+            body.push(Instr::Div(src));
+            body.push(Instr::Push(src));
+            body.push(Instr::Pop(src));
+            body.push(Instr::CallReg(src));
+        }
+
+        body.push(Instr::Cqo);
+        body.push(Instr::Ret);
+
+        let asm = function(body);
+
+        assert_eq!(encode(&asm).unwrap().code, assemble(&asm, "instructions"));
+    }
+
+    #[test]
+    fn jumps_are_resolved() {
+        let asm = function(vec![
+            Instr::Label("start".into()),
+            Instr::Jcc(Cond::Ne, "end".into()),
+            Instr::Jmp("start".into()),
+            Instr::Label("end".into()),
+            Instr::Ret,
+        ]);
+
+        let text = encode(&asm).unwrap();
+
+        assert_eq!(
+            text.code,
+            vec![0x0f, 0x85, 5, 0, 0, 0, 0xe9, 0xf5, 0xff, 0xff, 0xff, 0xc3]
+        );
+        assert!(text.relocations.is_empty());
+    }
+}
@@ -1 +1,16 @@
-extern crate underscore_ir as ir;
\ No newline at end of file
+extern crate underscore_ir as ir;
+extern crate underscore_syntax as syntax;
+extern crate underscore_util as util;
+
+mod asm;
+mod codegen;
+mod elf;
+mod encode;
+mod link;
+mod regalloc;
+pub mod runtime;
+
+pub use asm::{Assembly, Cond, Function, Instr, Operand, Reg};
+pub use codegen::{mangle, Codegen, CodegenError};
+pub use elf::object;
+pub use link::link;
@@ -0,0 +1,44 @@
+//! Builds executables with the system linker.
+use asm::Assembly;
+use codegen::CodegenError;
+use elf;
+use runtime;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Writes the program as an object and links it against the runtime and the C
+/// library into an executable at `output`
+pub fn link(asm: &Assembly, output: &Path) -> Result<(), CodegenError> {
+    let object = output.with_extension("o");
+    let source = output.with_extension("runtime.c");
+
+    write(&object, &elf::object(asm)?)?;
+    write(&source, runtime::SOURCE.as_bytes())?;
+
+    let result = Command::new("cc")
+        .arg(&object)
+        .arg(&source)
+        .arg("-o")
+        .arg(output)
+        .output();
+
+    let _ = fs::remove_file(&object);
+    let _ = fs::remove_file(&source);
+
+    let result =
+        result.map_err(|e| CodegenError::Toolchain(format!("Couldn't run `cc`: {}", e)))?;
+
+    if !result.status.success() {
+        return Err(CodegenError::Toolchain(
+            String::from_utf8_lossy(&result.stderr).into_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<(), CodegenError> {
+    fs::write(path, bytes)
+        .map_err(|e| CodegenError::Toolchain(format!("Couldn't write `{}`: {}", path.display(), e)))
+}
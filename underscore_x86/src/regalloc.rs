@@ -0,0 +1,256 @@
+//! Linear scan register allocation.
+//! Each temp gets a single interval covering every instruction it is live at and
+//! intervals are handed registers in order of where they start. When there aren't
+//! enough registers the interval that ends last is spilled to a stack slot.
+use asm::Reg;
+use ir::ir::{Function, Instruction, Temp};
+use ir::liveness;
+use std::collections::{HashMap, HashSet};
+
+/// Clobbered by calls, so only temps that aren't live across one can use them
+pub const CALLER_SAVED: [Reg; 6] = [Reg::Rsi, Reg::Rdi, Reg::R8, Reg::R9, Reg::R10, Reg::R11];
+
+/// Preserved by calls, but a function has to save them before using them
+pub const CALLEE_SAVED: [Reg; 5] = [Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15];
+
+// rax, rcx and rdx are never handed out as the code generator uses them as scratch
+
+#[derive(Debug, Default)]
+pub struct Allocation {
+    pub registers: HashMap<Temp, Reg>,
+    /// Temps that live in a stack slot
+    pub spilled: HashSet<Temp>,
+    /// The callee saved registers the function uses, which it has to preserve
+    pub saved: Vec<Reg>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    temp: Temp,
+    start: usize,
+    end: usize,
+    /// Whether the temp holds a value across a call
+    across_call: bool,
+}
+
+pub fn allocate(function: &Function) -> Allocation {
+    let mut intervals = intervals(function);
+
+    intervals.sort_by_key(|interval| (interval.start, interval.end, interval.temp.0));
+
+    let mut allocation = Allocation::default();
+    let mut free: Vec<Reg> = CALLER_SAVED
+        .iter()
+        .chain(CALLEE_SAVED.iter())
+        .cloned()
+        .collect();
+    // Sorted by where the interval ends
+    let mut active: Vec<Interval> = Vec::new();
+
+    for interval in intervals {
+        // Registers whose temps are dead by now can be reused
+        while !active.is_empty() && active[0].end < interval.start {
+            let expired = active.remove(0);
+            free.push(allocation.registers[&expired.temp]);
+        }
+
+        let choice = free
+            .iter()
+            .position(|reg| !interval.across_call || CALLEE_SAVED.contains(reg));
+
+        match choice {
+            Some(index) => {
+                let reg = free.remove(index);
+                allocation.registers.insert(interval.temp, reg);
+                insert(&mut active, interval);
+            }
+
+            None => {
+                // Take the register of the active interval that lasts longest if it ends
+                // after this one and this interval could use its register
+                let victim = active.iter().rposition(|active| {
+                    active.end > interval.end
+                        && (!interval.across_call
+                            || CALLEE_SAVED.contains(&allocation.registers[&active.temp]))
+                });
+
+                match victim {
+                    Some(index) => {
+                        let victim = active.remove(index);
+                        let reg = allocation.registers.remove(&victim.temp).unwrap();
+
+                        allocation.spilled.insert(victim.temp);
+                        allocation.registers.insert(interval.temp, reg);
+                        insert(&mut active, interval);
+                    }
+                    None => {
+                        allocation.spilled.insert(interval.temp);
+                    }
+                }
+            }
+        }
+    }
+
+    for reg in &CALLEE_SAVED {
+        if allocation.registers.values().any(|used| used == reg) {
+            allocation.saved.push(*reg);
+        }
+    }
+
+    allocation
+}
+
+fn insert(active: &mut Vec<Interval>, interval: Interval) {
+    let index = active
+        .iter()
+        .position(|active| active.end > interval.end)
+        .unwrap_or(active.len());
+
+    active.insert(index, interval);
+}
+
+fn intervals(function: &Function) -> Vec<Interval> {
+    let liveness = liveness::analyse(function);
+    let mut intervals: HashMap<Temp, Interval> = HashMap::new();
+
+    {
+        let mut extend = |temp: Temp, at: usize| {
+            let interval = intervals.entry(temp).or_insert(Interval {
+                temp,
+                start: at,
+                end: at,
+                across_call: false,
+            });
+
+            interval.start = interval.start.min(at);
+            interval.end = interval.end.max(at);
+        };
+
+        // Parameters are written on entry
+        for param in &function.params {
+            extend(*param, 0);
+        }
+
+        for (i, instruction) in function.body.iter().enumerate() {
+            for temp in liveness.live_in[i]
+                .iter()
+                .chain(&liveness.live_out[i])
+                .cloned()
+                .chain(instruction.uses())
+                .chain(instruction.defs())
+            {
+                extend(temp, i);
+            }
+        }
+    }
+
+    for (i, instruction) in function.body.iter().enumerate() {
+        // Allocating memory calls into the runtime
+        let calls = match *instruction {
+            Instruction::Alloc(_, _) => true,
+            _ => instruction.is_call(),
+        };
+
+        if !calls {
+            continue;
+        }
+
+        for temp in &liveness.live_out[i] {
+            if Some(*temp) != instruction.defs() {
+                intervals.get_mut(temp).unwrap().across_call = true;
+            }
+        }
+    }
+
+    intervals.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{allocate, CALLEE_SAVED};
+    use ir::ir::{BinOp, Function, Instruction, Temp, Value};
+    use ir::liveness;
+    use std::rc::Rc;
+    use syntax::ast::{Linkage, Sign, Size};
+    use util::symbol::{SymbolMap, Symbols};
+
+    fn function(body: Vec<Instruction>) -> Function {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+
+        Function {
+            name: symbols.symbol("f"),
+            params: vec![],
+            param_types: vec![],
+            returns: (Sign::Signed, Size::Bit32),
+            upvalues: vec![],
+            body,
+            linkage: Linkage::Normal,
+        }
+    }
+
+    fn constant(temp: Temp, value: u64) -> Instruction {
+        Instruction::Store(temp, Value::Const(value, Sign::Signed, Size::Bit32))
+    }
+
+    #[test]
+    fn live_temps_never_share_a_register() {
+        // Twenty values that are all live at once, then summed
+        let temps: Vec<Temp> = (0..20).map(|_| Temp::new()).collect();
+        let total = Temp::new();
+
+        let mut body: Vec<Instruction> = temps
+            .iter()
+            .enumerate()
+            .map(|(i, temp)| constant(*temp, i as u64))
+            .collect();
+
+        body.push(constant(total, 0));
+
+        for temp in &temps {
+            body.push(Instruction::BinOp(total, BinOp::Plus, *temp, total));
+        }
+
+        body.push(Instruction::Return(total));
+
+        let function = function(body);
+        let allocation = allocate(&function);
+        let liveness = liveness::analyse(&function);
+
+        assert!(!allocation.spilled.is_empty());
+
+        for live in &liveness.live_out {
+            let mut used = vec![];
+
+            for temp in live {
+                if let Some(reg) = allocation.registers.get(temp) {
+                    assert!(!used.contains(reg), "{:?} is used twice", reg);
+                    used.push(*reg);
+                }
+            }
+        }
+
+        for temp in temps.iter().chain(Some(&total)) {
+            assert!(allocation.registers.contains_key(temp) != allocation.spilled.contains(temp));
+        }
+    }
+
+    #[test]
+    fn values_live_across_calls_are_callee_saved() {
+        let mut symbols: Symbols<()> = Symbols::new(Rc::new(SymbolMap::new()));
+        let (kept, arg, result, total) = (Temp::new(), Temp::new(), Temp::new(), Temp::new());
+
+        let function = function(vec![
+            constant(kept, 1),
+            constant(arg, 2),
+            Instruction::Call(result, symbols.symbol("g"), vec![arg]),
+            Instruction::BinOp(kept, BinOp::Plus, result, total),
+            Instruction::Return(total),
+        ]);
+
+        let allocation = allocate(&function);
+
+        assert!(CALLEE_SAVED.contains(&allocation.registers[&kept]));
+        assert!(!CALLEE_SAVED.contains(&allocation.registers[&arg]));
+        assert_eq!(allocation.saved, vec![allocation.registers[&kept]]);
+    }
+}
@@ -0,0 +1,69 @@
+//! The runtime library native executables are linked against.
+//! It is written in C and compiled along with the program, so only the symbols
+//! the generated code calls live here.
+use ir::ir::Intrinsic;
+use syntax::ast::Sign;
+
+/// The source of the runtime
+pub const SOURCE: &str = include_str!("../runtime/runtime.c");
+
+/// The symbol the runtime calls to start the program
+pub const ENTRY: &str = "_us_main";
+
+/// Reports a runtime error, given a nul terminated message, and exits
+pub const ERROR: &str = "__us_rt_error";
+
+/// Returns a pointer to the given number of zeroed bytes on the heap
+pub const ALLOC: &str = "__us_rt_alloc";
+
+/// The function that implements an intrinsic, `sign` is the sign of its argument
+pub fn intrinsic(intrinsic: Intrinsic, sign: Sign) -> &'static str {
+    match intrinsic {
+        Intrinsic::PrintInt if sign == Sign::Unsigned => "__us_rt_print_uint",
+        Intrinsic::PrintInt => "__us_rt_print_int",
+        Intrinsic::PrintBool => "__us_rt_print_bool",
+        Intrinsic::PrintChar => "__us_rt_print_char",
+        Intrinsic::PrintStr => "__us_rt_print_str",
+        Intrinsic::Assert => "__us_rt_assert",
+        Intrinsic::Exit => "__us_rt_exit",
+        Intrinsic::StrLen => "__us_rt_len",
+        Intrinsic::StrEq => "__us_rt_str_eq",
+        Intrinsic::StrConcat => "__us_rt_concat",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{intrinsic, ALLOC, ENTRY, ERROR, SOURCE};
+    use ir::ir::Intrinsic;
+    use syntax::ast::Sign;
+
+    #[test]
+    fn every_intrinsic_is_defined() {
+        let intrinsics = [
+            Intrinsic::PrintInt,
+            Intrinsic::PrintBool,
+            Intrinsic::PrintChar,
+            Intrinsic::PrintStr,
+            Intrinsic::Assert,
+            Intrinsic::Exit,
+            Intrinsic::StrLen,
+            Intrinsic::StrEq,
+            Intrinsic::StrConcat,
+        ];
+
+        for name in intrinsics
+            .iter()
+            .flat_map(|i| vec![intrinsic(*i, Sign::Signed), intrinsic(*i, Sign::Unsigned)])
+            .chain(vec![ERROR, ALLOC])
+        {
+            assert!(
+                SOURCE.contains(&format!("{}(", name)),
+                "{} is missing",
+                name
+            );
+        }
+
+        assert!(SOURCE.contains(&format!("{}(void);", ENTRY)));
+    }
+}